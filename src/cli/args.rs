@@ -1,38 +1,257 @@
 //! Arguments for different `netbeat` CLI commands.
 
-use crate::core::config::{self, BindInterface};
+use crate::{
+    core::{
+        config::{self, BindInterface},
+        profile::{ClientProfile, ServerProfile},
+    },
+    output::{formats::OutputFormat, history},
+    utils::units,
+};
 use clap::Args;
+use std::{net::SocketAddr, path::PathBuf, time::SystemTime};
 
 /// `netbeat run` CLI arguments.
+///
+/// Settings left unset fall back to the `NETBEAT_*` environment variables, then the configuration file, then the defaults.
 #[derive(Debug, Args)]
 pub struct RunArgs {
-    /// Target server IP address
+    /// Target server IP address or hostname, optionally with port, or the name of a target profile (eg, 10.1.1.11, nas.lan:5050, [fe80::1]:5050, nas)
     pub target: String,
-    /// Target port on server (1-65535)
+    /// Target port on server (1-65535) [default: 5050]
+    #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..=65535))]
+    pub port: Option<u16>,
+    /// Time limit per test direction in seconds (1-3600) [default: 10]
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..=3600))]
+    pub time: Option<u64>,
+    /// Target size of data to be uploaded/downloaded in the speed test including units (eg, 10MB, 1GB, 2GB). Instead of time.
+    #[arg(short, long)]
+    pub data: Option<String>,
+    /// Buffer size for read/write operations (eg, 32KiB, 64KiB, 128KiB) [default: 64KiB]
+    #[arg(short, long)]
+    pub chunk_size: Option<String>,
+    /// Number of pings to perform for ping test (1-1000) [default: 20]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub ping_count: Option<u32>,
+    /// Number of parallel TCP streams per test direction (1-128) [default: 1]
+    #[arg(short = 'P', long, value_parser = clap::value_parser!(u32).range(1..=128))]
+    pub parallel: Option<u32>,
+    /// Run upload and download tests at the same time, each over its own streams
+    #[arg(long)]
+    pub bidir: bool,
+    /// Run upload and download tests over UDP at a fixed bitrate, reporting datagram loss and jitter
+    #[arg(short, long, conflicts_with_all = ["parallel", "bidir"])]
+    pub udp: bool,
+    /// Target bitrate for UDP tests including units (eg, 1Mbps, 100Mbps, 1Gbps) [default: 1Mbps]
+    #[arg(short, long)]
+    pub bitrate: Option<String>,
+    /// UDP datagram size in bytes (64-65507) [default: 1400]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(64..=65507))]
+    pub datagram_size: Option<u32>,
+    /// Probe latency on a separate connection during upload and download tests, grading bufferbloat
+    #[arg(long)]
+    pub latency_probe: bool,
+    /// Throughput reporting interval in seconds (0.1-60) [default: 1]
+    #[arg(short, long, value_parser = parse_interval)]
+    pub interval: Option<f64>,
+    /// Format of the results written to stdout [default: table]
+    #[arg(short, long, value_enum)]
+    pub format: Option<OutputFormat>,
+    /// Return results as json to stdout (same as --format json)
+    #[arg(short, long, conflicts_with = "format")]
+    pub json: bool,
+    /// Omit the csv header row (eg, when appending to an existing file)
+    #[arg(long)]
+    pub no_header: bool,
+    /// Fail when the download speed is below this including units (eg, 900Mbps)
+    #[arg(long, value_parser = validate_bitrate)]
+    pub min_download: Option<String>,
+    /// Fail when the upload speed is below this including units (eg, 900Mbps)
+    #[arg(long, value_parser = validate_bitrate)]
+    pub min_upload: Option<String>,
+    /// Fail when the average ping is above this including units (eg, 2ms, 500us)
+    #[arg(long, value_parser = validate_duration)]
+    pub max_latency: Option<String>,
+    /// Fail when the packet loss (or datagram loss with --udp) is above this (eg, 0%, 0.5%)
+    #[arg(long, value_parser = validate_percent)]
+    pub max_loss: Option<String>,
+    /// Do not keep the report in the local history
+    #[arg(long)]
+    pub no_history: bool,
+    /// Connection timeout in seconds [default: 30]
+    #[arg(long)]
+    pub timeout: Option<u64>,
+    /// Number of retry attempts on initial connection failure [default: 3]
+    #[arg(long)]
+    pub retries: Option<u32>,
+    /// File holding the pre-shared key to authenticate with, for servers that require one (or set NETBEAT_PSK)
+    #[arg(long = "psk-file", value_name = "PATH", value_parser = read_psk_file)]
+    pub psk: Option<String>,
+    /// Encrypt the connection with TLS, pinning the server certificate on first use
+    #[arg(long)]
+    pub tls: bool,
+    /// CA certificates (PEM) to verify the server certificate against, instead of pinning it on first use. Implies --tls
+    #[arg(long, value_name = "PATH")]
+    pub tls_ca: Option<PathBuf>,
+    /// Configuration file with client settings and target profiles, instead of the one in the netbeat config directory
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Suppress progress output (results & errors only)
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Enable verbose output
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+impl RunArgs {
+    /// Client settings given on the command line, unset where the flag was not given.
+    pub fn profile(&self) -> ClientProfile {
+        ClientProfile {
+            address: None,
+            port: self.port,
+            data: self.data.clone(),
+            time: self.time,
+            chunk_size: self.chunk_size.clone(),
+            ping_count: self.ping_count,
+            parallel: self.parallel,
+            bidirectional: self.bidir.then_some(true),
+            udp: self.udp.then_some(true),
+            bitrate: self.bitrate.clone(),
+            datagram_size: self.datagram_size,
+            latency_probe: self.latency_probe.then_some(true),
+            interval: self.interval,
+            format: self.json.then_some(OutputFormat::Json).or(self.format),
+            min_download: self.min_download.clone(),
+            min_upload: self.min_upload.clone(),
+            max_latency: self.max_latency.clone(),
+            max_loss: self.max_loss.clone(),
+            history: self.no_history.then_some(false),
+            timeout: self.timeout,
+            retries: self.retries,
+            psk: self.psk.clone(),
+            tls: self.tls.then_some(true),
+            tls_ca: self.tls_ca.clone(),
+        }
+    }
+}
+
+/// `netbeat serve` CLI arguments.
+///
+/// Settings left unset fall back to the `NETBEAT_*` environment variables, then the configuration file, then the defaults.
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Network interface to bind server to: 'all' (0.0.0.0), 'localhost' (127.0.0.1), an IPv4/IPv6 address ('::' for dual-stack) or an interface name (eg, eth0). Repeat or comma-separate to listen on several [default: all]
+    #[arg(short, long = "interface", value_delimiter = ',')]
+    pub interfaces: Vec<BindInterface>,
+    /// Port to listen on (1-65535) [default: 5050]
+    #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..=65535))]
+    pub port: Option<u16>,
+    /// Largest chunk size downloads are sent in (eg, 32KiB, 64KiB, 128KiB), capping the one each client negotiates [default: 64KiB]
+    #[arg(short, long)]
+    pub chunk_size: Option<String>,
+    /// Maximum concurrent connections [default: 50]
+    #[arg(long)]
+    pub connections: Option<u32>,
+    /// File holding the pre-shared key clients must authenticate with before running any test (or set NETBEAT_PSK)
+    #[arg(long = "psk-file", value_name = "PATH", value_parser = read_psk_file)]
+    pub psk: Option<String>,
+    /// Require clients to encrypt connections with TLS, generating a self-signed certificate unless --tls-cert is given
+    #[arg(long)]
+    pub tls: bool,
+    /// Certificate chain (PEM) presented to clients. Implies --tls, requires --tls-key
+    #[arg(long, value_name = "PATH", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// Private key (PEM) of the certificate given with --tls-cert
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Seconds active connections get to finish on SIGINT/SIGTERM before they are closed [default: 30]
+    #[arg(long)]
+    pub drain_timeout: Option<u64>,
+    /// Maximum seconds a client session may run before it is ended
+    #[arg(long, value_name = "SECS")]
+    pub max_session_duration: Option<u64>,
+    /// Maximum data a client session may transfer in both directions together, including units (eg, 500MB, 2GB)
+    #[arg(long, value_name = "SIZE")]
+    pub max_session_bytes: Option<String>,
+    /// Maximum data a client session may transfer in each direction, including units (eg, 500MB, 1GB)
+    #[arg(long, value_name = "SIZE")]
+    pub max_direction_bytes: Option<String>,
+    /// Seconds to wait on a client that sends or reads nothing within a test phase [default: 30]
+    #[arg(long, value_name = "SECS")]
+    pub idle_timeout: Option<u64>,
+    /// Configuration file with server settings, instead of the one in the netbeat config directory
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Suppress all output (errors only)
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Enable verbose output
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+impl ServeArgs {
+    /// Server settings given on the command line, unset where the flag was not given.
+    pub fn profile(&self) -> ServerProfile {
+        ServerProfile {
+            interfaces: (!self.interfaces.is_empty()).then(|| self.interfaces.clone()),
+            port: self.port,
+            chunk_size: self.chunk_size.clone(),
+            max_connections: self.connections,
+            psk: self.psk.clone(),
+            tls: self.tls.then_some(true),
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            drain_timeout: self.drain_timeout,
+            max_session_duration: self.max_session_duration,
+            max_session_bytes: self.max_session_bytes.clone(),
+            max_direction_bytes: self.max_direction_bytes.clone(),
+            idle_timeout: self.idle_timeout,
+        }
+    }
+}
+
+/// `netbeat exporter` CLI arguments.
+#[derive(Debug, Args)]
+pub struct ExporterArgs {
+    /// Target servers IP addresses or hostnames, optionally with port (eg, 10.1.1.11 nas.lan:5050)
+    #[arg(required = true)]
+    pub targets: Vec<String>,
+    /// Address to serve metrics on (eg, 127.0.0.1:9925, 0.0.0.0:9925)
+    #[arg(short, long, default_value = config::DEFAULT_EXPORTER_ADDR)]
+    pub listen: SocketAddr,
+    /// Seconds between the start of consecutive test rounds
+    #[arg(short, long, default_value_t = config::DEFAULT_EXPORTER_EVERY, value_parser = clap::value_parser!(u64).range(1..))]
+    pub every: u64,
+    /// Target port on servers without an explicit port (1-65535)
     #[arg(short, long, default_value_t = config::DEFAULT_PORT, value_parser = clap::value_parser!(u16).range(1..=65535))]
     pub port: u16,
     /// Time limit per test direction in seconds (1-3600).
     #[arg(short, long, default_value_t = config::DEFAULT_TEST_DURATION, value_parser = clap::value_parser!(u64).range(1..=3600))]
     pub time: u64,
-    /// Target size of data to be uploaded/downloaded in the speed test including units (eg, 10MB, 1GB, 2GB). Instead of time.
-    #[arg(short, long)]
-    pub data: Option<String>,
-    /// Buffer size for read/write operations (eg, 32KiB, 64KiB, 128KiB).
-    #[arg(short, long, default_value = config::DEFAULT_CHUNK_SIZE)]
-    pub chunk_size: String,
     /// Number of pings to perform for ping test (1-1000)
     #[arg(long, default_value_t = config::DEFAULT_PING_COUNT, value_parser = clap::value_parser!(u32).range(1..=1000))]
     pub ping_count: u32,
-    /// Return results as json to stdout
-    #[arg(short, long)]
-    pub json: bool,
+    /// Number of parallel TCP streams per test direction (1-128)
+    #[arg(short = 'P', long, default_value_t = config::DEFAULT_PARALLEL_STREAMS, value_parser = clap::value_parser!(u32).range(1..=128))]
+    pub parallel: u32,
     /// Connection timeout in seconds
     #[arg(long, default_value_t = config::DEFAULT_CONNECTION_TIMEOUT)]
     pub timeout: u64,
     /// Number of retry attempts on initial connection failure
     #[arg(long, default_value_t = config::DEFAULT_MAX_RETRIES)]
     pub retries: u32,
-    /// Suppress progress output (results & errors only)
+    /// File holding the pre-shared key to authenticate with, for servers that require one
+    #[arg(long = "psk-file", value_name = "PATH", value_parser = read_psk_file)]
+    pub psk: Option<String>,
+    /// Encrypt connections with TLS, pinning server certificates on first use
+    #[arg(long)]
+    pub tls: bool,
+    /// CA certificates (PEM) to verify server certificates against, instead of pinning them on first use. Implies --tls
+    #[arg(long, value_name = "PATH")]
+    pub tls_ca: Option<PathBuf>,
+    /// Suppress progress output (errors only)
     #[arg(short, long)]
     pub quiet: bool,
     /// Enable verbose output
@@ -40,25 +259,142 @@ pub struct RunArgs {
     pub verbose: bool,
 }
 
-/// `netbeat serve` CLI arguments.
+/// `netbeat monitor` CLI arguments.
 #[derive(Debug, Args)]
-pub struct ServeArgs {
-    /// Network interface to bind server to: 'all' (0.0.0.0) or 'localhost' (127.0.0.1)
-    #[arg(short, long, default_value_t = config::DEFAULT_BIND_INTERFACE, hide_possible_values = true)]
-    pub interface: BindInterface,
-    /// Port to listen on (1-65535)
+pub struct MonitorArgs {
+    /// Target servers IP addresses or hostnames, optionally with port (eg, 10.1.1.11 nas.lan:5050)
+    #[arg(required = true)]
+    pub targets: Vec<String>,
+    /// Seconds between tests of a target
+    #[arg(short, long, default_value_t = config::DEFAULT_MONITOR_EVERY, value_parser = clap::value_parser!(u64).range(1..))]
+    pub every: u64,
+    /// Maximum random seconds added to the time between tests
+    #[arg(long, default_value_t = config::DEFAULT_MONITOR_JITTER)]
+    pub jitter: u64,
+    /// Maximum seconds between tests of an unreachable target, doubling from --every with each failure
+    #[arg(long, default_value_t = config::DEFAULT_MONITOR_MAX_BACKOFF)]
+    pub max_backoff: u64,
+    /// File to append results and errors to as json lines, instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Do not keep the reports in the local history
+    #[arg(long)]
+    pub no_history: bool,
+    /// Target port on servers without an explicit port (1-65535)
     #[arg(short, long, default_value_t = config::DEFAULT_PORT, value_parser = clap::value_parser!(u16).range(1..=65535))]
     pub port: u16,
-    /// Buffer size for data transfer (eg, 32KiB, 64KiB, 128KiB).
-    #[arg(short, long, default_value = config::DEFAULT_CHUNK_SIZE)]
-    pub chunk_size: String,
-    /// Maximum concurrent connections
-    #[arg(long, default_value_t = config::DEFAULT_MAX_CONNECTIONS)]
-    pub connections: u32,
-    /// Suppress all output (errors only)
+    /// Time limit per test direction in seconds (1-3600).
+    #[arg(short, long, default_value_t = config::DEFAULT_TEST_DURATION, value_parser = clap::value_parser!(u64).range(1..=3600))]
+    pub time: u64,
+    /// Number of pings to perform for ping test (1-1000)
+    #[arg(long, default_value_t = config::DEFAULT_PING_COUNT, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub ping_count: u32,
+    /// Number of parallel TCP streams per test direction (1-128)
+    #[arg(short = 'P', long, default_value_t = config::DEFAULT_PARALLEL_STREAMS, value_parser = clap::value_parser!(u32).range(1..=128))]
+    pub parallel: u32,
+    /// Connection timeout in seconds
+    #[arg(long, default_value_t = config::DEFAULT_CONNECTION_TIMEOUT)]
+    pub timeout: u64,
+    /// Number of retry attempts on initial connection failure
+    #[arg(long, default_value_t = config::DEFAULT_MAX_RETRIES)]
+    pub retries: u32,
+    /// File holding the pre-shared key to authenticate with, for servers that require one
+    #[arg(long = "psk-file", value_name = "PATH", value_parser = read_psk_file)]
+    pub psk: Option<String>,
+    /// Encrypt connections with TLS, pinning server certificates on first use
+    #[arg(long)]
+    pub tls: bool,
+    /// CA certificates (PEM) to verify server certificates against, instead of pinning them on first use. Implies --tls
+    #[arg(long, value_name = "PATH")]
+    pub tls_ca: Option<PathBuf>,
+    /// Suppress progress output (errors only)
     #[arg(short, long)]
     pub quiet: bool,
     /// Enable verbose output
     #[arg(short, long)]
     pub verbose: bool,
 }
+
+/// `netbeat history` CLI arguments.
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    /// Only runs against this target, as given to `netbeat run` or as the server address (eg, nas.lan, 10.1.1.11:5050)
+    #[arg(short = 'T', long)]
+    pub target: Option<String>,
+    /// Only runs since this date, date and time (UTC) or age (eg, 2025-08-01, "2025-08-01 18:00:00", 7d)
+    #[arg(short, long, value_parser = history::parse_time)]
+    pub since: Option<SystemTime>,
+    /// Only runs until this date, date and time (UTC) or age (eg, 2025-08-31, 24h)
+    #[arg(short, long, value_parser = history::parse_time)]
+    pub until: Option<SystemTime>,
+    /// Only the most recent runs, up to this many
+    #[arg(short = 'n', long)]
+    pub limit: Option<usize>,
+    /// Show the average results of each target per day, with the change in download speed
+    #[arg(long)]
+    pub trend: bool,
+    /// Format of the runs written to stdout
+    #[arg(short, long, value_enum, default_value_t = config::DEFAULT_OUTPUT_FORMAT)]
+    pub format: OutputFormat,
+    /// Omit the csv header row
+    #[arg(long)]
+    pub no_header: bool,
+    /// History file to read instead of the one in the netbeat data directory
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+}
+
+/// `netbeat compare` CLI arguments.
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    /// Saved reports to compare, the first run being the baseline (json, ndjson or history files)
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Fail when a metric regresses from the baseline by more than this (eg, 5%)
+    #[arg(short, long, value_parser = units::parse_percent)]
+    pub threshold: Option<f64>,
+    /// Do not color regressions and improvements
+    #[arg(long)]
+    pub no_color: bool,
+}
+
+/// Validate a threshold bitrate, keeping it as given for the client builder.
+fn validate_bitrate(s: &str) -> Result<String, String> {
+    units::parse_bitrate(s).map(|_| s.to_string())
+}
+
+/// Validate a threshold duration, keeping it as given for the client builder.
+fn validate_duration(s: &str) -> Result<String, String> {
+    units::parse_duration(s).map(|_| s.to_string())
+}
+
+/// Validate a threshold percentage, keeping it as given for the client builder.
+fn validate_percent(s: &str) -> Result<String, String> {
+    units::parse_percent(s).map(|_| s.to_string())
+}
+
+/// Read a pre-shared key from a file, without the trailing newline.
+fn read_psk_file(path: &str) -> Result<String, String> {
+    let psk = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read pre-shared key file {path} - {e}"))?;
+    let psk = psk.trim_end_matches(['\r', '\n']);
+    if psk.is_empty() {
+        return Err(format!("Pre-shared key file {path} is empty"));
+    }
+    Ok(psk.to_string())
+}
+
+/// Parse a throughput reporting interval in seconds, within the allowed range.
+fn parse_interval(s: &str) -> Result<f64, String> {
+    let interval: f64 = s
+        .parse()
+        .map_err(|_| format!("Invalid interval ({s:?}) - expected seconds, eg 0.5"))?;
+    if !(config::MIN_INTERVAL..=config::MAX_INTERVAL).contains(&interval) {
+        return Err(format!(
+            "Invalid interval ({s:?}) - must be between {} and {} seconds",
+            config::MIN_INTERVAL,
+            config::MAX_INTERVAL
+        ));
+    }
+    Ok(interval)
+}
@@ -1,6 +1,6 @@
 //! Command-line interface commands for netbeat.
 
-use super::{RunArgs, ServeArgs};
+use super::{CompareArgs, ExporterArgs, HistoryArgs, MonitorArgs, RunArgs, ServeArgs};
 
 use clap::Subcommand;
 
@@ -11,6 +11,14 @@ pub enum Commands {
     Run(RunArgs),
     /// Start listening for incoming connections on a server.
     Serve(ServeArgs),
+    /// Serve Prometheus metrics from speed tests run against targets on a schedule.
+    Exporter(ExporterArgs),
+    /// Run speed tests against targets on a schedule, writing every result and error as json lines.
+    Monitor(MonitorArgs),
+    /// List past runs kept in the local history, and their trends.
+    History(HistoryArgs),
+    /// Compare saved reports side by side, with the change of each metric from the first.
+    Compare(CompareArgs),
     // TODO: Install & initialize netbeat on a target server.
     // TODO: Init(InitArgs),
 }
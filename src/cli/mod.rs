@@ -6,7 +6,7 @@ mod styles;
 
 use clap::Parser;
 
-pub use args::{RunArgs, ServeArgs};
+pub use args::{CompareArgs, ExporterArgs, HistoryArgs, MonitorArgs, RunArgs, ServeArgs};
 pub use commands::Commands;
 
 /// Core clap struct for netbeat command line interface.
@@ -19,7 +19,13 @@ pub struct Cli {
 
 #[cfg(test)]
 mod tests {
-    use crate::core::config::BindInterface;
+    use crate::{
+        core::{
+            config::BindInterface,
+            profile::{ClientProfile, ServerProfile},
+        },
+        output::formats::OutputFormat,
+    };
 
     use super::*;
     use clap::Parser;
@@ -32,11 +38,26 @@ mod tests {
         match cli.command {
             Commands::Run(run_args) => {
                 assert_eq!(run_args.target, "192.168.1.1");
-                assert_eq!(run_args.port, 5050); // default
-                assert!(run_args.data.is_none()); // default
-                assert_eq!(run_args.time, 10); // default
-                assert_eq!(run_args.chunk_size, "64KiB"); // default
-                assert_eq!(run_args.ping_count, 20); // default
+                assert!(run_args.port.is_none()); // unset
+                assert!(run_args.data.is_none()); // unset
+                assert!(run_args.time.is_none()); // unset
+                assert!(run_args.chunk_size.is_none()); // unset
+                assert!(run_args.ping_count.is_none()); // unset
+                assert!(run_args.parallel.is_none()); // unset
+                assert!(!run_args.bidir); // default
+                assert!(!run_args.udp); // default
+                assert!(run_args.bitrate.is_none()); // unset
+                assert!(run_args.datagram_size.is_none()); // unset
+                assert!(!run_args.latency_probe); // default
+                assert!(run_args.interval.is_none()); // unset
+                assert!(run_args.format.is_none()); // unset
+                assert!(!run_args.json); // default
+                assert!(!run_args.no_header); // default
+                assert!(run_args.min_download.is_none()); // default
+                assert!(run_args.max_loss.is_none()); // default
+                assert!(run_args.config.is_none()); // default
+                assert_eq!(run_args.profile(), ClientProfile::default());
+                assert!(!run_args.no_history); // default
             }
             _ => panic!("Expected Run command"),
         }
@@ -58,20 +79,147 @@ mod tests {
             "128KiB",
             "--ping-count",
             "20",
+            "--parallel",
+            "8",
+            "--bidir",
+            "--latency-probe",
+            "--interval",
+            "0.5",
         ];
         let cli = Cli::try_parse_from(args).unwrap();
 
         match cli.command {
             Commands::Run(run_args) => {
                 assert_eq!(run_args.target, "example.com");
-                assert_eq!(run_args.port, 8080);
+                assert_eq!(run_args.port, Some(8080));
                 assert_eq!(run_args.data, Some("1GiB".to_string()));
-                assert_eq!(run_args.time, 30);
-                assert_eq!(run_args.chunk_size, "128KiB");
-                assert_eq!(run_args.ping_count, 20);
+                assert_eq!(run_args.time, Some(30));
+                assert_eq!(run_args.chunk_size.as_deref(), Some("128KiB"));
+                assert_eq!(run_args.ping_count, Some(20));
+                assert_eq!(run_args.parallel, Some(8));
+                assert!(run_args.bidir);
+                assert!(run_args.latency_probe);
+                assert_eq!(run_args.interval, Some(0.5));
+
+                let profile = run_args.profile();
+                assert_eq!(profile.port, Some(8080));
+                assert_eq!(profile.bidirectional, Some(true));
+                assert_eq!(profile.latency_probe, Some(true));
+                assert!(profile.udp.is_none());
+                assert!(profile.history.is_none());
+            }
+            _ => panic!("Expected Run command"),
+        }
+    }
+
+    #[test]
+    fn test_run_command_udp() {
+        let args = [
+            "netbeat",
+            "run",
+            "10.1.1.11",
+            "--udp",
+            "--bitrate",
+            "100Mbps",
+            "--datagram-size",
+            "1200",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Run(run_args) => {
+                assert!(run_args.udp);
+                assert_eq!(run_args.bitrate.as_deref(), Some("100Mbps"));
+                assert_eq!(run_args.datagram_size, Some(1200));
+            }
+            _ => panic!("Expected Run command"),
+        }
+
+        // UDP mode runs a single stream in one direction at a time
+        let args = ["netbeat", "run", "10.1.1.11", "--udp", "--bidir"];
+        assert!(Cli::try_parse_from(args).is_err());
+        let args = ["netbeat", "run", "10.1.1.11", "-u", "-P", "4"];
+        assert!(Cli::try_parse_from(args).is_err());
+        let args = ["netbeat", "run", "10.1.1.11", "--datagram-size", "16"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_run_command_format() {
+        let args = [
+            "netbeat",
+            "run",
+            "10.1.1.11",
+            "--format",
+            "csv",
+            "--no-header",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Run(run_args) => {
+                assert_eq!(run_args.format, Some(OutputFormat::Csv));
+                assert!(run_args.no_header);
             }
             _ => panic!("Expected Run command"),
         }
+
+        let args = ["netbeat", "run", "10.1.1.11", "-f", "ndjson"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Commands::Run(run_args) => assert_eq!(run_args.format, Some(OutputFormat::Ndjson)),
+            _ => panic!("Expected Run command"),
+        }
+
+        let args = ["netbeat", "run", "10.1.1.11", "--json", "--no-history"];
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Run(run_args) => {
+                let profile = run_args.profile();
+                assert_eq!(profile.format, Some(OutputFormat::Json));
+                assert_eq!(profile.history, Some(false));
+            }
+            _ => panic!("Expected Run command"),
+        }
+
+        let args = ["netbeat", "run", "10.1.1.11", "--format", "xml"];
+        assert!(Cli::try_parse_from(args).is_err());
+        let args = ["netbeat", "run", "10.1.1.11", "--json", "--format", "csv"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_run_command_thresholds() {
+        let args = [
+            "netbeat",
+            "run",
+            "10.1.1.11",
+            "--min-download",
+            "900Mbps",
+            "--min-upload",
+            "800Mbps",
+            "--max-latency",
+            "2ms",
+            "--max-loss",
+            "0%",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Run(run_args) => {
+                assert_eq!(run_args.min_download.as_deref(), Some("900Mbps"));
+                assert_eq!(run_args.min_upload.as_deref(), Some("800Mbps"));
+                assert_eq!(run_args.max_latency.as_deref(), Some("2ms"));
+                assert_eq!(run_args.max_loss.as_deref(), Some("0%"));
+            }
+            _ => panic!("Expected Run command"),
+        }
+
+        let args = ["netbeat", "run", "10.1.1.11", "--min-download", "fast"];
+        assert!(Cli::try_parse_from(args).is_err());
+        let args = ["netbeat", "run", "10.1.1.11", "--max-latency", "2"];
+        assert!(Cli::try_parse_from(args).is_err());
+        let args = ["netbeat", "run", "10.1.1.11", "--max-loss", "101%"];
+        assert!(Cli::try_parse_from(args).is_err());
     }
 
     #[test]
@@ -81,9 +229,10 @@ mod tests {
 
         match cli.command {
             Commands::Serve(serve_args) => {
-                assert!(matches!(serve_args.interface, BindInterface::All)); // default
-                assert_eq!(serve_args.port, 5050); // default
-                assert_eq!(serve_args.chunk_size, "64KiB"); // default
+                assert!(serve_args.interfaces.is_empty()); // unset
+                assert!(serve_args.port.is_none()); // unset
+                assert!(serve_args.chunk_size.is_none()); // unset
+                assert_eq!(serve_args.profile(), ServerProfile::default());
             }
             _ => panic!("Expected Serve command"),
         }
@@ -100,17 +249,270 @@ mod tests {
             "9090",
             "--chunk-size",
             "32KiB",
+            "--config",
+            "netbeat.toml",
         ];
         let cli = Cli::try_parse_from(args).unwrap();
 
         match cli.command {
             Commands::Serve(serve_args) => {
-                assert!(matches!(serve_args.interface, BindInterface::All));
-                assert_eq!(serve_args.port, 9090);
-                assert_eq!(serve_args.chunk_size, "32KiB");
+                assert_eq!(serve_args.interfaces, vec![BindInterface::All]);
+                assert_eq!(serve_args.port, Some(9090));
+                assert_eq!(serve_args.chunk_size.as_deref(), Some("32KiB"));
+                assert_eq!(
+                    serve_args.config,
+                    Some(std::path::PathBuf::from("netbeat.toml"))
+                );
+                assert_eq!(serve_args.profile().port, Some(9090));
+            }
+            _ => panic!("Expected Serve command"),
+        }
+    }
+
+    #[test]
+    fn test_serve_command_multiple_interfaces() {
+        let args = [
+            "netbeat",
+            "serve",
+            "-i",
+            "10.0.0.2,::",
+            "--interface",
+            "eth0",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Serve(serve_args) => {
+                assert_eq!(
+                    serve_args.interfaces,
+                    vec![
+                        BindInterface::Address("10.0.0.2".parse().unwrap()),
+                        BindInterface::Address("::".parse().unwrap()),
+                        BindInterface::Named("eth0".to_string()),
+                    ]
+                );
+            }
+            _ => panic!("Expected Serve command"),
+        }
+
+        let args = ["netbeat", "serve", "-i", "not an interface"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_psk_file() {
+        let path = std::env::temp_dir().join(format!("netbeat-test-{}-psk", std::process::id()));
+        std::fs::write(&path, "correct horse\n").unwrap();
+        let path_arg = path.to_str().unwrap();
+
+        let cli = Cli::try_parse_from(["netbeat", "serve", "--psk-file", path_arg]).unwrap();
+        match cli.command {
+            Commands::Serve(serve_args) => {
+                assert_eq!(serve_args.psk.as_deref(), Some("correct horse"));
+                assert_eq!(serve_args.profile().psk.as_deref(), Some("correct horse"));
+            }
+            _ => panic!("Expected Serve command"),
+        }
+
+        let cli =
+            Cli::try_parse_from(["netbeat", "run", "127.0.0.1", "--psk-file", path_arg]).unwrap();
+        match cli.command {
+            Commands::Run(run_args) => {
+                assert_eq!(run_args.profile().psk.as_deref(), Some("correct horse"));
+            }
+            _ => panic!("Expected Run command"),
+        }
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(Cli::try_parse_from(["netbeat", "serve", "--psk-file", path_arg]).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(Cli::try_parse_from(["netbeat", "serve", "--psk-file", path_arg]).is_err());
+    }
+
+    #[test]
+    fn test_tls_flags() {
+        let cli =
+            Cli::try_parse_from(["netbeat", "run", "127.0.0.1", "--tls-ca", "ca.pem"]).unwrap();
+        match cli.command {
+            Commands::Run(run_args) => {
+                assert!(!run_args.tls);
+                assert_eq!(
+                    run_args.profile().tls_ca,
+                    Some(std::path::PathBuf::from("ca.pem"))
+                );
+            }
+            _ => panic!("Expected Run command"),
+        }
+
+        let args = [
+            "netbeat",
+            "serve",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Commands::Serve(serve_args) => {
+                let profile = serve_args.profile();
+                assert!(profile.tls.is_none());
+                assert_eq!(profile.tls_cert, Some(std::path::PathBuf::from("cert.pem")));
+                assert_eq!(profile.tls_key, Some(std::path::PathBuf::from("key.pem")));
             }
             _ => panic!("Expected Serve command"),
         }
+
+        let cli = Cli::try_parse_from(["netbeat", "serve", "--tls"]).unwrap();
+        match cli.command {
+            Commands::Serve(serve_args) => assert_eq!(serve_args.profile().tls, Some(true)),
+            _ => panic!("Expected Serve command"),
+        }
+
+        // Certificate and key go together
+        assert!(Cli::try_parse_from(["netbeat", "serve", "--tls-cert", "cert.pem"]).is_err());
+    }
+
+    #[test]
+    fn test_exporter_command() {
+        let args = [
+            "netbeat",
+            "exporter",
+            "nas.lan",
+            "10.1.1.12:6000",
+            "--listen",
+            "0.0.0.0:9000",
+            "--every",
+            "60",
+            "-t",
+            "5",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Exporter(exporter_args) => {
+                assert_eq!(exporter_args.targets, vec!["nas.lan", "10.1.1.12:6000"]);
+                assert_eq!(exporter_args.listen, "0.0.0.0:9000".parse().unwrap());
+                assert_eq!(exporter_args.every, 60);
+                assert_eq!(exporter_args.time, 5);
+                assert_eq!(exporter_args.port, 5050); // default
+            }
+            _ => panic!("Expected Exporter command"),
+        }
+
+        let args = ["netbeat", "exporter", "nas.lan"];
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Exporter(exporter_args) => {
+                assert_eq!(exporter_args.listen, "127.0.0.1:9925".parse().unwrap()); // default
+                assert_eq!(exporter_args.every, 300); // default
+            }
+            _ => panic!("Expected Exporter command"),
+        }
+
+        assert!(Cli::try_parse_from(["netbeat", "exporter"]).is_err());
+        assert!(Cli::try_parse_from(["netbeat", "exporter", "nas.lan", "-e", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_monitor_command() {
+        let args = [
+            "netbeat",
+            "monitor",
+            "nas.lan",
+            "pi.lan:6000",
+            "--every",
+            "60",
+            "--jitter",
+            "10",
+            "--output",
+            "results.ndjson",
+            "--no-history",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Monitor(monitor_args) => {
+                assert_eq!(monitor_args.targets, vec!["nas.lan", "pi.lan:6000"]);
+                assert_eq!(monitor_args.every, 60);
+                assert_eq!(monitor_args.jitter, 10);
+                assert_eq!(monitor_args.max_backoff, 3600); // default
+                assert_eq!(
+                    monitor_args.output,
+                    Some(std::path::PathBuf::from("results.ndjson"))
+                );
+                assert!(monitor_args.no_history);
+            }
+            _ => panic!("Expected Monitor command"),
+        }
+
+        assert!(Cli::try_parse_from(["netbeat", "monitor"]).is_err());
+        assert!(Cli::try_parse_from(["netbeat", "monitor", "nas.lan", "-e", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_history_command() {
+        let args = [
+            "netbeat",
+            "history",
+            "--target",
+            "nas.lan",
+            "--since",
+            "2025-08-01",
+            "-n",
+            "10",
+            "--trend",
+            "-f",
+            "csv",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::History(history_args) => {
+                assert_eq!(history_args.target.as_deref(), Some("nas.lan"));
+                assert!(history_args.since.is_some());
+                assert!(history_args.until.is_none()); // default
+                assert_eq!(history_args.limit, Some(10));
+                assert!(history_args.trend);
+                assert_eq!(history_args.format, OutputFormat::Csv);
+                assert!(history_args.file.is_none()); // default
+            }
+            _ => panic!("Expected History command"),
+        }
+
+        let args = ["netbeat", "history", "--since", "last tuesday"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_compare_command() {
+        let args = [
+            "netbeat",
+            "compare",
+            "before.json",
+            "after.json",
+            "--threshold",
+            "5%",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Compare(compare_args) => {
+                assert_eq!(
+                    compare_args.files,
+                    vec![
+                        std::path::PathBuf::from("before.json"),
+                        std::path::PathBuf::from("after.json")
+                    ]
+                );
+                assert_eq!(compare_args.threshold, Some(5.0));
+                assert!(!compare_args.no_color); // default
+            }
+            _ => panic!("Expected Compare command"),
+        }
+
+        assert!(Cli::try_parse_from(["netbeat", "compare"]).is_err());
+        let args = ["netbeat", "compare", "a.json", "-t", "often"];
+        assert!(Cli::try_parse_from(args).is_err());
     }
 
     #[test]
@@ -147,10 +549,10 @@ mod tests {
         match cli.command {
             Commands::Run(run_args) => {
                 assert_eq!(run_args.target, "localhost");
-                assert_eq!(run_args.port, 3000);
+                assert_eq!(run_args.port, Some(3000));
                 assert_eq!(run_args.data, Some("50MB".to_string()));
-                assert_eq!(run_args.time, 60);
-                assert_eq!(run_args.chunk_size, "16KiB");
+                assert_eq!(run_args.time, Some(60));
+                assert_eq!(run_args.chunk_size.as_deref(), Some("16KiB"));
             }
             _ => panic!("Expected Run command"),
         }
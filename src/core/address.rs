@@ -0,0 +1,333 @@
+//! Address parsing, resolution and connection utilities for netbeat
+
+use crate::utils::error::{NetbeatError, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, SocketAddrV6, TcpListener, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+/// Delay before starting a connection attempt to the next address (RFC 8305 recommends 250ms)
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Split a target into host and optional port.
+///
+/// Accepts IPv4/IPv6 literals, bracketed IPv6 literals, hostnames and any of these followed by `:port`
+/// (eg, `10.1.1.11`, `fe80::1`, `[fe80::1]:5050`, `nas.lan:5050`).
+pub fn parse_target(target: &str) -> Result<(String, Option<u16>)> {
+    let invalid =
+        |reason: &str| NetbeatError::client(format!("Invalid target ({target}) - {reason}"));
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .ok()
+            .filter(|port| *port != 0)
+            .ok_or_else(|| invalid("port must be between 1 and 65535"))
+    };
+
+    if target.is_empty() {
+        return Err(invalid("target must not be empty"));
+    }
+    if let Ok(ip) = IpAddr::from_str(target) {
+        return Ok((ip.to_string(), None));
+    }
+    if let Some(rest) = target.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| invalid("missing closing bracket"))?;
+        let ip =
+            IpAddr::from_str(host).map_err(|_| invalid("expected IPv6 address in brackets"))?;
+        return match rest {
+            "" => Ok((ip.to_string(), None)),
+            _ => match rest.strip_prefix(':') {
+                Some(port) => Ok((ip.to_string(), Some(parse_port(port)?))),
+                None => Err(invalid("unexpected characters after closing bracket")),
+            },
+        };
+    }
+    match target.rsplit_once(':') {
+        Some((host, _)) if host.contains(':') => {
+            Err(invalid("IPv6 addresses with a port must be bracketed"))
+        }
+        Some(("", _)) => Err(invalid("missing host")),
+        Some((host, port)) => Ok((host.to_string(), Some(parse_port(port)?))),
+        None => Ok((target.to_string(), None)),
+    }
+}
+
+/// Resolve a host through the system resolver into all of its IPv4 and IPv6 socket addresses.
+pub fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for addr in (host, port)
+        .to_socket_addrs()
+        .map_err(|e| NetbeatError::client(format!("Failed to resolve host ({host}) - {e}")))?
+    {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    if addrs.is_empty() {
+        return Err(NetbeatError::client(format!(
+            "Failed to resolve host ({host}) - no addresses found"
+        )));
+    }
+    Ok(addrs)
+}
+
+/// Resolve a named network interface (eg, eth0) into socket addresses for each of its IP addresses.
+///
+/// IPv6 link-local addresses carry the interface index as scope id so they can be bound.
+pub fn interface_addrs(name: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let interfaces = if_addrs::get_if_addrs()
+        .map_err(|e| NetbeatError::server(format!("Failed to list network interfaces - {e}")))?;
+    let addrs: Vec<SocketAddr> = interfaces
+        .into_iter()
+        .filter(|iface| iface.name == name)
+        .map(|iface| match iface.ip() {
+            IpAddr::V6(ip) if iface.is_link_local() => {
+                SocketAddrV6::new(ip, port, 0, iface.index.unwrap_or(0)).into()
+            }
+            ip => SocketAddr::new(ip, port),
+        })
+        .collect();
+    if addrs.is_empty() {
+        return Err(NetbeatError::server(format!(
+            "Network interface ({name}) not found or has no IP addresses"
+        )));
+    }
+    Ok(addrs)
+}
+
+/// Bind a TCP listener, with explicit control over dual-stack behaviour for IPv6 addresses.
+///
+/// With `dual_stack`, the unspecified IPv6 address (`::`) also accepts IPv4 connections regardless of platform defaults.
+pub fn bind_listener(addr: SocketAddr, dual_stack: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!(dual_stack && addr.ip().is_unspecified()))?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+/// Order addresses for connection attempts, alternating address families while keeping resolver preference.
+pub fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return Vec::new();
+    };
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+        .iter()
+        .partition(|addr| addr.is_ipv4() == first.is_ipv4());
+
+    let mut ordered = Vec::with_capacity(addrs.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+/// Connect to the first reachable address, Happy Eyeballs style (RFC 8305).
+///
+/// Attempts start in interleaved family order, each one [`CONNECTION_ATTEMPT_DELAY`] after the previous
+/// unless it fails sooner. The first successful connection wins and the remaining attempts are discarded.
+pub fn connect_happy_eyeballs(
+    addrs: &[SocketAddr],
+    timeout: Duration,
+) -> io::Result<(SocketAddr, TcpStream)> {
+    let (tx, rx) = mpsc::channel();
+    let mut in_flight = 0;
+    let mut last_error = None;
+
+    for addr in interleave_families(addrs) {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            // Receiver is gone once another attempt has won
+            let _ = tx.send((addr, TcpStream::connect_timeout(&addr, timeout)));
+        });
+        in_flight += 1;
+
+        match rx.recv_timeout(CONNECTION_ATTEMPT_DELAY) {
+            Ok((addr, Ok(stream))) => return Ok((addr, stream)),
+            Ok((_, Err(e))) => {
+                in_flight -= 1;
+                last_error = Some(e);
+            }
+            Err(_) => {}
+        }
+    }
+
+    while in_flight > 0 {
+        match rx.recv() {
+            Ok((addr, Ok(stream))) => return Ok((addr, stream)),
+            Ok((_, Err(e))) => {
+                in_flight -= 1;
+                last_error = Some(e);
+            }
+            Err(_) => break,
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "No addresses to connect to",
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            parse_target("10.1.1.11").unwrap(),
+            ("10.1.1.11".to_string(), None)
+        );
+        assert_eq!(
+            parse_target("10.1.1.11:6000").unwrap(),
+            ("10.1.1.11".to_string(), Some(6000))
+        );
+        assert_eq!(
+            parse_target("fe80::1").unwrap(),
+            ("fe80::1".to_string(), None)
+        );
+        assert_eq!(parse_target("[::1]").unwrap(), ("::1".to_string(), None));
+        assert_eq!(
+            parse_target("[::1]:6000").unwrap(),
+            ("::1".to_string(), Some(6000))
+        );
+        assert_eq!(
+            parse_target("nas.lan").unwrap(),
+            ("nas.lan".to_string(), None)
+        );
+        assert_eq!(
+            parse_target("nas.lan:6000").unwrap(),
+            ("nas.lan".to_string(), Some(6000))
+        );
+    }
+
+    #[test]
+    fn test_parse_target_invalid() {
+        for (target, reason) in [
+            ("", "must not be empty"),
+            ("[::1", "missing closing bracket"),
+            ("[nas.lan]", "expected IPv6 address"),
+            ("[::1]6000", "unexpected characters"),
+            ("nas.lan:0", "port must be"),
+            ("nas.lan:http", "port must be"),
+            (":6000", "missing host"),
+            ("fe80::1::6000", "must be bracketed"),
+        ] {
+            let result = parse_target(target);
+            assert!(result.is_err(), "{target} should be invalid");
+            if let Err(e) = result {
+                assert!(matches!(e, NetbeatError::ClientError { .. }));
+                assert!(e.to_string().contains(reason), "{target} - {e}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let addrs = resolve("127.0.0.1", 5050).unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:5050".parse().unwrap()]);
+
+        let addrs = resolve("::1", 5050).unwrap();
+        assert_eq!(addrs, vec!["[::1]:5050".parse().unwrap()]);
+
+        let addrs = resolve("localhost", 5050).unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+
+        let result = resolve("invalid_host.invalid", 5050);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e.to_string().contains("Failed to resolve host"));
+        }
+    }
+
+    #[test]
+    fn test_interface_addrs() {
+        let loopback = if_addrs::get_if_addrs()
+            .unwrap()
+            .into_iter()
+            .find(|iface| iface.is_loopback())
+            .map(|iface| iface.name);
+        if let Some(name) = loopback {
+            let addrs = interface_addrs(&name, 5050).unwrap();
+            assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+            assert!(addrs.iter().all(|addr| addr.port() == 5050));
+        }
+
+        let result = interface_addrs("netbeat-missing0", 5050);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ServerError { .. }));
+            assert!(e.to_string().contains("not found"));
+        }
+    }
+
+    #[test]
+    fn test_bind_listener_dual_stack() {
+        let Ok(listener) = bind_listener("[::]:0".parse().unwrap(), true) else {
+            // IPv6 not available on this host
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_ok());
+        assert!(TcpStream::connect(("::1", port)).is_ok());
+
+        let listener = bind_listener("[::]:0".parse().unwrap(), false).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let addrs: Vec<SocketAddr> = ["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let ordered: Vec<String> = interleave_families(&addrs)
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        assert_eq!(
+            ordered,
+            ["[::1]:1", "10.0.0.1:1", "[::2]:1", "10.0.0.2:1", "[::3]:1"]
+        );
+        assert!(interleave_families(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_connect_happy_eyeballs() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let reachable = listener.local_addr().unwrap();
+
+        // Closed port first, reachable address second
+        let closed = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let (addr, _stream) =
+            connect_happy_eyeballs(&[closed, reachable], Duration::from_secs(1)).unwrap();
+        assert_eq!(addr, reachable);
+
+        let result = connect_happy_eyeballs(&[closed], Duration::from_secs(1));
+        assert!(result.is_err());
+        assert!(connect_happy_eyeballs(&[], Duration::from_secs(1)).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! Pre-shared key authentication of clients, through an HMAC challenge-response right after the hello.
+//!
+//! A server holding a key sets [`protocol::CAP_AUTH`] in its hello and sends a random nonce. The client answers with
+//! the HMAC-SHA256 of the nonce keyed with the pre-shared key, and the server accepts or rejects the connection before
+//! any test starts. The key itself never crosses the wire.
+
+use super::protocol::{self, MessageKind};
+use crate::utils::error::{NetbeatError, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    fmt,
+    io::{Read, Write},
+};
+
+/// Length of the challenge nonce in bytes
+pub const NONCE_LEN: usize = 32;
+
+/// Prefix of every authenticated message, so responses cannot be replayed in another protocol
+const AUTH_CONTEXT: &[u8] = b"NETBEAT_AUTH";
+
+/// Secret shared by a server and the clients allowed to run tests against it.
+///
+/// The key is redacted from debug output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PreSharedKey(Vec<u8>);
+
+impl PreSharedKey {
+    /// Create a pre-shared key, which must not be empty.
+    pub fn new(key: impl Into<String>) -> Result<Self> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(NetbeatError::auth(
+                "Pre-shared key must not be empty".to_string(),
+            ));
+        }
+        Ok(Self(key.into_bytes()))
+    }
+
+    /// Response to a challenge nonce: HMAC-SHA256 of the nonce keyed with the pre-shared key.
+    pub fn respond(&self, nonce: &[u8]) -> Vec<u8> {
+        self.mac(nonce).finalize().into_bytes().to_vec()
+    }
+
+    /// Whether a response matches the challenge nonce, compared in constant time.
+    pub fn verify(&self, nonce: &[u8], response: &[u8]) -> bool {
+        self.mac(nonce).verify_slice(response).is_ok()
+    }
+
+    fn mac(&self, nonce: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(AUTH_CONTEXT);
+        mac.update(nonce);
+        mac
+    }
+}
+
+impl fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PreSharedKey(..)")
+    }
+}
+
+/// Challenge the client on the server side, rejecting it unless it answers with the pre-shared key.
+pub fn authenticate_client(stream: &mut (impl Read + Write), key: &PreSharedKey) -> Result<()> {
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    protocol::write_frame(stream, MessageKind::AuthChallenge, &nonce).map_err(|e| {
+        NetbeatError::protocol(format!("Failed to send authentication challenge - {e}"))
+    })?;
+
+    let frame = protocol::read_frame(stream).map_err(|e| {
+        NetbeatError::auth(format!(
+            "Client did not answer the authentication challenge - {e}"
+        ))
+    })?;
+    let reason = match frame.kind {
+        MessageKind::AuthResponse if key.verify(&nonce, &frame.payload) => {
+            return protocol::write_frame(stream, MessageKind::AuthAccept, &[]).map_err(|e| {
+                NetbeatError::protocol(format!("Failed to send authentication accept - {e}"))
+            });
+        }
+        MessageKind::AuthResponse => "Invalid pre-shared key",
+        _ => "Pre-shared key authentication required",
+    };
+    protocol::write_frame(stream, MessageKind::AuthReject, reason.as_bytes()).map_err(|e| {
+        NetbeatError::protocol(format!("Failed to send authentication reject - {e}"))
+    })?;
+    Err(NetbeatError::auth(reason.to_string()))
+}
+
+/// Answer the server challenge on the client side with the pre-shared key, if any.
+pub fn authenticate(stream: &mut (impl Read + Write), key: Option<&PreSharedKey>) -> Result<()> {
+    let Some(key) = key else {
+        return Err(NetbeatError::auth(
+            "Server requires a pre-shared key".to_string(),
+        ));
+    };
+
+    let challenge = protocol::expect_frame(stream, MessageKind::AuthChallenge).map_err(|e| {
+        NetbeatError::protocol(format!("Failed to read authentication challenge - {e}"))
+    })?;
+    if challenge.payload.len() != NONCE_LEN {
+        return Err(NetbeatError::protocol(
+            "Malformed authentication challenge".to_string(),
+        ));
+    }
+    protocol::write_frame(
+        stream,
+        MessageKind::AuthResponse,
+        &key.respond(&challenge.payload),
+    )
+    .map_err(|e| NetbeatError::protocol(format!("Failed to send authentication response - {e}")))?;
+
+    let frame = protocol::read_frame(stream).map_err(|e| {
+        NetbeatError::protocol(format!("Failed to read authentication reply - {e}"))
+    })?;
+    match frame.kind {
+        MessageKind::AuthAccept => Ok(()),
+        MessageKind::AuthReject => Err(NetbeatError::auth(
+            String::from_utf8_lossy(&frame.payload).into_owned(),
+        )),
+        kind => Err(protocol::unexpected_message(
+            MessageKind::AuthAccept,
+            kind,
+            &frame.payload,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    /// Run the server side of the exchange with `server_key` against a client holding `client_key`.
+    fn exchange(server_key: &str, client_key: Option<&str>) -> (Result<()>, Result<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let server_key = PreSharedKey::new(server_key).unwrap();
+        let handle = thread::spawn(move || authenticate_client(&mut server, &server_key));
+        let client_key = client_key.map(|key| PreSharedKey::new(key).unwrap());
+        let client_result = authenticate(&mut client, client_key.as_ref());
+        drop(client);
+        (handle.join().unwrap(), client_result)
+    }
+
+    #[test]
+    fn test_pre_shared_key() {
+        let key = PreSharedKey::new("correct horse").unwrap();
+        let nonce = [7u8; NONCE_LEN];
+        let response = key.respond(&nonce);
+        assert_eq!(response.len(), 32);
+        assert!(key.verify(&nonce, &response));
+        assert!(!key.verify(&[8u8; NONCE_LEN], &response));
+        assert!(!key.verify(&nonce, &response[..16]));
+        assert!(
+            !PreSharedKey::new("battery staple")
+                .unwrap()
+                .verify(&nonce, &response)
+        );
+
+        assert_eq!(format!("{key:?}"), "PreSharedKey(..)");
+        assert!(matches!(
+            PreSharedKey::new(""),
+            Err(NetbeatError::AuthError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let (server, client) = exchange("correct horse", Some("correct horse"));
+        assert!(server.is_ok());
+        assert!(client.is_ok());
+
+        let (server, client) = exchange("correct horse", Some("battery staple"));
+        assert_eq!(
+            server.unwrap_err().to_string(),
+            "Authentication error: Invalid pre-shared key"
+        );
+        assert_eq!(
+            client.unwrap_err().to_string(),
+            "Authentication error: Invalid pre-shared key"
+        );
+
+        let (server, client) = exchange("correct horse", None);
+        assert!(matches!(server, Err(NetbeatError::AuthError { .. })));
+        assert_eq!(
+            client.unwrap_err().to_string(),
+            "Authentication error: Server requires a pre-shared key"
+        );
+    }
+
+    #[test]
+    fn test_authenticate_client_without_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let key = PreSharedKey::new("correct horse").unwrap();
+        let handle = thread::spawn(move || authenticate_client(&mut server, &key));
+        protocol::expect_frame(&mut client, MessageKind::AuthChallenge).unwrap();
+        protocol::write_frame(&mut client, MessageKind::SessionSetup, &[]).unwrap();
+
+        let reply = protocol::read_frame(&mut client).unwrap();
+        assert_eq!(reply.kind, MessageKind::AuthReject);
+        assert_eq!(reply.payload, b"Pre-shared key authentication required");
+        assert!(handle.join().unwrap().is_err());
+    }
+}
@@ -1,29 +1,60 @@
 //! Core Client functionality for netbeat.
 
-use super::{config, protocol};
+use super::{
+    address,
+    auth::{self, PreSharedKey},
+    config,
+    intervals::{IntervalRecorder, IntervalSample},
+    profile::ClientProfile,
+    protocol::{self, MessageKind, TestPlan},
+    thresholds::Thresholds,
+    tls::{KnownHosts, TlsClient, Transport},
+    udp::{self, UdpParams, UdpReady, UdpReceiver, UdpSent, UdpStats},
+};
 use crate::{
-    output::reports::{self, NetbeatReport, PingReport, Report, SpeedReport},
+    output::{
+        events::{Event, PhaseReport},
+        formats::{self, OutputFormat},
+        history::History,
+        reports::{
+            self, LatencyReport, LoadedLatency, NetbeatReport, PingReport, Report, SpeedReport,
+            StreamResult, TestParameters, TlsReport,
+        },
+    },
     utils::{
         error::{NetbeatError, Result},
         logging::Logger,
+        units,
     },
 };
 
 use byte_unit::Byte;
 use spinners::{Spinner, Spinners};
 use std::{
-    io::{ErrorKind, Read, Write},
-    net::{IpAddr, Shutdown, SocketAddr, TcpStream},
-    str::FromStr,
-    thread,
+    io::{ErrorKind, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    thread::{self, ScopedJoinHandle},
     time::{Duration, Instant},
 };
 
+/// Interval between checks on stream workers while a test is running
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Interval between latency probes while a test is loading the link
+const LATENCY_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval between checks for the server ending an upload early, eg over a session limit
+const UPLOAD_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
 /// Core `Client` struct for netbeat.
 #[derive(Debug, Clone)]
 pub struct Client {
-    /// Socket address of the target server.
-    pub socket_addr: SocketAddr,
+    /// Target server host, as given (IP address or hostname).
+    pub target: String,
+    /// Socket addresses the target resolved to, in resolver preference order.
+    pub socket_addrs: Vec<SocketAddr>,
     /// Target size of data to be uploaded/downloaded in the speed test including units (eg, 10MB, 1GB, 2GB). Instead of time.
     pub data: Option<u64>,
     /// Time limit per test direction in seconds (1-3600).
@@ -32,12 +63,36 @@ pub struct Client {
     pub chunk_size: u64,
     /// Number of pings to perform for ping test (1-1000)
     pub ping_count: u32,
-    /// Return results as json
-    pub return_json: bool,
+    /// Number of parallel TCP streams per test direction (1-128)
+    pub parallel: u32,
+    /// Run upload and download tests at the same time
+    pub bidirectional: bool,
+    /// Run upload and download tests over UDP at a fixed bitrate
+    pub udp: bool,
+    /// Target bitrate for UDP tests in bits per second
+    pub bitrate: u64,
+    /// UDP datagram size in bytes (64-65507)
+    pub datagram_size: u32,
+    /// Probe latency on a separate connection during upload and download tests
+    pub latency_probe: bool,
+    /// Throughput reporting interval (0.1-60 seconds)
+    pub interval: Duration,
+    /// Format of the results written to stdout
+    pub format: OutputFormat,
+    /// Write the header row ahead of csv results
+    pub csv_header: bool,
+    /// Pass/fail thresholds checked against the finished report
+    pub thresholds: Thresholds,
+    /// History the finished report is appended to
+    pub history: Option<History>,
     /// Connection timeout
     pub timeout: Duration,
     /// Number of retry attempts on initial connection failure
     pub retries: u32,
+    /// Pre-shared key to authenticate with, for servers that require one
+    pub psk: Option<PreSharedKey>,
+    /// TLS settings, when the connection is encrypted
+    pub tls: Option<TlsClient>,
     /// Netbeat custom logger
     pub logger: Logger,
 }
@@ -51,9 +106,27 @@ pub struct ClientBuilder {
     time: Option<u64>,
     chunk_size: Option<String>,
     ping_count: Option<u32>,
-    return_json: Option<bool>,
+    parallel: Option<u32>,
+    bidirectional: Option<bool>,
+    udp: Option<bool>,
+    bitrate: Option<String>,
+    datagram_size: Option<u32>,
+    latency_probe: Option<bool>,
+    interval: Option<f64>,
+    format: Option<OutputFormat>,
+    csv_header: Option<bool>,
+    min_download: Option<String>,
+    min_upload: Option<String>,
+    max_latency: Option<String>,
+    max_loss: Option<String>,
+    history: Option<bool>,
+    history_file: Option<PathBuf>,
     timeout: Option<u64>,
     retries: Option<u32>,
+    psk: Option<String>,
+    tls: Option<bool>,
+    tls_ca: Option<PathBuf>,
+    known_hosts_file: Option<PathBuf>,
     quiet: Option<bool>,
     verbose: Option<bool>,
 }
@@ -67,22 +140,25 @@ impl Client {
     /// Contact target server to run speed test.
     pub fn contact(&self) -> Result<NetbeatReport> {
         for attempt in 1..=self.retries {
-            match TcpStream::connect_timeout(&self.socket_addr, self.timeout) {
-                Ok(mut stream) => {
-                    stream
-                        .set_nodelay(true)
-                        .map_err(NetbeatError::ConnectionError)?;
-                    stream
-                        .set_write_timeout(Some(self.timeout))
-                        .map_err(NetbeatError::ConnectionError)?;
-                    stream
-                        .set_read_timeout(Some(self.timeout))
-                        .map_err(NetbeatError::ConnectionError)?;
-
-                    self.logger
-                        .info(&format!("🔗 Connected to server at {}\n", self.socket_addr));
-
-                    return self.run_speed_test(&mut stream);
+            match address::connect_happy_eyeballs(&self.socket_addrs, self.timeout) {
+                Ok((server_addr, stream)) => {
+                    self.configure_stream(&stream)?;
+
+                    if self.target == server_addr.ip().to_string() {
+                        self.logger
+                            .info(&format!("🔗 Connected to server at {server_addr}\n"));
+                    } else {
+                        self.logger.info(&format!(
+                            "🔗 Connected to server at {} ({server_addr})\n",
+                            self.target
+                        ));
+                    }
+
+                    let (mut stream, tls_report) = self.handshake(stream)?;
+                    let session_id = self.setup_session(&mut stream)?;
+                    let mut streams = vec![stream];
+                    streams.extend(self.join_session(server_addr, session_id)?);
+                    return self.run_speed_test(&mut streams, server_addr, session_id, tls_report);
                 }
                 Err(_) if attempt < self.retries => continue,
                 Err(e) => {
@@ -95,56 +171,354 @@ impl Client {
         ))
     }
 
-    fn run_speed_test(&self, stream: &mut TcpStream) -> Result<NetbeatReport> {
-        let mut random_buffer = protocol::generate_random_buffer(self.chunk_size as usize);
-        let target_bytes = self.data;
+    fn configure_stream(&self, stream: &TcpStream) -> Result<()> {
+        stream
+            .set_nodelay(true)
+            .map_err(NetbeatError::ConnectionError)?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(NetbeatError::ConnectionError)?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(NetbeatError::ConnectionError)?;
+        Ok(())
+    }
+
+    /// Exchange hellos, then run the TLS handshake and authenticate as the server requires.
+    fn handshake(&self, mut stream: TcpStream) -> Result<(Transport, Option<TlsReport>)> {
+        let mut hello = protocol::Hello::local();
+        if self.tls.is_some() {
+            hello.capabilities |= protocol::CAP_TLS;
+        }
+        protocol::write_hello(&mut stream, &hello)
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send hello message - {e}")))?;
+        let server_hello = protocol::read_hello(&mut stream)?;
+        self.logger.verbose(&format!(
+            "Server protocol version {} (capabilities {:#x})",
+            server_hello.version_string(),
+            server_hello.capabilities
+        ));
+
+        protocol::check_version(&server_hello)?;
+        let mut required = protocol::CAP_PING | protocol::CAP_UPLOAD | protocol::CAP_DOWNLOAD;
+        if self.parallel > 1 {
+            required |= protocol::CAP_PARALLEL;
+        }
+        if self.bidirectional {
+            required |= protocol::CAP_PARALLEL | protocol::CAP_BIDIRECTIONAL;
+        }
+        if self.udp {
+            required |= protocol::CAP_UDP;
+        }
+        if self.latency_probe {
+            required |= protocol::CAP_PARALLEL | protocol::CAP_LATENCY_PROBE;
+        }
+        if !server_hello.supports(required) {
+            return Err(NetbeatError::protocol(format!(
+                "Server does not support required capabilities (server {:#x}, required {:#x})",
+                server_hello.capabilities, required
+            )));
+        }
+
+        let (mut stream, tls_report) = match (&self.tls, server_hello.supports(protocol::CAP_TLS)) {
+            (Some(tls), true) => {
+                let first_use = tls.first_use()?;
+                let (stream, tls_report) = tls.connect(stream)?;
+                if first_use && let Some(known_hosts) = tls.known_hosts() {
+                    self.logger.warn(&format!(
+                        "Trusted certificate of {} on first use ({}), pinned in {}",
+                        self.target,
+                        tls_report.fingerprint,
+                        known_hosts.path().display()
+                    ));
+                }
+                self.logger.verbose(&format!(
+                    "TLS handshake completed in {:.2?} - {} ({})",
+                    tls_report.handshake_time, tls_report.version, tls_report.cipher_suite
+                ));
+                (stream, Some(tls_report))
+            }
+            (None, true) => {
+                return Err(NetbeatError::tls(
+                    "Server requires TLS - run with --tls".to_string(),
+                ));
+            }
+            (Some(_), false) => {
+                return Err(NetbeatError::tls("Server does not support TLS".to_string()));
+            }
+            (None, false) => (Transport::from(stream), None),
+        };
+
+        if server_hello.supports(protocol::CAP_AUTH) {
+            auth::authenticate(&mut stream, self.psk.as_ref())?;
+            self.logger.verbose("Authenticated with the pre-shared key");
+        } else if self.psk.is_some() {
+            self.logger
+                .verbose("Server does not require authentication");
+        }
+        Ok((stream, tls_report))
+    }
+
+    /// Propose the test plan to the server, returning the session id on acceptance.
+    fn setup_session(&self, stream: &mut Transport) -> Result<u64> {
+        let plan = self.test_plan();
+        protocol::write_frame(stream, MessageKind::SessionSetup, &plan.encode())
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send session setup - {e}")))?;
+        self.logger.verbose(&format!("Sent test plan - {plan:?}"));
+
+        read_session_reply(stream)
+    }
+
+    /// Open the extra connections for parallel streams and attach them to the session.
+    fn join_session(&self, server_addr: SocketAddr, session_id: u64) -> Result<Vec<Transport>> {
+        let plan = self.test_plan();
+        (1..plan.probe_stream().unwrap_or(plan.connections()))
+            .map(|index| self.join_stream(server_addr, session_id, index))
+            .collect()
+    }
+
+    /// Open a single extra connection and attach it to the session as the given stream.
+    fn join_stream(
+        &self,
+        server_addr: SocketAddr,
+        session_id: u64,
+        index: u32,
+    ) -> Result<Transport> {
+        let stream = TcpStream::connect_timeout(&server_addr, self.timeout)
+            .map_err(NetbeatError::ConnectionError)?;
+        self.configure_stream(&stream)?;
+        let (mut stream, _) = self.handshake(stream)?;
+
+        let join = protocol::SessionJoin {
+            session_id,
+            stream: index,
+        };
+        protocol::write_frame(&mut stream, MessageKind::SessionJoin, &join.encode())
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send session join - {e}")))?;
+        read_session_reply(&mut stream)?;
+        self.logger
+            .verbose(&format!("Stream {} joined session", index + 1));
+        Ok(stream)
+    }
+
+    /// Parameters recorded alongside the results in the netbeat report.
+    pub fn test_parameters(&self) -> TestParameters {
+        TestParameters {
+            time_limit: Duration::from_secs(self.time),
+            data: self.data,
+            chunk_size: self.chunk_size,
+            ping_count: self.ping_count,
+            parallel: self.parallel,
+            bidirectional: self.bidirectional,
+            udp: self.udp,
+            bitrate: self.bitrate,
+            datagram_size: self.datagram_size,
+            latency_probe: self.latency_probe,
+            interval: self.interval,
+        }
+    }
+
+    /// Test plan announced to the server at session setup.
+    fn test_plan(&self) -> TestPlan {
+        let mut mode = if self.bidirectional {
+            protocol::TEST_BIDIRECTIONAL
+        } else if self.udp {
+            protocol::TEST_UDP
+        } else {
+            0
+        };
+        if self.latency_probe {
+            mode |= protocol::TEST_LATENCY_PROBE;
+        }
+        TestPlan {
+            tests: protocol::ALL_TESTS | mode,
+            duration: Duration::from_secs(self.time),
+            bytes: self.data,
+            chunk_size: self.chunk_size,
+            streams: self.parallel,
+            interval: self.interval,
+        }
+    }
+
+    fn run_speed_test(
+        &self,
+        streams: &mut [Transport],
+        server_addr: SocketAddr,
+        session_id: u64,
+        tls_report: Option<TlsReport>,
+    ) -> Result<NetbeatReport> {
+        let random_buffer = protocol::generate_random_buffer(self.chunk_size as usize);
         let target_time = Duration::from_secs(self.time);
-        let use_time = target_bytes.is_none();
 
         // Ping Test
         let ping_report = self
-            .run_ping_test(stream)
+            .run_ping_test(&mut streams[0])
             .map_err(|e| NetbeatError::test_execution(format!("Ping test failed - {e}")))?;
 
-        // Upload Test
-        let upload_report = self
-            .run_upload_test(
-                stream,
-                &mut random_buffer,
-                target_bytes,
-                target_time,
-                use_time,
-            )
-            .map_err(|e| NetbeatError::test_execution(format!("Upload test failed - {e}")))?;
+        // Latency probe connection, joined once the idle ping test is done
+        let mut probe = match self.test_plan().probe_stream() {
+            Some(index) => Some(self.join_stream(server_addr, session_id, index)?),
+            None => None,
+        };
+        if probe.is_some() {
+            self.emit(Event::PhaseStart { phase: "latency" });
+        }
+        let mut loaded_latency = Vec::new();
 
-        thread::sleep(Duration::from_millis(500));
+        let (upload_report, download_report) = if self.udp {
+            // Upload & Download Test, over UDP
+            let (upload_report, upload_latency) = self
+                .with_latency_probe(probe.as_mut(), || {
+                    self.run_udp_test(&mut streams[0], server_addr, protocol::TEST_UPLOAD)
+                })
+                .map_err(|e| {
+                    NetbeatError::test_execution(format!("UDP upload test failed - {e}"))
+                })?;
+            self.logger
+                .info(&format!("{}", upload_report.to_table_report()));
 
-        // Download Test
-        let download_report = self
-            .run_download_test(
-                stream,
-                &mut random_buffer,
-                target_bytes,
-                target_time,
-                use_time,
-            )
-            .map_err(|e| NetbeatError::test_execution(format!("Download test failed - {e}")))?;
+            let (download_report, download_latency) = self
+                .with_latency_probe(probe.as_mut(), || {
+                    self.run_udp_test(&mut streams[0], server_addr, protocol::TEST_DOWNLOAD)
+                })
+                .map_err(|e| {
+                    NetbeatError::test_execution(format!("UDP download test failed - {e}"))
+                })?;
+            self.logger
+                .info(&format!("{}", download_report.to_table_report()));
+            loaded_latency.push(LoadedLatency::new("upload", upload_latency));
+            loaded_latency.push(LoadedLatency::new("download", download_latency));
+            (upload_report, download_report)
+        } else if self.bidirectional {
+            // Upload & Download Test, at the same time
+            let ((upload_report, download_report), latency) = self
+                .with_latency_probe(probe.as_mut(), || {
+                    self.run_bidirectional_test(streams, &random_buffer, target_time)
+                })
+                .map_err(|e| {
+                    NetbeatError::test_execution(format!("Bidirectional test failed - {e}"))
+                })?;
+            self.log_intervals(&upload_report);
+            self.log_intervals(&download_report);
+            loaded_latency.push(LoadedLatency::new("bidirectional", latency));
+            (upload_report, download_report)
+        } else {
+            // Upload Test
+            let (upload_report, upload_latency) = self
+                .with_latency_probe(probe.as_mut(), || {
+                    self.run_upload_test(streams, &random_buffer, target_time)
+                })
+                .map_err(|e| NetbeatError::test_execution(format!("Upload test failed - {e}")))?;
+            self.log_intervals(&upload_report);
+            self.logger
+                .info(&format!("{}", upload_report.to_table_report()));
 
-        let netbeat_report = NetbeatReport::new(ping_report, upload_report, download_report);
+            // Download Test
+            let (download_report, download_latency) = self
+                .with_latency_probe(probe.as_mut(), || {
+                    self.run_download_test(streams, target_time)
+                })
+                .map_err(|e| NetbeatError::test_execution(format!("Download test failed - {e}")))?;
+            self.log_intervals(&download_report);
+            self.logger
+                .info(&format!("{}", download_report.to_table_report()));
+            loaded_latency.push(LoadedLatency::new("upload", upload_latency));
+            loaded_latency.push(LoadedLatency::new("download", download_latency));
+            (upload_report, download_report)
+        };
+
+        let mut netbeat_report = NetbeatReport::new(ping_report, upload_report, download_report)
+            .with_server(&self.target, server_addr)
+            .with_parameters(self.test_parameters());
+        if let Some(tls_report) = tls_report {
+            netbeat_report = netbeat_report.with_tls(tls_report);
+        }
+        if self.bidirectional {
+            netbeat_report = netbeat_report.with_bidirectional();
+        }
+        if probe.is_some() {
+            let latency_report =
+                LatencyReport::new(netbeat_report.ping_report.avg_ping, loaded_latency);
+            self.logger
+                .info(&format!("{}", latency_report.to_table_report()));
+            self.emit(Event::PhaseEnd {
+                phase: "latency",
+                report: PhaseReport::Latency(&latency_report),
+            });
+            netbeat_report = netbeat_report.with_latency(latency_report);
+        }
 
         self.logger
             .info(&format!("{}", netbeat_report.to_table_report()));
 
-        if self.return_json {
-            self.logger.result(&format!("{}", netbeat_report.to_json()));
+        match self.format {
+            OutputFormat::Table => {}
+            OutputFormat::Json => self.logger.result(&format!("{}", netbeat_report.to_json())),
+            OutputFormat::Ndjson => self.emit(Event::Report {
+                report: &netbeat_report,
+            }),
+            OutputFormat::Csv => {
+                if self.csv_header {
+                    self.logger.result(&formats::csv_header());
+                }
+                self.logger.result(&formats::csv_row(&netbeat_report));
+            }
+        }
+        for violation in self.thresholds.check(&netbeat_report) {
+            self.logger
+                .error(&format!("Threshold not met - {violation}"));
+        }
+        if let Some(history) = &self.history {
+            match history.append(&netbeat_report) {
+                Ok(()) => self
+                    .logger
+                    .verbose(&format!("Saved report to {}", history.path().display())),
+                Err(e) => self
+                    .logger
+                    .warn(&format!("Failed to save report to history - {e}")),
+            }
+        }
+        for stream in streams.iter_mut().chain(probe.as_mut()) {
+            protocol::write_frame(stream, MessageKind::Goodbye, &[]).map_err(|e| {
+                NetbeatError::protocol(format!("Failed to send goodbye message - {e}"))
+            })?;
+            stream.shutdown().map_err(NetbeatError::ConnectionError)?;
         }
-        stream
-            .shutdown(Shutdown::Both)
-            .map_err(NetbeatError::ConnectionError)?;
         Ok(netbeat_report)
     }
 
-    fn run_ping_test(&self, stream: &mut TcpStream) -> Result<PingReport> {
+    /// Run a test phase while probing latency on the probe connection, returning the probe round trip times.
+    fn with_latency_probe<T>(
+        &self,
+        probe: Option<&mut Transport>,
+        phase: impl FnOnce() -> Result<T>,
+    ) -> Result<(T, Vec<Duration>)> {
+        let Some(probe) = probe else {
+            return Ok((phase()?, Vec::new()));
+        };
+
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let prober = scope.spawn(|| probe_latency(probe, &done));
+            let result = phase();
+            done.store(true, Ordering::Relaxed);
+            let samples = prober.join().unwrap_or_else(|_| {
+                Err(NetbeatError::test_execution(
+                    "Latency probe panicked".to_string(),
+                ))
+            });
+            let result = result?;
+            let samples = samples
+                .map_err(|e| NetbeatError::protocol(format!("Latency probe failed - {e}")))?;
+            self.logger.verbose(&format!(
+                "Latency probe completed with {} samples",
+                samples.len()
+            ));
+            Ok((result, samples))
+        })
+    }
+
+    fn run_ping_test(&self, stream: &mut Transport) -> Result<PingReport> {
         let msg = "🏓 Running ping test...";
         let sp = if !self.logger.quiet & !self.logger.verbose {
             Some(Spinner::new(Spinners::Dots2, msg.into()))
@@ -152,25 +526,28 @@ impl Client {
             None
         };
         self.logger.verbose(msg);
+        self.emit(Event::PhaseStart { phase: "ping" });
 
-        let mut ping_buffer = [0u8; protocol::PING_RESPONSE.len()];
         let mut ping_times: Vec<Duration> = Vec::with_capacity(self.ping_count as usize);
         let mut successful_pings = 0;
 
-        // Send initial ping
-        protocol::write_message(stream, protocol::PING_MESSAGE)
+        // Send initial ping to warm up the connection
+        protocol::write_frame(stream, MessageKind::Ping, &0u32.to_be_bytes())
             .map_err(|e| NetbeatError::protocol(format!("Failed to write ping message - {e}")))?;
+        protocol::expect_frame(stream, MessageKind::Pong).map_err(|e| {
+            NetbeatError::protocol(format!("Failed to read initial ping response - {e}"))
+        })?;
         self.logger.verbose("Sent initial ping");
 
         // Ping test
         for i in 1..self.ping_count + 1 {
             let start_time = Instant::now();
-            match protocol::write_message(stream, protocol::PING_MESSAGE) {
-                Ok(_) => match stream.read_exact(&mut ping_buffer) {
-                    Ok(_) => {
+            match protocol::write_frame(stream, MessageKind::Ping, &i.to_be_bytes()) {
+                Ok(_) => match read_pong(stream, i) {
+                    Ok(valid) => {
                         self.logger.verbose(&format!("Sent ping {i}"));
                         let ping_time = start_time.elapsed();
-                        if ping_buffer == protocol::PING_RESPONSE {
+                        if valid {
                             successful_pings += 1;
                             self.logger.verbose(&format!("Received ping response {i}"));
                             ping_times.push(ping_time);
@@ -179,8 +556,8 @@ impl Client {
                                 .verbose(&format!("Received invalid ping response {i}"));
                         }
                     }
-                    Err(e) => match e.kind() {
-                        ErrorKind::TimedOut => {
+                    Err(NetbeatError::ConnectionError(e)) => match e.kind() {
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock => {
                             self.logger
                                 .verbose(&format!("Timed out waiting for ping response {i}"));
                             continue;
@@ -198,6 +575,10 @@ impl Client {
                                 .warn(&format!("Failed to read ping response {i} - {e}"));
                         }
                     },
+                    Err(e) => {
+                        self.logger
+                            .warn(&format!("Failed to read ping response {i} - {e}"));
+                    }
                 },
                 Err(e) => self
                     .logger
@@ -214,7 +595,7 @@ impl Client {
         }
 
         // Send close message
-        protocol::write_message(stream, protocol::PING_DONE).map_err(|e| {
+        protocol::write_frame(stream, MessageKind::PingDone, &[]).map_err(|e| {
             NetbeatError::protocol(format!("Failed to write ping termination - {e}"))
         })?;
         self.logger.verbose("Sent ping termination message");
@@ -224,219 +605,678 @@ impl Client {
         if successful_pings > 0 {
             self.logger
                 .info(&format!("{}", ping_report.to_table_report()));
+            self.logger.info(&format!(
+                "{}",
+                ping_report.to_histogram_chart(reports::HISTOGRAM_BUCKETS)
+            ));
         } else {
             self.logger
                 .error("Ping test failed - no successful responses received");
         }
+        self.emit(Event::PhaseEnd {
+            phase: "ping",
+            report: PhaseReport::Ping(&ping_report),
+        });
 
         Ok(ping_report)
     }
 
-    #[allow(clippy::collapsible_if)]
     fn run_upload_test(
         &self,
-        stream: &mut TcpStream,
-        buffer: &mut [u8],
-        target_bytes: Option<u64>,
+        streams: &mut [Transport],
+        buffer: &[u8],
         target_time: Duration,
-        use_time: bool,
     ) -> Result<SpeedReport> {
         let msg = "🚀 Running upload speed test...";
-        let mut sp = if !self.logger.quiet & !self.logger.verbose {
+        let sp = self.start_spinner(msg);
+        self.emit(Event::PhaseStart { phase: "upload" });
+
+        let data_frame = protocol::encode_frame(MessageKind::Data, buffer);
+        let progress = AtomicU64::new(0);
+
+        let start_time = Instant::now();
+        let (sp, results) = thread::scope(|scope| {
+            let uploads =
+                self.spawn_uploads(scope, streams, &data_frame, buffer, target_time, &progress);
+            let sp = wait_for_streams(
+                || uploads.iter().all(|handle| handle.is_finished()),
+                start_time,
+                &progress,
+                sp,
+                msg,
+                self.interval,
+                |sample| self.emit(Event::interval("upload", sample)),
+            );
+            (sp, join_streams(uploads))
+        });
+        let results = stop_spinner(sp, msg, results)?;
+
+        let report = upload_report(results)?;
+        self.emit(Event::PhaseEnd {
+            phase: "upload",
+            report: PhaseReport::Speed(&report),
+        });
+        Ok(report)
+    }
+
+    fn run_download_test(
+        &self,
+        streams: &mut [Transport],
+        target_time: Duration,
+    ) -> Result<SpeedReport> {
+        let msg = "🚀 Running download speed test...";
+        let sp = self.start_spinner(msg);
+        self.emit(Event::PhaseStart { phase: "download" });
+
+        let progress = AtomicU64::new(0);
+
+        let start_time = Instant::now();
+        let (sp, results) = thread::scope(|scope| {
+            let downloads = self.spawn_downloads(scope, streams, target_time, &progress);
+            let sp = wait_for_streams(
+                || downloads.iter().all(|handle| handle.is_finished()),
+                start_time,
+                &progress,
+                sp,
+                msg,
+                self.interval,
+                |sample| self.emit(Event::interval("download", sample)),
+            );
+            (sp, join_streams(downloads))
+        });
+        let results = stop_spinner(sp, msg, results)?;
+
+        let report = speed_report(SpeedReport::from_streams("download", results))?;
+        self.emit(Event::PhaseEnd {
+            phase: "download",
+            report: PhaseReport::Speed(&report),
+        });
+        Ok(report)
+    }
+
+    /// Upload over the first half of the streams while downloading over the second half, in the same window.
+    fn run_bidirectional_test(
+        &self,
+        streams: &mut [Transport],
+        buffer: &[u8],
+        target_time: Duration,
+    ) -> Result<(SpeedReport, SpeedReport)> {
+        let msg = "🚀 Running bidirectional speed test...";
+        let sp = self.start_spinner(msg);
+        self.emit(Event::PhaseStart {
+            phase: "bidirectional",
+        });
+
+        let data_frame = protocol::encode_frame(MessageKind::Data, buffer);
+        let progress = AtomicU64::new(0);
+        let (upload_streams, download_streams) = streams.split_at_mut(self.parallel as usize);
+
+        let start_time = Instant::now();
+        let (sp, results) = thread::scope(|scope| {
+            let uploads = self.spawn_uploads(
+                scope,
+                upload_streams,
+                &data_frame,
+                buffer,
+                target_time,
+                &progress,
+            );
+            let downloads = self.spawn_downloads(scope, download_streams, target_time, &progress);
+            let sp = wait_for_streams(
+                || {
+                    uploads.iter().all(|handle| handle.is_finished())
+                        && downloads.iter().all(|handle| handle.is_finished())
+                },
+                start_time,
+                &progress,
+                sp,
+                msg,
+                self.interval,
+                |sample| self.emit(Event::interval("bidirectional", sample)),
+            );
+            let results =
+                join_streams(uploads).and_then(|uploads| Ok((uploads, join_streams(downloads)?)));
+            (sp, results)
+        });
+        let (upload_results, download_results) = stop_spinner(sp, msg, results)?;
+
+        let upload = upload_report(upload_results)?;
+        let download = speed_report(SpeedReport::from_streams("download", download_results))?;
+        self.emit(Event::PhaseEnd {
+            phase: "bidirectional",
+            report: PhaseReport::Bidirectional {
+                upload: &upload,
+                download: &download,
+            },
+        });
+        Ok((upload, download))
+    }
+
+    /// Run a UDP test in one direction at the target bitrate, the TCP stream carrying control messages only.
+    fn run_udp_test(
+        &self,
+        stream: &mut Transport,
+        server_addr: SocketAddr,
+        direction: u8,
+    ) -> Result<SpeedReport> {
+        let (report_type, msg) = match direction {
+            protocol::TEST_UPLOAD => ("upload", "📶 Running UDP upload test..."),
+            _ => ("download", "📶 Running UDP download test..."),
+        };
+        let sp = self.start_spinner(msg);
+        self.emit(Event::PhaseStart { phase: report_type });
+
+        let params = UdpParams {
+            direction,
+            bitrate: self.bitrate,
+            datagram_size: self.datagram_size,
+        };
+        let result = self.udp_transfer(stream, server_addr, &params);
+        let stats = stop_spinner(sp, msg, result)?;
+        self.logger
+            .verbose(&format!("UDP {report_type} - {stats:?}"));
+
+        let report = speed_report(SpeedReport::new(report_type, stats.duration, stats.bytes))?
+            .with_udp(stats, self.bitrate);
+        self.emit(Event::PhaseEnd {
+            phase: report_type,
+            report: PhaseReport::Speed(&report),
+        });
+        Ok(report)
+    }
+
+    /// Exchange datagrams with the server, returning the statistics measured by the receiving side.
+    fn udp_transfer(
+        &self,
+        stream: &mut Transport,
+        server_addr: SocketAddr,
+        params: &UdpParams,
+    ) -> Result<UdpStats> {
+        protocol::write_frame(stream, MessageKind::UdpStart, &params.encode())
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send UDP start - {e}")))?;
+        let ready = protocol::expect_frame(stream, MessageKind::UdpReady)
+            .and_then(|frame| UdpReady::decode(&frame.payload))
+            .map_err(|e| NetbeatError::protocol(format!("Failed to read UDP ready - {e}")))?;
+
+        let unspecified = match server_addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind((unspecified, 0))
+            .and_then(|socket| {
+                socket.connect(SocketAddr::new(server_addr.ip(), ready.port))?;
+                Ok(socket)
+            })
+            .map_err(|e| NetbeatError::client(format!("Failed to open UDP socket - {e}")))?;
+
+        if params.direction == protocol::TEST_UPLOAD {
+            let sent =
+                udp::send_datagrams(&socket, params, Duration::from_secs(self.time), self.data)
+                    .map_err(|e| {
+                        NetbeatError::protocol(format!("Failed to send datagram - {e}"))
+                    })?;
+            protocol::write_frame(stream, MessageKind::UdpDone, &sent.encode())
+                .map_err(|e| NetbeatError::protocol(format!("Failed to send UDP done - {e}")))?;
+
+            // Server-side measurement
+            protocol::expect_frame(stream, MessageKind::UdpResult)
+                .and_then(|frame| UdpStats::decode(&frame.payload))
+                .map_err(|e| NetbeatError::protocol(format!("Failed to read UDP result - {e}")))
+        } else {
+            let mut receiver = UdpReceiver::new();
+            let mut pending = Vec::new();
+            let mut last_announce: Option<Instant> = None;
+            let sent = udp::receive_datagrams(
+                &socket,
+                &mut receiver,
+                params.datagram_size as usize,
+                |receiver| {
+                    // Keep announcing until datagrams arrive, in case an announce is lost
+                    if receiver.received() == 0
+                        && last_announce.is_none_or(|at| at.elapsed() >= udp::ANNOUNCE_INTERVAL)
+                    {
+                        udp::announce(&socket, ready.token)?;
+                        last_announce = Some(Instant::now());
+                    }
+                    match protocol::poll_frame(stream, &mut pending)? {
+                        Some(frame) if frame.kind == MessageKind::UdpDone => {
+                            Ok(Some(UdpSent::decode(&frame.payload)?))
+                        }
+                        Some(frame) => Err(protocol::unexpected_message(
+                            MessageKind::UdpDone,
+                            frame.kind,
+                            &frame.payload,
+                        )),
+                        None => Ok(None),
+                    }
+                },
+            )?;
+            Ok(receiver.stats(&sent))
+        }
+    }
+
+    /// Write a progress event to stdout, when streaming ndjson output.
+    fn emit(&self, event: Event) {
+        if self.format == OutputFormat::Ndjson {
+            self.logger.result(&event.to_json());
+        }
+    }
+
+    /// Log the per-interval table of a speed test, when intervals were recorded.
+    fn log_intervals(&self, report: &SpeedReport) {
+        if !report.intervals.is_empty() {
+            self.logger.info(&format!("{}", report.to_interval_table()));
+        }
+    }
+
+    fn start_spinner(&self, msg: &str) -> Option<Spinner> {
+        let sp = if !self.logger.quiet & !self.logger.verbose {
             Some(Spinner::new(Spinners::Dots2, msg.into()))
         } else {
             None
         };
         self.logger.verbose(msg);
+        sp
+    }
 
+    /// Start an upload worker per stream, splitting target data evenly across them.
+    fn spawn_uploads<'scope, 'env>(
+        &'env self,
+        scope: &'scope thread::Scope<'scope, 'env>,
+        streams: &'env mut [Transport],
+        data_frame: &'env [u8],
+        buffer: &'env [u8],
+        target_time: Duration,
+        progress: &'env AtomicU64,
+    ) -> Vec<ScopedJoinHandle<'scope, Result<(StreamResult, StreamResult)>>> {
+        let targets = split_target_bytes(self.data, streams.len());
+        streams
+            .iter_mut()
+            .zip(targets)
+            .map(|(stream, target_bytes)| {
+                scope.spawn(move || {
+                    self.upload_stream(
+                        stream,
+                        data_frame,
+                        buffer,
+                        target_bytes,
+                        target_time,
+                        progress,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Start a download worker per stream, splitting target data evenly across them.
+    fn spawn_downloads<'scope, 'env>(
+        &'env self,
+        scope: &'scope thread::Scope<'scope, 'env>,
+        streams: &'env mut [Transport],
+        target_time: Duration,
+        progress: &'env AtomicU64,
+    ) -> Vec<ScopedJoinHandle<'scope, Result<StreamResult>>> {
+        let targets = split_target_bytes(self.data, streams.len());
+        streams
+            .iter_mut()
+            .zip(targets)
+            .map(|(stream, target_bytes)| {
+                scope.spawn(move || {
+                    self.download_stream(stream, target_bytes, target_time, progress)
+                })
+            })
+            .collect()
+    }
+
+    /// Upload on a single stream, returning the server-side and client-side results.
+    fn upload_stream(
+        &self,
+        stream: &mut Transport,
+        data_frame: &[u8],
+        buffer: &[u8],
+        target_bytes: Option<u64>,
+        target_time: Duration,
+        progress: &AtomicU64,
+    ) -> Result<(StreamResult, StreamResult)> {
         let mut bytes_sent: u64 = 0;
-        let update_interval = Duration::from_secs(1);
-        let mut iteration_count = 0u64;
-        let check_interval = 500;
-        let target_bytes = target_bytes.unwrap_or(0);
 
         // Send initial upload start
-        protocol::write_message(stream, protocol::UPLOAD_START).map_err(|e| {
+        protocol::write_frame(stream, MessageKind::UploadStart, &[]).map_err(|e| {
             NetbeatError::protocol(format!("Failed to send upload start message - {e}"))
         })?;
 
         let start_time = Instant::now();
-        let mut last_update = Instant::now();
-        // Upload test
-        if use_time {
+        let mut last_poll = Instant::now();
+        let mut pending = Vec::new();
+        match target_bytes {
             // Time-based upload test
-            while start_time.elapsed() < target_time {
-                protocol::write_message(stream, buffer).map_err(|e| {
-                    NetbeatError::protocol(format!("Failed to send upload buffer - {e}"))
-                })?;
-                bytes_sent += buffer.len() as u64;
-                iteration_count += 1;
-                if iteration_count % check_interval == 0 {
-                    if last_update.elapsed() >= update_interval {
-                        sp =
-                            reports::print_progress(start_time.elapsed(), bytes_sent, &mut sp, msg);
-                        last_update = Instant::now();
-                    }
+            None => {
+                while start_time.elapsed() < target_time {
+                    protocol::write_message(stream, data_frame).map_err(|e| {
+                        NetbeatError::protocol(format!("Failed to send upload buffer - {e}"))
+                    })?;
+                    bytes_sent += buffer.len() as u64;
+                    progress.fetch_add(buffer.len() as u64, Ordering::Relaxed);
+                    poll_upload_abort(stream, &mut pending, &mut last_poll)?;
                 }
             }
-        } else {
             // Byte-based upload test
-            while bytes_sent < target_bytes {
-                let remaining = target_bytes - bytes_sent;
-                let to_write = if remaining >= buffer.len() as u64 {
-                    buffer.len() as u64
-                } else {
-                    remaining
-                };
-                protocol::write_message(stream, &buffer[..to_write as usize]).map_err(|e| {
-                    NetbeatError::protocol(format!("Failed to send upload buffer - {e}"))
-                })?;
-                bytes_sent += to_write;
-                iteration_count += 1;
-                if iteration_count % check_interval == 0 {
-                    if last_update.elapsed() >= update_interval {
-                        sp =
-                            reports::print_progress(start_time.elapsed(), bytes_sent, &mut sp, msg);
-                        last_update = Instant::now();
-                    }
+            Some(target_bytes) => {
+                while bytes_sent < target_bytes {
+                    let remaining = target_bytes - bytes_sent;
+                    let result = if remaining >= buffer.len() as u64 {
+                        protocol::write_message(stream, data_frame)
+                    } else {
+                        protocol::write_frame(
+                            stream,
+                            MessageKind::Data,
+                            &buffer[..remaining as usize],
+                        )
+                    };
+                    result.map_err(|e| {
+                        NetbeatError::protocol(format!("Failed to send upload buffer - {e}"))
+                    })?;
+                    let sent = remaining.min(buffer.len() as u64);
+                    bytes_sent += sent;
+                    progress.fetch_add(sent, Ordering::Relaxed);
+                    poll_upload_abort(stream, &mut pending, &mut last_poll)?;
                 }
             }
         }
         let upload_time = start_time.elapsed();
-        if let Some(mut sp) = sp {
-            sp.stop_with_message(format!("{msg} ✅ Completed."));
-        }
 
         // Send close message
-        protocol::write_message(stream, protocol::UPLOAD_DONE)
+        protocol::write_frame(stream, MessageKind::UploadDone, &[])
             .map_err(|e| NetbeatError::protocol(format!("Failed to send close message - {e}")))?;
 
         stream
             .flush()
             .map_err(|e| NetbeatError::protocol(format!("Failed to flush stream - {e}")))?;
 
-        // Report
-        let upload_report = SpeedReport::new("upload", upload_time, bytes_sent).unwrap();
-        self.logger
-            .info(&format!("{}", upload_report.to_table_report()));
-        Ok(upload_report)
+        // Server-side measurement
+        let upload_result = protocol::expect_frame(stream, MessageKind::UploadResult)
+            .and_then(|frame| protocol::TransferResult::decode(&frame.payload))
+            .map_err(|e| NetbeatError::protocol(format!("Failed to read upload result - {e}")))?;
+        self.logger.verbose(&format!(
+            "Server received {} bytes in {:.2?}",
+            upload_result.bytes, upload_result.duration
+        ));
+
+        Ok((
+            StreamResult::new(upload_result.duration, upload_result.bytes)
+                .with_intervals(upload_result.intervals),
+            StreamResult::new(upload_time, bytes_sent),
+        ))
     }
 
-    #[allow(clippy::collapsible_if)]
-    fn run_download_test(
+    /// Download on a single stream, returning the client-side result.
+    fn download_stream(
         &self,
-        stream: &mut TcpStream,
-        buffer: &mut [u8],
+        stream: &mut Transport,
         target_bytes: Option<u64>,
         target_time: Duration,
-        use_time: bool,
-    ) -> Result<SpeedReport> {
-        let msg = "🚀 Running download speed test...";
-        let mut sp = if !self.logger.quiet & !self.logger.verbose {
-            Some(Spinner::new(Spinners::Dots2, msg.into()))
-        } else {
-            None
-        };
-        self.logger.verbose(msg);
-
+        progress: &AtomicU64,
+    ) -> Result<StreamResult> {
         let mut bytes_received: u64 = 0;
-
-        let mut last_update = Instant::now();
-        let update_interval = Duration::from_secs(1);
-        let mut iteration_count = 0u64;
-        let check_interval = 500;
-        let target_bytes = target_bytes.unwrap_or(0);
+        let mut payload = Vec::with_capacity(self.chunk_size as usize);
 
         // Send initial download start
-        protocol::write_message(stream, protocol::DOWNLOAD_START).map_err(|e| {
+        protocol::write_frame(stream, MessageKind::DownloadStart, &[]).map_err(|e| {
             NetbeatError::protocol(format!("Failed to send download start message - {e}"))
         })?;
 
         let start_time = Instant::now();
-        if use_time {
-            // Time-base download test
-            while start_time.elapsed() < target_time {
-                match stream.read(buffer) {
-                    Ok(0) => break,
-                    Ok(n) => bytes_received += n as u64,
-                    Err(e) => {
-                        if let Some(mut sp) = sp {
-                            sp.stop();
-                        }
-                        return Err(NetbeatError::protocol(format!(
-                            "Failed to read download buffer - {e}"
-                        )));
-                    }
+        let mut recorder = IntervalRecorder::new(self.interval);
+        // Time-based or byte-based download test
+        while match target_bytes {
+            None => start_time.elapsed() < target_time,
+            Some(target_bytes) => bytes_received < target_bytes,
+        } {
+            match protocol::read_frame_into(stream, &mut payload) {
+                Ok(MessageKind::Data) => {
+                    bytes_received += payload.len() as u64;
+                    recorder.record(payload.len() as u64);
+                    progress.fetch_add(payload.len() as u64, Ordering::Relaxed);
                 }
-                iteration_count += 1;
-                if iteration_count % check_interval == 0 {
-                    if last_update.elapsed() >= update_interval {
-                        sp = reports::print_progress(
-                            start_time.elapsed(),
-                            bytes_received,
-                            &mut sp,
-                            msg,
-                        );
-                        last_update = Instant::now();
-                    }
+                Ok(kind) => {
+                    return Err(protocol::unexpected_message(
+                        MessageKind::Data,
+                        kind,
+                        &payload,
+                    ));
                 }
-            }
-        } else {
-            // Byte-base download test
-            while bytes_received < target_bytes {
-                let remaining = target_bytes - bytes_received;
-                let to_read = if remaining >= buffer.len() as u64 {
-                    buffer.len() as u64
-                } else {
-                    remaining
-                };
-                match stream.read(&mut buffer[..to_read as usize]) {
-                    Ok(0) => break,
-                    Ok(n) => bytes_received += n as u64,
-                    Err(e) => {
-                        if let Some(mut sp) = sp {
-                            sp.stop();
-                        }
-                        return Err(NetbeatError::protocol(format!(
-                            "Failed to read download buffer - {e}"
-                        )));
-                    }
-                }
-                iteration_count += 1;
-                if iteration_count % check_interval == 0 {
-                    if last_update.elapsed() >= update_interval {
-                        sp = reports::print_progress(
-                            start_time.elapsed(),
-                            bytes_received,
-                            &mut sp,
-                            msg,
-                        );
-                        last_update = Instant::now();
-                    }
+                Err(e) => {
+                    return Err(NetbeatError::protocol(format!(
+                        "Failed to read download buffer - {e}"
+                    )));
                 }
             }
         }
         let download_time = start_time.elapsed();
-        if let Some(mut sp) = sp {
-            sp.stop_with_message(format!("{msg} ✅ Completed."))
-        };
 
-        // Send close message
-        // protocol::write_message(stream, protocol::DOWNLOAD_DONE)?;
+        // Ask server to stop and drain data in flight until it confirms
+        protocol::write_frame(stream, MessageKind::DownloadStop, &[])
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send stop message - {e}")))?;
         stream
             .flush()
             .map_err(|e| NetbeatError::protocol(format!("Failed to flush stream - {e}")))?;
 
-        // Report
-        let download_report = SpeedReport::new("download", download_time, bytes_received).unwrap();
-        self.logger
-            .info(&format!("{}", download_report.to_table_report()));
-        Ok(download_report)
+        let mut bytes_drained: u64 = 0;
+        let download_result = loop {
+            match protocol::read_frame_into(stream, &mut payload) {
+                Ok(MessageKind::Data) => bytes_drained += payload.len() as u64,
+                Ok(MessageKind::DownloadDone) => {
+                    break protocol::TransferResult::decode(&payload)?;
+                }
+                Ok(kind) => {
+                    return Err(protocol::unexpected_message(
+                        MessageKind::DownloadDone,
+                        kind,
+                        &payload,
+                    ));
+                }
+                Err(e) => {
+                    return Err(NetbeatError::protocol(format!(
+                        "Failed to read download result - {e}"
+                    )));
+                }
+            }
+        };
+        self.logger.verbose(&format!(
+            "Server sent {} bytes in {:.2?}, {bytes_drained} bytes drained after stop",
+            download_result.bytes, download_result.duration
+        ));
+        if bytes_received + bytes_drained != download_result.bytes {
+            return Err(NetbeatError::protocol(format!(
+                "Download byte count mismatch - received {}, server sent {}",
+                bytes_received + bytes_drained,
+                download_result.bytes
+            )));
+        }
+
+        Ok(StreamResult::new(download_time, bytes_received)
+            .with_intervals(recorder.finish(download_time)))
+    }
+}
+
+/// Read ping responses until the one matching `seq` arrives.
+///
+/// Late responses to earlier, timed out pings are skipped. Returns whether the response was valid.
+fn read_pong(stream: &mut Transport, seq: u32) -> Result<bool> {
+    loop {
+        let frame = protocol::expect_frame(stream, MessageKind::Pong)?;
+        let Ok(bytes) = <[u8; 4]>::try_from(frame.payload.as_slice()) else {
+            return Ok(false);
+        };
+        match u32::from_be_bytes(bytes) {
+            pong_seq if pong_seq < seq => continue,
+            pong_seq => return Ok(pong_seq == seq),
+        }
+    }
+}
+
+/// Ping over the probe connection every [`LATENCY_PROBE_INTERVAL`] until `done` is set, returning the round trip times.
+fn probe_latency(stream: &mut Transport, done: &AtomicBool) -> Result<Vec<Duration>> {
+    let mut samples = Vec::new();
+    let mut seq: u32 = 0;
+    while !done.load(Ordering::Relaxed) {
+        seq += 1;
+        let start_time = Instant::now();
+        protocol::write_frame(stream, MessageKind::Ping, &seq.to_be_bytes())
+            .map_err(|e| NetbeatError::protocol(format!("Failed to write probe message - {e}")))?;
+        if read_pong(stream, seq)? {
+            samples.push(start_time.elapsed());
+        }
+
+        // Wait out the rest of the interval, checking often so the probe stops with the test
+        while !done.load(Ordering::Relaxed) && start_time.elapsed() < LATENCY_PROBE_INTERVAL {
+            let remaining = LATENCY_PROBE_INTERVAL.saturating_sub(start_time.elapsed());
+            thread::sleep(PROGRESS_POLL_INTERVAL.min(remaining));
+        }
+    }
+
+    protocol::write_frame(stream, MessageKind::PingDone, &[])
+        .map_err(|e| NetbeatError::protocol(format!("Failed to write probe termination - {e}")))?;
+    Ok(samples)
+}
+
+/// Check for a message from the server while uploading, every [`UPLOAD_POLL_INTERVAL`].
+///
+/// The server only writes during an upload when it ends the test early, so any message fails the upload.
+fn poll_upload_abort(
+    stream: &mut Transport,
+    pending: &mut Vec<u8>,
+    last_poll: &mut Instant,
+) -> Result<()> {
+    if last_poll.elapsed() < UPLOAD_POLL_INTERVAL {
+        return Ok(());
     }
+    *last_poll = Instant::now();
+    match protocol::poll_frame(stream, pending)? {
+        Some(frame) => Err(protocol::unexpected_message(
+            MessageKind::UploadResult,
+            frame.kind,
+            &frame.payload,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Read the server reply to a session setup or join, returning the session id.
+fn read_session_reply(stream: &mut Transport) -> Result<u64> {
+    let frame = protocol::read_frame(stream)
+        .map_err(|e| NetbeatError::protocol(format!("Failed to read session reply - {e}")))?;
+    match frame.kind {
+        MessageKind::SessionAccept => protocol::decode_session_id(&frame.payload),
+        MessageKind::SessionReject => Err(NetbeatError::session_rejected(
+            String::from_utf8_lossy(&frame.payload).into_owned(),
+        )),
+        kind => Err(protocol::unexpected_message(
+            MessageKind::SessionAccept,
+            kind,
+            &frame.payload,
+        )),
+    }
+}
+
+/// Split the target data evenly across streams, `None` for time-based tests.
+fn split_target_bytes(target_bytes: Option<u64>, streams: usize) -> Vec<Option<u64>> {
+    let streams = streams as u64;
+    (0..streams)
+        .map(|i| target_bytes.map(|bytes| bytes / streams + u64::from(i < bytes % streams)))
+        .collect()
+}
+
+/// Wait until all stream workers have finished, updating the spinner with aggregate progress every second
+/// and reporting the bytes moved during each reporting interval.
+fn wait_for_streams(
+    finished: impl Fn() -> bool,
+    start_time: Instant,
+    progress: &AtomicU64,
+    mut sp: Option<Spinner>,
+    msg: &str,
+    interval: Duration,
+    mut on_interval: impl FnMut(IntervalSample),
+) -> Option<Spinner> {
+    let update_interval = Duration::from_secs(1);
+    let mut last_update = Instant::now();
+    let (mut interval_start, mut interval_bytes) = (Duration::ZERO, 0);
+    while !finished() {
+        thread::sleep(PROGRESS_POLL_INTERVAL);
+        let elapsed = start_time.elapsed();
+        if elapsed - interval_start >= interval {
+            let bytes = progress.load(Ordering::Relaxed);
+            on_interval(IntervalSample {
+                start: interval_start,
+                end: elapsed,
+                bytes: bytes - interval_bytes,
+            });
+            (interval_start, interval_bytes) = (elapsed, bytes);
+        }
+        if last_update.elapsed() >= update_interval {
+            sp = reports::print_progress(
+                start_time.elapsed(),
+                progress.load(Ordering::Relaxed),
+                &mut sp,
+                msg,
+            );
+            last_update = Instant::now();
+        }
+    }
+    sp
+}
+
+/// Collect stream worker results, failing on the first stream that failed.
+fn join_streams<T>(handles: Vec<ScopedJoinHandle<'_, Result<T>>>) -> Result<Vec<T>> {
+    handles
+        .into_iter()
+        .map(|handle| {
+            handle.join().unwrap_or_else(|_| {
+                Err(NetbeatError::test_execution(
+                    "Stream worker panicked".to_string(),
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Stop the spinner, marking the test as completed when it succeeded.
+fn stop_spinner<T>(sp: Option<Spinner>, msg: &str, result: Result<T>) -> Result<T> {
+    if let Some(mut sp) = sp {
+        match result {
+            Ok(_) => sp.stop_with_message(format!("{msg} ✅ Completed.")),
+            Err(_) => sp.stop(),
+        }
+    }
+    result
+}
+
+/// Turn a failure to build a speed report into a test execution error.
+fn speed_report(report: anyhow::Result<SpeedReport>) -> Result<SpeedReport> {
+    report.map_err(|e| NetbeatError::test_execution(format!("Failed to build speed report - {e}")))
+}
+
+/// Build the upload report from server-side results, keeping the client-side measurement for reference.
+fn upload_report(results: Vec<(StreamResult, StreamResult)>) -> Result<SpeedReport> {
+    let (server_results, client_results): (Vec<StreamResult>, Vec<StreamResult>) =
+        results.into_iter().unzip();
+    let client_time = client_results
+        .iter()
+        .map(|result| result.duration)
+        .max()
+        .unwrap_or_default();
+    let client_bytes = client_results.iter().map(|result| result.bytes).sum();
+    Ok(
+        speed_report(SpeedReport::from_streams("upload", server_results))?
+            .with_client_measurement(client_time, client_bytes),
+    )
 }
 
 impl ClientBuilder {
-    /// Create a new client builder with the given target server IP address.
+    /// Create a new client builder with the given target server.
+    ///
+    /// The target may be an IP address or hostname, optionally followed by a port
+    /// (eg, `10.1.1.11`, `nas.lan:5050`, `[fe80::1]:5050`). A port in the target takes precedence over [`ClientBuilder::port`].
     pub fn new(target: impl Into<String>) -> Self {
         ClientBuilder {
             target: target.into(),
@@ -445,14 +1285,67 @@ impl ClientBuilder {
             time: None,
             chunk_size: None,
             ping_count: None,
-            return_json: None,
+            parallel: None,
+            bidirectional: None,
+            udp: None,
+            bitrate: None,
+            datagram_size: None,
+            latency_probe: None,
+            interval: None,
+            format: None,
+            csv_header: None,
+            min_download: None,
+            min_upload: None,
+            max_latency: None,
+            max_loss: None,
+            history: None,
+            history_file: None,
             timeout: None,
             retries: None,
+            psk: None,
+            tls: None,
+            tls_ca: None,
+            known_hosts_file: None,
             quiet: None,
             verbose: None,
         }
     }
 
+    /// Create a client builder from a loaded profile, with the profile address, if any, in place of the target.
+    ///
+    /// Settings left unset in the profile fall back to the defaults, and can still be overridden with the other builder methods.
+    pub fn from_profile(target: impl Into<String>, profile: &ClientProfile) -> Result<Self> {
+        let mut builder = ClientBuilder::new(profile.address.clone().unwrap_or(target.into()));
+        if let Some(chunk_size) = &profile.chunk_size {
+            builder = builder.chunk_size(chunk_size)?;
+        }
+        Ok(ClientBuilder {
+            port: profile.port,
+            data: profile.data.clone(),
+            time: profile.time,
+            ping_count: profile.ping_count,
+            parallel: profile.parallel,
+            bidirectional: profile.bidirectional,
+            udp: profile.udp,
+            bitrate: profile.bitrate.clone(),
+            datagram_size: profile.datagram_size,
+            latency_probe: profile.latency_probe,
+            interval: profile.interval,
+            format: profile.format,
+            min_download: profile.min_download.clone(),
+            min_upload: profile.min_upload.clone(),
+            max_latency: profile.max_latency.clone(),
+            max_loss: profile.max_loss.clone(),
+            history: profile.history,
+            timeout: profile.timeout,
+            retries: profile.retries,
+            psk: profile.psk.clone(),
+            tls: profile.tls,
+            tls_ca: profile.tls_ca.clone(),
+            ..builder
+        })
+    }
+
     /// Target port on server (1-65535)
     pub fn port(mut self, port: u16) -> Self {
         self.port = Some(port);
@@ -483,9 +1376,101 @@ impl ClientBuilder {
         self
     }
 
-    /// Return results as json
+    /// Number of parallel TCP streams per test direction (1-128)
+    pub fn parallel(mut self, parallel: u32) -> Self {
+        self.parallel = Some(parallel);
+        self
+    }
+
+    /// Run upload and download tests at the same time, each over its own streams
+    pub fn bidirectional(mut self, bidirectional: bool) -> Self {
+        self.bidirectional = Some(bidirectional);
+        self
+    }
+
+    /// Run upload and download tests over UDP at a fixed bitrate, reporting datagram loss and jitter
+    pub fn udp(mut self, udp: bool) -> Self {
+        self.udp = Some(udp);
+        self
+    }
+
+    /// Target bitrate for UDP tests including units (eg, 1Mbps, 100Mbps, 1Gbps)
+    pub fn bitrate(mut self, bitrate: impl Into<String>) -> Self {
+        self.bitrate = Some(bitrate.into());
+        self
+    }
+
+    /// UDP datagram size in bytes (64-65507)
+    pub fn datagram_size(mut self, datagram_size: u32) -> Self {
+        self.datagram_size = Some(datagram_size);
+        self
+    }
+
+    /// Probe latency on a separate connection during upload and download tests, grading bufferbloat
+    pub fn latency_probe(mut self, latency_probe: bool) -> Self {
+        self.latency_probe = Some(latency_probe);
+        self
+    }
+
+    /// Throughput reporting interval in seconds (0.1-60)
+    pub fn interval(mut self, interval: f64) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Return results as json, shorthand for `.format(OutputFormat::Json)`
     pub fn return_json(mut self, return_json: bool) -> Self {
-        self.return_json = Some(return_json);
+        if return_json {
+            self.format = Some(OutputFormat::Json);
+        }
+        self
+    }
+
+    /// Format of the results written to stdout (table, json, ndjson or csv)
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Write the header row ahead of csv results (disable when appending to an existing file)
+    pub fn csv_header(mut self, csv_header: bool) -> Self {
+        self.csv_header = Some(csv_header);
+        self
+    }
+
+    /// Minimum download speed including units (eg, 100Mbps, 900Mbps, 2.5Gbps)
+    pub fn min_download(mut self, min_download: Option<impl Into<String>>) -> Self {
+        self.min_download = min_download.map(|m| m.into());
+        self
+    }
+
+    /// Minimum upload speed including units (eg, 100Mbps, 900Mbps, 2.5Gbps)
+    pub fn min_upload(mut self, min_upload: Option<impl Into<String>>) -> Self {
+        self.min_upload = min_upload.map(|m| m.into());
+        self
+    }
+
+    /// Maximum average idle ping including units (eg, 500us, 2ms, 1.5ms)
+    pub fn max_latency(mut self, max_latency: Option<impl Into<String>>) -> Self {
+        self.max_latency = max_latency.map(|m| m.into());
+        self
+    }
+
+    /// Maximum ping loss, and datagram loss in UDP mode (eg, 0%, 0.5%)
+    pub fn max_loss(mut self, max_loss: Option<impl Into<String>>) -> Self {
+        self.max_loss = max_loss.map(|m| m.into());
+        self
+    }
+
+    /// Keep the finished report in the history of the netbeat data directory
+    pub fn history(mut self, history: bool) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Keep the finished report in the given history file instead of the netbeat data directory
+    pub fn history_file(mut self, history_file: impl Into<PathBuf>) -> Self {
+        self.history_file = Some(history_file.into());
         self
     }
 
@@ -501,6 +1486,30 @@ impl ClientBuilder {
         self
     }
 
+    /// Pre-shared key to authenticate with, for servers that require one
+    pub fn psk(mut self, psk: Option<impl Into<String>>) -> Self {
+        self.psk = psk.map(|p| p.into());
+        self
+    }
+
+    /// Encrypt the connection with TLS, pinning the server certificate on first use
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// CA certificates (PEM) to verify the server certificate against, instead of pinning it on first use. Enables TLS.
+    pub fn tls_ca(mut self, tls_ca: Option<impl Into<PathBuf>>) -> Self {
+        self.tls_ca = tls_ca.map(|p| p.into());
+        self
+    }
+
+    /// Pin server certificates in the given known hosts file instead of the default one
+    pub fn known_hosts_file(mut self, known_hosts_file: impl Into<PathBuf>) -> Self {
+        self.known_hosts_file = Some(known_hosts_file.into());
+        self
+    }
+
     /// Suppress progress output (results & errors only)
     pub fn quiet(mut self, quiet: bool) -> Self {
         self.quiet = Some(quiet);
@@ -513,15 +1522,62 @@ impl ClientBuilder {
         self
     }
 
-    /// Complete build of `Client`.
+    /// Complete build of `Client`, resolving the target to all of its addresses.
     pub fn build(self) -> Result<Client> {
+        let (host, target_port) = address::parse_target(&self.target)?;
+        let port = target_port.or(self.port).unwrap_or(config::DEFAULT_PORT);
+        let parallel = self.parallel.unwrap_or(config::DEFAULT_PARALLEL_STREAMS);
+        if !(1..=config::MAX_STREAMS).contains(&parallel) {
+            return Err(NetbeatError::client(format!(
+                "Invalid parallel stream count ({parallel}) - must be between 1 and {}",
+                config::MAX_STREAMS
+            )));
+        }
+        let interval = self.interval.unwrap_or(config::DEFAULT_INTERVAL);
+        if !(config::MIN_INTERVAL..=config::MAX_INTERVAL).contains(&interval) {
+            return Err(NetbeatError::client(format!(
+                "Invalid interval ({interval}) - must be between {} and {} seconds",
+                config::MIN_INTERVAL,
+                config::MAX_INTERVAL
+            )));
+        }
+        let udp = self.udp.unwrap_or(false);
+        let bidirectional = self.bidirectional.unwrap_or(false);
+        if udp && (parallel > 1 || bidirectional) {
+            return Err(NetbeatError::client(
+                "UDP mode runs a single stream and cannot be combined with parallel or bidirectional tests"
+                    .to_string(),
+            ));
+        }
+        let params = UdpParams {
+            direction: protocol::TEST_UPLOAD,
+            bitrate: units::parse_bitrate(
+                self.bitrate
+                    .as_deref()
+                    .unwrap_or(config::DEFAULT_UDP_BITRATE),
+            )
+            .map_err(NetbeatError::client)?,
+            datagram_size: self.datagram_size.unwrap_or(config::DEFAULT_DATAGRAM_SIZE),
+        };
+        params
+            .validate()
+            .map_err(|reason| NetbeatError::client(format!("Invalid UDP settings - {reason}")))?;
+        let tls = match (self.tls_ca, self.tls.unwrap_or(false)) {
+            (Some(ca), _) => Some(TlsClient::with_ca_file(&host, &ca)?),
+            (None, true) => Some(TlsClient::trust_on_first_use(
+                &host,
+                port,
+                match self.known_hosts_file {
+                    Some(path) => KnownHosts::new(path),
+                    None => KnownHosts::open_default()?,
+                },
+            )?),
+            (None, false) => None,
+        };
+
         Ok(Client {
-            socket_addr: SocketAddr::new(
-                IpAddr::from_str(&self.target).map_err(|e| {
-                    NetbeatError::client(format!("Invalid IP address ({}) - {e}", self.target))
-                })?,
-                self.port.unwrap_or(config::DEFAULT_PORT),
-            ),
+            socket_addrs: address::resolve(&host, port)?,
+            target: host,
             data: match self.data.as_deref() {
                 Some(data) => Some(
                     Byte::parse_str(data, false)
@@ -547,11 +1603,52 @@ impl ClientBuilder {
             })?
             .as_u64(),
             ping_count: self.ping_count.unwrap_or(config::DEFAULT_PING_COUNT),
-            return_json: self.return_json.unwrap_or(false),
+            parallel,
+            bidirectional,
+            udp,
+            bitrate: params.bitrate,
+            datagram_size: params.datagram_size,
+            latency_probe: self.latency_probe.unwrap_or(false),
+            interval: Duration::from_secs_f64(interval),
+            format: self.format.unwrap_or(config::DEFAULT_OUTPUT_FORMAT),
+            csv_header: self.csv_header.unwrap_or(true),
+            thresholds: Thresholds {
+                min_download: self
+                    .min_download
+                    .as_deref()
+                    .map(units::parse_bitrate)
+                    .transpose()
+                    .map_err(NetbeatError::client)?,
+                min_upload: self
+                    .min_upload
+                    .as_deref()
+                    .map(units::parse_bitrate)
+                    .transpose()
+                    .map_err(NetbeatError::client)?,
+                max_latency: self
+                    .max_latency
+                    .as_deref()
+                    .map(units::parse_duration)
+                    .transpose()
+                    .map_err(NetbeatError::client)?,
+                max_loss: self
+                    .max_loss
+                    .as_deref()
+                    .map(units::parse_percent)
+                    .transpose()
+                    .map_err(NetbeatError::client)?,
+            },
+            history: match (self.history_file, self.history.unwrap_or(false)) {
+                (Some(path), _) => Some(History::new(path)),
+                (None, true) => Some(History::open_default()?),
+                (None, false) => None,
+            },
             timeout: Duration::from_secs(
                 self.timeout.unwrap_or(config::DEFAULT_CONNECTION_TIMEOUT),
             ),
             retries: self.retries.unwrap_or(config::DEFAULT_MAX_RETRIES),
+            psk: self.psk.map(PreSharedKey::new).transpose()?,
+            tls,
             logger: Logger::new(self.verbose.unwrap_or(false), self.quiet.unwrap_or(false)),
         })
     }
@@ -571,6 +1668,10 @@ mod tests {
             .chunk_size("1024")
             .unwrap()
             .ping_count(10)
+            .parallel(4)
+            .bidirectional(true)
+            .latency_probe(true)
+            .interval(0.5)
             .return_json(false)
             .timeout(60)
             .retries(5)
@@ -579,16 +1680,27 @@ mod tests {
             .build()
             .unwrap();
 
-        let _: SocketAddr = client.socket_addr;
+        let _: Vec<SocketAddr> = client.socket_addrs.clone();
 
-        assert_eq!(client.socket_addr.port(), 8080);
-        assert_eq!(client.socket_addr.ip().to_string(), "0.0.0.0");
-        assert_eq!(client.socket_addr.to_string(), "0.0.0.0:8080");
+        assert_eq!(client.target, "0.0.0.0");
+        assert_eq!(client.socket_addrs.len(), 1);
+        assert_eq!(client.socket_addrs[0].port(), 8080);
+        assert_eq!(client.socket_addrs[0].ip().to_string(), "0.0.0.0");
+        assert_eq!(client.socket_addrs[0].to_string(), "0.0.0.0:8080");
         assert_eq!(client.data, Some(100 * 1e6 as u64));
         assert_eq!(client.time, 10);
         assert_eq!(client.chunk_size, 1024);
         assert_eq!(client.ping_count, 10);
-        assert!(!client.return_json);
+        assert_eq!(client.parallel, 4);
+        assert!(client.bidirectional);
+        assert!(client.latency_probe);
+        // Two sets of streams plus the latency probe
+        assert_eq!(client.test_plan().connections(), 9);
+        assert_eq!(client.test_plan().probe_stream(), Some(8));
+        assert_eq!(client.interval, Duration::from_millis(500));
+        assert_eq!(client.test_plan().interval, Duration::from_millis(500));
+        assert_eq!(client.format, OutputFormat::Table);
+        assert!(client.csv_header);
         assert_eq!(client.timeout, Duration::from_secs(60));
         assert_eq!(client.retries, 5);
 
@@ -597,15 +1709,193 @@ mod tests {
         assert!(!client.logger.verbose);
     }
 
+    #[test]
+    fn test_build_client_udp() {
+        let client = Client::builder("127.0.0.1")
+            .udp(true)
+            .bitrate("50Mbps")
+            .datagram_size(1200)
+            .build()
+            .unwrap();
+        assert!(client.udp);
+        assert_eq!(client.bitrate, 50_000_000);
+        assert_eq!(client.datagram_size, 1200);
+        assert!(client.test_plan().includes(protocol::TEST_UDP));
+        assert_eq!(client.test_plan().connections(), 1);
+
+        let client = Client::builder("127.0.0.1").build().unwrap();
+        assert!(!client.udp);
+        assert_eq!(client.bitrate, 1_000_000);
+        assert_eq!(client.datagram_size, config::DEFAULT_DATAGRAM_SIZE);
+        assert!(!client.test_plan().includes(protocol::TEST_UDP));
+
+        // Latency probes are opt-in, adding a connection
+        assert!(!client.test_plan().includes(protocol::TEST_LATENCY_PROBE));
+        let client = Client::builder("127.0.0.1")
+            .latency_probe(true)
+            .build()
+            .unwrap();
+        assert!(client.test_plan().includes(protocol::TEST_LATENCY_PROBE));
+        assert_eq!(client.test_plan().connections(), 2);
+    }
+
+    #[test]
+    fn test_build_client_thresholds() {
+        let client = Client::builder("127.0.0.1")
+            .min_download(Some("900Mbps"))
+            .min_upload(Some("1Gbps"))
+            .max_latency(Some("2ms"))
+            .max_loss(Some("0%"))
+            .build()
+            .unwrap();
+        assert_eq!(client.thresholds.min_download, Some(900_000_000));
+        assert_eq!(client.thresholds.min_upload, Some(1_000_000_000));
+        assert_eq!(
+            client.thresholds.max_latency,
+            Some(Duration::from_millis(2))
+        );
+        assert_eq!(client.thresholds.max_loss, Some(0.0));
+
+        let client = Client::builder("127.0.0.1").build().unwrap();
+        assert!(client.thresholds.is_empty());
+
+        let result = Client::builder("127.0.0.1").max_loss(Some("150%")).build();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid percentage")
+        );
+        let result = Client::builder("127.0.0.1")
+            .min_download(Some("fast"))
+            .build();
+        assert!(result.unwrap_err().to_string().contains("Invalid bitrate"));
+        let result = Client::builder("127.0.0.1").max_latency(Some("2")).build();
+        assert!(result.unwrap_err().to_string().contains("Invalid duration"));
+    }
+
+    #[test]
+    fn test_build_client_history() {
+        let client = Client::builder("127.0.0.1").build().unwrap();
+        assert!(client.history.is_none());
+
+        let client = Client::builder("127.0.0.1")
+            .history_file("/tmp/netbeat/history.jsonl")
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.history.unwrap().path(),
+            std::path::Path::new("/tmp/netbeat/history.jsonl")
+        );
+    }
+
+    #[test]
+    fn test_build_client_from_profile() {
+        let profile = ClientProfile {
+            address: Some("127.0.0.1:6000".to_string()),
+            time: Some(20),
+            chunk_size: Some("128KiB".to_string()),
+            format: Some(OutputFormat::Csv),
+            max_latency: Some("2ms".to_string()),
+            ..Default::default()
+        };
+        let client = ClientBuilder::from_profile("nas", &profile)
+            .unwrap()
+            .time(5)
+            .build()
+            .unwrap();
+        assert_eq!(client.target, "127.0.0.1");
+        assert_eq!(client.socket_addrs[0].to_string(), "127.0.0.1:6000");
+        assert_eq!(client.time, 5); // builder method over profile
+        assert_eq!(client.chunk_size, 128 * 1024);
+        assert_eq!(client.format, OutputFormat::Csv);
+        assert_eq!(
+            client.thresholds.max_latency,
+            Some(Duration::from_millis(2))
+        );
+        assert_eq!(client.ping_count, config::DEFAULT_PING_COUNT);
+
+        let client = ClientBuilder::from_profile("127.0.0.1", &ClientProfile::default())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(client.target, "127.0.0.1");
+        assert_eq!(client.socket_addrs[0].port(), config::DEFAULT_PORT);
+
+        let profile = ClientProfile {
+            chunk_size: Some("1B".to_string()),
+            ..Default::default()
+        };
+        assert!(ClientBuilder::from_profile("127.0.0.1", &profile).is_err());
+    }
+
+    #[test]
+    fn test_build_client_target_forms() {
+        let client = Client::builder("localhost").port(8080).build().unwrap();
+        assert_eq!(client.target, "localhost");
+        assert!(!client.socket_addrs.is_empty());
+        assert!(
+            client
+                .socket_addrs
+                .iter()
+                .all(|addr| addr.ip().is_loopback() && addr.port() == 8080)
+        );
+
+        // Port in target takes precedence
+        let client = Client::builder("[::1]:6000").port(8080).build().unwrap();
+        assert_eq!(client.target, "::1");
+        assert_eq!(client.socket_addrs[0].to_string(), "[::1]:6000");
+
+        let client = Client::builder("127.0.0.1:6000").build().unwrap();
+        assert_eq!(client.socket_addrs[0].to_string(), "127.0.0.1:6000");
+    }
+
+    #[test]
+    fn test_split_target_bytes() {
+        assert_eq!(split_target_bytes(None, 3), vec![None, None, None]);
+        assert_eq!(split_target_bytes(Some(100), 1), vec![Some(100)]);
+        assert_eq!(
+            split_target_bytes(Some(11), 4),
+            vec![Some(3), Some(3), Some(3), Some(2)]
+        );
+    }
+
+    #[test]
+    fn test_upload_report() {
+        let report = upload_report(vec![(
+            StreamResult::new(Duration::from_secs(1), 1_000_000),
+            StreamResult::new(Duration::from_secs(1), 1_200_000),
+        )])
+        .unwrap();
+        assert_eq!(report.bytes, 1_000_000);
+
+        // No streams is an error rather than a panic
+        let Err(error) = upload_report(Vec::new()) else {
+            panic!("Expected an upload report without streams to fail");
+        };
+        assert_eq!(
+            error.to_string(),
+            "Test execution error: Failed to build speed report - Expected at least one stream"
+        );
+    }
+
     #[test]
     fn test_build_client_invalid_input() {
         // Invalid target
-        let result = Client::builder("invalid_target").build();
+        let result = Client::builder("invalid_target.invalid").build();
 
         assert!(result.is_err());
         if let Err(e) = result {
             assert!(matches!(e, NetbeatError::ClientError { .. }));
-            assert!(e.to_string().contains("Invalid IP address"));
+            assert!(e.to_string().contains("Failed to resolve host"));
+        }
+
+        let result = Client::builder("[::1").build();
+
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ClientError { .. }));
+            assert!(e.to_string().contains("Invalid target"));
         }
 
         // Invalid Target Data
@@ -617,6 +1907,49 @@ mod tests {
             assert!(e.to_string().contains("Invalid target data"));
         }
 
+        // Invalid Parallel Streams
+        let result = Client::builder("0.0.0.0").parallel(0).build();
+
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ClientError { .. }));
+            assert!(e.to_string().contains("Invalid parallel stream count"));
+        }
+
+        // Invalid Interval
+        let result = Client::builder("0.0.0.0").interval(0.01).build();
+
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ClientError { .. }));
+            assert!(e.to_string().contains("Invalid interval"));
+        }
+
+        // Invalid UDP settings
+        let result = Client::builder("0.0.0.0").udp(true).parallel(2).build();
+
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ClientError { .. }));
+            assert!(e.to_string().contains("UDP mode runs a single stream"));
+        }
+
+        let result = Client::builder("0.0.0.0").bitrate("fast").build();
+
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ClientError { .. }));
+            assert!(e.to_string().contains("Invalid bitrate"));
+        }
+
+        let result = Client::builder("0.0.0.0").datagram_size(16).build();
+
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ClientError { .. }));
+            assert!(e.to_string().contains("Datagram size of 16 bytes"));
+        }
+
         // Invalid Chunk Size
         let result = Client::builder("0.0.0.0").chunk_size("1MM");
 
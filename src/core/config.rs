@@ -1,6 +1,11 @@
 //! Configuration constants and limits for netbeat
 
-use clap::ValueEnum;
+use super::address;
+use crate::{output::formats::OutputFormat, utils::error::Result};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+};
 
 /// Default server port
 pub const DEFAULT_PORT: u16 = 5050;
@@ -8,42 +13,157 @@ pub const DEFAULT_PORT: u16 = 5050;
 /// Default chunk size for data transfer
 pub const DEFAULT_CHUNK_SIZE: &str = "64KiB";
 
+/// Minimum chunk size for data transfer in bytes (1KiB)
+pub const MIN_CHUNK_SIZE: u64 = 1024;
+
+/// Maximum chunk size for data transfer in bytes (16MiB)
+pub const MAX_CHUNK_SIZE: u64 = 1024 * 1024 * 16;
+
 /// Default test duration in seconds
 pub const DEFAULT_TEST_DURATION: u64 = 10;
 
+/// Maximum test duration in seconds
+pub const MAX_TEST_DURATION: u64 = 3600;
+
+/// Default throughput reporting interval in seconds
+pub const DEFAULT_INTERVAL: f64 = 1.0;
+
+/// Minimum throughput reporting interval in seconds
+pub const MIN_INTERVAL: f64 = 0.1;
+
+/// Maximum throughput reporting interval in seconds
+pub const MAX_INTERVAL: f64 = 60.0;
+
+/// Default number of parallel TCP streams per test direction
+pub const DEFAULT_PARALLEL_STREAMS: u32 = 1;
+
+/// Maximum number of parallel TCP streams per test direction
+pub const MAX_STREAMS: u32 = 128;
+
+/// Default target bitrate for UDP tests
+pub const DEFAULT_UDP_BITRATE: &str = "1Mbps";
+
+/// Default UDP datagram size in bytes, fitting a 1500 byte MTU
+pub const DEFAULT_DATAGRAM_SIZE: u32 = 1400;
+
+/// Minimum UDP datagram size in bytes
+pub const MIN_DATAGRAM_SIZE: u32 = 64;
+
+/// Maximum UDP datagram size in bytes
+pub const MAX_DATAGRAM_SIZE: u32 = 65507;
+
+/// Maximum target bitrate for UDP tests in bits per second (100Gbps)
+pub const MAX_UDP_BITRATE: u64 = 100_000_000_000;
+
 // /// Default target data size (defaults to using test duration)
 // pub const DEFAULT_TARGET_DATA: Option<String> = None;
 
 /// Default ping count
 pub const DEFAULT_PING_COUNT: u32 = 20;
 
+/// Maximum ping count
+pub const MAX_PING_COUNT: u32 = 1000;
+
 /// Default maximum concurrent connections allowed
 pub const DEFAULT_MAX_CONNECTIONS: u32 = 50;
 
+/// Default seconds active connections get to finish when the server shuts down
+pub const DEFAULT_DRAIN_TIMEOUT: u64 = 30;
+
+/// Default seconds the server waits on an idle client within a test phase
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 30;
+
 /// Default connection timeout
 pub const DEFAULT_CONNECTION_TIMEOUT: u64 = 30;
 
 /// Default number of retries
 pub const DEFAULT_MAX_RETRIES: u32 = 3;
 
+/// Default format of the results written to stdout
+pub const DEFAULT_OUTPUT_FORMAT: OutputFormat = OutputFormat::Table;
+
+/// Default address the exporter serves metrics on
+pub const DEFAULT_EXPORTER_ADDR: &str = "127.0.0.1:9925";
+
+/// Default seconds between exporter test rounds
+pub const DEFAULT_EXPORTER_EVERY: u64 = 300;
+
+/// Default seconds between monitor tests of a target
+pub const DEFAULT_MONITOR_EVERY: u64 = 300;
+
+/// Default maximum random seconds added to the time between monitor tests
+pub const DEFAULT_MONITOR_JITTER: u64 = 30;
+
+/// Default maximum seconds between monitor tests of an unreachable target
+pub const DEFAULT_MONITOR_MAX_BACKOFF: u64 = 3600;
+
+/// Name of the result history file, kept in the netbeat data directory
+pub const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+/// Name of the configuration file, kept in the netbeat config directory
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Name of the generated server certificate file, kept in the netbeat config directory
+pub const TLS_CERT_FILE_NAME: &str = "cert.pem";
+
+/// Name of the generated server private key file, kept in the netbeat config directory
+pub const TLS_KEY_FILE_NAME: &str = "key.pem";
+
+/// Name of the file pinning server certificate fingerprints, kept in the netbeat data directory
+pub const KNOWN_HOSTS_FILE_NAME: &str = "known_hosts";
+
+/// Prefix of the environment variables overriding configuration file settings (eg, NETBEAT_PORT)
+pub const ENV_PREFIX: &str = "NETBEAT_";
+
 /// Default server IP
 pub const DEFAULT_BIND_INTERFACE: BindInterface = BindInterface::All;
 
 /// Network interface binding options for the server
-#[derive(Debug, Clone, ValueEnum, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BindInterface {
     /// Listen on all network interfaces (0.0.0.0)
     All,
     /// Listen on localhost only (127.0.0.1)
     Localhost,
+    /// Listen on a specific IPv4 or IPv6 address ('::' listens dual-stack on IPv4 and IPv6)
+    Address(IpAddr),
+    /// Listen on all addresses of a named network interface (eg, eth0)
+    Named(String),
 }
 
 impl BindInterface {
-    /// Convert to the actual IP address string
-    pub fn to_ip(&self) -> &'static str {
+    /// Resolve to the socket addresses to listen on.
+    pub fn to_socket_addrs(&self, port: u16) -> Result<Vec<SocketAddr>> {
         match self {
-            BindInterface::All => "0.0.0.0",
-            BindInterface::Localhost => "127.0.0.1",
+            BindInterface::All => Ok(vec![SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port)]),
+            BindInterface::Localhost => Ok(vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)]),
+            BindInterface::Address(ip) => Ok(vec![SocketAddr::new(*ip, port)]),
+            BindInterface::Named(name) => address::interface_addrs(name, port),
+        }
+    }
+}
+
+impl FromStr for BindInterface {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let bracketed = s.strip_prefix('[').and_then(|s| s.strip_suffix(']'));
+        match s {
+            "all" => Ok(BindInterface::All),
+            "localhost" => Ok(BindInterface::Localhost),
+            _ => match IpAddr::from_str(bracketed.unwrap_or(s)) {
+                Ok(ip) => Ok(BindInterface::Address(ip)),
+                Err(_)
+                    if !s.is_empty()
+                        && s.chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) =>
+                {
+                    Ok(BindInterface::Named(s.to_string()))
+                }
+                Err(_) => Err(format!(
+                    "'{s}' is not 'all', 'localhost', an IP address or a network interface name"
+                )),
+            },
         }
     }
 }
@@ -54,6 +174,8 @@ impl std::fmt::Display for BindInterface {
         match self {
             BindInterface::All => write!(f, "all"),
             BindInterface::Localhost => write!(f, "localhost"),
+            BindInterface::Address(ip) => write!(f, "{ip}"),
+            BindInterface::Named(name) => write!(f, "{name}"),
         }
     }
 }
@@ -64,14 +186,64 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_bind_interface_to_ip() {
-        assert_eq!(BindInterface::All.to_ip(), "0.0.0.0");
-        assert_eq!(BindInterface::Localhost.to_ip(), "127.0.0.1");
+    fn test_bind_interface_to_socket_addrs() {
+        assert_eq!(
+            BindInterface::All.to_socket_addrs(5050).unwrap(),
+            vec!["0.0.0.0:5050".parse().unwrap()]
+        );
+        assert_eq!(
+            BindInterface::Localhost.to_socket_addrs(5050).unwrap(),
+            vec!["127.0.0.1:5050".parse().unwrap()]
+        );
+        assert_eq!(
+            BindInterface::Address("::".parse().unwrap())
+                .to_socket_addrs(5050)
+                .unwrap(),
+            vec!["[::]:5050".parse().unwrap()]
+        );
+        assert!(
+            BindInterface::Named("netbeat-missing0".to_string())
+                .to_socket_addrs(5050)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_bind_interface_from_str() {
+        assert_eq!("all".parse::<BindInterface>().unwrap(), BindInterface::All);
+        assert_eq!(
+            "localhost".parse::<BindInterface>().unwrap(),
+            BindInterface::Localhost
+        );
+        assert_eq!(
+            "10.1.1.11".parse::<BindInterface>().unwrap(),
+            BindInterface::Address("10.1.1.11".parse().unwrap())
+        );
+        assert_eq!(
+            "::".parse::<BindInterface>().unwrap(),
+            BindInterface::Address("::".parse().unwrap())
+        );
+        assert_eq!(
+            "[fe80::1]".parse::<BindInterface>().unwrap(),
+            BindInterface::Address("fe80::1".parse().unwrap())
+        );
+        assert_eq!(
+            "eth0".parse::<BindInterface>().unwrap(),
+            BindInterface::Named("eth0".to_string())
+        );
+        assert!("".parse::<BindInterface>().is_err());
+        assert!("eth 0".parse::<BindInterface>().is_err());
+        assert!("[eth0]".parse::<BindInterface>().is_err());
     }
 
     #[test]
     fn test_bind_interface_display() {
         assert_eq!(BindInterface::All.to_string(), "all");
         assert_eq!(BindInterface::Localhost.to_string(), "localhost");
+        assert_eq!(
+            BindInterface::Address("::1".parse().unwrap()).to_string(),
+            "::1"
+        );
+        assert_eq!(BindInterface::Named("eth0".to_string()).to_string(), "eth0");
     }
 }
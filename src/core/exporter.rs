@@ -0,0 +1,450 @@
+//! Core Exporter functionality for netbeat.
+//!
+//! The exporter tests a list of targets on a schedule and serves the latest results as Prometheus metrics over a
+//! local HTTP `/metrics` endpoint.
+
+use super::{auth::PreSharedKey, client::Client, config};
+use crate::{
+    output::prometheus::{self, TargetMetrics},
+    utils::{
+        error::{NetbeatError, Result},
+        logging::Logger,
+    },
+};
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Time allowed for a scraper to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Core `Exporter` struct for netbeat.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    /// Target servers to test, as given (IP address or hostname, optionally with port).
+    pub targets: Vec<String>,
+    /// Address to serve metrics on.
+    pub listen_addr: SocketAddr,
+    /// Time between the start of consecutive test rounds.
+    pub every: Duration,
+    /// Target port on servers without an explicit port (1-65535).
+    pub port: u16,
+    /// Time limit per test direction in seconds (1-3600).
+    pub time: u64,
+    /// Number of pings to perform for ping test (1-1000)
+    pub ping_count: u32,
+    /// Number of parallel TCP streams per test direction (1-128)
+    pub parallel: u32,
+    /// Connection timeout
+    pub timeout: u64,
+    /// Number of retry attempts on initial connection failure
+    pub retries: u32,
+    /// Pre-shared key to authenticate with, for servers that require one
+    pub psk: Option<PreSharedKey>,
+    /// Encrypt connections with TLS
+    pub tls: bool,
+    /// CA certificates (PEM) to verify server certificates against, instead of pinning them on first use
+    pub tls_ca: Option<PathBuf>,
+    /// Netbeat custom logger
+    pub logger: Logger,
+}
+
+/// Builder for `Exporter` struct.
+#[derive(Debug)]
+pub struct ExporterBuilder {
+    targets: Vec<String>,
+    listen_addr: Option<SocketAddr>,
+    every: Option<u64>,
+    port: Option<u16>,
+    time: Option<u64>,
+    ping_count: Option<u32>,
+    parallel: Option<u32>,
+    timeout: Option<u64>,
+    retries: Option<u32>,
+    psk: Option<String>,
+    tls: Option<bool>,
+    tls_ca: Option<PathBuf>,
+    quiet: Option<bool>,
+    verbose: Option<bool>,
+}
+
+impl Exporter {
+    /// Start building a new `Exporter`.
+    pub fn builder(targets: impl IntoIterator<Item = impl Into<String>>) -> ExporterBuilder {
+        ExporterBuilder::new(targets)
+    }
+
+    /// Serve metrics while testing every target once per round, forever.
+    pub fn run(&self) -> Result<()> {
+        let listener = TcpListener::bind(self.listen_addr).map_err(|e| {
+            NetbeatError::server(format!("Failed to listen on {} - {e}", self.listen_addr))
+        })?;
+        self.logger.info(&format!(
+            "📈 Serving metrics on http://{}/metrics",
+            listener
+                .local_addr()
+                .map_err(NetbeatError::ConnectionError)?
+        ));
+
+        let metrics = Arc::new(Mutex::new(
+            self.targets
+                .iter()
+                .map(TargetMetrics::new)
+                .collect::<Vec<TargetMetrics>>(),
+        ));
+
+        let exporter = self.clone();
+        let round_metrics = Arc::clone(&metrics);
+        thread::spawn(move || {
+            loop {
+                let round_start = Instant::now();
+                exporter.run_round(&round_metrics);
+                thread::sleep(exporter.every.saturating_sub(round_start.elapsed()));
+            }
+        });
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_request(stream, &metrics) {
+                        self.logger
+                            .verbose(&format!("Failed to serve metrics - {e}"));
+                    }
+                }
+                Err(e) => self.logger.error(&format!("Connection failed - {e}")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Test every target once, one after another so tests do not compete for bandwidth.
+    pub fn run_round(&self, metrics: &Mutex<Vec<TargetMetrics>>) {
+        for (i, target) in self.targets.iter().enumerate() {
+            self.logger.info(&format!("🚀 Testing {target}..."));
+            let start = Instant::now();
+            let result = self.client(target).and_then(|client| client.contact());
+            match &result {
+                Ok(report) => self.logger.success(&format!(
+                    "{target} - ⬆️ {:.2} Mbps ⬇️ {:.2} Mbps 🏓 {:.2?}",
+                    report.upload_report.speed / 1e6 * 8.0,
+                    report.download_report.speed / 1e6 * 8.0,
+                    report.ping_report.avg_ping
+                )),
+                Err(e) => self.logger.error(&format!("{target} - {e}")),
+            }
+            metrics.lock().unwrap()[i].record(result, start.elapsed());
+        }
+    }
+
+    /// Client testing a single target with the exporter settings.
+    fn client(&self, target: &str) -> Result<Client> {
+        let mut client = Client::builder(target)
+            .port(self.port)
+            .time(self.time)
+            .ping_count(self.ping_count)
+            .parallel(self.parallel)
+            .timeout(self.timeout)
+            .retries(self.retries)
+            .tls(self.tls)
+            .tls_ca(self.tls_ca.as_ref())
+            .quiet(true)
+            .build()?;
+        client.psk = self.psk.clone();
+        Ok(client)
+    }
+}
+
+/// Answer a single HTTP request, serving the metrics on `/metrics`.
+fn handle_request(mut stream: TcpStream, metrics: &Mutex<Vec<TargetMetrics>>) -> Result<()> {
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .map_err(NetbeatError::ConnectionError)?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .map_err(NetbeatError::ConnectionError)?;
+    // Skip headers, the request line is all that matters
+    let mut header = String::new();
+    while reader
+        .read_line(&mut header)
+        .map_err(NetbeatError::ConnectionError)?
+        > 2
+    {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", prometheus::render(&metrics.lock().unwrap())),
+        (Some("GET"), Some("/")) => (
+            "200 OK",
+            "Netbeat exporter - metrics on /metrics\n".to_string(),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .map_err(NetbeatError::ConnectionError)
+}
+
+impl ExporterBuilder {
+    /// Create a new exporter builder for the given targets.
+    pub fn new(targets: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        ExporterBuilder {
+            targets: targets.into_iter().map(Into::into).collect(),
+            listen_addr: None,
+            every: None,
+            port: None,
+            time: None,
+            ping_count: None,
+            parallel: None,
+            timeout: None,
+            retries: None,
+            psk: None,
+            tls: None,
+            tls_ca: None,
+            quiet: None,
+            verbose: None,
+        }
+    }
+
+    /// Address to serve metrics on
+    pub fn listen_addr(mut self, listen_addr: SocketAddr) -> Self {
+        self.listen_addr = Some(listen_addr);
+        self
+    }
+
+    /// Seconds between the start of consecutive test rounds
+    pub fn every(mut self, every: u64) -> Self {
+        self.every = Some(every);
+        self
+    }
+
+    /// Target port on servers without an explicit port (1-65535)
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Time limit per test direction in seconds (1-3600)
+    pub fn time(mut self, time: u64) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Number of pings to perform for ping test (1-1000)
+    pub fn ping_count(mut self, ping_count: u32) -> Self {
+        self.ping_count = Some(ping_count);
+        self
+    }
+
+    /// Number of parallel TCP streams per test direction (1-128)
+    pub fn parallel(mut self, parallel: u32) -> Self {
+        self.parallel = Some(parallel);
+        self
+    }
+
+    /// Connection timeout in seconds
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Number of retry attempts on initial connection failure
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Pre-shared key to authenticate with, for servers that require one
+    pub fn psk(mut self, psk: Option<impl Into<String>>) -> Self {
+        self.psk = psk.map(|p| p.into());
+        self
+    }
+
+    /// Encrypt connections with TLS, pinning server certificates on first use
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// CA certificates (PEM) to verify server certificates against, instead of pinning them on first use. Enables TLS.
+    pub fn tls_ca(mut self, tls_ca: Option<impl Into<PathBuf>>) -> Self {
+        self.tls_ca = tls_ca.map(|p| p.into());
+        self
+    }
+
+    /// Suppress progress output (errors only)
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = Some(quiet);
+        self
+    }
+
+    /// Enable verbose output
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = Some(verbose);
+        self
+    }
+
+    /// Complete build of `Exporter`, validating the targets and test settings.
+    pub fn build(self) -> Result<Exporter> {
+        if self.targets.is_empty() {
+            return Err(NetbeatError::client(
+                "Exporter requires at least one target".to_string(),
+            ));
+        }
+        let every = self.every.unwrap_or(config::DEFAULT_EXPORTER_EVERY);
+        if every == 0 {
+            return Err(NetbeatError::client(
+                "Invalid test schedule - must be at least 1 second between rounds".to_string(),
+            ));
+        }
+        let exporter = Exporter {
+            targets: self.targets,
+            listen_addr: self.listen_addr.unwrap_or_else(|| {
+                config::DEFAULT_EXPORTER_ADDR
+                    .parse()
+                    .expect("Default exporter address is valid")
+            }),
+            every: Duration::from_secs(every),
+            port: self.port.unwrap_or(config::DEFAULT_PORT),
+            time: self.time.unwrap_or(config::DEFAULT_TEST_DURATION),
+            ping_count: self.ping_count.unwrap_or(config::DEFAULT_PING_COUNT),
+            parallel: self.parallel.unwrap_or(config::DEFAULT_PARALLEL_STREAMS),
+            timeout: self.timeout.unwrap_or(config::DEFAULT_CONNECTION_TIMEOUT),
+            retries: self.retries.unwrap_or(config::DEFAULT_MAX_RETRIES),
+            psk: self.psk.map(PreSharedKey::new).transpose()?,
+            tls: self.tls.unwrap_or(false),
+            tls_ca: self.tls_ca,
+            logger: Logger::new(self.verbose.unwrap_or(false), self.quiet.unwrap_or(false)),
+        };
+        // Surface invalid test settings at startup rather than on every round
+        for target in &exporter.targets {
+            crate::core::address::parse_target(target)?;
+        }
+        Client::builder("127.0.0.1")
+            .time(exporter.time)
+            .parallel(exporter.parallel)
+            .tls_ca(exporter.tls_ca.as_ref())
+            .build()?;
+        Ok(exporter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Server;
+    use std::io::Read;
+
+    #[test]
+    fn test_build_exporter() {
+        let exporter = Exporter::builder(["nas.lan", "10.1.1.12:6000"])
+            .listen_addr("0.0.0.0:9000".parse().unwrap())
+            .every(60)
+            .time(5)
+            .parallel(2)
+            .quiet(true)
+            .build()
+            .unwrap();
+        assert_eq!(exporter.targets, vec!["nas.lan", "10.1.1.12:6000"]);
+        assert_eq!(exporter.listen_addr, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(exporter.every, Duration::from_secs(60));
+        assert_eq!(exporter.time, 5);
+        assert_eq!(exporter.parallel, 2);
+        assert_eq!(exporter.port, config::DEFAULT_PORT);
+
+        let exporter = Exporter::builder(["nas.lan"]).build().unwrap();
+        assert_eq!(
+            exporter.listen_addr,
+            config::DEFAULT_EXPORTER_ADDR.parse().unwrap()
+        );
+        assert_eq!(
+            exporter.every,
+            Duration::from_secs(config::DEFAULT_EXPORTER_EVERY)
+        );
+
+        assert!(Exporter::builder(Vec::<String>::new()).build().is_err());
+        assert!(Exporter::builder(["nas.lan"]).every(0).build().is_err());
+        assert!(Exporter::builder(["nas.lan:0"]).build().is_err());
+        assert!(Exporter::builder(["nas.lan"]).parallel(0).build().is_err());
+    }
+
+    #[test]
+    fn test_run_round() {
+        let server = Server::builder()
+            .interface(config::BindInterface::Localhost)
+            .port(0)
+            .quiet(true)
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let target = server.local_addr().to_string();
+
+        let exporter = Exporter::builder([target.as_str(), "127.0.0.1:1"])
+            .time(1)
+            .ping_count(3)
+            .retries(1)
+            .timeout(1)
+            .quiet(true)
+            .build()
+            .unwrap();
+        let metrics = Mutex::new(exporter.targets.iter().map(TargetMetrics::new).collect());
+        exporter.run_round(&metrics);
+
+        let metrics = metrics.into_inner().unwrap();
+        assert!(metrics[0].report.is_some());
+        assert!(metrics[0].last_error.is_none());
+        assert_eq!(metrics[1].failures, 1);
+        assert!(metrics[1].last_error.is_some());
+
+        let rendered = prometheus::render(&metrics);
+        assert!(rendered.contains(&format!("netbeat_up{{target=\"{target}\"}} 1")));
+        assert!(rendered.contains("netbeat_up{target=\"127.0.0.1:1\"} 0"));
+        assert!(rendered.contains(&format!(
+            "netbeat_throughput_bits_per_second{{target=\"{target}\",direction=\"download\"}}"
+        )));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_handle_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Arc::new(Mutex::new(vec![TargetMetrics::new("nas.lan")]));
+
+        let request = |request: &str| {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(request.as_bytes()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            handle_request(stream, &metrics).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = request("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.ends_with("netbeat_test_failures_total{target=\"nas.lan\"} 0\n"));
+
+        let response = request("GET /favicon.ico HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = request("POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
@@ -0,0 +1,207 @@
+//! Per-interval throughput recording for netbeat transfers.
+
+use crate::utils::error::{NetbeatError, Result};
+use std::time::{Duration, Instant};
+
+/// Encoded length of a single interval sample
+const SAMPLE_LEN: usize = 24;
+
+/// Bytes received during one reporting interval of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntervalSample {
+    /// Start of the interval, relative to the start of the transfer
+    pub start: Duration,
+    /// End of the interval, relative to the start of the transfer
+    pub end: Duration,
+    /// Payload bytes received during the interval
+    pub bytes: u64,
+}
+
+impl IntervalSample {
+    /// Speed over the interval in bytes per second
+    pub fn speed(&self) -> f64 {
+        let seconds = (self.end - self.start).as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Records received bytes into fixed length intervals while a transfer runs.
+#[derive(Debug)]
+pub struct IntervalRecorder {
+    interval: Duration,
+    start_time: Instant,
+    current: IntervalSample,
+    samples: Vec<IntervalSample>,
+}
+
+impl IntervalRecorder {
+    /// Start recording intervals of the given length from now.
+    pub fn new(interval: Duration) -> Self {
+        IntervalRecorder {
+            interval,
+            start_time: Instant::now(),
+            current: IntervalSample {
+                start: Duration::ZERO,
+                end: interval,
+                bytes: 0,
+            },
+            samples: Vec::new(),
+        }
+    }
+
+    /// Record bytes received now, closing any intervals that have ended since the last call.
+    pub fn record(&mut self, bytes: u64) {
+        self.close_until(self.start_time.elapsed());
+        self.current.bytes += bytes;
+    }
+
+    /// Finish recording at the end of the transfer, returning the samples.
+    ///
+    /// A trailing partial interval shorter than half an interval is folded into the previous sample.
+    pub fn finish(mut self, duration: Duration) -> Vec<IntervalSample> {
+        self.close_until(duration);
+        let partial = IntervalSample {
+            end: duration,
+            ..self.current
+        };
+        match self.samples.last_mut() {
+            Some(last) if partial.end - partial.start < self.interval / 2 => {
+                last.end = partial.end;
+                last.bytes += partial.bytes;
+            }
+            _ if partial.end > partial.start => self.samples.push(partial),
+            _ => {}
+        }
+        self.samples
+    }
+
+    /// Close intervals that ended at or before `elapsed`, including empty ones during stalls.
+    fn close_until(&mut self, elapsed: Duration) {
+        while elapsed >= self.current.end && !self.interval.is_zero() {
+            self.samples.push(self.current);
+            self.current = IntervalSample {
+                start: self.current.end,
+                end: self.current.end + self.interval,
+                bytes: 0,
+            };
+        }
+    }
+}
+
+/// Combine the samples of parallel streams, summing bytes per interval.
+pub fn merge(streams: &[Vec<IntervalSample>]) -> Vec<IntervalSample> {
+    let len = streams.iter().map(Vec::len).max().unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            let samples: Vec<&IntervalSample> =
+                streams.iter().filter_map(|stream| stream.get(i)).collect();
+            IntervalSample {
+                start: samples.iter().map(|s| s.start).min().unwrap_or_default(),
+                end: samples.iter().map(|s| s.end).max().unwrap_or_default(),
+                bytes: samples.iter().map(|s| s.bytes).sum(),
+            }
+        })
+        .collect()
+}
+
+/// Encode samples: count followed by start and end in nanoseconds and bytes of each sample
+pub fn encode(samples: &[IntervalSample]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(4 + samples.len() * SAMPLE_LEN);
+    payload.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    for sample in samples {
+        payload.extend_from_slice(&(sample.start.as_nanos() as u64).to_be_bytes());
+        payload.extend_from_slice(&(sample.end.as_nanos() as u64).to_be_bytes());
+        payload.extend_from_slice(&sample.bytes.to_be_bytes());
+    }
+    payload
+}
+
+/// Decode samples written by [`encode`]
+pub fn decode(payload: &[u8]) -> Result<Vec<IntervalSample>> {
+    let invalid = || NetbeatError::protocol("Malformed interval samples".to_string());
+    let (count, rest) = payload.split_first_chunk::<4>().ok_or_else(invalid)?;
+    let count = u32::from_be_bytes(*count) as usize;
+    if rest.len() != count * SAMPLE_LEN {
+        return Err(invalid());
+    }
+    let u64_at = |chunk: &[u8], i: usize| u64::from_be_bytes(chunk[i..i + 8].try_into().unwrap());
+    Ok(rest
+        .chunks_exact(SAMPLE_LEN)
+        .map(|chunk| IntervalSample {
+            start: Duration::from_nanos(u64_at(chunk, 0)),
+            end: Duration::from_nanos(u64_at(chunk, 8)),
+            bytes: u64_at(chunk, 16),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(start_ms: u64, end_ms: u64, bytes: u64) -> IntervalSample {
+        IntervalSample {
+            start: Duration::from_millis(start_ms),
+            end: Duration::from_millis(end_ms),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_interval_recorder() {
+        let mut recorder = IntervalRecorder::new(Duration::from_millis(1000));
+        recorder.record(100);
+        recorder.close_until(Duration::from_millis(2500));
+        recorder.current.bytes += 50;
+        let samples = recorder.finish(Duration::from_millis(3200));
+
+        // Stalled second interval is kept as an empty sample, short tail folded into the last one
+        assert_eq!(
+            samples,
+            vec![
+                sample(0, 1000, 100),
+                sample(1000, 2000, 0),
+                sample(2000, 3200, 50)
+            ]
+        );
+
+        let recorder = IntervalRecorder::new(Duration::from_millis(1000));
+        assert_eq!(
+            recorder.finish(Duration::from_millis(600)),
+            vec![sample(0, 600, 0)]
+        );
+    }
+
+    #[test]
+    fn test_speed() {
+        assert_eq!(sample(0, 1000, 1_000_000).speed(), 1e6);
+        assert_eq!(sample(500, 1000, 1_000_000).speed(), 2e6);
+        assert_eq!(sample(1000, 1000, 10).speed(), 0.0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let merged = merge(&[
+            vec![sample(0, 1000, 10), sample(1000, 2000, 20)],
+            vec![sample(0, 1010, 5)],
+        ]);
+        assert_eq!(merged, vec![sample(0, 1010, 15), sample(1000, 2000, 20)]);
+        assert!(merge(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let samples = vec![sample(0, 1000, 10), sample(1000, 1800, 20)];
+        assert_eq!(decode(&encode(&samples)).unwrap(), samples);
+        assert!(decode(&encode(&[])).unwrap().is_empty());
+
+        let mut payload = encode(&samples);
+        payload.pop();
+        assert!(decode(&payload).is_err());
+        assert!(decode(&[0, 0]).is_err());
+    }
+}
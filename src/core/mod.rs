@@ -1,19 +1,48 @@
 //! Core module for netbeat.
 //!
-//! This module contains the core components of netbeat, including the client, configuration, protocol, and server modules.
+//! This module contains the core components of netbeat, including the address, auth, client, configuration, exporter, intervals, monitor, profile, protocol, server, thresholds, tls, and udp modules.
+//!
+//! The **address** module provides target parsing, name resolution, and connection utilities.
+//!
+//! The **auth** module provides pre-shared key authentication of clients through an HMAC challenge-response.
 //!
 //! The **client** module provides the functionality for connecting to a netbeat server and sending/receiving data.
 //!
 //! The **config** module provides the default parametrization for client and server modules.
 //!
+//! The **exporter** module provides scheduled tests against several targets, served as Prometheus metrics.
+//!
+//! The **intervals** module records per-interval throughput samples during transfers.
+//!
+//! The **monitor** module provides scheduled tests against several targets, writing every result and error to a sink.
+//!
+//! The **profile** module provides client and server settings loaded from the configuration file and environment.
+//!
 //! The **protocol** module provides the custom protocol for network communication over netbeat client and server.
 //!
 //! The **server** module provides the functionality for running a netbeat server and handling incoming connections.
+//!
+//! The **thresholds** module provides the pass/fail checks run against a finished netbeat report.
+//!
+//! The **tls** module provides the TLS transport, server certificates and trust-on-first-use pinning for clients.
+//!
+//! The **udp** module provides the datagram pacing and loss/jitter accounting for UDP tests.
 
+pub mod address;
+pub mod auth;
 pub mod client;
 pub mod config;
+pub mod exporter;
+pub mod intervals;
+pub mod monitor;
+pub mod profile;
 pub mod protocol;
 pub mod server;
+pub mod thresholds;
+pub mod tls;
+pub mod udp;
 
 pub use client::Client;
-pub use server::Server;
+pub use exporter::Exporter;
+pub use monitor::Monitor;
+pub use server::{Server, ServerHandle};
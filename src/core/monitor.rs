@@ -0,0 +1,580 @@
+//! Core Monitor functionality for netbeat.
+//!
+//! The monitor tests a list of targets on a schedule, for as long as it runs. Each result or error is written to a sink
+//! as a line of json, and each report is kept in the history. A target that fails is tested again after a backoff that
+//! doubles with every consecutive failure.
+
+use super::{auth::PreSharedKey, client::Client, config};
+use crate::{
+    output::{events::Event, history::History},
+    utils::{
+        error::{NetbeatError, Result},
+        logging::Logger,
+    },
+};
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Interval between checks for a stop request while waiting for the next test
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Core `Monitor` struct for netbeat.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    /// Target servers to test, as given (IP address or hostname, optionally with port).
+    pub targets: Vec<String>,
+    /// Time between tests of a target.
+    pub every: Duration,
+    /// Maximum random time added to the time between tests, spreading tests out.
+    pub jitter: Duration,
+    /// Maximum time between tests of a target that keeps failing.
+    pub max_backoff: Duration,
+    /// File results and errors are appended to, instead of stdout.
+    pub output: Option<PathBuf>,
+    /// History the reports are kept in.
+    pub history: Option<History>,
+    /// Target port on servers without an explicit port (1-65535).
+    pub port: u16,
+    /// Time limit per test direction in seconds (1-3600).
+    pub time: u64,
+    /// Number of pings to perform for ping test (1-1000)
+    pub ping_count: u32,
+    /// Number of parallel TCP streams per test direction (1-128)
+    pub parallel: u32,
+    /// Connection timeout
+    pub timeout: u64,
+    /// Number of retry attempts on initial connection failure
+    pub retries: u32,
+    /// Pre-shared key to authenticate with, for servers that require one
+    pub psk: Option<PreSharedKey>,
+    /// Encrypt connections with TLS
+    pub tls: bool,
+    /// CA certificates (PEM) to verify server certificates against, instead of pinning them on first use
+    pub tls_ca: Option<PathBuf>,
+    /// Netbeat custom logger
+    pub logger: Logger,
+}
+
+/// Schedule of a single target.
+#[derive(Debug)]
+struct TargetState {
+    target: String,
+    next_test: Instant,
+    failures: u32,
+}
+
+/// Builder for `Monitor` struct.
+#[derive(Debug)]
+pub struct MonitorBuilder {
+    targets: Vec<String>,
+    every: Option<u64>,
+    jitter: Option<u64>,
+    max_backoff: Option<u64>,
+    output: Option<PathBuf>,
+    history: Option<bool>,
+    history_file: Option<PathBuf>,
+    port: Option<u16>,
+    time: Option<u64>,
+    ping_count: Option<u32>,
+    parallel: Option<u32>,
+    timeout: Option<u64>,
+    retries: Option<u32>,
+    psk: Option<String>,
+    tls: Option<bool>,
+    tls_ca: Option<PathBuf>,
+    quiet: Option<bool>,
+    verbose: Option<bool>,
+}
+
+impl Monitor {
+    /// Start building a new `Monitor`.
+    pub fn builder(targets: impl IntoIterator<Item = impl Into<String>>) -> MonitorBuilder {
+        MonitorBuilder::new(targets)
+    }
+
+    /// Test the targets on schedule until `stop` is set, then return once the current test completes.
+    pub fn run(&self, stop: &AtomicBool) -> Result<()> {
+        let mut sink: Box<dyn Write> = match &self.output {
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| {
+                        NetbeatError::client(format!("Failed to open {} - {e}", path.display()))
+                    })?,
+            ),
+            None => Box::new(io::stdout()),
+        };
+
+        let start = Instant::now();
+        let mut states: Vec<TargetState> = self
+            .targets
+            .iter()
+            .map(|target| TargetState {
+                target: target.clone(),
+                next_test: start,
+                failures: 0,
+            })
+            .collect();
+        self.logger.info(&format!(
+            "🩺 Monitoring {} target(s) every {}",
+            states.len(),
+            humantime::format_duration(self.every)
+        ));
+
+        loop {
+            let state = states
+                .iter_mut()
+                .min_by_key(|state| state.next_test)
+                .expect("Monitor has at least one target");
+            if !wait_until(state.next_test, stop) {
+                break;
+            }
+            self.test_target(state, &mut sink);
+        }
+        self.logger.info("👋 Monitor stopped");
+        Ok(())
+    }
+
+    /// Test a single target, recording the result and scheduling its next test.
+    fn test_target(&self, state: &mut TargetState, sink: &mut dyn Write) {
+        self.logger.verbose(&format!("Testing {}", state.target));
+        let result = self
+            .client(&state.target)
+            .and_then(|client| client.contact());
+        let line = match result {
+            Ok(report) => {
+                state.failures = 0;
+                state.next_test = Instant::now() + self.every + self.random_jitter();
+                self.logger.success(&format!(
+                    "{} - ⬆️ {:.2} Mbps ⬇️ {:.2} Mbps 🏓 {:.2?}",
+                    state.target,
+                    report.upload_report.speed / 1e6 * 8.0,
+                    report.download_report.speed / 1e6 * 8.0,
+                    report.ping_report.avg_ping
+                ));
+                if let Some(history) = &self.history
+                    && let Err(e) = history.append(&report)
+                {
+                    self.logger.error(&format!("Failed to save report - {e}"));
+                }
+                Event::Report { report: &report }.to_json()
+            }
+            Err(e) => {
+                state.failures += 1;
+                let retry_in = self.backoff(state.failures) + self.random_jitter();
+                state.next_test = Instant::now() + retry_in;
+                self.logger.error(&format!(
+                    "{} - {e} (retrying in {})",
+                    state.target,
+                    humantime::format_duration(Duration::from_secs(retry_in.as_secs()))
+                ));
+                Event::Error {
+                    target: &state.target,
+                    timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                    error: e.to_string(),
+                    consecutive_failures: state.failures,
+                    retry_in_ns: retry_in,
+                }
+                .to_json()
+            }
+        };
+        if let Err(e) = writeln!(sink, "{line}").and_then(|_| sink.flush()) {
+            self.logger.error(&format!("Failed to write result - {e}"));
+        }
+    }
+
+    /// Time before testing a target again after consecutive failures, doubling up to the maximum backoff.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let backoff = self
+            .every
+            .saturating_mul(2u32.saturating_pow(failures.min(16)));
+        backoff.min(self.max_backoff.max(self.every))
+    }
+
+    /// Random time of up to the jitter.
+    fn random_jitter(&self) -> Duration {
+        self.jitter.mul_f64(rand::random::<f64>())
+    }
+
+    /// Client testing a single target with the monitor settings.
+    fn client(&self, target: &str) -> Result<Client> {
+        let mut client = Client::builder(target)
+            .port(self.port)
+            .time(self.time)
+            .ping_count(self.ping_count)
+            .parallel(self.parallel)
+            .timeout(self.timeout)
+            .retries(self.retries)
+            .tls(self.tls)
+            .tls_ca(self.tls_ca.as_ref())
+            .quiet(true)
+            .build()?;
+        client.psk = self.psk.clone();
+        Ok(client)
+    }
+}
+
+/// Sleep until the deadline, returning early with `false` when `stop` is set.
+fn wait_until(deadline: Instant, stop: &AtomicBool) -> bool {
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            return false;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+        thread::sleep(remaining.min(STOP_POLL_INTERVAL));
+    }
+}
+
+impl MonitorBuilder {
+    /// Create a new monitor builder for the given targets.
+    pub fn new(targets: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        MonitorBuilder {
+            targets: targets.into_iter().map(Into::into).collect(),
+            every: None,
+            jitter: None,
+            max_backoff: None,
+            output: None,
+            history: None,
+            history_file: None,
+            port: None,
+            time: None,
+            ping_count: None,
+            parallel: None,
+            timeout: None,
+            retries: None,
+            psk: None,
+            tls: None,
+            tls_ca: None,
+            quiet: None,
+            verbose: None,
+        }
+    }
+
+    /// Seconds between tests of a target
+    pub fn every(mut self, every: u64) -> Self {
+        self.every = Some(every);
+        self
+    }
+
+    /// Maximum random seconds added to the time between tests
+    pub fn jitter(mut self, jitter: u64) -> Self {
+        self.jitter = Some(jitter);
+        self
+    }
+
+    /// Maximum seconds between tests of a target that keeps failing
+    pub fn max_backoff(mut self, max_backoff: u64) -> Self {
+        self.max_backoff = Some(max_backoff);
+        self
+    }
+
+    /// File to append results and errors to, instead of stdout
+    pub fn output(mut self, output: Option<impl Into<PathBuf>>) -> Self {
+        self.output = output.map(|o| o.into());
+        self
+    }
+
+    /// Keep the reports in the history of the netbeat data directory
+    pub fn history(mut self, history: bool) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Keep the reports in the given history file instead of the netbeat data directory
+    pub fn history_file(mut self, history_file: impl Into<PathBuf>) -> Self {
+        self.history_file = Some(history_file.into());
+        self
+    }
+
+    /// Target port on servers without an explicit port (1-65535)
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Time limit per test direction in seconds (1-3600)
+    pub fn time(mut self, time: u64) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Number of pings to perform for ping test (1-1000)
+    pub fn ping_count(mut self, ping_count: u32) -> Self {
+        self.ping_count = Some(ping_count);
+        self
+    }
+
+    /// Number of parallel TCP streams per test direction (1-128)
+    pub fn parallel(mut self, parallel: u32) -> Self {
+        self.parallel = Some(parallel);
+        self
+    }
+
+    /// Connection timeout in seconds
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Number of retry attempts on initial connection failure
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Pre-shared key to authenticate with, for servers that require one
+    pub fn psk(mut self, psk: Option<impl Into<String>>) -> Self {
+        self.psk = psk.map(|p| p.into());
+        self
+    }
+
+    /// Encrypt connections with TLS, pinning server certificates on first use
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// CA certificates (PEM) to verify server certificates against, instead of pinning them on first use. Enables TLS.
+    pub fn tls_ca(mut self, tls_ca: Option<impl Into<PathBuf>>) -> Self {
+        self.tls_ca = tls_ca.map(|p| p.into());
+        self
+    }
+
+    /// Suppress progress output (errors only)
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = Some(quiet);
+        self
+    }
+
+    /// Enable verbose output
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = Some(verbose);
+        self
+    }
+
+    /// Complete build of `Monitor`, validating the targets and test settings.
+    pub fn build(self) -> Result<Monitor> {
+        if self.targets.is_empty() {
+            return Err(NetbeatError::client(
+                "Monitor requires at least one target".to_string(),
+            ));
+        }
+        let every = self.every.unwrap_or(config::DEFAULT_MONITOR_EVERY);
+        if every == 0 {
+            return Err(NetbeatError::client(
+                "Invalid test schedule - must be at least 1 second between tests".to_string(),
+            ));
+        }
+        let monitor = Monitor {
+            targets: self.targets,
+            every: Duration::from_secs(every),
+            jitter: Duration::from_secs(self.jitter.unwrap_or(config::DEFAULT_MONITOR_JITTER)),
+            max_backoff: Duration::from_secs(
+                self.max_backoff
+                    .unwrap_or(config::DEFAULT_MONITOR_MAX_BACKOFF),
+            ),
+            output: self.output,
+            history: match (self.history_file, self.history.unwrap_or(false)) {
+                (Some(path), _) => Some(History::new(path)),
+                (None, true) => Some(History::open_default()?),
+                (None, false) => None,
+            },
+            port: self.port.unwrap_or(config::DEFAULT_PORT),
+            time: self.time.unwrap_or(config::DEFAULT_TEST_DURATION),
+            ping_count: self.ping_count.unwrap_or(config::DEFAULT_PING_COUNT),
+            parallel: self.parallel.unwrap_or(config::DEFAULT_PARALLEL_STREAMS),
+            timeout: self.timeout.unwrap_or(config::DEFAULT_CONNECTION_TIMEOUT),
+            retries: self.retries.unwrap_or(config::DEFAULT_MAX_RETRIES),
+            psk: self.psk.map(PreSharedKey::new).transpose()?,
+            tls: self.tls.unwrap_or(false),
+            tls_ca: self.tls_ca,
+            logger: Logger::new(self.verbose.unwrap_or(false), self.quiet.unwrap_or(false)),
+        };
+        // Surface invalid test settings at startup rather than on every test
+        for target in &monitor.targets {
+            crate::core::address::parse_target(target)?;
+        }
+        Client::builder("127.0.0.1")
+            .time(monitor.time)
+            .parallel(monitor.parallel)
+            .tls_ca(monitor.tls_ca.as_ref())
+            .build()?;
+        Ok(monitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Server;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_build_monitor() {
+        let monitor = Monitor::builder(["nas.lan", "10.1.1.12:6000"])
+            .every(60)
+            .jitter(5)
+            .max_backoff(600)
+            .output(Some("/tmp/netbeat.ndjson"))
+            .history_file("/tmp/netbeat/history.jsonl")
+            .quiet(true)
+            .build()
+            .unwrap();
+        assert_eq!(monitor.targets, vec!["nas.lan", "10.1.1.12:6000"]);
+        assert_eq!(monitor.every, Duration::from_secs(60));
+        assert_eq!(monitor.jitter, Duration::from_secs(5));
+        assert_eq!(monitor.max_backoff, Duration::from_secs(600));
+        assert_eq!(monitor.output, Some(PathBuf::from("/tmp/netbeat.ndjson")));
+        assert!(monitor.history.is_some());
+
+        let monitor = Monitor::builder(["nas.lan"]).build().unwrap();
+        assert_eq!(
+            monitor.every,
+            Duration::from_secs(config::DEFAULT_MONITOR_EVERY)
+        );
+        assert!(monitor.output.is_none());
+        assert!(monitor.history.is_none());
+
+        assert!(Monitor::builder(Vec::<String>::new()).build().is_err());
+        assert!(Monitor::builder(["nas.lan"]).every(0).build().is_err());
+        assert!(Monitor::builder(["nas.lan:0"]).build().is_err());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let monitor = Monitor::builder(["nas.lan"])
+            .every(60)
+            .max_backoff(600)
+            .build()
+            .unwrap();
+        assert_eq!(monitor.backoff(1), Duration::from_secs(120));
+        assert_eq!(monitor.backoff(2), Duration::from_secs(240));
+        assert_eq!(monitor.backoff(3), Duration::from_secs(480));
+        assert_eq!(monitor.backoff(4), Duration::from_secs(600));
+        assert_eq!(monitor.backoff(u32::MAX), Duration::from_secs(600));
+
+        // The backoff never tests a target more often than the schedule
+        let monitor = Monitor::builder(["nas.lan"])
+            .every(600)
+            .max_backoff(60)
+            .build()
+            .unwrap();
+        assert_eq!(monitor.backoff(1), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_test_target() {
+        let server = Server::builder()
+            .interface(config::BindInterface::Localhost)
+            .port(0)
+            .quiet(true)
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let target = server.local_addr().to_string();
+
+        let history = std::env::temp_dir()
+            .join(format!("netbeat-test-{}-monitor", std::process::id()))
+            .join(config::HISTORY_FILE_NAME);
+        let monitor = Monitor::builder([target.as_str(), "127.0.0.1:1"])
+            .every(60)
+            .jitter(0)
+            .time(1)
+            .ping_count(3)
+            .retries(1)
+            .timeout(1)
+            .history_file(&history)
+            .quiet(true)
+            .build()
+            .unwrap();
+
+        let mut sink = Vec::new();
+        let mut state = TargetState {
+            target: "127.0.0.1:1".to_string(),
+            next_test: Instant::now(),
+            failures: 1,
+        };
+        monitor.test_target(&mut state, &mut sink);
+        assert_eq!(state.failures, 2);
+        assert!(state.next_test > Instant::now() + Duration::from_secs(230));
+
+        let mut state = TargetState {
+            target: target.clone(),
+            next_test: Instant::now(),
+            failures: 3,
+        };
+        monitor.test_target(&mut state, &mut sink);
+        assert_eq!(state.failures, 0);
+        assert!(state.next_test <= Instant::now() + Duration::from_secs(60));
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(sink)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["event"], "error");
+        assert_eq!(lines[0]["target"], "127.0.0.1:1");
+        assert_eq!(lines[0]["consecutive_failures"], 2);
+        assert_eq!(lines[0]["retry_in_ns"], 240_000_000_000u64);
+        assert_eq!(lines[1]["event"], "report");
+        assert_eq!(lines[1]["report"]["target"], "127.0.0.1");
+
+        let reports = monitor
+            .history
+            .as_ref()
+            .unwrap()
+            .load(&Default::default())
+            .unwrap();
+        assert_eq!(reports.len(), 1);
+        std::fs::remove_dir_all(history.parent().unwrap()).unwrap();
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_run_stops() {
+        let monitor = Monitor::builder(["127.0.0.1:1"])
+            .every(3600)
+            .jitter(0)
+            .retries(1)
+            .timeout(1)
+            .output(Some(std::env::temp_dir().join(format!(
+                "netbeat-test-{}-monitor.ndjson",
+                std::process::id()
+            ))))
+            .quiet(true)
+            .build()
+            .unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (monitor, stop) = (monitor.clone(), Arc::clone(&stop));
+            thread::spawn(move || monitor.run(&stop))
+        };
+        thread::sleep(Duration::from_millis(500));
+        stop.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+        handle.join().unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let output = monitor.output.unwrap();
+        let written = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(written.lines().count(), 1);
+        assert!(written.contains("\"event\":\"error\""));
+        std::fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,728 @@
+//! Client and server settings loaded from the netbeat configuration file and environment.
+//!
+//! The TOML configuration file holds defaults for every run under `[client]`, server settings under `[server]`
+//! and named target profiles under `[targets.<name>]`:
+//!
+//! ```toml
+//! [client]
+//! time = 20
+//! chunk_size = "128KiB"
+//!
+//! [server]
+//! interfaces = ["localhost", "eth0"]
+//! max_connections = 10
+//!
+//! [targets.nas]
+//! address = "nas.lan"
+//! port = 6000
+//! parallel = 4
+//! ```
+//!
+//! Each setting can also be given as a `NETBEAT_`-prefixed environment variable (eg, `NETBEAT_PORT`). Profiles are
+//! layered with [`ClientProfile::or`] and [`ServerProfile::or`], and the builders fall back to the `config::DEFAULT_*`
+//! values for settings left unset.
+
+use super::{
+    address,
+    config::{self, BindInterface},
+};
+use crate::{
+    output::formats::OutputFormat,
+    utils::{
+        error::{NetbeatError, Result},
+        units,
+    },
+};
+use byte_unit::Byte;
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, de};
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Contents of a netbeat configuration file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Settings for every client run, under `[client]`
+    pub client: ClientProfile,
+    /// Settings for the server, under `[server]`
+    pub server: ServerProfile,
+    /// Named target profiles, under `[targets.<name>]`
+    pub targets: BTreeMap<String, ClientProfile>,
+}
+
+/// Client settings, each left unset to fall back on the next layer.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientProfile {
+    /// Target server IP address or hostname, optionally with port (target profiles only)
+    pub address: Option<String>,
+    /// Target port on server (1-65535)
+    pub port: Option<u16>,
+    /// Target size of data to be uploaded/downloaded including units (eg, 10MB, 1GB)
+    pub data: Option<String>,
+    /// Time limit per test direction in seconds (1-3600)
+    pub time: Option<u64>,
+    /// Buffer size for read/write operations (eg, 32KiB, 64KiB, 128KiB)
+    pub chunk_size: Option<String>,
+    /// Number of pings to perform for ping test (1-1000)
+    pub ping_count: Option<u32>,
+    /// Number of parallel TCP streams per test direction (1-128)
+    pub parallel: Option<u32>,
+    /// Run upload and download tests at the same time
+    pub bidirectional: Option<bool>,
+    /// Run upload and download tests over UDP at a fixed bitrate
+    pub udp: Option<bool>,
+    /// Target bitrate for UDP tests including units (eg, 1Mbps, 100Mbps)
+    pub bitrate: Option<String>,
+    /// UDP datagram size in bytes (64-65507)
+    pub datagram_size: Option<u32>,
+    /// Probe latency during upload and download tests
+    pub latency_probe: Option<bool>,
+    /// Throughput reporting interval in seconds (0.1-60)
+    pub interval: Option<f64>,
+    /// Format of the results written to stdout
+    pub format: Option<OutputFormat>,
+    /// Minimum download speed including units (eg, 900Mbps)
+    pub min_download: Option<String>,
+    /// Minimum upload speed including units (eg, 900Mbps)
+    pub min_upload: Option<String>,
+    /// Maximum average idle ping including units (eg, 2ms)
+    pub max_latency: Option<String>,
+    /// Maximum ping loss, and datagram loss in UDP mode (eg, 0.5%)
+    pub max_loss: Option<String>,
+    /// Keep the finished report in the local history
+    pub history: Option<bool>,
+    /// Connection timeout in seconds
+    pub timeout: Option<u64>,
+    /// Number of retry attempts on initial connection failure
+    pub retries: Option<u32>,
+    /// Pre-shared key to authenticate with, for servers that require one
+    pub psk: Option<String>,
+    /// Encrypt the connection with TLS
+    pub tls: Option<bool>,
+    /// CA certificates (PEM) to verify the server certificate against, instead of pinning it on first use
+    pub tls_ca: Option<PathBuf>,
+}
+
+/// Server settings, each left unset to fall back on the next layer.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerProfile {
+    /// Network interfaces to bind server to (eg, ["all"], ["localhost", "eth0"])
+    #[serde(default, deserialize_with = "deserialize_interfaces")]
+    pub interfaces: Option<Vec<BindInterface>>,
+    /// Port to listen on (1-65535)
+    pub port: Option<u16>,
+    /// Buffer size for data transfer (eg, 32KiB, 64KiB, 128KiB)
+    pub chunk_size: Option<String>,
+    /// Maximum concurrent connections
+    pub max_connections: Option<u32>,
+    /// Pre-shared key clients must authenticate with
+    pub psk: Option<String>,
+    /// Encrypt connections with TLS
+    pub tls: Option<bool>,
+    /// Certificate chain presented to clients (PEM), instead of a generated self-signed certificate
+    pub tls_cert: Option<PathBuf>,
+    /// Private key of the certificate presented to clients (PEM)
+    pub tls_key: Option<PathBuf>,
+    /// Seconds active connections get to finish on shutdown before they are closed
+    pub drain_timeout: Option<u64>,
+    /// Maximum seconds a client session may run
+    pub max_session_duration: Option<u64>,
+    /// Maximum data a client session may transfer in both directions together (eg, 500MB, 2GB)
+    pub max_session_bytes: Option<String>,
+    /// Maximum data a client session may transfer in each direction (eg, 500MB, 1GB)
+    pub max_direction_bytes: Option<String>,
+    /// Seconds to wait on an idle client within a test phase
+    pub idle_timeout: Option<u64>,
+}
+
+impl ConfigFile {
+    /// Path of the configuration file in the netbeat config directory (eg, ~/.config/netbeat/config.toml).
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("netbeat").join(config::CONFIG_FILE_NAME))
+    }
+
+    /// Load the configuration file at the given path, or the one in the netbeat config directory if it exists.
+    pub fn open(path: Option<&Path>) -> Result<ConfigFile> {
+        match path {
+            Some(path) => ConfigFile::load(path),
+            None => match ConfigFile::default_path() {
+                Some(path) if path.exists() => ConfigFile::load(&path),
+                _ => Ok(ConfigFile::default()),
+            },
+        }
+    }
+
+    /// Load and validate the configuration file at the given path.
+    pub fn load(path: impl AsRef<Path>) -> Result<ConfigFile> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| {
+            NetbeatError::config(format!("Failed to read {} - {e}", path.display()))
+        })?;
+        ConfigFile::parse(&contents, &path.display().to_string())
+    }
+
+    /// Parse and validate configuration file contents, naming the source in errors.
+    pub fn parse(contents: &str, source: &str) -> Result<ConfigFile> {
+        let config: ConfigFile = toml::from_str(contents)
+            .map_err(|e| NetbeatError::config(format!("Failed to parse {source} - {e}")))?;
+
+        if config.client.address.is_some() {
+            return Err(NetbeatError::config(format!(
+                "Invalid client.address in {source} - an address is only allowed in [targets.<name>] profiles"
+            )));
+        }
+        config
+            .client
+            .validate(|key| format!("client.{key} in {source}"))?;
+        config
+            .server
+            .validate(|key| format!("server.{key} in {source}"))?;
+        for (name, profile) in &config.targets {
+            profile.validate(|key| format!("targets.{name}.{key} in {source}"))?;
+        }
+        Ok(config)
+    }
+
+    /// Client settings for a target, layering its `[targets.<name>]` profile, if any, over `[client]`.
+    pub fn client_profile(&self, target: &str) -> ClientProfile {
+        match self.targets.get(target) {
+            Some(profile) => profile.clone().or(self.client.clone()),
+            None => self.client.clone(),
+        }
+    }
+
+    /// Server settings from `[server]`.
+    pub fn server_profile(&self) -> ServerProfile {
+        self.server.clone()
+    }
+}
+
+impl ClientProfile {
+    /// Client settings from `NETBEAT_`-prefixed environment variables (eg, NETBEAT_TIME, NETBEAT_CHUNK_SIZE).
+    pub fn from_env() -> Result<ClientProfile> {
+        ClientProfile::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Client settings from variables looked up by their environment variable name.
+    fn from_vars(vars: impl Fn(&str) -> Option<String>) -> Result<ClientProfile> {
+        let profile = ClientProfile {
+            address: None,
+            port: var(&vars, "port", str::parse)?,
+            data: var(&vars, "data", parse_string)?,
+            time: var(&vars, "time", str::parse)?,
+            chunk_size: var(&vars, "chunk_size", parse_string)?,
+            ping_count: var(&vars, "ping_count", str::parse)?,
+            parallel: var(&vars, "parallel", str::parse)?,
+            bidirectional: var(&vars, "bidirectional", str::parse)?,
+            udp: var(&vars, "udp", str::parse)?,
+            bitrate: var(&vars, "bitrate", parse_string)?,
+            datagram_size: var(&vars, "datagram_size", str::parse)?,
+            latency_probe: var(&vars, "latency_probe", str::parse)?,
+            interval: var(&vars, "interval", str::parse)?,
+            format: var(&vars, "format", |s| OutputFormat::from_str(s, true))?,
+            min_download: var(&vars, "min_download", parse_string)?,
+            min_upload: var(&vars, "min_upload", parse_string)?,
+            max_latency: var(&vars, "max_latency", parse_string)?,
+            max_loss: var(&vars, "max_loss", parse_string)?,
+            history: var(&vars, "history", str::parse)?,
+            timeout: var(&vars, "timeout", str::parse)?,
+            retries: var(&vars, "retries", str::parse)?,
+            psk: var(&vars, "psk", parse_string)?,
+            tls: var(&vars, "tls", str::parse)?,
+            tls_ca: var(&vars, "tls_ca", str::parse)?,
+        };
+        profile.validate(env_name)?;
+        Ok(profile)
+    }
+
+    /// Layer these settings over another profile, keeping the other setting wherever this one is unset.
+    pub fn or(self, other: ClientProfile) -> ClientProfile {
+        ClientProfile {
+            address: self.address.or(other.address),
+            port: self.port.or(other.port),
+            data: self.data.or(other.data),
+            time: self.time.or(other.time),
+            chunk_size: self.chunk_size.or(other.chunk_size),
+            ping_count: self.ping_count.or(other.ping_count),
+            parallel: self.parallel.or(other.parallel),
+            bidirectional: self.bidirectional.or(other.bidirectional),
+            udp: self.udp.or(other.udp),
+            bitrate: self.bitrate.or(other.bitrate),
+            datagram_size: self.datagram_size.or(other.datagram_size),
+            latency_probe: self.latency_probe.or(other.latency_probe),
+            interval: self.interval.or(other.interval),
+            format: self.format.or(other.format),
+            min_download: self.min_download.or(other.min_download),
+            min_upload: self.min_upload.or(other.min_upload),
+            max_latency: self.max_latency.or(other.max_latency),
+            max_loss: self.max_loss.or(other.max_loss),
+            history: self.history.or(other.history),
+            timeout: self.timeout.or(other.timeout),
+            retries: self.retries.or(other.retries),
+            psk: self.psk.or(other.psk),
+            tls: self.tls.or(other.tls),
+            tls_ca: self.tls_ca.or(other.tls_ca),
+        }
+    }
+
+    /// Check each setting is within its allowed range, naming settings with the given function in errors.
+    fn validate(&self, name: impl Fn(&str) -> String) -> Result<()> {
+        check(&name, "address", &self.address, |address| {
+            address::parse_target(address).map(|_| ())
+        })?;
+        check(&name, "port", &self.port, |&port| {
+            in_range(port, 1, u16::MAX)
+        })?;
+        check(&name, "data", &self.data, |data| {
+            Byte::parse_str(data, false).map(|_| ())
+        })?;
+        check(&name, "time", &self.time, |&time| {
+            in_range(time, 1, config::MAX_TEST_DURATION)
+        })?;
+        check(&name, "chunk_size", &self.chunk_size, |s| chunk_size(s))?;
+        check(&name, "ping_count", &self.ping_count, |&count| {
+            in_range(count, 1, config::MAX_PING_COUNT)
+        })?;
+        check(&name, "parallel", &self.parallel, |&parallel| {
+            in_range(parallel, 1, config::MAX_STREAMS)
+        })?;
+        check(&name, "bitrate", &self.bitrate, |s| {
+            units::parse_bitrate(s).map(|_| ())
+        })?;
+        check(&name, "datagram_size", &self.datagram_size, |&size| {
+            in_range(size, config::MIN_DATAGRAM_SIZE, config::MAX_DATAGRAM_SIZE)
+        })?;
+        check(&name, "interval", &self.interval, |&interval| {
+            in_range(interval, config::MIN_INTERVAL, config::MAX_INTERVAL)
+        })?;
+        check(&name, "min_download", &self.min_download, |s| {
+            units::parse_bitrate(s).map(|_| ())
+        })?;
+        check(&name, "min_upload", &self.min_upload, |s| {
+            units::parse_bitrate(s).map(|_| ())
+        })?;
+        check(&name, "max_latency", &self.max_latency, |s| {
+            units::parse_duration(s).map(|_| ())
+        })?;
+        check(&name, "max_loss", &self.max_loss, |s| {
+            units::parse_percent(s).map(|_| ())
+        })?;
+        check_psk(&name, &self.psk)?;
+        if self.udp == Some(true)
+            && (self.parallel.is_some_and(|parallel| parallel > 1)
+                || self.bidirectional == Some(true))
+        {
+            return Err(NetbeatError::config(format!(
+                "Invalid {} - UDP mode cannot be combined with parallel or bidirectional tests",
+                name("udp")
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl ServerProfile {
+    /// Server settings from `NETBEAT_`-prefixed environment variables (eg, NETBEAT_PORT, NETBEAT_INTERFACES).
+    pub fn from_env() -> Result<ServerProfile> {
+        ServerProfile::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Server settings from variables looked up by their environment variable name.
+    fn from_vars(vars: impl Fn(&str) -> Option<String>) -> Result<ServerProfile> {
+        let profile = ServerProfile {
+            interfaces: var(&vars, "interfaces", |s| {
+                s.split(',')
+                    .map(str::parse)
+                    .collect::<std::result::Result<Vec<_>, _>>()
+            })?,
+            port: var(&vars, "port", str::parse)?,
+            chunk_size: var(&vars, "chunk_size", parse_string)?,
+            max_connections: var(&vars, "max_connections", str::parse)?,
+            psk: var(&vars, "psk", parse_string)?,
+            tls: var(&vars, "tls", str::parse)?,
+            tls_cert: var(&vars, "tls_cert", str::parse)?,
+            tls_key: var(&vars, "tls_key", str::parse)?,
+            drain_timeout: var(&vars, "drain_timeout", str::parse)?,
+            max_session_duration: var(&vars, "max_session_duration", str::parse)?,
+            max_session_bytes: var(&vars, "max_session_bytes", parse_string)?,
+            max_direction_bytes: var(&vars, "max_direction_bytes", parse_string)?,
+            idle_timeout: var(&vars, "idle_timeout", str::parse)?,
+        };
+        profile.validate(env_name)?;
+        Ok(profile)
+    }
+
+    /// Layer these settings over another profile, keeping the other setting wherever this one is unset.
+    pub fn or(self, other: ServerProfile) -> ServerProfile {
+        ServerProfile {
+            interfaces: self.interfaces.or(other.interfaces),
+            port: self.port.or(other.port),
+            chunk_size: self.chunk_size.or(other.chunk_size),
+            max_connections: self.max_connections.or(other.max_connections),
+            psk: self.psk.or(other.psk),
+            tls: self.tls.or(other.tls),
+            tls_cert: self.tls_cert.or(other.tls_cert),
+            tls_key: self.tls_key.or(other.tls_key),
+            drain_timeout: self.drain_timeout.or(other.drain_timeout),
+            max_session_duration: self.max_session_duration.or(other.max_session_duration),
+            max_session_bytes: self.max_session_bytes.or(other.max_session_bytes),
+            max_direction_bytes: self.max_direction_bytes.or(other.max_direction_bytes),
+            idle_timeout: self.idle_timeout.or(other.idle_timeout),
+        }
+    }
+
+    /// Check each setting is within its allowed range, naming settings with the given function in errors.
+    fn validate(&self, name: impl Fn(&str) -> String) -> Result<()> {
+        check(&name, "interfaces", &self.interfaces, |interfaces| {
+            if interfaces.is_empty() {
+                Err("expected at least one interface")
+            } else {
+                Ok(())
+            }
+        })?;
+        check(&name, "port", &self.port, |&port| {
+            in_range(port, 1, u16::MAX)
+        })?;
+        check(&name, "chunk_size", &self.chunk_size, |s| chunk_size(s))?;
+        check(&name, "max_connections", &self.max_connections, |&max| {
+            in_range(max, 1, u32::MAX)
+        })?;
+        check_psk(&name, &self.psk)?;
+        check(
+            &name,
+            "max_session_duration",
+            &self.max_session_duration,
+            |&secs| in_range(secs, 1, u64::MAX),
+        )?;
+        check(&name, "max_session_bytes", &self.max_session_bytes, |s| {
+            byte_limit(s)
+        })?;
+        check(
+            &name,
+            "max_direction_bytes",
+            &self.max_direction_bytes,
+            |s| byte_limit(s),
+        )?;
+        check(&name, "idle_timeout", &self.idle_timeout, |&secs| {
+            in_range(secs, 1, u64::MAX)
+        })?;
+        Ok(())
+    }
+}
+
+/// Name of the environment variable for a setting (eg, chunk_size is NETBEAT_CHUNK_SIZE).
+fn env_name(key: &str) -> String {
+    format!("{}{}", config::ENV_PREFIX, key.to_uppercase())
+}
+
+/// Parse the environment variable of a setting, if set.
+fn var<T, E: Display>(
+    vars: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    parse: impl Fn(&str) -> std::result::Result<T, E>,
+) -> Result<Option<T>> {
+    let name = env_name(key);
+    vars(&name)
+        .map(|value| {
+            parse(value.trim())
+                .map_err(|e| NetbeatError::config(format!("Invalid {name} ({value:?}) - {e}")))
+        })
+        .transpose()
+}
+
+/// Keep a string setting as given, to be validated along with the rest of the profile.
+fn parse_string(s: &str) -> std::result::Result<String, String> {
+    Ok(s.to_string())
+}
+
+/// Check a setting, if set, with a validation that describes why it is invalid.
+fn check<T: Debug, E: Display>(
+    name: &impl Fn(&str) -> String,
+    key: &str,
+    value: &Option<T>,
+    validate: impl Fn(&T) -> std::result::Result<(), E>,
+) -> Result<()> {
+    match value {
+        Some(value) => validate(value)
+            .map_err(|e| NetbeatError::config(format!("Invalid {} ({value:?}) - {e}", name(key)))),
+        None => Ok(()),
+    }
+}
+
+/// Check a pre-shared key is not empty, leaving the key itself out of the error.
+fn check_psk(name: &impl Fn(&str) -> String, psk: &Option<String>) -> Result<()> {
+    if psk.as_deref() == Some("") {
+        return Err(NetbeatError::config(format!(
+            "Invalid {} - must not be empty",
+            name("psk")
+        )));
+    }
+    Ok(())
+}
+
+/// Check a number is within an inclusive range.
+fn in_range<T: PartialOrd + Display>(value: T, min: T, max: T) -> std::result::Result<(), String> {
+    if value < min || value > max {
+        return Err(format!("must be between {min} and {max}"));
+    }
+    Ok(())
+}
+
+/// Check a chunk size is within the allowed range.
+fn chunk_size(s: &str) -> std::result::Result<(), String> {
+    let size = Byte::parse_str(s, false).map_err(|e| e.to_string())?;
+    in_range(
+        size.as_u64(),
+        config::MIN_CHUNK_SIZE,
+        config::MAX_CHUNK_SIZE,
+    )
+    .map_err(|_| "must be between 1KiB and 16MiB".to_string())
+}
+
+/// Check a byte limit is given with valid units and is greater than zero.
+fn byte_limit(s: &str) -> std::result::Result<(), String> {
+    match Byte::parse_str(s, false)
+        .map_err(|e| e.to_string())?
+        .as_u64()
+    {
+        0 => Err("must be greater than zero".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Deserialize a list of network interfaces, as given to `netbeat serve --interface`.
+fn deserialize_interfaces<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<BindInterface>>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const CONFIG: &str = r#"
+[client]
+time = 20
+chunk_size = "128KiB"
+format = "json"
+
+[server]
+interfaces = ["localhost", "::1"]
+port = 6000
+max_connections = 10
+tls = true
+
+[targets.nas]
+address = "nas.lan"
+port = 6000
+parallel = 4
+max_latency = "2ms"
+"#;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = ConfigFile::parse(CONFIG, "config.toml").unwrap();
+        assert_eq!(config.client.time, Some(20));
+        assert_eq!(config.client.format, Some(OutputFormat::Json));
+        assert_eq!(
+            config.server.interfaces,
+            Some(vec![
+                BindInterface::Localhost,
+                BindInterface::Address("::1".parse().unwrap())
+            ])
+        );
+        assert_eq!(config.server_profile().max_connections, Some(10));
+        assert_eq!(config.server_profile().tls, Some(true));
+
+        let nas = config.client_profile("nas");
+        assert_eq!(nas.address.as_deref(), Some("nas.lan"));
+        assert_eq!(nas.port, Some(6000));
+        assert_eq!(nas.parallel, Some(4));
+        assert_eq!(nas.time, Some(20)); // from [client]
+        assert_eq!(nas.chunk_size.as_deref(), Some("128KiB")); // from [client]
+
+        let other = config.client_profile("10.1.1.11");
+        assert!(other.address.is_none());
+        assert!(other.port.is_none());
+        assert_eq!(other.time, Some(20));
+
+        assert_eq!(
+            ConfigFile::parse("", "config.toml").unwrap(),
+            ConfigFile::default()
+        );
+    }
+
+    #[test]
+    fn test_parse_config_errors() {
+        let error = ConfigFile::parse("[client]\nchunk = \"64KiB\"\n", "config.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Config error: Failed to parse config.toml"));
+        assert!(error.contains("unknown field `chunk`"));
+
+        let error = ConfigFile::parse("[clients]\n", "config.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("unknown field `clients`"));
+
+        let error = ConfigFile::parse("[targets.nas]\nport = \"6000\"\n", "config.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("invalid type"));
+
+        let error = ConfigFile::parse("[server]\ninterfaces = [\"eth 0\"]\n", "config.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("'eth 0' is not 'all', 'localhost'"));
+
+        assert_eq!(
+            ConfigFile::parse("[targets.nas]\ntime = 0\n", "config.toml")
+                .unwrap_err()
+                .to_string(),
+            "Config error: Invalid targets.nas.time in config.toml (0) - must be between 1 and 3600"
+        );
+        assert_eq!(
+            ConfigFile::parse("[server]\nchunk_size = \"1B\"\n", "config.toml")
+                .unwrap_err()
+                .to_string(),
+            "Config error: Invalid server.chunk_size in config.toml (\"1B\") - must be between 1KiB and 16MiB"
+        );
+        assert!(
+            ConfigFile::parse("[client]\naddress = \"nas.lan\"\n", "config.toml")
+                .unwrap_err()
+                .to_string()
+                .contains("only allowed in [targets.<name>] profiles")
+        );
+        assert!(
+            ConfigFile::parse("[targets.nas]\nudp = true\nparallel = 4\n", "config.toml")
+                .unwrap_err()
+                .to_string()
+                .contains("UDP mode cannot be combined")
+        );
+    }
+
+    #[test]
+    fn test_load_config() {
+        let path =
+            std::env::temp_dir().join(format!("netbeat-test-{}-config.toml", std::process::id()));
+        fs::write(&path, CONFIG).unwrap();
+        let config = ConfigFile::open(Some(&path)).unwrap();
+        assert_eq!(config.client_profile("nas").port, Some(6000));
+        fs::remove_file(&path).unwrap();
+
+        let error = ConfigFile::load(&path).unwrap_err().to_string();
+        assert!(error.starts_with("Config error: Failed to read"));
+    }
+
+    #[test]
+    fn test_profile_from_vars() {
+        let profile = ClientProfile::from_vars(vars(&[
+            ("NETBEAT_PORT", "6000"),
+            ("NETBEAT_CHUNK_SIZE", "32KiB"),
+            ("NETBEAT_FORMAT", "CSV"),
+            ("NETBEAT_UDP", "true"),
+            ("NETBEAT_TLS_CA", "/etc/netbeat/ca.pem"),
+            ("PORT", "7000"),
+        ]))
+        .unwrap();
+        assert_eq!(profile.port, Some(6000));
+        assert_eq!(profile.chunk_size.as_deref(), Some("32KiB"));
+        assert_eq!(profile.format, Some(OutputFormat::Csv));
+        assert_eq!(profile.udp, Some(true));
+        assert_eq!(profile.tls_ca, Some(PathBuf::from("/etc/netbeat/ca.pem")));
+        assert!(profile.time.is_none());
+
+        assert_eq!(
+            ClientProfile::from_vars(vars(&[("NETBEAT_TIME", "ten")]))
+                .unwrap_err()
+                .to_string(),
+            "Config error: Invalid NETBEAT_TIME (\"ten\") - invalid digit found in string"
+        );
+        assert_eq!(
+            ClientProfile::from_vars(vars(&[("NETBEAT_PARALLEL", "0")]))
+                .unwrap_err()
+                .to_string(),
+            "Config error: Invalid NETBEAT_PARALLEL (0) - must be between 1 and 128"
+        );
+
+        let profile = ServerProfile::from_vars(vars(&[
+            ("NETBEAT_INTERFACES", "localhost,eth0"),
+            ("NETBEAT_MAX_CONNECTIONS", "5"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            profile.interfaces,
+            Some(vec![
+                BindInterface::Localhost,
+                BindInterface::Named("eth0".to_string())
+            ])
+        );
+        assert_eq!(profile.max_connections, Some(5));
+        assert!(ServerProfile::from_vars(vars(&[("NETBEAT_MAX_CONNECTIONS", "0")])).is_err());
+
+        let profile = ServerProfile::from_vars(vars(&[
+            ("NETBEAT_MAX_SESSION_DURATION", "120"),
+            ("NETBEAT_MAX_DIRECTION_BYTES", "1GB"),
+            ("NETBEAT_IDLE_TIMEOUT", "10"),
+        ]))
+        .unwrap();
+        assert_eq!(profile.max_session_duration, Some(120));
+        assert_eq!(profile.max_direction_bytes.as_deref(), Some("1GB"));
+        assert_eq!(profile.idle_timeout, Some(10));
+        assert!(profile.max_session_bytes.is_none());
+        assert_eq!(
+            ServerProfile::from_vars(vars(&[("NETBEAT_MAX_SESSION_BYTES", "0MB")]))
+                .unwrap_err()
+                .to_string(),
+            "Config error: Invalid NETBEAT_MAX_SESSION_BYTES (\"0MB\") - must be greater than zero"
+        );
+        assert!(ServerProfile::from_vars(vars(&[("NETBEAT_IDLE_TIMEOUT", "0")])).is_err());
+    }
+
+    #[test]
+    fn test_profile_precedence() {
+        let config = ConfigFile::parse(CONFIG, "config.toml").unwrap();
+        let cli = ClientProfile {
+            time: Some(5),
+            ..Default::default()
+        };
+        let env =
+            ClientProfile::from_vars(vars(&[("NETBEAT_TIME", "30"), ("NETBEAT_PARALLEL", "2")]))
+                .unwrap();
+
+        let profile = cli.or(env).or(config.client_profile("nas"));
+        assert_eq!(profile.time, Some(5)); // cli
+        assert_eq!(profile.parallel, Some(2)); // environment
+        assert_eq!(profile.port, Some(6000)); // file
+        assert!(profile.ping_count.is_none()); // builder default
+
+        let server = ServerProfile {
+            port: Some(7000),
+            ..Default::default()
+        }
+        .or(config.server_profile());
+        assert_eq!(server.port, Some(7000));
+        assert_eq!(server.max_connections, Some(10));
+    }
+}
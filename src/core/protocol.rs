@@ -1,26 +1,604 @@
 //! Network protocol definitions and utilities for netbeat
 
+use super::{
+    config,
+    intervals::{self, IntervalSample},
+    tls::Transport,
+};
 use crate::utils::error::{NetbeatError, Result};
 use rand::RngCore;
-use std::io::{self, Write};
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
 
 /// Protocol Version
-pub const PROTOCOL_VERSION: &[u8] = b"NETBEAT_1.0";
+pub const PROTOCOL_VERSION: &[u8] = b"NETBEAT_2.0";
 
-/// Protocol messages
-pub const PING_MESSAGE: &[u8] = b"NETBEAT_PING";
-pub const PING_RESPONSE: &[u8] = b"NETBEAT_PONG";
-pub const PING_DONE: &[u8] = b"NETBEAT_DONE";
-pub const UPLOAD_START: &[u8] = b"NETBEAT_UPLOAD_START";
-pub const UPLOAD_DONE: &[u8] = b"NETBEAT_UPLOAD_DONE";
-pub const DOWNLOAD_START: &[u8] = b"NETBEAT_DOWNLOAD_START";
-pub const DOWNLOAD_DONE: &[u8] = b"NETBEAT_DOWNLOAD_DONE";
+/// Capability flags advertised during the version handshake
+pub const CAP_PING: u32 = 1 << 0;
+pub const CAP_UPLOAD: u32 = 1 << 1;
+pub const CAP_DOWNLOAD: u32 = 1 << 2;
+pub const CAP_PARALLEL: u32 = 1 << 3;
+pub const CAP_BIDIRECTIONAL: u32 = 1 << 4;
+pub const CAP_UDP: u32 = 1 << 5;
+pub const CAP_LATENCY_PROBE: u32 = 1 << 6;
+
+/// Capability flag set by servers that require pre-shared key authentication right after the hello
+pub const CAP_AUTH: u32 = 1 << 7;
+
+/// Capability flag set by servers with TLS enabled, both sides running a TLS handshake right after the hello
+pub const CAP_TLS: u32 = 1 << 8;
+
+/// Capabilities supported by this build of netbeat
+pub const CAPABILITIES: u32 = CAP_PING
+    | CAP_UPLOAD
+    | CAP_DOWNLOAD
+    | CAP_PARALLEL
+    | CAP_BIDIRECTIONAL
+    | CAP_UDP
+    | CAP_LATENCY_PROBE;
+
+/// Length of a frame header: message kind (1 byte) followed by payload length (4 bytes, big endian)
+pub const FRAME_HEADER_LEN: usize = 5;
+
+/// Largest payload accepted in a single frame (matches the maximum chunk size)
+pub const MAX_FRAME_PAYLOAD: usize = config::MAX_CHUNK_SIZE as usize;
+
+/// Kinds of messages exchanged between client and server.
+///
+/// Every message travels in a frame of `[kind][payload length][payload]`, so control messages
+/// can never be confused with bulk test data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageKind {
+    /// Protocol version and capabilities
+    Hello = 0x01,
+    /// Test plan proposed by the client
+    SessionSetup = 0x02,
+    /// Server accepted the test plan or stream join, carrying the session id
+    SessionAccept = 0x03,
+    /// Server rejected the test plan or stream join, carrying the reason
+    SessionReject = 0x04,
+    /// Additional stream joining an accepted session
+    SessionJoin = 0x05,
+    /// Random nonce the client must answer with its pre-shared key
+    AuthChallenge = 0x06,
+    /// HMAC of the challenge nonce computed by the client
+    AuthResponse = 0x07,
+    /// Server accepted the client authentication
+    AuthAccept = 0x08,
+    /// Server rejected the client authentication, carrying the reason
+    AuthReject = 0x09,
+    /// Latency probe carrying a sequence number
+    Ping = 0x10,
+    /// Response to a ping, echoing its sequence number
+    Pong = 0x11,
+    /// End of the ping test
+    PingDone = 0x12,
+    /// Start of the upload test
+    UploadStart = 0x20,
+    /// End of the upload test
+    UploadDone = 0x21,
+    /// Upload throughput as measured by the server
+    UploadResult = 0x22,
+    /// Start of the download test
+    DownloadStart = 0x30,
+    /// End of the download test, carrying the exact number of bytes sent by the server
+    DownloadDone = 0x31,
+    /// Request from the client to stop the download test
+    DownloadStop = 0x32,
+    /// Bulk test data
+    Data = 0x40,
+    /// Start of a UDP test direction, carrying its parameters
+    UdpStart = 0x50,
+    /// Server UDP socket is ready, carrying its port
+    UdpReady = 0x51,
+    /// Sender is done with the UDP test, carrying what it sent
+    UdpDone = 0x52,
+    /// UDP statistics as measured by the server
+    UdpResult = 0x53,
+    /// End of the session
+    Goodbye = 0x7E,
+    /// Fatal error description, sent before closing the connection
+    Error = 0x7F,
+}
+
+impl TryFrom<u8> for MessageKind {
+    type Error = NetbeatError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        Ok(match value {
+            0x01 => MessageKind::Hello,
+            0x02 => MessageKind::SessionSetup,
+            0x03 => MessageKind::SessionAccept,
+            0x04 => MessageKind::SessionReject,
+            0x05 => MessageKind::SessionJoin,
+            0x06 => MessageKind::AuthChallenge,
+            0x07 => MessageKind::AuthResponse,
+            0x08 => MessageKind::AuthAccept,
+            0x09 => MessageKind::AuthReject,
+            0x10 => MessageKind::Ping,
+            0x11 => MessageKind::Pong,
+            0x12 => MessageKind::PingDone,
+            0x20 => MessageKind::UploadStart,
+            0x21 => MessageKind::UploadDone,
+            0x22 => MessageKind::UploadResult,
+            0x30 => MessageKind::DownloadStart,
+            0x31 => MessageKind::DownloadDone,
+            0x32 => MessageKind::DownloadStop,
+            0x40 => MessageKind::Data,
+            0x50 => MessageKind::UdpStart,
+            0x51 => MessageKind::UdpReady,
+            0x52 => MessageKind::UdpDone,
+            0x53 => MessageKind::UdpResult,
+            0x7E => MessageKind::Goodbye,
+            0x7F => MessageKind::Error,
+            _ => {
+                return Err(NetbeatError::protocol(format!(
+                    "Unknown message kind ({value:#04x})"
+                )));
+            }
+        })
+    }
+}
+
+/// A single protocol message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Kind of message
+    pub kind: MessageKind,
+    /// Message payload
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Create a new frame.
+    pub fn new(kind: MessageKind, payload: Vec<u8>) -> Self {
+        Self { kind, payload }
+    }
+
+    /// Create a frame without payload.
+    pub fn empty(kind: MessageKind) -> Self {
+        Self::new(kind, Vec::new())
+    }
+}
 
 /// Simple helper to write message and flush
 pub fn write_message(stream: &mut impl Write, message: &[u8]) -> io::Result<()> {
     stream.write_all(message)
 }
 
+/// Encode a frame into its wire representation.
+///
+/// Useful to encode bulk data frames once and write them repeatedly with [`write_message`].
+pub fn encode_frame(kind: MessageKind, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    bytes.push(kind as u8);
+    bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Decode a frame header into its message kind and payload length.
+fn decode_header(header: &[u8; FRAME_HEADER_LEN]) -> Result<(MessageKind, usize)> {
+    let kind = MessageKind::try_from(header[0])?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_FRAME_PAYLOAD {
+        return Err(NetbeatError::protocol(format!(
+            "Frame payload of {len} bytes exceeds maximum of {MAX_FRAME_PAYLOAD} bytes"
+        )));
+    }
+    Ok((kind, len))
+}
+
+/// Decode a frame from the start of `bytes`.
+///
+/// Returns the frame and the number of bytes consumed, or `None` if `bytes` does not yet hold a complete frame.
+pub fn decode_frame(bytes: &[u8]) -> Result<Option<(Frame, usize)>> {
+    let Some(header) = bytes.first_chunk::<FRAME_HEADER_LEN>() else {
+        return Ok(None);
+    };
+    let (kind, len) = decode_header(header)?;
+    let total = FRAME_HEADER_LEN + len;
+    if bytes.len() < total {
+        return Ok(None);
+    }
+    Ok(Some((
+        Frame::new(kind, bytes[FRAME_HEADER_LEN..total].to_vec()),
+        total,
+    )))
+}
+
+/// Write a single frame to the stream.
+pub fn write_frame(stream: &mut impl Write, kind: MessageKind, payload: &[u8]) -> io::Result<()> {
+    write_message(stream, &encode_frame(kind, payload))
+}
+
+/// Read a single frame from the stream.
+pub fn read_frame(stream: &mut impl Read) -> Result<Frame> {
+    let mut payload = Vec::new();
+    let kind = read_frame_into(stream, &mut payload)?;
+    Ok(Frame::new(kind, payload))
+}
+
+/// Read a single frame from the stream, reusing `payload` as the payload buffer.
+pub fn read_frame_into(stream: &mut impl Read, payload: &mut Vec<u8>) -> Result<MessageKind> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    stream
+        .read_exact(&mut header)
+        .map_err(NetbeatError::ConnectionError)?;
+    let (kind, len) = decode_header(&header)?;
+    payload.resize(len, 0);
+    stream
+        .read_exact(payload)
+        .map_err(NetbeatError::ConnectionError)?;
+    Ok(kind)
+}
+
+/// Read a frame if one is available, without blocking.
+///
+/// Bytes of a partially received frame are kept in `pending` until the rest arrives. Never reads past
+/// the end of the frame, so the stream remains in sync for subsequent blocking reads.
+pub fn poll_frame(stream: &mut Transport, pending: &mut Vec<u8>) -> Result<Option<Frame>> {
+    stream
+        .socket()
+        .set_nonblocking(true)
+        .map_err(NetbeatError::ConnectionError)?;
+    let result = fill_pending(stream, pending);
+    stream
+        .socket()
+        .set_nonblocking(false)
+        .map_err(NetbeatError::ConnectionError)?;
+    result?;
+
+    match decode_frame(pending)? {
+        Some((frame, consumed)) => {
+            pending.drain(..consumed);
+            Ok(Some(frame))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Read available bytes into `pending`, up to the end of the next frame.
+fn fill_pending(stream: &mut Transport, pending: &mut Vec<u8>) -> Result<()> {
+    loop {
+        let needed = match pending.first_chunk::<FRAME_HEADER_LEN>() {
+            Some(header) => FRAME_HEADER_LEN + decode_header(header)?.1 - pending.len(),
+            None => FRAME_HEADER_LEN - pending.len(),
+        };
+        if needed == 0 {
+            return Ok(());
+        }
+
+        let start = pending.len();
+        pending.resize(start + needed, 0);
+        match stream.read(&mut pending[start..]) {
+            Ok(0) => {
+                pending.truncate(start);
+                return Err(NetbeatError::ConnectionError(
+                    io::ErrorKind::UnexpectedEof.into(),
+                ));
+            }
+            Ok(n) => pending.truncate(start + n),
+            Err(e) => {
+                pending.truncate(start);
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(()),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(NetbeatError::ConnectionError(e)),
+                }
+            }
+        }
+    }
+}
+
+/// Read a frame and ensure it is of the expected kind.
+///
+/// Error frames from the peer are turned into protocol errors carrying the peer's message.
+pub fn expect_frame(stream: &mut impl Read, expected: MessageKind) -> Result<Frame> {
+    let frame = read_frame(stream)?;
+    if frame.kind == expected {
+        Ok(frame)
+    } else {
+        Err(unexpected_message(expected, frame.kind, &frame.payload))
+    }
+}
+
+/// Build the error for a message that arrived out of order.
+///
+/// Error frames from the peer carry their own message, which is preserved.
+pub fn unexpected_message(
+    expected: MessageKind,
+    kind: MessageKind,
+    payload: &[u8],
+) -> NetbeatError {
+    match kind {
+        MessageKind::Error => NetbeatError::protocol(format!(
+            "Peer reported error - {}",
+            String::from_utf8_lossy(payload)
+        )),
+        kind => NetbeatError::protocol(format!("Expected {expected:?} message, got {kind:?}")),
+    }
+}
+
+/// Write an error frame describing why the connection is being closed.
+pub fn write_error(stream: &mut impl Write, message: &str) -> io::Result<()> {
+    write_frame(stream, MessageKind::Error, message.as_bytes())
+}
+
+/// Version and capabilities exchanged by client and server before any test runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    /// Protocol version of the peer
+    pub version: Vec<u8>,
+    /// Capability flags of the peer
+    pub capabilities: u32,
+}
+
+impl Hello {
+    /// Hello for this build of netbeat
+    pub fn local() -> Self {
+        Self {
+            version: PROTOCOL_VERSION.to_vec(),
+            capabilities: CAPABILITIES,
+        }
+    }
+
+    /// Protocol version as a printable string
+    pub fn version_string(&self) -> String {
+        String::from_utf8_lossy(&self.version).into_owned()
+    }
+
+    /// Whether the peer supports all of the given capabilities
+    pub fn supports(&self, capabilities: u32) -> bool {
+        self.capabilities & capabilities == capabilities
+    }
+
+    /// Encode hello payload: version length, version and capabilities
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(1 + self.version.len() + 4);
+        payload.push(self.version.len() as u8);
+        payload.extend_from_slice(&self.version);
+        payload.extend_from_slice(&self.capabilities.to_be_bytes());
+        payload
+    }
+
+    /// Decode hello payload written by [`Hello::encode`]
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let invalid = || NetbeatError::protocol("Malformed hello message".to_string());
+        let (version_len, rest) = payload.split_first().ok_or_else(invalid)?;
+        let version_len = *version_len as usize;
+        if rest.len() != version_len + 4 {
+            return Err(invalid());
+        }
+        let (version, capabilities) = rest.split_at(version_len);
+        Ok(Self {
+            version: version.to_vec(),
+            capabilities: u32::from_be_bytes(capabilities.try_into().map_err(|_| invalid())?),
+        })
+    }
+}
+
+/// Write hello frame
+pub fn write_hello(stream: &mut impl Write, hello: &Hello) -> io::Result<()> {
+    if hello.version.len() > u8::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Protocol version too long",
+        ));
+    }
+    write_frame(stream, MessageKind::Hello, &hello.encode())
+}
+
+/// Read hello frame written by [`write_hello`].
+///
+/// Peers that do not speak the framed protocol are reported as a version mismatch.
+pub fn read_hello(stream: &mut impl Read) -> Result<Hello> {
+    match read_frame(stream) {
+        Ok(frame) if frame.kind == MessageKind::Hello => Hello::decode(&frame.payload),
+        Ok(frame) => Err(unexpected_message(
+            MessageKind::Hello,
+            frame.kind,
+            &frame.payload,
+        )),
+        Err(NetbeatError::ProtocolError { .. }) => Err(NetbeatError::version_mismatch(
+            String::from_utf8_lossy(PROTOCOL_VERSION).into_owned(),
+            "unknown (peer does not speak the framed protocol)".to_string(),
+        )),
+        Err(e) => Err(NetbeatError::protocol(format!(
+            "Failed to read hello message - {e}"
+        ))),
+    }
+}
+
+/// Check a peer hello against the local protocol version
+pub fn check_version(peer: &Hello) -> Result<()> {
+    if peer.version != PROTOCOL_VERSION {
+        return Err(NetbeatError::version_mismatch(
+            String::from_utf8_lossy(PROTOCOL_VERSION).into_owned(),
+            peer.version_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Test flags used in a [`TestPlan`]
+pub const TEST_PING: u8 = 1 << 0;
+pub const TEST_UPLOAD: u8 = 1 << 1;
+pub const TEST_DOWNLOAD: u8 = 1 << 2;
+
+/// All tests known to this build of netbeat
+pub const ALL_TESTS: u8 = TEST_PING | TEST_UPLOAD | TEST_DOWNLOAD;
+
+/// Test mode flag: probe latency on an extra connection while upload and download run
+pub const TEST_LATENCY_PROBE: u8 = 1 << 5;
+
+/// Test mode flag: run upload and download over UDP at a fixed bitrate
+pub const TEST_UDP: u8 = 1 << 6;
+
+/// Test mode flag: run upload and download at the same time, each over its own set of streams
+pub const TEST_BIDIRECTIONAL: u8 = 1 << 7;
+
+/// Full test plan sent by the client when setting up a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestPlan {
+    /// Tests to be run (combination of `TEST_*` flags)
+    pub tests: u8,
+    /// Time limit per test direction
+    pub duration: Duration,
+    /// Target bytes per test direction, used instead of duration when set
+    pub bytes: Option<u64>,
+    /// Buffer size for read/write operations
+    pub chunk_size: u64,
+    /// Number of TCP streams per test direction
+    pub streams: u32,
+    /// Length of the throughput reporting intervals recorded by the receiving side
+    pub interval: Duration,
+}
+
+impl TestPlan {
+    /// Encoded length of a test plan payload
+    const ENCODED_LEN: usize = 1 + 8 + 1 + 8 + 8 + 4 + 4;
+
+    /// Whether the plan includes all of the given tests
+    pub fn includes(&self, tests: u8) -> bool {
+        self.tests & tests == tests
+    }
+
+    /// Number of connections making up the session, one set of streams per direction when bidirectional
+    /// plus the latency probe connection
+    pub fn connections(&self) -> u32 {
+        let streams = if self.includes(TEST_BIDIRECTIONAL) {
+            self.streams.saturating_mul(2)
+        } else {
+            self.streams
+        };
+        streams.saturating_add(u32::from(self.includes(TEST_LATENCY_PROBE)))
+    }
+
+    /// Stream index of the latency probe connection, the last one to join the session
+    pub fn probe_stream(&self) -> Option<u32> {
+        self.includes(TEST_LATENCY_PROBE)
+            .then(|| self.connections() - 1)
+    }
+
+    /// Encode test plan payload: tests, duration in seconds, optional bytes, chunk size, streams and interval in milliseconds
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(Self::ENCODED_LEN);
+        payload.push(self.tests);
+        payload.extend_from_slice(&self.duration.as_secs().to_be_bytes());
+        payload.push(self.bytes.is_some() as u8);
+        payload.extend_from_slice(&self.bytes.unwrap_or(0).to_be_bytes());
+        payload.extend_from_slice(&self.chunk_size.to_be_bytes());
+        payload.extend_from_slice(&self.streams.to_be_bytes());
+        payload.extend_from_slice(&(self.interval.as_millis() as u32).to_be_bytes());
+        payload
+    }
+
+    /// Decode test plan payload written by [`TestPlan::encode`]
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let invalid = || NetbeatError::protocol("Malformed session setup message".to_string());
+        if payload.len() != Self::ENCODED_LEN {
+            return Err(invalid());
+        }
+        let u64_at = |i: usize| -> Result<u64> {
+            Ok(u64::from_be_bytes(
+                payload[i..i + 8].try_into().map_err(|_| invalid())?,
+            ))
+        };
+        Ok(Self {
+            tests: payload[0],
+            duration: Duration::from_secs(u64_at(1)?),
+            bytes: match payload[9] {
+                0 => None,
+                1 => Some(u64_at(10)?),
+                _ => return Err(invalid()),
+            },
+            chunk_size: u64_at(18)?,
+            streams: u32::from_be_bytes(payload[26..30].try_into().map_err(|_| invalid())?),
+            interval: Duration::from_millis(u64::from(u32::from_be_bytes(
+                payload[30..34].try_into().map_err(|_| invalid())?,
+            ))),
+        })
+    }
+}
+
+/// Request for an additional stream to join an accepted session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionJoin {
+    /// Session id handed out by the server on session accept
+    pub session_id: u64,
+    /// Index of the joining stream within the session (the session connection is stream 0)
+    pub stream: u32,
+}
+
+impl SessionJoin {
+    /// Encode session join payload: session id and stream index
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(12);
+        payload.extend_from_slice(&self.session_id.to_be_bytes());
+        payload.extend_from_slice(&self.stream.to_be_bytes());
+        payload
+    }
+
+    /// Decode session join payload written by [`SessionJoin::encode`]
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let invalid = || NetbeatError::protocol("Malformed session join message".to_string());
+        let bytes: [u8; 12] = payload.try_into().map_err(|_| invalid())?;
+        let (session_id, stream) = bytes.split_at(8);
+        Ok(Self {
+            session_id: u64::from_be_bytes(session_id.try_into().map_err(|_| invalid())?),
+            stream: u32::from_be_bytes(stream.try_into().map_err(|_| invalid())?),
+        })
+    }
+}
+
+/// Decode the session id carried by a session accept message
+pub fn decode_session_id(payload: &[u8]) -> Result<u64> {
+    let bytes: [u8; 8] = payload
+        .try_into()
+        .map_err(|_| NetbeatError::protocol("Malformed session accept message".to_string()))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Outcome of a transfer as measured by the receiving side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferResult {
+    /// Payload bytes received
+    pub bytes: u64,
+    /// Time between the start and end of the transfer
+    pub duration: Duration,
+    /// Bytes received per reporting interval, empty when measured by the sending side
+    pub intervals: Vec<IntervalSample>,
+}
+
+impl TransferResult {
+    /// Encode transfer result payload: bytes, duration in nanoseconds and interval samples
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(16);
+        payload.extend_from_slice(&self.bytes.to_be_bytes());
+        payload.extend_from_slice(&(self.duration.as_nanos() as u64).to_be_bytes());
+        payload.extend_from_slice(&intervals::encode(&self.intervals));
+        payload
+    }
+
+    /// Decode transfer result payload written by [`TransferResult::encode`]
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let invalid = || NetbeatError::protocol("Malformed transfer result message".to_string());
+        let (bytes, samples) = payload.split_first_chunk::<16>().ok_or_else(invalid)?;
+        let (transferred, nanos) = bytes.split_at(8);
+        Ok(Self {
+            bytes: u64::from_be_bytes(transferred.try_into().map_err(|_| invalid())?),
+            duration: Duration::from_nanos(u64::from_be_bytes(
+                nanos.try_into().map_err(|_| invalid())?,
+            )),
+            intervals: intervals::decode(samples).map_err(|_| invalid())?,
+        })
+    }
+}
+
 /// Generate a random buffer of specified size for testing
 pub fn generate_random_buffer(size: usize) -> Vec<u8> {
     let mut buffer = vec![0u8; size];
@@ -43,11 +621,10 @@ pub fn validate_chunk_size(s: &str, err_type: &str) -> Result<String> {
         .map_err(|e| create_error(format!("Invalid chunk size '{s}' - {e}")))?;
 
     let size = byte_size.as_u64();
-    if size < 1024 {
+    if size < config::MIN_CHUNK_SIZE {
         return Err(create_error("Chunk size must be at least 1KiB".to_string()));
     }
-    if size > 1024 * 1024 * 16 {
-        // 16MiB max
+    if size > config::MAX_CHUNK_SIZE {
         return Err(create_error("Chunk size must not exceed 16MiB".to_string()));
     }
 
@@ -73,14 +650,49 @@ mod tests {
 
     #[test]
     fn test_protocol_constants() {
-        assert_eq!(PROTOCOL_VERSION, b"NETBEAT_1.0");
-        assert_eq!(PING_MESSAGE, b"NETBEAT_PING");
-        assert_eq!(PING_RESPONSE, b"NETBEAT_PONG");
-        assert_eq!(PING_DONE, b"NETBEAT_DONE");
-        assert_eq!(UPLOAD_START, b"NETBEAT_UPLOAD_START");
-        assert_eq!(UPLOAD_DONE, b"NETBEAT_UPLOAD_DONE");
-        assert_eq!(DOWNLOAD_START, b"NETBEAT_DOWNLOAD_START");
-        assert_eq!(DOWNLOAD_DONE, b"NETBEAT_DOWNLOAD_DONE");
+        assert_eq!(PROTOCOL_VERSION, b"NETBEAT_2.0");
+        assert_eq!(FRAME_HEADER_LEN, 5);
+        assert_eq!(MAX_FRAME_PAYLOAD, 16 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_message_kind_round_trip() {
+        for kind in [
+            MessageKind::Hello,
+            MessageKind::SessionSetup,
+            MessageKind::SessionAccept,
+            MessageKind::SessionReject,
+            MessageKind::SessionJoin,
+            MessageKind::AuthChallenge,
+            MessageKind::AuthResponse,
+            MessageKind::AuthAccept,
+            MessageKind::AuthReject,
+            MessageKind::Ping,
+            MessageKind::Pong,
+            MessageKind::PingDone,
+            MessageKind::UploadStart,
+            MessageKind::UploadDone,
+            MessageKind::UploadResult,
+            MessageKind::DownloadStart,
+            MessageKind::DownloadDone,
+            MessageKind::DownloadStop,
+            MessageKind::Data,
+            MessageKind::UdpStart,
+            MessageKind::UdpReady,
+            MessageKind::UdpDone,
+            MessageKind::UdpResult,
+            MessageKind::Goodbye,
+            MessageKind::Error,
+        ] {
+            assert_eq!(MessageKind::try_from(kind as u8).unwrap(), kind);
+        }
+
+        let result = MessageKind::try_from(0xEE);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ProtocolError { .. }));
+            assert!(e.to_string().contains("Unknown message kind"));
+        }
     }
 
     #[test]
@@ -91,6 +703,278 @@ mod tests {
         assert_eq!(buffer, message);
     }
 
+    #[test]
+    fn test_encode_decode_frame() {
+        let bytes = encode_frame(MessageKind::Data, b"payload");
+        assert_eq!(bytes.len(), FRAME_HEADER_LEN + 7);
+        assert_eq!(bytes[0], MessageKind::Data as u8);
+        assert_eq!(&bytes[1..5], &7u32.to_be_bytes());
+
+        let (frame, consumed) = decode_frame(&bytes).unwrap().unwrap();
+        assert_eq!(frame, Frame::new(MessageKind::Data, b"payload".to_vec()));
+        assert_eq!(consumed, bytes.len());
+
+        // Empty payloads
+        let bytes = encode_frame(MessageKind::UploadDone, &[]);
+        let (frame, consumed) = decode_frame(&bytes).unwrap().unwrap();
+        assert_eq!(frame, Frame::empty(MessageKind::UploadDone));
+        assert_eq!(consumed, FRAME_HEADER_LEN);
+    }
+
+    #[test]
+    fn test_decode_frame_incomplete() {
+        let bytes = encode_frame(MessageKind::Data, b"payload");
+        assert!(decode_frame(&[]).unwrap().is_none());
+        assert!(decode_frame(&bytes[..3]).unwrap().is_none());
+        assert!(decode_frame(&bytes[..bytes.len() - 1]).unwrap().is_none());
+
+        // Trailing bytes of the next frame are left alone
+        let mut two = bytes.clone();
+        two.extend(encode_frame(MessageKind::UploadDone, &[]));
+        let (_, consumed) = decode_frame(&two).unwrap().unwrap();
+        assert_eq!(consumed, bytes.len());
+        let (frame, _) = decode_frame(&two[consumed..]).unwrap().unwrap();
+        assert_eq!(frame.kind, MessageKind::UploadDone);
+    }
+
+    #[test]
+    fn test_decode_frame_invalid() {
+        let result = decode_frame(&[0xEE, 0, 0, 0, 0]);
+        assert!(result.is_err());
+
+        let mut bytes = vec![MessageKind::Data as u8];
+        bytes.extend_from_slice(&(MAX_FRAME_PAYLOAD as u32 + 1).to_be_bytes());
+        let result = decode_frame(&bytes);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e.to_string().contains("exceeds maximum"));
+        }
+    }
+
+    #[test]
+    fn test_read_write_frame() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, MessageKind::Ping, &7u32.to_be_bytes()).unwrap();
+        write_frame(&mut buffer, MessageKind::Data, b"abc").unwrap();
+        write_frame(&mut buffer, MessageKind::Data, b"defg").unwrap();
+
+        let mut reader = buffer.as_slice();
+        let frame = read_frame(&mut reader).unwrap();
+        assert_eq!(frame.kind, MessageKind::Ping);
+        assert_eq!(frame.payload, 7u32.to_be_bytes());
+
+        let mut payload = Vec::new();
+        assert_eq!(
+            read_frame_into(&mut reader, &mut payload).unwrap(),
+            MessageKind::Data
+        );
+        assert_eq!(payload, b"abc");
+        assert_eq!(
+            read_frame_into(&mut reader, &mut payload).unwrap(),
+            MessageKind::Data
+        );
+        assert_eq!(payload, b"defg");
+
+        // Stream exhausted
+        let result = read_frame(&mut reader);
+        assert!(matches!(result, Err(NetbeatError::ConnectionError(_))));
+    }
+
+    #[test]
+    fn test_expect_frame() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, MessageKind::UploadStart, &[]).unwrap();
+        write_frame(&mut buffer, MessageKind::UploadStart, &[]).unwrap();
+        write_error(&mut buffer, "Something broke").unwrap();
+
+        let mut reader = buffer.as_slice();
+        assert!(expect_frame(&mut reader, MessageKind::UploadStart).is_ok());
+
+        let result = expect_frame(&mut reader, MessageKind::DownloadStart);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e.to_string().contains("Expected DownloadStart message"));
+        }
+
+        let result = expect_frame(&mut reader, MessageKind::DownloadStart);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::ProtocolError { .. }));
+            assert!(e.to_string().contains("Something broke"));
+        }
+    }
+
+    #[test]
+    fn test_poll_frame() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = Transport::from(listener.accept().unwrap().0);
+        let mut pending = Vec::new();
+
+        // Nothing sent yet
+        assert!(poll_frame(&mut server, &mut pending).unwrap().is_none());
+
+        // Partial frame is kept until complete
+        let bytes = encode_frame(MessageKind::Ping, &3u32.to_be_bytes());
+        client.write_all(&bytes[..3]).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(poll_frame(&mut server, &mut pending).unwrap().is_none());
+        assert_eq!(pending.len(), 3);
+
+        // Following frame is left on the stream
+        client.write_all(&bytes[3..]).unwrap();
+        write_frame(&mut client, MessageKind::DownloadStop, &[]).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let frame = poll_frame(&mut server, &mut pending).unwrap().unwrap();
+        assert_eq!(
+            frame,
+            Frame::new(MessageKind::Ping, 3u32.to_be_bytes().to_vec())
+        );
+        assert!(pending.is_empty());
+
+        let frame = read_frame(&mut server).unwrap();
+        assert_eq!(frame.kind, MessageKind::DownloadStop);
+
+        // Closed connection
+        drop(client);
+        std::thread::sleep(Duration::from_millis(50));
+        let result = poll_frame(&mut server, &mut pending);
+        assert!(matches!(result, Err(NetbeatError::ConnectionError(_))));
+    }
+
+    #[test]
+    fn test_hello_round_trip() {
+        let mut buffer = Vec::new();
+        write_hello(&mut buffer, &Hello::local()).unwrap();
+        assert_eq!(buffer[0], MessageKind::Hello as u8);
+
+        let hello = read_hello(&mut buffer.as_slice()).unwrap();
+        assert_eq!(hello, Hello::local());
+        assert_eq!(hello.version_string(), "NETBEAT_2.0");
+        assert!(hello.supports(CAP_PING | CAP_UPLOAD | CAP_DOWNLOAD));
+        assert!(check_version(&hello).is_ok());
+
+        assert!(Hello::decode(&[]).is_err());
+        assert!(Hello::decode(&[3, b'a', b'b']).is_err());
+    }
+
+    #[test]
+    fn test_hello_version_mismatch() {
+        let peer = Hello {
+            version: b"NETBEAT_9.9".to_vec(),
+            capabilities: CAP_PING,
+        };
+        assert!(!peer.supports(CAP_UPLOAD));
+
+        let result = check_version(&peer);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::VersionMismatch { .. }));
+            assert!(e.to_string().contains("NETBEAT_2.0"));
+            assert!(e.to_string().contains("NETBEAT_9.9"));
+        }
+
+        // Peers without framing open with raw byte markers
+        let result = read_hello(&mut b"NETBEAT_PINGNETBEAT_PING".as_slice());
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(matches!(e, NetbeatError::VersionMismatch { .. }));
+        }
+    }
+
+    #[test]
+    fn test_transfer_result_round_trip() {
+        let result = TransferResult {
+            bytes: 1_250_000_000,
+            duration: Duration::from_nanos(10_000_123_456),
+            intervals: Vec::new(),
+        };
+        let payload = result.encode();
+        assert_eq!(payload.len(), 20);
+        assert_eq!(TransferResult::decode(&payload).unwrap(), result);
+
+        let result = TransferResult {
+            intervals: vec![IntervalSample {
+                start: Duration::ZERO,
+                end: Duration::from_secs(1),
+                bytes: 125_000_000,
+            }],
+            ..result
+        };
+        assert_eq!(TransferResult::decode(&result.encode()).unwrap(), result);
+
+        let result = TransferResult::decode(&payload[..15]);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e.to_string().contains("Malformed transfer result"));
+        }
+    }
+
+    #[test]
+    fn test_session_join_round_trip() {
+        let join = SessionJoin {
+            session_id: 0xDEAD_BEEF_0BAD_F00D,
+            stream: 3,
+        };
+        let payload = join.encode();
+        assert_eq!(payload.len(), 12);
+        assert_eq!(SessionJoin::decode(&payload).unwrap(), join);
+
+        let result = SessionJoin::decode(&payload[..11]);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e.to_string().contains("Malformed session join"));
+        }
+
+        assert_eq!(decode_session_id(&42u64.to_be_bytes()).unwrap(), 42);
+        assert!(decode_session_id(&[]).is_err());
+    }
+
+    #[test]
+    fn test_test_plan_round_trip() {
+        let plan = TestPlan {
+            tests: ALL_TESTS,
+            duration: Duration::from_secs(10),
+            bytes: None,
+            chunk_size: 64 * 1024,
+            streams: 1,
+            interval: Duration::from_millis(500),
+        };
+        assert!(plan.includes(TEST_PING | TEST_DOWNLOAD));
+        assert_eq!(TestPlan::decode(&plan.encode()).unwrap(), plan);
+
+        let plan = TestPlan {
+            tests: TEST_UPLOAD,
+            bytes: Some(100_000_000),
+            ..plan
+        };
+        assert!(!plan.includes(TEST_PING));
+        assert_eq!(TestPlan::decode(&plan.encode()).unwrap(), plan);
+        assert_eq!(plan.connections(), 1);
+
+        let plan = TestPlan {
+            tests: TEST_UPLOAD | TEST_DOWNLOAD | TEST_BIDIRECTIONAL,
+            streams: 4,
+            ..plan
+        };
+        assert_eq!(TestPlan::decode(&plan.encode()).unwrap(), plan);
+        assert_eq!(plan.connections(), 8);
+        assert_eq!(plan.probe_stream(), None);
+
+        let plan = TestPlan {
+            tests: plan.tests | TEST_LATENCY_PROBE,
+            ..plan
+        };
+        assert_eq!(TestPlan::decode(&plan.encode()).unwrap(), plan);
+        assert_eq!(plan.connections(), 9);
+        assert_eq!(plan.probe_stream(), Some(8));
+
+        let mut payload = plan.encode();
+        payload[9] = 7;
+        assert!(TestPlan::decode(&payload).is_err());
+        assert!(TestPlan::decode(&payload[..10]).is_err());
+    }
+
     #[test]
     fn test_validate_chunk_size() {
         let size = validate_chunk_size("1024", "server").unwrap();
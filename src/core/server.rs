@@ -1,41 +1,346 @@
 //! Core Server functionality for netbeat.
 
-use super::{config, protocol};
+use super::{
+    address,
+    auth::{self, PreSharedKey},
+    config,
+    intervals::IntervalRecorder,
+    profile::ServerProfile,
+    protocol::{self, Frame, MessageKind, TestPlan},
+    tls::{TlsServer, Transport},
+    udp::{self, UdpParams, UdpReady, UdpReceiver, UdpSent},
+};
 use crate::utils::{
     error::{NetbeatError, Result},
     logging::Logger,
 };
-use byte_unit::Byte;
+use byte_unit::{Byte, UnitType};
 use spinners::{Spinner, Spinners};
 use std::{
-    io::{self, Read},
-    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
-    str::FromStr,
-    sync::{Arc, Mutex},
-    thread,
-    time::Duration,
+    collections::HashMap,
+    io,
+    net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+/// Interval between polls of the listeners when no connection is pending
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Connections being handled, keyed by connection id, so they can be closed on shutdown
+type ConnectionRegistry = Arc<Mutex<HashMap<u64, TcpStream>>>;
+
+/// Sessions accepting additional streams, keyed by session id
+type SessionRegistry = Arc<Mutex<HashMap<u64, SessionEntry>>>;
+
+/// Accounting for a session in progress, so extra streams can join it.
+#[derive(Debug)]
+struct SessionEntry {
+    /// Test plan agreed at session setup
+    plan: TestPlan,
+    /// Address of the client that set up the session
+    peer: IpAddr,
+    /// Number of streams connected so far, including the session connection
+    streams: u32,
+    /// Resources used by all streams of the session
+    usage: Arc<SessionUsage>,
+}
+
+/// Server limits a client test plan must fit within.
+#[derive(Debug, Clone, Copy)]
+struct PlanLimits {
+    /// Maximum concurrent connections, which caps the connections a single plan may need
+    max_connections: u32,
+    /// Largest chunk size downloads are sent in, whatever the client negotiates
+    max_chunk_size: u64,
+}
+
+impl PlanLimits {
+    /// Check a client test plan against the server limits, returning the reason for rejection.
+    fn check(&self, plan: &TestPlan) -> std::result::Result<(), String> {
+        if plan.connections() > self.max_connections {
+            return Err(format!(
+                "Test plan needs {} connections, more than the server maximum of {}",
+                plan.connections(),
+                self.max_connections
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fit a checked client test plan to the server limits.
+    fn clamp(&self, plan: TestPlan) -> TestPlan {
+        TestPlan {
+            chunk_size: plan.chunk_size.min(self.max_chunk_size),
+            ..plan
+        }
+    }
+}
+
+/// Limits on the resources a single client session may use.
+///
+/// A session going over a limit is ended with an error reported to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionLimits {
+    /// Maximum time from session setup to the end of the last test, if limited
+    pub max_duration: Option<Duration>,
+    /// Maximum bytes transferred over the session in both directions together, if limited
+    pub max_bytes: Option<u64>,
+    /// Maximum bytes transferred over the session in each direction, if limited
+    pub max_direction_bytes: Option<u64>,
+    /// Time the server waits on a client that sends (or reads) nothing within a test phase
+    pub idle_timeout: Duration,
+}
+
+/// Resources used by a session so far, shared by all of its streams.
+#[derive(Debug)]
+struct SessionUsage {
+    started: Instant,
+    uploaded: AtomicU64,
+    downloaded: AtomicU64,
+}
+
+/// Session limits along with the usage of the session they apply to.
+#[derive(Debug, Clone)]
+struct SessionBudget {
+    limits: SessionLimits,
+    usage: Arc<SessionUsage>,
+}
+
+impl SessionLimits {
+    /// Error for a client that went quiet for longer than the idle timeout.
+    fn idle(&self, phase: &str) -> NetbeatError {
+        NetbeatError::session_limit(format!(
+            "client idle for longer than {} {phase}",
+            humantime::format_duration(self.idle_timeout)
+        ))
+    }
+}
+
+/// A session limit, along with its maximum.
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    Duration(Duration),
+    DirectionBytes(&'static str, u64),
+    SessionBytes(u64),
+}
+
+impl Limit {
+    /// Error ending a session that hit this limit.
+    fn error(self) -> NetbeatError {
+        NetbeatError::session_limit(match self {
+            Limit::Duration(max) => format!(
+                "maximum session duration of {} reached",
+                humantime::format_duration(max)
+            ),
+            Limit::DirectionBytes(direction, max) => {
+                format!("maximum {direction} of {} exceeded", format_bytes(max))
+            }
+            Limit::SessionBytes(max) => {
+                format!("maximum session transfer of {} exceeded", format_bytes(max))
+            }
+        })
+    }
+}
+
+impl SessionBudget {
+    /// Start tracking a new session.
+    fn new(limits: SessionLimits) -> Self {
+        Self {
+            limits,
+            usage: Arc::new(SessionUsage {
+                started: Instant::now(),
+                uploaded: AtomicU64::new(0),
+                downloaded: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Ensure the session has not run for its maximum duration.
+    fn check_duration(&self) -> Result<()> {
+        match self.limits.max_duration {
+            Some(max) if self.usage.started.elapsed() >= max => Err(Limit::Duration(max).error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record bytes received from the client, failing once the session goes over a limit.
+    fn record_upload(&self, bytes: u64) -> Result<()> {
+        let uploaded = self.usage.uploaded.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.check_bytes("upload", uploaded)
+    }
+
+    /// Record bytes sent to the client, failing once the session goes over a limit.
+    fn record_download(&self, bytes: u64) -> Result<()> {
+        let downloaded = self.usage.downloaded.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.check_bytes("download", downloaded)
+    }
+
+    /// Reserve up to `wanted` bytes to send to the client, as much as fits within the session limits.
+    ///
+    /// Reserving is atomic, so parallel streams together stay within the limits. Fails once nothing fits.
+    fn reserve_download(&self, wanted: u64) -> Result<u64> {
+        self.check_duration()?;
+        let mut granted = wanted;
+        let mut exhausted = None;
+        let reserved = self.usage.downloaded.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |downloaded| match self.download_room(downloaded) {
+                Some((0, limit)) => {
+                    exhausted = Some(limit);
+                    None
+                }
+                Some((room, _)) => {
+                    granted = room.min(wanted);
+                    Some(downloaded + granted)
+                }
+                None => Some(downloaded + wanted),
+            },
+        );
+        match (reserved, exhausted) {
+            (Err(_), Some(limit)) => Err(limit.error()),
+            _ => Ok(granted),
+        }
+    }
+
+    /// Bytes left to send before the tightest byte limit, given the bytes sent so far, if any applies.
+    fn download_room(&self, downloaded: u64) -> Option<(u64, Limit)> {
+        let uploaded = self.usage.uploaded.load(Ordering::Relaxed);
+        let direction = self.limits.max_direction_bytes.map(|max| {
+            (
+                max.saturating_sub(downloaded),
+                Limit::DirectionBytes("download", max),
+            )
+        });
+        let session = self.limits.max_bytes.map(|max| {
+            (
+                max.saturating_sub(uploaded + downloaded),
+                Limit::SessionBytes(max),
+            )
+        });
+        [direction, session]
+            .into_iter()
+            .flatten()
+            .min_by_key(|(room, _)| *room)
+    }
+
+    /// Ensure the session is within its byte limits, given the bytes moved in one direction, and its duration.
+    fn check_bytes(&self, direction: &'static str, moved: u64) -> Result<()> {
+        if let Some(max) = self.limits.max_direction_bytes
+            && moved > max
+        {
+            return Err(Limit::DirectionBytes(direction, max).error());
+        }
+        if let Some(max) = self.limits.max_bytes
+            && self.transferred() > max
+        {
+            return Err(Limit::SessionBytes(max).error());
+        }
+        self.check_duration()
+    }
+
+    /// Bytes moved in both directions so far.
+    fn transferred(&self) -> u64 {
+        self.usage.uploaded.load(Ordering::Relaxed) + self.usage.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Fit a UDP download into what is left of the session, given its paced bitrate.
+    ///
+    /// Returns the duration and byte target to send with, along with the limit to report if the test
+    /// was cut short.
+    fn cap_udp_download(
+        &self,
+        duration: Duration,
+        bytes: Option<u64>,
+        bitrate: u64,
+    ) -> (Duration, Option<u64>, Option<NetbeatError>) {
+        let byte_rate = (bitrate / 8).max(1) as f64;
+        let mut room = self.download_room(self.usage.downloaded.load(Ordering::Relaxed));
+        if let Some(max) = self.limits.max_duration {
+            let remaining = max.saturating_sub(self.usage.started.elapsed());
+            let time_room = (remaining.as_secs_f64() * byte_rate) as u64;
+            if room.is_none_or(|(bytes_room, _)| time_room < bytes_room) {
+                room = Some((time_room, Limit::Duration(max)));
+            }
+        }
+
+        // Byte-based tests are capped on bytes, time-based tests on the time to send the bytes left
+        let wanted = bytes.unwrap_or((duration.as_secs_f64() * byte_rate) as u64);
+        match room {
+            Some((room, limit)) if room < wanted => match bytes {
+                Some(_) => (duration, Some(room), Some(limit.error())),
+                None => (
+                    Duration::from_secs_f64(room as f64 / byte_rate),
+                    None,
+                    Some(limit.error()),
+                ),
+            },
+            _ => (duration, bytes, None),
+        }
+    }
+}
+
+/// Format a byte count with the most readable decimal unit.
+fn format_bytes(bytes: u64) -> String {
+    format!(
+        "{:.2}",
+        Byte::from_u64(bytes).get_appropriate_unit(UnitType::Decimal)
+    )
+}
+
 /// Core `Server` struct for netbet.
 #[derive(Debug, Clone)]
 pub struct Server {
-    /// Socket address to listen on server.
-    pub socket_addr: SocketAddr,
-    /// Buffer size for read/write operations (eg, 32KiB, 64KiB, 128KiB).
+    /// Socket addresses to listen on server.
+    pub socket_addrs: Vec<SocketAddr>,
+    /// Largest chunk size downloads are sent in (eg, 32KiB, 64KiB, 128KiB), capping the one each client negotiates.
     pub chunk_size: u64,
     /// Maximum concurrent connections
     pub max_connections: u32,
+    /// Pre-shared key clients must authenticate with, if any
+    pub psk: Option<PreSharedKey>,
+    /// TLS settings, when connections are encrypted
+    pub tls: Option<TlsServer>,
+    /// Time active connections get to finish on shutdown before they are closed
+    pub drain_timeout: Duration,
+    /// Limits on the resources a single client session may use
+    pub limits: SessionLimits,
     /// Netbeat custom logger
     pub logger: Logger,
 }
 
+/// Handle to a server listening on a background thread, returned by [`Server::spawn`].
+///
+/// Dropping the handle leaves the server running, call [`ServerHandle::shutdown`] to stop it.
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<()>>,
+}
+
 /// Builder for `Server` struct.
 #[derive(Debug, Default)]
 pub struct ServerBuilder {
-    interface: Option<config::BindInterface>,
+    interfaces: Vec<config::BindInterface>,
     port: Option<u16>,
     chunk_size: Option<String>,
     max_connections: Option<u32>,
+    psk: Option<String>,
+    tls: Option<bool>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    drain_timeout: Option<u64>,
+    max_session_duration: Option<u64>,
+    max_session_bytes: Option<String>,
+    max_direction_bytes: Option<String>,
+    idle_timeout: Option<u64>,
     quiet: Option<bool>,
     verbose: Option<bool>,
 }
@@ -48,82 +353,658 @@ impl Server {
 
     /// Listen for incoming client connections to run speed test.
     pub fn listen(&self) -> Result<()> {
-        let listener =
-            TcpListener::bind(self.socket_addr).map_err(NetbeatError::ConnectionError)?;
-        self.logger.info(&format!(
-            "📡 Server Listening on {}",
-            listener.local_addr().unwrap()
-        ));
+        self.serve(self.bind()?, &AtomicBool::new(false))
+    }
 
-        let connection_count = Arc::new(Mutex::new(0usize));
-
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    {
-                        let mut count = connection_count.lock().unwrap();
-                        if *count >= self.max_connections as usize {
-                            self.logger.error(&format!(
-                                "Maximum connections reached, rejecting {}.",
-                                stream.peer_addr().unwrap()
-                            ));
-                            drop(stream);
-                            continue;
-                        }
-                        *count += 1;
-                    }
-                    stream
-                        .set_nodelay(true)
-                        .map_err(NetbeatError::ConnectionError)?;
-                    stream
-                        .set_write_timeout(Some(Duration::from_secs(30)))
-                        .map_err(NetbeatError::ConnectionError)?;
-
-                    self.logger.info(&format!(
-                        "\n🔗 New connection from {}",
-                        stream.peer_addr().unwrap()
-                    ));
+    /// Listen for incoming client connections until `stop` is set, then drain the active connections.
+    pub fn listen_until(&self, stop: &AtomicBool) -> Result<()> {
+        self.serve(self.bind()?, stop)
+    }
+
+    /// Start listening on a background thread, returning a handle to learn the bound addresses and stop the server.
+    ///
+    /// Listeners are bound before returning, so clients can connect as soon as the handle is available.
+    pub fn spawn(&self) -> Result<ServerHandle> {
+        let listeners = self.bind()?;
+        let local_addrs = listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<io::Result<Vec<SocketAddr>>>()
+            .map_err(NetbeatError::ConnectionError)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = self.clone();
+        let flag = Arc::clone(&stop);
+        let thread = thread::spawn(move || server.serve(listeners, &flag));
+        Ok(ServerHandle {
+            local_addrs,
+            stop,
+            thread,
+        })
+    }
 
-                    let count_clone = Arc::clone(&connection_count);
-                    let chunk_size = self.chunk_size;
-                    let logger = self.logger.clone();
-                    thread::spawn(move || {
-                        let result = handle_client(stream, chunk_size, &logger);
-                        if let Err(e) = result {
-                            logger.error(&format!("Error handling client - {e}"));
+    /// Accept connections on the listeners until `stop` is set, then drain the active connections.
+    fn serve(&self, listeners: Vec<TcpListener>, stop: &AtomicBool) -> Result<()> {
+        let connections = ConnectionRegistry::default();
+        let sessions = SessionRegistry::default();
+        let mut next_id = 0;
+        if self.psk.is_some() {
+            self.logger
+                .info("🔒 Clients must authenticate with the pre-shared key");
+        }
+        if let Some(tls) = &self.tls {
+            self.logger.info(&format!(
+                "🔐 TLS enabled, certificate fingerprint {}",
+                tls.fingerprint
+            ));
+        }
+
+        while !stop.load(Ordering::SeqCst) {
+            let mut accepted = false;
+            for listener in &listeners {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        accepted = true;
+                        next_id += 1;
+                        // A connection failing to set up must not stop the server
+                        if let Err(e) =
+                            self.handle_connection(stream, next_id, &connections, &sessions)
+                        {
+                            self.logger
+                                .error(&format!("Failed to set up connection - {e}"));
                         }
-                        let mut count = count_clone.lock().unwrap();
-                        *count -= 1;
-                    });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => self.logger.error(&format!("Connection failed - {e}")),
                 }
-                Err(e) => self.logger.error(&format!("Connection failed - {e}")),
+            }
+            if !accepted {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
             }
         }
+
+        drop(listeners);
+        self.drain(&connections);
+        Ok(())
+    }
+
+    /// Wait for the active connections to finish, closing any left once the drain timeout has passed.
+    fn drain(&self, connections: &ConnectionRegistry) {
+        let active = connections.lock().unwrap().len();
+        if active > 0 {
+            self.logger.info(&format!(
+                "⏳ Waiting up to {:?} for {active} active connection(s) to finish...",
+                self.drain_timeout
+            ));
+        }
+        let deadline = Instant::now() + self.drain_timeout;
+        while !connections.lock().unwrap().is_empty() && Instant::now() < deadline {
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+
+        let remaining = connections.lock().unwrap();
+        if !remaining.is_empty() {
+            self.logger.warn(&format!(
+                "Closing {} connection(s) still active after {:?}",
+                remaining.len(),
+                self.drain_timeout
+            ));
+            for stream in remaining.values() {
+                // Best effort, the handler may be closing it at the same time
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        self.logger.info("🛑 Server stopped");
+    }
+
+    /// Bind a non-blocking listener on every socket address.
+    fn bind(&self) -> Result<Vec<TcpListener>> {
+        // `::` only covers IPv4 as well when IPv4 is not requested separately
+        let dual_stack = !self
+            .socket_addrs
+            .iter()
+            .any(|addr| addr.is_ipv4() && addr.ip().is_unspecified());
+
+        let mut listeners = Vec::with_capacity(self.socket_addrs.len());
+        for addr in &self.socket_addrs {
+            let listener = address::bind_listener(*addr, dual_stack)
+                .map_err(|e| NetbeatError::server(format!("Failed to listen on {addr} - {e}")))?;
+            listener
+                .set_nonblocking(true)
+                .map_err(NetbeatError::ConnectionError)?;
+            self.logger.info(&format!(
+                "📡 Server Listening on {}",
+                listener.local_addr().unwrap()
+            ));
+            listeners.push(listener);
+        }
+        Ok(listeners)
+    }
+
+    /// Limits client test plans must fit within on this server.
+    fn plan_limits(&self) -> PlanLimits {
+        PlanLimits {
+            max_connections: self.max_connections,
+            max_chunk_size: self.chunk_size,
+        }
+    }
+
+    /// Set up an accepted connection and handle it on its own thread, registering it only once set up.
+    fn handle_connection(
+        &self,
+        stream: TcpStream,
+        id: u64,
+        connections: &ConnectionRegistry,
+        sessions: &SessionRegistry,
+    ) -> Result<()> {
+        let peer = stream.peer_addr().map_err(NetbeatError::ConnectionError)?;
+        // Accepted sockets may inherit non-blocking mode from the listener
+        stream
+            .set_nonblocking(false)
+            .map_err(NetbeatError::ConnectionError)?;
+        stream
+            .set_nodelay(true)
+            .map_err(NetbeatError::ConnectionError)?;
+        stream
+            .set_write_timeout(Some(self.limits.idle_timeout))
+            .map_err(NetbeatError::ConnectionError)?;
+        let registered = stream.try_clone().map_err(NetbeatError::ConnectionError)?;
+
+        {
+            let mut active = connections.lock().unwrap();
+            if active.len() >= self.max_connections as usize {
+                self.logger
+                    .error(&format!("Maximum connections reached, rejecting {peer}."));
+                // Best effort and off the accept loop, the client may already be gone
+                let idle_timeout = self.limits.idle_timeout;
+                thread::spawn(move || {
+                    let mut stream = Transport::from(stream);
+                    let _ = protocol::write_error(&mut stream, "Maximum connections reached");
+                    discard_until_closed(&mut stream, idle_timeout);
+                });
+                return Ok(());
+            }
+            active.insert(id, registered);
+        }
+
+        let connections = Arc::clone(connections);
+        let sessions = Arc::clone(sessions);
+        let psk = self.psk.clone();
+        let tls = self.tls.clone();
+        let limits = self.limits;
+        let plan_limits = self.plan_limits();
+        let logger = self.logger.clone();
+        thread::spawn(move || {
+            let result = handle_client(
+                stream,
+                &sessions,
+                tls.as_ref(),
+                psk.as_ref(),
+                &limits,
+                &plan_limits,
+                &logger,
+            );
+            if let Err(e) = result {
+                logger.error(&format!("Error handling client - {e}"));
+            }
+            connections.lock().unwrap().remove(&id);
+        });
         Ok(())
     }
 }
 
-fn handle_client(mut stream: TcpStream, chunk_size: u64, logger: &Logger) -> Result<()> {
-    // Ping Test
-    handle_ping_test(&mut stream, logger)
-        .map_err(|e| NetbeatError::test_execution(format!("Ping test failed - {e}")))?;
+impl ServerHandle {
+    /// First address the server is listening on, with the port picked by the OS when the server was built with port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    /// Every address the server is listening on.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Stop accepting connections and start draining the active ones. Use [`ServerHandle::join`] to wait until done.
+    pub fn shutdown(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Wait for the server to stop, returning the error it stopped with, if any.
+    pub fn join(self) -> Result<()> {
+        self.thread
+            .join()
+            .map_err(|_| NetbeatError::server("Server thread panicked".to_string()))?
+    }
+}
+
+fn handle_client(
+    mut stream: TcpStream,
+    sessions: &SessionRegistry,
+    tls: Option<&TlsServer>,
+    psk: Option<&PreSharedKey>,
+    limits: &SessionLimits,
+    plan_limits: &PlanLimits,
+    logger: &Logger,
+) -> Result<()> {
+    let peer = stream.peer_addr().map_err(NetbeatError::ConnectionError)?;
+
+    // Version Handshake, in the clear
+    if let Err(e) = handle_handshake(
+        &mut stream,
+        tls.is_some(),
+        psk.is_some(),
+        limits.idle_timeout,
+        logger,
+    ) {
+        // Best effort, the client may already be gone
+        let _ = protocol::write_error(&mut stream, &e.to_string());
+        return Err(e);
+    }
+
+    // Everything after the hello goes over TLS when the server has it enabled
+    let mut stream = match tls {
+        Some(tls) => {
+            let (stream, report) = tls.accept(stream)?;
+            logger.verbose(&format!(
+                "TLS handshake with {peer} completed in {:.2?} ({}, {})",
+                report.handshake_time, report.version, report.cipher_suite
+            ));
+            stream
+        }
+        None => Transport::from(stream),
+    };
+    let result = handle_session(
+        &mut stream,
+        peer,
+        sessions,
+        psk,
+        limits,
+        plan_limits,
+        logger,
+    );
+    if let Err(e) = &result {
+        // Best effort, the client may already be gone
+        let _ = protocol::write_error(&mut stream, &e.to_string());
+        if matches!(e, NetbeatError::SessionLimit { .. }) {
+            discard_until_closed(&mut stream, limits.idle_timeout);
+        }
+    }
+    result
+}
+
+/// Discard what the client still sends until it closes the connection, for up to the idle timeout, so the
+/// error written last is not lost to a connection reset.
+fn discard_until_closed(stream: &mut Transport, idle_timeout: Duration) {
+    if stream
+        .socket()
+        .set_read_timeout(Some(idle_timeout))
+        .is_err()
+    {
+        return;
+    }
+    let deadline = Instant::now() + idle_timeout;
+    let mut buffer = [0u8; 16 * 1024];
+    while Instant::now() < deadline && matches!(io::Read::read(stream, &mut buffer), Ok(1..)) {}
+}
+
+/// Whether an I/O error is a socket read or write timing out.
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn handle_session(
+    stream: &mut Transport,
+    peer: SocketAddr,
+    sessions: &SessionRegistry,
+    psk: Option<&PreSharedKey>,
+    limits: &SessionLimits,
+    plan_limits: &PlanLimits,
+    logger: &Logger,
+) -> Result<()> {
+    // Authentication, when the server holds a pre-shared key
+    if let Some(psk) = psk {
+        auth::authenticate_client(stream, psk)?;
+        logger.verbose("Client authenticated with the pre-shared key");
+    }
+
+    // Session Setup, or an extra stream joining a session in progress
+    let frame = protocol::read_frame(stream)
+        .map_err(|e| NetbeatError::protocol(format!("Failed to read session setup - {e}")))?;
+    match frame.kind {
+        MessageKind::SessionSetup => {
+            let plan = handle_session_setup(stream, &frame, plan_limits, logger)?;
+            let budget = SessionBudget::new(*limits);
+            let session_id = register_session(sessions, plan, peer.ip(), &budget);
+            protocol::write_frame(
+                stream,
+                MessageKind::SessionAccept,
+                &session_id.to_be_bytes(),
+            )
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send session accept - {e}")))?;
+            let mode = match (plan.includes(protocol::TEST_BIDIRECTIONAL), plan.streams) {
+                (true, 1) => " (bidirectional)".to_string(),
+                (true, streams) => format!(" (bidirectional, {streams} parallel streams)"),
+                (false, 1) if plan.includes(protocol::TEST_UDP) => " (UDP)".to_string(),
+                (false, 1) => String::new(),
+                (false, streams) => format!(" ({streams} parallel streams)"),
+            };
+            logger.info(&format!("\n🔗 New connection from {peer}{mode}"));
+
+            let result = run_tests(stream, &plan, plan.tests, &budget, logger);
+            sessions.lock().unwrap().remove(&session_id);
+            result
+        }
+        MessageKind::SessionJoin => {
+            let (plan, stream_index, usage) =
+                handle_session_join(stream, &frame, sessions, peer.ip())?;
+            let budget = SessionBudget {
+                limits: *limits,
+                usage,
+            };
+            logger.verbose(&format!(
+                "Stream {} of {} joined session from {peer}",
+                stream_index + 1,
+                plan.connections()
+            ));
+
+            // Extra streams only carry bulk data or latency probes, progress is reported on the session connection
+            let stream_logger = Logger::new(logger.verbose, true);
+            let tests = if plan.probe_stream() == Some(stream_index) {
+                protocol::TEST_PING
+            } else {
+                plan.tests & (protocol::TEST_UPLOAD | protocol::TEST_DOWNLOAD)
+            };
+            run_tests(stream, &plan, tests, &budget, &stream_logger)
+        }
+        kind => Err(protocol::unexpected_message(
+            MessageKind::SessionSetup,
+            kind,
+            &frame.payload,
+        )),
+    }
+}
+
+/// Run tests requested by the client until it ends the session.
+fn run_tests(
+    stream: &mut Transport,
+    plan: &TestPlan,
+    tests: u8,
+    budget: &SessionBudget,
+    logger: &Logger,
+) -> Result<()> {
+    loop {
+        let frame = match protocol::read_frame(stream) {
+            Ok(frame) => frame,
+            Err(NetbeatError::ConnectionError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                logger.verbose("Client closed connection");
+                break;
+            }
+            Err(NetbeatError::ConnectionError(e)) if is_timeout(&e) => {
+                return Err(budget.limits.idle("between tests"));
+            }
+            Err(e) => return Err(e),
+        };
 
-    thread::sleep(Duration::from_millis(50));
+        let test = match frame.kind {
+            MessageKind::Ping => protocol::TEST_PING,
+            MessageKind::UploadStart => protocol::TEST_UPLOAD,
+            MessageKind::DownloadStart => protocol::TEST_DOWNLOAD,
+            MessageKind::UdpStart => protocol::TEST_UDP,
+            _ => 0,
+        };
+        let wrong_transport = matches!(
+            frame.kind,
+            MessageKind::UploadStart | MessageKind::DownloadStart
+        ) && plan.includes(protocol::TEST_UDP);
+        if tests & test != test || wrong_transport {
+            return Err(NetbeatError::protocol(format!(
+                "{:?} message for a test outside of the session plan",
+                frame.kind
+            )));
+        }
+        if test != 0 {
+            budget.check_duration()?;
+        }
 
-    // Upload Test
-    handle_upload_test(&mut stream, chunk_size, logger)
-        .map_err(|e| NetbeatError::test_execution(format!("Upload test failed - {e}")))?;
+        match frame.kind {
+            MessageKind::Ping => {
+                handle_ping_test(stream, &frame, budget, logger).map_err(test_failed("Ping"))?
+            }
+            MessageKind::UploadStart => {
+                handle_upload_test(stream, plan, budget, logger).map_err(test_failed("Upload"))?
+            }
+            MessageKind::DownloadStart => handle_download_test(stream, plan, budget, logger)
+                .map_err(test_failed("Download"))?,
+            MessageKind::UdpStart => {
+                handle_udp_test(stream, &frame, plan, budget, logger).map_err(test_failed("UDP"))?
+            }
+            MessageKind::Goodbye => {
+                logger.verbose("Client ended session");
+                break;
+            }
+            kind => {
+                return Err(NetbeatError::protocol(format!(
+                    "Unexpected {kind:?} message between tests"
+                )));
+            }
+        }
+    }
 
-    thread::sleep(Duration::from_millis(50));
+    Ok(())
+}
 
-    // Download Test
-    handle_download_test(&mut stream, chunk_size, logger)
-        .map_err(|e| NetbeatError::test_execution(format!("Download test failed - {e}")))?;
+/// Wrap the error of a failed test, keeping session limit errors as they are so the client sees the limit.
+fn test_failed(test: &str) -> impl Fn(NetbeatError) -> NetbeatError + '_ {
+    move |e| match e {
+        NetbeatError::SessionLimit { .. } => e,
+        e => NetbeatError::test_execution(format!("{test} test failed - {e}")),
+    }
+}
+
+fn handle_handshake(
+    stream: &mut TcpStream,
+    tls: bool,
+    psk: bool,
+    idle_timeout: Duration,
+    logger: &Logger,
+) -> Result<()> {
+    stream
+        .set_read_timeout(Some(idle_timeout))
+        .map_err(NetbeatError::ConnectionError)?;
+
+    let client_hello = protocol::read_hello(stream)?;
+    logger.verbose(&format!(
+        "Client protocol version {} (capabilities {:#x})",
+        client_hello.version_string(),
+        client_hello.capabilities
+    ));
+
+    // Always answer so the client can report a mismatch as well
+    let mut hello = protocol::Hello::local();
+    if tls {
+        hello.capabilities |= protocol::CAP_TLS;
+    }
+    if psk {
+        hello.capabilities |= protocol::CAP_AUTH;
+    }
+    protocol::write_hello(stream, &hello)
+        .map_err(|e| NetbeatError::protocol(format!("Failed to send hello message - {e}")))?;
 
+    protocol::check_version(&client_hello)?;
+    if tls && !client_hello.supports(protocol::CAP_TLS) {
+        return Err(NetbeatError::tls(
+            "Client did not enable TLS, which the server requires".to_string(),
+        ));
+    }
     Ok(())
 }
 
-fn handle_ping_test(stream: &mut TcpStream, logger: &Logger) -> Result<()> {
+fn handle_session_setup(
+    stream: &mut Transport,
+    frame: &Frame,
+    limits: &PlanLimits,
+    logger: &Logger,
+) -> Result<TestPlan> {
+    let plan = TestPlan::decode(&frame.payload)?;
+    logger.verbose(&format!("Client test plan - {plan:?}"));
+
+    if let Err(reason) = validate_plan(&plan).and_then(|()| limits.check(&plan)) {
+        logger.warn(&format!("Rejecting client test plan - {reason}"));
+        return reject_session(stream, reason);
+    }
+    Ok(limits.clamp(plan))
+}
+
+/// Record a new session so extra streams can join it, returning its id.
+fn register_session(
+    sessions: &SessionRegistry,
+    plan: TestPlan,
+    peer: IpAddr,
+    budget: &SessionBudget,
+) -> u64 {
+    let mut sessions = sessions.lock().unwrap();
+    let session_id = loop {
+        let id = rand::random::<u64>();
+        if !sessions.contains_key(&id) {
+            break id;
+        }
+    };
+    sessions.insert(
+        session_id,
+        SessionEntry {
+            plan,
+            peer,
+            streams: 1,
+            usage: Arc::clone(&budget.usage),
+        },
+    );
+    session_id
+}
+
+/// Attach an extra stream to a session in progress, returning the session plan, stream index and usage.
+fn handle_session_join(
+    stream: &mut Transport,
+    frame: &Frame,
+    sessions: &SessionRegistry,
+    peer: IpAddr,
+) -> Result<(TestPlan, u32, Arc<SessionUsage>)> {
+    let join = protocol::SessionJoin::decode(&frame.payload)?;
+    let joined = {
+        let mut sessions = sessions.lock().unwrap();
+        match sessions.get_mut(&join.session_id) {
+            Some(session) if session.peer != peer => {
+                Err("Stream must join from the address that set up the session".to_string())
+            }
+            Some(session) if session.streams >= session.plan.connections() => Err(format!(
+                "Session already has all of its {} streams",
+                session.plan.connections()
+            )),
+            Some(session) => {
+                session.streams += 1;
+                Ok((session.plan, Arc::clone(&session.usage)))
+            }
+            None => Err("Unknown session".to_string()),
+        }
+    };
+
+    match joined {
+        Ok((plan, usage)) => {
+            protocol::write_frame(
+                stream,
+                MessageKind::SessionAccept,
+                &join.session_id.to_be_bytes(),
+            )
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send session accept - {e}")))?;
+            Ok((plan, join.stream, usage))
+        }
+        Err(reason) => reject_session(stream, reason),
+    }
+}
+
+/// Send a session reject with the given reason to the client.
+fn reject_session<T>(stream: &mut Transport, reason: String) -> Result<T> {
+    protocol::write_frame(stream, MessageKind::SessionReject, reason.as_bytes())
+        .map_err(|e| NetbeatError::protocol(format!("Failed to send session reject - {e}")))?;
+    Err(NetbeatError::session_rejected(reason))
+}
+
+/// Validate a client test plan against the server limits, returning the reason for rejection.
+fn validate_plan(plan: &TestPlan) -> std::result::Result<(), String> {
+    if plan.tests & protocol::ALL_TESTS == 0
+        || plan.tests
+            & !(protocol::ALL_TESTS
+                | protocol::TEST_LATENCY_PROBE
+                | protocol::TEST_UDP
+                | protocol::TEST_BIDIRECTIONAL)
+            != 0
+    {
+        return Err(format!("Unsupported tests requested ({:#x})", plan.tests));
+    }
+    if plan.includes(protocol::TEST_BIDIRECTIONAL)
+        && !plan.includes(protocol::TEST_UPLOAD | protocol::TEST_DOWNLOAD)
+    {
+        return Err("Bidirectional mode requires both upload and download tests".to_string());
+    }
+    if plan.includes(protocol::TEST_LATENCY_PROBE)
+        && plan.tests & (protocol::TEST_UPLOAD | protocol::TEST_DOWNLOAD) == 0
+    {
+        return Err("Latency probes require an upload or download test".to_string());
+    }
+    if plan.includes(protocol::TEST_UDP) {
+        if plan.tests & (protocol::TEST_UPLOAD | protocol::TEST_DOWNLOAD) == 0 {
+            return Err("UDP mode requires an upload or download test".to_string());
+        }
+        if plan.streams != 1 || plan.includes(protocol::TEST_BIDIRECTIONAL) {
+            return Err("UDP mode runs a single stream in one direction at a time".to_string());
+        }
+    }
+    match plan.bytes {
+        Some(0) => return Err("Target data must be greater than zero".to_string()),
+        Some(_) => {}
+        None => {
+            let secs = plan.duration.as_secs();
+            if !(1..=config::MAX_TEST_DURATION).contains(&secs) {
+                return Err(format!(
+                    "Test duration of {secs}s outside allowed range of 1-{}s",
+                    config::MAX_TEST_DURATION
+                ));
+            }
+        }
+    }
+    if !(config::MIN_CHUNK_SIZE..=config::MAX_CHUNK_SIZE).contains(&plan.chunk_size) {
+        return Err(format!(
+            "Chunk size of {} bytes outside allowed range of {}-{} bytes",
+            plan.chunk_size,
+            config::MIN_CHUNK_SIZE,
+            config::MAX_CHUNK_SIZE
+        ));
+    }
+    if !(1..=config::MAX_STREAMS).contains(&plan.streams) {
+        return Err(format!(
+            "Stream count of {} outside allowed range of 1-{}",
+            plan.streams,
+            config::MAX_STREAMS
+        ));
+    }
+    let interval = plan.interval.as_secs_f64();
+    if !(config::MIN_INTERVAL..=config::MAX_INTERVAL).contains(&interval) {
+        return Err(format!(
+            "Reporting interval of {interval}s outside allowed range of {}-{}s",
+            config::MIN_INTERVAL,
+            config::MAX_INTERVAL
+        ));
+    }
+    Ok(())
+}
+
+fn handle_ping_test(
+    stream: &mut Transport,
+    first_ping: &Frame,
+    budget: &SessionBudget,
+    logger: &Logger,
+) -> Result<()> {
     let msg = "🏓 Running ping test for client...";
     let sp = if !logger.quiet & !logger.verbose {
         Some(Spinner::new(Spinners::Dots2, msg.into()))
@@ -133,43 +1014,41 @@ fn handle_ping_test(stream: &mut TcpStream, logger: &Logger) -> Result<()> {
     logger.verbose(msg);
 
     stream
-        .set_read_timeout(Some(Duration::from_secs(30)))
+        .socket()
+        .set_read_timeout(Some(budget.limits.idle_timeout))
         .map_err(NetbeatError::ConnectionError)?;
 
-    let mut ping_buffer = [0u8; protocol::PING_MESSAGE.len()];
-    let mut ping_count = 0;
+    protocol::write_frame(stream, MessageKind::Pong, &first_ping.payload)
+        .map_err(|e| NetbeatError::protocol(format!("Failed to send ping response - {e}")))?;
+    let mut ping_count = 1;
 
     loop {
-        match stream.read_exact(&mut ping_buffer) {
-            Ok(_) => {
-                if ping_buffer == protocol::PING_DONE {
+        match protocol::read_frame(stream) {
+            Ok(frame) => match frame.kind {
+                MessageKind::PingDone => {
                     logger.verbose(&format!("Ping test completed after {ping_count} pings"));
                     break;
-                } else if ping_buffer == protocol::PING_MESSAGE {
-                    match protocol::write_message(stream, protocol::PING_RESPONSE) {
-                        Ok(_) => {
-                            ping_count += 1;
-                            logger.verbose(&format!(
-                                "Ping response sent on ping number {ping_count}"
-                            ));
-                        }
-                        Err(e) => {
-                            logger.error(&format!("Failed to send ping response - {e}"));
-                            continue;
-                        }
-                    }
-                } else {
+                }
+                MessageKind::Ping => {
+                    // A client pinging on and on must not outlast the session
+                    budget.check_duration()?;
+                    protocol::write_frame(stream, MessageKind::Pong, &frame.payload).map_err(
+                        |e| NetbeatError::protocol(format!("Failed to send ping response - {e}")),
+                    )?;
+                    ping_count += 1;
+                    logger.verbose(&format!("Ping response sent on ping number {ping_count}"));
+                }
+                kind => {
                     logger.warn(&format!(
-                        "Received unexpected message during ping test - {:?}",
-                        ping_buffer
+                        "Received unexpected message during ping test - {kind:?}"
                     ));
                     continue;
                 }
+            },
+            Err(NetbeatError::ConnectionError(e)) if is_timeout(&e) => {
+                return Err(budget.limits.idle("during the ping test"));
             }
-            Err(e) => {
-                logger.error(&format!("Error reading from client - {e}"));
-                return Ok(());
-            }
+            Err(e) => return Err(e),
         }
     }
     if let Some(mut sp) = sp {
@@ -178,8 +1057,13 @@ fn handle_ping_test(stream: &mut TcpStream, logger: &Logger) -> Result<()> {
     Ok(())
 }
 
-fn handle_upload_test(stream: &mut TcpStream, chunk_size: u64, logger: &Logger) -> Result<()> {
-    let mut buffer = vec![0u8; chunk_size as usize];
+fn handle_upload_test(
+    stream: &mut Transport,
+    plan: &TestPlan,
+    budget: &SessionBudget,
+    logger: &Logger,
+) -> Result<()> {
+    let mut payload = Vec::with_capacity(plan.chunk_size as usize);
     let msg = "🚀 Running upload speed test for client...";
     let sp = if !logger.quiet & !logger.verbose {
         Some(Spinner::new(Spinners::Dots2, msg.into()))
@@ -188,47 +1072,60 @@ fn handle_upload_test(stream: &mut TcpStream, chunk_size: u64, logger: &Logger)
     };
     logger.verbose(msg);
 
-    // Wait for upload signal
-    let mut start_buf = [0u8; protocol::UPLOAD_START.len()];
-    stream
-        .read_exact(&mut start_buf)
-        .map_err(|e| NetbeatError::protocol(format!("Failed to read upload signal - {e}")))?;
-    if start_buf != *protocol::UPLOAD_START {
-        return Err(NetbeatError::protocol("Expected upload start".into()));
-    }
-
-    // Read data until termination signal
-    let termination_marker = protocol::UPLOAD_DONE;
-    let mut marker_pos = 0;
-
-    'read_loop: loop {
-        match stream.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(n) => {
-                for byte in &buffer[..n] {
-                    if *byte == termination_marker[marker_pos] {
-                        marker_pos += 1;
-                        if marker_pos == termination_marker.len() {
-                            break 'read_loop;
-                        }
-                    } else {
-                        marker_pos = 0;
-                    }
-                }
+    // Read data frames until termination message
+    let start_time = Instant::now();
+    let mut recorder = IntervalRecorder::new(plan.interval);
+    let mut bytes_received: u64 = 0;
+    loop {
+        match protocol::read_frame_into(stream, &mut payload) {
+            Ok(MessageKind::Data) => {
+                bytes_received += payload.len() as u64;
+                recorder.record(payload.len() as u64);
+                budget.record_upload(payload.len() as u64)?;
             }
-            Err(e) => {
-                logger.error(&format!("Error reading from client - {e}"));
-                return Ok(());
+            Ok(MessageKind::UploadDone) => break,
+            Ok(kind) => {
+                return Err(protocol::unexpected_message(
+                    MessageKind::UploadDone,
+                    kind,
+                    &payload,
+                ));
             }
+            Err(NetbeatError::ConnectionError(e)) if is_timeout(&e) => {
+                return Err(budget.limits.idle("during the upload test"));
+            }
+            Err(e) => return Err(e),
         }
     }
+    let duration = start_time.elapsed();
+    let upload_result = protocol::TransferResult {
+        bytes: bytes_received,
+        duration,
+        intervals: recorder.finish(duration),
+    };
+    logger.verbose(&format!(
+        "Received {bytes_received} bytes from client in {:.2?}",
+        upload_result.duration
+    ));
+
+    // Report server-side measurement back to client
+    protocol::write_frame(stream, MessageKind::UploadResult, &upload_result.encode())
+        .map_err(|e| NetbeatError::protocol(format!("Failed to send upload result - {e}")))?;
     if let Some(mut sp) = sp {
         sp.stop_with_message(format!("{msg} ✅ Completed."));
     }
     Ok(())
 }
 
-fn handle_download_test(stream: &mut TcpStream, chunk_size: u64, logger: &Logger) -> Result<()> {
+fn handle_download_test(
+    stream: &mut Transport,
+    plan: &TestPlan,
+    budget: &SessionBudget,
+    logger: &Logger,
+) -> Result<()> {
+    let chunk_size = plan
+        .chunk_size
+        .clamp(config::MIN_CHUNK_SIZE, config::MAX_CHUNK_SIZE);
     let random_buffer = protocol::generate_random_buffer(chunk_size as usize);
 
     let msg = "🚀 Running download speed test for client...";
@@ -239,36 +1136,181 @@ fn handle_download_test(stream: &mut TcpStream, chunk_size: u64, logger: &Logger
     };
     logger.verbose(msg);
 
-    // Wait for download signal
-    let mut start_buf = [0u8; protocol::DOWNLOAD_START.len()];
-    stream
-        .read_exact(&mut start_buf)
-        .map_err(|e| NetbeatError::protocol(format!("Failed to read download signal - {e}")))?;
-    if start_buf != *protocol::DOWNLOAD_START {
-        return Err(NetbeatError::protocol("Expected download start".into()));
+    let data_frame = protocol::encode_frame(MessageKind::Data, &random_buffer);
+    let poll_interval = Duration::from_millis(5);
+
+    // Send data frames until client asks to stop
+    let start_time = Instant::now();
+    let mut last_poll = Instant::now();
+    let mut pending = Vec::new();
+    let mut bytes_sent: u64 = 0;
+    loop {
+        // The last frame before a session limit is cut down to fit
+        let len = budget.reserve_download(random_buffer.len() as u64)?;
+        let result = if len == random_buffer.len() as u64 {
+            protocol::write_message(stream, &data_frame)
+        } else {
+            protocol::write_frame(stream, MessageKind::Data, &random_buffer[..len as usize])
+        };
+        match result {
+            Ok(()) => {}
+            Err(e) if is_timeout(&e) => return Err(budget.limits.idle("during the download test")),
+            Err(e) => {
+                return Err(NetbeatError::protocol(format!(
+                    "Failed to send download buffer - {e}"
+                )));
+            }
+        }
+        bytes_sent += len;
+
+        if last_poll.elapsed() >= poll_interval {
+            last_poll = Instant::now();
+            match protocol::poll_frame(stream, &mut pending)? {
+                Some(frame) if frame.kind == MessageKind::DownloadStop => break,
+                Some(frame) => {
+                    return Err(protocol::unexpected_message(
+                        MessageKind::DownloadStop,
+                        frame.kind,
+                        &frame.payload,
+                    ));
+                }
+                None => {}
+            }
+        }
     }
+    let download_result = protocol::TransferResult {
+        intervals: Vec::new(),
+        bytes: bytes_sent,
+        duration: start_time.elapsed(),
+    };
+    logger.verbose(&format!(
+        "Sent {bytes_sent} bytes to client in {:.2?}",
+        download_result.duration
+    ));
 
-    stream
-        .set_write_timeout(Some(Duration::from_secs(1)))
-        .map_err(NetbeatError::ConnectionError)?;
+    // Confirm exact byte count to client
+    protocol::write_frame(stream, MessageKind::DownloadDone, &download_result.encode())
+        .map_err(|e| NetbeatError::protocol(format!("Failed to send download result - {e}")))?;
 
-    loop {
-        match protocol::write_message(stream, &random_buffer) {
-            Ok(_) => {}
-            Err(e) => match e.kind() {
-                io::ErrorKind::TimedOut
-                | io::ErrorKind::BrokenPipe
-                | io::ErrorKind::ConnectionReset => {
-                    // Assuming client finished - this is brittle logic
-                    break;
+    if let Some(mut sp) = sp {
+        sp.stop_with_message(format!("{msg} ✅ Completed."));
+    }
+    Ok(())
+}
+
+fn handle_udp_test(
+    stream: &mut Transport,
+    start: &Frame,
+    plan: &TestPlan,
+    budget: &SessionBudget,
+    logger: &Logger,
+) -> Result<()> {
+    let params = UdpParams::decode(&start.payload)?;
+    if !matches!(
+        params.direction,
+        protocol::TEST_UPLOAD | protocol::TEST_DOWNLOAD
+    ) || !plan.includes(params.direction)
+    {
+        return Err(NetbeatError::protocol(format!(
+            "UDP test direction ({:#x}) outside of the session plan",
+            params.direction
+        )));
+    }
+    params.validate().map_err(NetbeatError::protocol)?;
+    let upload = params.direction == protocol::TEST_UPLOAD;
+
+    let msg = if upload {
+        "📶 Running UDP upload test for client..."
+    } else {
+        "📶 Running UDP download test for client..."
+    };
+    let sp = if !logger.quiet & !logger.verbose {
+        Some(Spinner::new(Spinners::Dots2, msg.into()))
+    } else {
+        None
+    };
+    logger.verbose(msg);
+
+    // Datagrams use the address the client reached the server on
+    let local_ip = stream
+        .socket()
+        .local_addr()
+        .map_err(NetbeatError::ConnectionError)?
+        .ip()
+        .to_canonical();
+    let socket = UdpSocket::bind((local_ip, 0))
+        .map_err(|e| NetbeatError::server(format!("Failed to bind UDP socket - {e}")))?;
+    let ready = UdpReady {
+        port: socket
+            .local_addr()
+            .map_err(NetbeatError::ConnectionError)?
+            .port(),
+        token: rand::random(),
+    };
+    protocol::write_frame(stream, MessageKind::UdpReady, &ready.encode())
+        .map_err(|e| NetbeatError::protocol(format!("Failed to send UDP ready - {e}")))?;
+
+    if upload {
+        // Receive datagrams until the client is done sending
+        let mut receiver = UdpReceiver::new();
+        let mut pending = Vec::new();
+        let mut recorded: u64 = 0;
+        let mut last_activity = Instant::now();
+        let sent = udp::receive_datagrams(
+            &socket,
+            &mut receiver,
+            params.datagram_size as usize,
+            |receiver| {
+                if receiver.bytes() > recorded {
+                    budget.record_upload(receiver.bytes() - recorded)?;
+                    recorded = receiver.bytes();
+                    last_activity = Instant::now();
+                } else if last_activity.elapsed() >= budget.limits.idle_timeout {
+                    return Err(budget.limits.idle("during the UDP upload test"));
                 }
-                _ => {
-                    logger.error(&format!("Unexpected error in download test - {e}"));
-                    break;
+                match protocol::poll_frame(stream, &mut pending)? {
+                    Some(frame) if frame.kind == MessageKind::UdpDone => {
+                        Ok(Some(UdpSent::decode(&frame.payload)?))
+                    }
+                    Some(frame) => Err(protocol::unexpected_message(
+                        MessageKind::UdpDone,
+                        frame.kind,
+                        &frame.payload,
+                    )),
+                    None => Ok(None),
                 }
             },
+        )?;
+        budget.record_upload(receiver.bytes() - recorded)?;
+        let stats = receiver.stats(&sent);
+        logger.verbose(&format!("UDP upload from client - {stats:?}"));
+
+        // Report server-side measurement back to client
+        protocol::write_frame(stream, MessageKind::UdpResult, &stats.encode())
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send UDP result - {e}")))?;
+    } else {
+        let client_addr = udp::wait_for_announce(&socket, ready.token)?;
+        socket
+            .connect(client_addr)
+            .map_err(NetbeatError::ConnectionError)?;
+        // Datagrams are paced, so the test is cut short up front to stay within the session limits
+        let (duration, bytes, limit) =
+            budget.cap_udp_download(plan.duration, plan.bytes, params.bitrate);
+        let sent = udp::send_datagrams(&socket, &params, duration, bytes)
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send datagram - {e}")))?;
+        logger.verbose(&format!(
+            "Sent {} datagrams to client in {:.2?}",
+            sent.datagrams, sent.duration
+        ));
+        if let Some(limit) = limit {
+            return Err(limit);
         }
+        budget.record_download(sent.datagrams * params.datagram_size as u64)?;
+
+        protocol::write_frame(stream, MessageKind::UdpDone, &sent.encode())
+            .map_err(|e| NetbeatError::protocol(format!("Failed to send UDP done - {e}")))?;
     }
+
     if let Some(mut sp) = sp {
         sp.stop_with_message(format!("{msg} ✅ Completed."));
     }
@@ -281,19 +1323,53 @@ impl ServerBuilder {
         Self::default()
     }
 
-    /// Network interface to bind server to.
+    /// Create a server builder from a loaded profile.
+    ///
+    /// Settings left unset in the profile fall back to the defaults, and can still be overridden with the other builder methods.
+    pub fn from_profile(profile: &ServerProfile) -> Result<Self> {
+        let mut builder = ServerBuilder::new();
+        if let Some(chunk_size) = &profile.chunk_size {
+            builder = builder.chunk_size(chunk_size)?;
+        }
+        Ok(ServerBuilder {
+            interfaces: profile.interfaces.clone().unwrap_or_default(),
+            port: profile.port,
+            max_connections: profile.max_connections,
+            psk: profile.psk.clone(),
+            tls: profile.tls,
+            tls_cert: profile.tls_cert.clone(),
+            tls_key: profile.tls_key.clone(),
+            drain_timeout: profile.drain_timeout,
+            max_session_duration: profile.max_session_duration,
+            max_session_bytes: profile.max_session_bytes.clone(),
+            max_direction_bytes: profile.max_direction_bytes.clone(),
+            idle_timeout: profile.idle_timeout,
+            ..builder
+        })
+    }
+
+    /// Network interface to bind server to. Call repeatedly to listen on several interfaces.
     pub fn interface(mut self, interface: config::BindInterface) -> Self {
-        self.interface = Some(interface);
+        self.interfaces.push(interface);
         self
     }
 
-    /// Port to listen on (1-65535)
+    /// Network interfaces to bind server to.
+    pub fn interfaces(
+        mut self,
+        interfaces: impl IntoIterator<Item = config::BindInterface>,
+    ) -> Self {
+        self.interfaces.extend(interfaces);
+        self
+    }
+
+    /// Port to listen on (1-65535), or 0 for a free port picked by the OS, see [`ServerHandle::local_addr`]
     pub fn port(mut self, port: u16) -> Self {
         self.port = Some(port);
         self
     }
 
-    /// Buffer size for data transfer (eg, 32KiB, 64KiB, 128KiB).
+    /// Largest chunk size downloads are sent in (eg, 32KiB, 64KiB, 128KiB), capping the one each client negotiates.
     pub fn chunk_size(mut self, chunk_size: impl Into<String>) -> Result<Self> {
         self.chunk_size = Some(protocol::validate_chunk_size(&chunk_size.into(), "server")?);
         Ok(self)
@@ -305,6 +1381,61 @@ impl ServerBuilder {
         self
     }
 
+    /// Pre-shared key clients must authenticate with before running any test
+    pub fn psk(mut self, psk: Option<impl Into<String>>) -> Self {
+        self.psk = psk.map(|p| p.into());
+        self
+    }
+
+    /// Encrypt connections with TLS, presenting a self-signed certificate generated on first use unless
+    /// `tls_cert` and `tls_key` are given
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Certificate chain presented to clients (PEM), instead of the self-signed certificate. Enables TLS.
+    pub fn tls_cert(mut self, tls_cert: Option<impl Into<PathBuf>>) -> Self {
+        self.tls_cert = tls_cert.map(|p| p.into());
+        self
+    }
+
+    /// Private key of the certificate presented to clients (PEM). Enables TLS.
+    pub fn tls_key(mut self, tls_key: Option<impl Into<PathBuf>>) -> Self {
+        self.tls_key = tls_key.map(|p| p.into());
+        self
+    }
+
+    /// Seconds active connections get to finish on shutdown before they are closed
+    pub fn drain_timeout(mut self, drain_timeout: u64) -> Self {
+        self.drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    /// Maximum seconds a client session may run, from setup to the end of its last test
+    pub fn max_session_duration(mut self, max_session_duration: u64) -> Self {
+        self.max_session_duration = Some(max_session_duration);
+        self
+    }
+
+    /// Maximum data a client session may transfer in both directions together, including units (eg, 500MB, 2GB)
+    pub fn max_session_bytes(mut self, max_session_bytes: Option<impl Into<String>>) -> Self {
+        self.max_session_bytes = max_session_bytes.map(|b| b.into());
+        self
+    }
+
+    /// Maximum data a client session may transfer in each direction, including units (eg, 500MB, 1GB)
+    pub fn max_direction_bytes(mut self, max_direction_bytes: Option<impl Into<String>>) -> Self {
+        self.max_direction_bytes = max_direction_bytes.map(|b| b.into());
+        self
+    }
+
+    /// Seconds to wait on a client that sends or reads nothing within a test phase before ending its session
+    pub fn idle_timeout(mut self, idle_timeout: u64) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
     /// Suppress all output (errors only)
     pub fn quiet(mut self, quiet: bool) -> Self {
         self.quiet = Some(quiet);
@@ -317,26 +1448,25 @@ impl ServerBuilder {
         self
     }
 
-    /// Complete build of `Server`.
+    /// Complete build of `Server`, resolving interfaces to the socket addresses to listen on.
     pub fn build(self) -> Result<Server> {
+        let port = self.port.unwrap_or(config::DEFAULT_PORT);
+        let interfaces = if self.interfaces.is_empty() {
+            vec![config::DEFAULT_BIND_INTERFACE]
+        } else {
+            self.interfaces.clone()
+        };
+        let mut socket_addrs: Vec<SocketAddr> = Vec::new();
+        for interface in &interfaces {
+            for addr in interface.to_socket_addrs(port)? {
+                if !socket_addrs.contains(&addr) {
+                    socket_addrs.push(addr);
+                }
+            }
+        }
+
         Ok(Server {
-            socket_addr: SocketAddr::new(
-                IpAddr::from_str(
-                    self.interface
-                        .unwrap_or(config::DEFAULT_BIND_INTERFACE)
-                        .to_ip(),
-                )
-                .map_err(|e| {
-                    // Shouldn't occur
-                    NetbeatError::server(format!(
-                        "Invalid IP address ({}) - {e}",
-                        self.interface
-                            .unwrap_or(config::DEFAULT_BIND_INTERFACE)
-                            .to_ip()
-                    ))
-                })?,
-                self.port.unwrap_or(config::DEFAULT_PORT),
-            ),
+            socket_addrs,
             chunk_size: Byte::parse_str(
                 self.chunk_size
                     .as_deref()
@@ -350,11 +1480,69 @@ impl ServerBuilder {
             max_connections: self
                 .max_connections
                 .unwrap_or(config::DEFAULT_MAX_CONNECTIONS),
+            psk: self.psk.map(PreSharedKey::new).transpose()?,
+            tls: match (&self.tls_cert, &self.tls_key) {
+                (Some(cert), Some(key)) => Some(TlsServer::load(cert, key)?),
+                (None, None) if self.tls.unwrap_or(false) => {
+                    Some(TlsServer::load_or_generate(&TlsServer::default_dir()?)?)
+                }
+                (None, None) => None,
+                _ => {
+                    return Err(NetbeatError::server(
+                        "TLS certificate and private key must be given together".to_string(),
+                    ));
+                }
+            },
+            drain_timeout: Duration::from_secs(
+                self.drain_timeout.unwrap_or(config::DEFAULT_DRAIN_TIMEOUT),
+            ),
+            limits: SessionLimits {
+                max_duration: match self.max_session_duration {
+                    Some(0) => {
+                        return Err(NetbeatError::server(
+                            "Max session duration must be greater than zero".to_string(),
+                        ));
+                    }
+                    max => max.map(Duration::from_secs),
+                },
+                max_bytes: parse_byte_limit(
+                    self.max_session_bytes.as_deref(),
+                    "max session bytes",
+                )?,
+                max_direction_bytes: parse_byte_limit(
+                    self.max_direction_bytes.as_deref(),
+                    "max direction bytes",
+                )?,
+                idle_timeout: match self.idle_timeout.unwrap_or(config::DEFAULT_IDLE_TIMEOUT) {
+                    0 => {
+                        return Err(NetbeatError::server(
+                            "Idle timeout must be greater than zero".to_string(),
+                        ));
+                    }
+                    secs => Duration::from_secs(secs),
+                },
+            },
             logger: Logger::new(self.verbose.unwrap_or(false), self.quiet.unwrap_or(false)),
         })
     }
 }
 
+/// Parse an optional byte limit including units, rejecting zero.
+fn parse_byte_limit(limit: Option<&str>, name: &str) -> Result<Option<u64>> {
+    let Some(limit) = limit else {
+        return Ok(None);
+    };
+    match Byte::parse_str(limit, false) {
+        Ok(bytes) if bytes.as_u64() > 0 => Ok(Some(bytes.as_u64())),
+        Ok(_) => Err(NetbeatError::server(format!(
+            "Invalid {name} ({limit:?}) - must be greater than zero"
+        ))),
+        Err(e) => Err(NetbeatError::server(format!(
+            "Invalid {name} ({limit:?}) - {e}"
+        ))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -373,11 +1561,12 @@ mod tests {
             .build()
             .unwrap();
 
-        let _: SocketAddr = server.socket_addr;
+        let _: Vec<SocketAddr> = server.socket_addrs.clone();
 
-        assert_eq!(server.socket_addr.port(), 8080);
-        assert_eq!(server.socket_addr.ip().to_string(), "0.0.0.0");
-        assert_eq!(server.socket_addr.to_string(), "0.0.0.0:8080");
+        assert_eq!(server.socket_addrs.len(), 1);
+        assert_eq!(server.socket_addrs[0].port(), 8080);
+        assert_eq!(server.socket_addrs[0].ip().to_string(), "0.0.0.0");
+        assert_eq!(server.socket_addrs[0].to_string(), "0.0.0.0:8080");
         assert_eq!(server.chunk_size, 1024);
         assert_eq!(server.max_connections, 100);
 
@@ -386,6 +1575,688 @@ mod tests {
         assert!(!server.logger.verbose);
     }
 
+    #[test]
+    fn test_build_server_multiple_interfaces() {
+        let server = Server::builder()
+            .interfaces([
+                config::BindInterface::Localhost,
+                config::BindInterface::Address("::1".parse().unwrap()),
+                config::BindInterface::Address("127.0.0.1".parse().unwrap()),
+            ])
+            .port(8080)
+            .build()
+            .unwrap();
+
+        // Duplicates are dropped
+        let addrs: Vec<String> = server.socket_addrs.iter().map(|a| a.to_string()).collect();
+        assert_eq!(addrs, ["127.0.0.1:8080", "[::1]:8080"]);
+
+        // Default interface
+        let server = Server::builder().build().unwrap();
+        assert_eq!(server.socket_addrs[0].to_string(), "0.0.0.0:5050");
+
+        let result = Server::builder()
+            .interface(config::BindInterface::Named("netbeat-missing0".to_string()))
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_build_server_from_profile() {
+        let profile = ServerProfile {
+            interfaces: Some(vec![config::BindInterface::Localhost]),
+            port: Some(6000),
+            chunk_size: Some("32KiB".to_string()),
+            max_connections: None,
+            psk: Some("correct horse".to_string()),
+            ..ServerProfile::default()
+        };
+        let server = ServerBuilder::from_profile(&profile)
+            .unwrap()
+            .max_connections(5)
+            .build()
+            .unwrap();
+        assert_eq!(server.socket_addrs[0].to_string(), "127.0.0.1:6000");
+        assert_eq!(server.chunk_size, 32 * 1024);
+        assert_eq!(server.max_connections, 5);
+        assert!(server.psk.is_some());
+
+        let server = ServerBuilder::from_profile(&ServerProfile::default())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(server.socket_addrs[0].to_string(), "0.0.0.0:5050");
+        assert_eq!(server.max_connections, config::DEFAULT_MAX_CONNECTIONS);
+    }
+
+    #[test]
+    fn test_build_server_tls() {
+        let dir =
+            std::env::temp_dir().join(format!("netbeat-test-{}-server-tls", std::process::id()));
+        let generated = TlsServer::load_or_generate(&dir).unwrap();
+        let cert = dir.join(config::TLS_CERT_FILE_NAME);
+        let key = dir.join(config::TLS_KEY_FILE_NAME);
+
+        let server = Server::builder()
+            .tls_cert(Some(&cert))
+            .tls_key(Some(&key))
+            .build()
+            .unwrap();
+        assert_eq!(server.tls.unwrap().fingerprint, generated.fingerprint);
+
+        let result = Server::builder().tls_cert(Some(&cert)).build();
+        assert!(result.is_err());
+        assert!(Server::builder().build().unwrap().tls.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spawn_shutdown() {
+        let handle = Server::builder()
+            .interface(config::BindInterface::Localhost)
+            .port(0)
+            .drain_timeout(0)
+            .quiet(true)
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let addr = handle.local_addr();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(handle.local_addrs(), [addr]);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        protocol::write_hello(&mut stream, &protocol::Hello::local()).unwrap();
+        protocol::read_hello(&mut stream).unwrap();
+
+        // The idle connection is closed once the drain timeout has passed
+        handle.shutdown();
+        handle.join().unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(io::Read::read(&mut stream, &mut buf).unwrap_or(0), 0);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn test_max_connections_keeps_serving() {
+        let handle = Server::builder()
+            .interface(config::BindInterface::Localhost)
+            .port(0)
+            .max_connections(1)
+            .drain_timeout(0)
+            .quiet(true)
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let addr = handle.local_addr();
+        let connect = || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            protocol::write_hello(&mut stream, &protocol::Hello::local()).unwrap();
+            stream
+        };
+
+        let mut first = connect();
+        protocol::read_hello(&mut first).unwrap();
+
+        // Rejected connections are told why and closed while the server keeps accepting
+        let mut rejected = connect();
+        let error = protocol::read_hello(&mut rejected).unwrap_err();
+        assert!(error.to_string().contains("Maximum connections reached"));
+        drop(first);
+        let accepted = (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            protocol::read_hello(&mut connect()).is_ok()
+        });
+        assert!(accepted);
+
+        handle.shutdown();
+        handle.join().unwrap();
+    }
+
+    /// Session limits of a server built with the defaults.
+    fn limits() -> SessionLimits {
+        Server::builder().build().unwrap().limits
+    }
+
+    /// Plan limits of a server built with the defaults.
+    fn plan_limits() -> PlanLimits {
+        Server::builder().build().unwrap().plan_limits()
+    }
+
+    #[test]
+    fn test_build_server_session_limits() {
+        let server = Server::builder()
+            .max_session_duration(60)
+            .max_session_bytes(Some("2GB"))
+            .max_direction_bytes(Some("500MB"))
+            .idle_timeout(10)
+            .build()
+            .unwrap();
+        assert_eq!(
+            server.limits,
+            SessionLimits {
+                max_duration: Some(Duration::from_secs(60)),
+                max_bytes: Some(2_000_000_000),
+                max_direction_bytes: Some(500_000_000),
+                idle_timeout: Duration::from_secs(10),
+            }
+        );
+        assert_eq!(
+            limits(),
+            SessionLimits {
+                max_duration: None,
+                max_bytes: None,
+                max_direction_bytes: None,
+                idle_timeout: Duration::from_secs(config::DEFAULT_IDLE_TIMEOUT),
+            }
+        );
+
+        for builder in [
+            Server::builder().max_session_duration(0),
+            Server::builder().idle_timeout(0),
+            Server::builder().max_session_bytes(Some("0B")),
+            Server::builder().max_direction_bytes(Some("lots")),
+        ] {
+            assert!(builder.build().is_err());
+        }
+    }
+
+    #[test]
+    fn test_session_budget() {
+        let budget = SessionBudget::new(SessionLimits {
+            max_bytes: Some(3_000),
+            max_direction_bytes: Some(2_000),
+            ..limits()
+        });
+        budget.record_upload(2_000).unwrap();
+        assert_eq!(
+            budget.record_upload(1).unwrap_err().to_string(),
+            "Session limit reached: maximum upload of 2.00 KB exceeded"
+        );
+        budget.record_download(999).unwrap();
+        assert_eq!(
+            budget.record_download(1).unwrap_err().to_string(),
+            "Session limit reached: maximum session transfer of 3.00 KB exceeded"
+        );
+
+        // Downloads are reserved up to the limit, before anything is sent
+        let budget = SessionBudget::new(SessionLimits {
+            max_direction_bytes: Some(1_000),
+            ..limits()
+        });
+        assert_eq!(budget.reserve_download(600).unwrap(), 600);
+        assert_eq!(budget.reserve_download(600).unwrap(), 400);
+        assert_eq!(
+            budget.reserve_download(600).unwrap_err().to_string(),
+            "Session limit reached: maximum download of 1.00 KB exceeded"
+        );
+        assert_eq!(budget.transferred(), 1_000);
+
+        // Paced UDP downloads are cut short to what is left of the session
+        let budget = SessionBudget::new(SessionLimits {
+            max_direction_bytes: Some(1_000_000),
+            ..limits()
+        });
+        let (duration, bytes, limit) =
+            budget.cap_udp_download(Duration::from_secs(10), None, 8_000_000);
+        assert_eq!((duration, bytes), (Duration::from_secs(1), None));
+        assert!(limit.is_some());
+        let (_, bytes, limit) =
+            budget.cap_udp_download(Duration::from_secs(10), Some(500_000), 8_000_000);
+        assert_eq!(bytes, Some(500_000));
+        assert!(limit.is_none());
+
+        let budget = SessionBudget::new(SessionLimits {
+            max_duration: Some(Duration::ZERO),
+            ..limits()
+        });
+        assert_eq!(
+            budget.check_duration().unwrap_err().to_string(),
+            "Session limit reached: maximum session duration of 0s reached"
+        );
+    }
+
+    #[test]
+    fn test_session_limits_end_session() {
+        let plan = TestPlan {
+            tests: protocol::TEST_PING | protocol::TEST_DOWNLOAD,
+            duration: Duration::from_secs(10),
+            bytes: None,
+            chunk_size: 1024,
+            streams: 1,
+            interval: Duration::from_secs(1),
+        };
+        let session_limits = SessionLimits {
+            max_direction_bytes: Some(10_000),
+            idle_timeout: Duration::from_millis(200),
+            ..limits()
+        };
+
+        for (start, session_limits, message) in [
+            (
+                Some(MessageKind::DownloadStart),
+                session_limits,
+                "maximum download of 10.00 KB exceeded",
+            ),
+            (
+                Some(MessageKind::Ping),
+                SessionLimits {
+                    max_duration: Some(Duration::from_millis(200)),
+                    ..limits()
+                },
+                "maximum session duration of 200ms reached",
+            ),
+            (
+                None,
+                session_limits,
+                "client idle for longer than 200ms between tests",
+            ),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (server_stream, _) = listener.accept().unwrap();
+            let handle = thread::spawn(move || {
+                handle_client(
+                    server_stream,
+                    &SessionRegistry::default(),
+                    None,
+                    None,
+                    &session_limits,
+                    &plan_limits(),
+                    &Logger::new(false, true),
+                )
+            });
+
+            protocol::write_hello(&mut stream, &protocol::Hello::local()).unwrap();
+            protocol::read_hello(&mut stream).unwrap();
+            protocol::write_frame(&mut stream, MessageKind::SessionSetup, &plan.encode()).unwrap();
+            protocol::expect_frame(&mut stream, MessageKind::SessionAccept).unwrap();
+            if let Some(kind) = start {
+                protocol::write_frame(&mut stream, kind, &[]).unwrap();
+            }
+
+            // Data sent up to the limit and pings answered until it, then the limit reported to the client
+            let mut received = 0;
+            let error = loop {
+                let frame = protocol::read_frame(&mut stream).unwrap();
+                match frame.kind {
+                    MessageKind::Data => received += frame.payload.len() as u64,
+                    MessageKind::Pong => {
+                        thread::sleep(Duration::from_millis(20));
+                        protocol::write_frame(&mut stream, MessageKind::Ping, &[]).unwrap();
+                    }
+                    MessageKind::Error => break String::from_utf8(frame.payload).unwrap(),
+                    kind => panic!("Unexpected {kind:?} message"),
+                }
+            };
+            assert_eq!(error, format!("Session limit reached: {message}"));
+            assert!(received <= 10_000);
+            drop(stream);
+            assert!(matches!(
+                handle.join().unwrap(),
+                Err(NetbeatError::SessionLimit { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_download_stop_keeps_session_usable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_stream, _) = listener.accept().unwrap();
+        let handle = thread::spawn(move || {
+            handle_client(
+                server_stream,
+                &SessionRegistry::default(),
+                None,
+                None,
+                &limits(),
+                &plan_limits(),
+                &Logger::new(false, true),
+            )
+        });
+
+        protocol::write_hello(&mut stream, &protocol::Hello::local()).unwrap();
+        protocol::read_hello(&mut stream).unwrap();
+        let plan = TestPlan {
+            tests: protocol::TEST_DOWNLOAD,
+            duration: Duration::from_secs(1),
+            bytes: None,
+            chunk_size: 2048,
+            streams: 1,
+            interval: Duration::from_secs(1),
+        };
+        protocol::write_frame(&mut stream, MessageKind::SessionSetup, &plan.encode()).unwrap();
+        protocol::expect_frame(&mut stream, MessageKind::SessionAccept).unwrap();
+
+        for _ in 0..2 {
+            protocol::write_frame(&mut stream, MessageKind::DownloadStart, &[]).unwrap();
+            let mut received = 0;
+            for _ in 0..10 {
+                let frame = protocol::expect_frame(&mut stream, MessageKind::Data).unwrap();
+                // Frames follow the chunk size negotiated in the plan
+                assert_eq!(frame.payload.len(), 2048);
+                received += frame.payload.len() as u64;
+            }
+            protocol::write_frame(&mut stream, MessageKind::DownloadStop, &[]).unwrap();
+
+            let result = loop {
+                let frame = protocol::read_frame(&mut stream).unwrap();
+                match frame.kind {
+                    MessageKind::Data => received += frame.payload.len() as u64,
+                    MessageKind::DownloadDone => {
+                        break protocol::TransferResult::decode(&frame.payload).unwrap();
+                    }
+                    kind => panic!("Unexpected {kind:?} message"),
+                }
+            };
+            assert_eq!(result.bytes, received);
+        }
+
+        protocol::write_frame(&mut stream, MessageKind::Goodbye, &[]).unwrap();
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_validate_plan() {
+        let plan = TestPlan {
+            tests: protocol::ALL_TESTS,
+            duration: Duration::from_secs(10),
+            bytes: None,
+            chunk_size: 64 * 1024,
+            streams: 1,
+            interval: Duration::from_secs(1),
+        };
+        assert!(validate_plan(&plan).is_ok());
+        assert!(
+            validate_plan(&TestPlan {
+                duration: Duration::ZERO,
+                bytes: Some(1024),
+                ..plan
+            })
+            .is_ok()
+        );
+
+        let reason = validate_plan(&TestPlan { tests: 0, ..plan }).unwrap_err();
+        assert!(reason.contains("Unsupported tests"));
+        let reason = validate_plan(&TestPlan {
+            duration: Duration::from_secs(7200),
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("Test duration of 7200s"));
+        let reason = validate_plan(&TestPlan {
+            bytes: Some(0),
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("Target data"));
+        let reason = validate_plan(&TestPlan {
+            chunk_size: 1,
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("Chunk size of 1 bytes"));
+        let reason = validate_plan(&TestPlan { streams: 0, ..plan }).unwrap_err();
+        assert!(reason.contains("Stream count of 0"));
+        let reason = validate_plan(&TestPlan {
+            interval: Duration::from_millis(10),
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("Reporting interval of 0.01s"));
+
+        // Bidirectional mode
+        let bidirectional = protocol::ALL_TESTS | protocol::TEST_BIDIRECTIONAL;
+        assert!(
+            validate_plan(&TestPlan {
+                tests: bidirectional,
+                ..plan
+            })
+            .is_ok()
+        );
+        let reason = validate_plan(&TestPlan {
+            tests: protocol::TEST_UPLOAD | protocol::TEST_BIDIRECTIONAL,
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("requires both upload and download"));
+        let reason = validate_plan(&TestPlan {
+            tests: protocol::TEST_BIDIRECTIONAL,
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("Unsupported tests"));
+
+        // Latency probes
+        let probed = protocol::ALL_TESTS | protocol::TEST_LATENCY_PROBE;
+        assert!(
+            validate_plan(&TestPlan {
+                tests: probed,
+                ..plan
+            })
+            .is_ok()
+        );
+        let reason = validate_plan(&TestPlan {
+            tests: protocol::TEST_PING | protocol::TEST_LATENCY_PROBE,
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("Latency probes require"));
+
+        // UDP mode
+        let udp = protocol::ALL_TESTS | protocol::TEST_UDP;
+        assert!(validate_plan(&TestPlan { tests: udp, ..plan }).is_ok());
+        let reason = validate_plan(&TestPlan {
+            tests: protocol::TEST_PING | protocol::TEST_UDP,
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("requires an upload or download"));
+        let reason = validate_plan(&TestPlan {
+            tests: udp,
+            streams: 2,
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("single stream"));
+        let reason = validate_plan(&TestPlan {
+            tests: udp | protocol::TEST_BIDIRECTIONAL,
+            ..plan
+        })
+        .unwrap_err();
+        assert!(reason.contains("single stream"));
+    }
+
+    #[test]
+    fn test_plan_limits() {
+        let limits = PlanLimits {
+            max_connections: 4,
+            max_chunk_size: 32 * 1024,
+        };
+        let plan = TestPlan {
+            tests: protocol::ALL_TESTS | protocol::TEST_BIDIRECTIONAL,
+            duration: Duration::from_secs(10),
+            bytes: None,
+            chunk_size: 64 * 1024,
+            streams: 2,
+            interval: Duration::from_secs(1),
+        };
+        assert!(limits.check(&plan).is_ok());
+        let reason = limits
+            .check(&TestPlan {
+                tests: plan.tests | protocol::TEST_LATENCY_PROBE,
+                ..plan
+            })
+            .unwrap_err();
+        assert_eq!(
+            reason,
+            "Test plan needs 5 connections, more than the server maximum of 4"
+        );
+
+        // Chunk sizes above the server maximum are capped, smaller ones kept
+        assert_eq!(limits.clamp(plan).chunk_size, 32 * 1024);
+        let plan = TestPlan {
+            chunk_size: 2048,
+            ..plan
+        };
+        assert_eq!(limits.clamp(plan), plan);
+    }
+
+    #[test]
+    fn test_session_setup_reject() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_stream, _) = listener.accept().unwrap();
+        let handle = thread::spawn(move || {
+            handle_client(
+                server_stream,
+                &SessionRegistry::default(),
+                None,
+                None,
+                &limits(),
+                &plan_limits(),
+                &Logger::new(false, true),
+            )
+        });
+
+        protocol::write_hello(&mut stream, &protocol::Hello::local()).unwrap();
+        protocol::read_hello(&mut stream).unwrap();
+        let plan = TestPlan {
+            tests: protocol::ALL_TESTS,
+            duration: Duration::from_secs(10),
+            bytes: None,
+            chunk_size: 1024,
+            streams: config::MAX_STREAMS + 1,
+            interval: Duration::from_secs(1),
+        };
+        protocol::write_frame(&mut stream, MessageKind::SessionSetup, &plan.encode()).unwrap();
+        let frame = protocol::expect_frame(&mut stream, MessageKind::SessionReject).unwrap();
+        assert!(String::from_utf8_lossy(&frame.payload).contains("Stream count"));
+
+        let result = handle.join().unwrap();
+        assert!(matches!(result, Err(NetbeatError::SessionRejected { .. })));
+    }
+
+    #[test]
+    fn test_session_join() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sessions = SessionRegistry::default();
+        let connect = || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let (server_stream, _) = listener.accept().unwrap();
+            let sessions = Arc::clone(&sessions);
+            let handle = thread::spawn(move || {
+                handle_client(
+                    server_stream,
+                    &sessions,
+                    None,
+                    None,
+                    &limits(),
+                    &plan_limits(),
+                    &Logger::new(false, true),
+                )
+            });
+            protocol::write_hello(&mut stream, &protocol::Hello::local()).unwrap();
+            protocol::read_hello(&mut stream).unwrap();
+            (stream, handle)
+        };
+        let join = |stream: &mut TcpStream, session_id: u64, index: u32| {
+            let join = protocol::SessionJoin {
+                session_id,
+                stream: index,
+            };
+            protocol::write_frame(stream, MessageKind::SessionJoin, &join.encode()).unwrap();
+            protocol::read_frame(stream).unwrap()
+        };
+
+        let (mut control, control_handle) = connect();
+        let plan = TestPlan {
+            tests: protocol::TEST_PING | protocol::TEST_UPLOAD,
+            duration: Duration::from_secs(1),
+            bytes: None,
+            chunk_size: 1024,
+            streams: 2,
+            interval: Duration::from_secs(1),
+        };
+        protocol::write_frame(&mut control, MessageKind::SessionSetup, &plan.encode()).unwrap();
+        let frame = protocol::expect_frame(&mut control, MessageKind::SessionAccept).unwrap();
+        let session_id = protocol::decode_session_id(&frame.payload).unwrap();
+
+        let (mut extra, extra_handle) = connect();
+        let frame = join(&mut extra, session_id, 1);
+        assert_eq!(frame.kind, MessageKind::SessionAccept);
+        assert_eq!(sessions.lock().unwrap()[&session_id].streams, 2);
+
+        // Session is full
+        let (mut rejected, rejected_handle) = connect();
+        let frame = join(&mut rejected, session_id, 2);
+        assert_eq!(frame.kind, MessageKind::SessionReject);
+        assert!(String::from_utf8_lossy(&frame.payload).contains("all of its 2 streams"));
+        assert!(matches!(
+            rejected_handle.join().unwrap(),
+            Err(NetbeatError::SessionRejected { .. })
+        ));
+
+        // Unknown session
+        let (mut rejected, rejected_handle) = connect();
+        let frame = join(&mut rejected, session_id.wrapping_add(1), 1);
+        assert_eq!(frame.kind, MessageKind::SessionReject);
+        assert!(String::from_utf8_lossy(&frame.payload).contains("Unknown session"));
+        assert!(rejected_handle.join().unwrap().is_err());
+
+        // Extra stream carries upload data under the session plan
+        protocol::write_frame(&mut extra, MessageKind::UploadStart, &[]).unwrap();
+        for _ in 0..4 {
+            protocol::write_frame(&mut extra, MessageKind::Data, &[0u8; 1024]).unwrap();
+        }
+        protocol::write_frame(&mut extra, MessageKind::UploadDone, &[]).unwrap();
+        let frame = protocol::expect_frame(&mut extra, MessageKind::UploadResult).unwrap();
+        let result = protocol::TransferResult::decode(&frame.payload).unwrap();
+        assert_eq!(result.bytes, 4096);
+
+        // ...but not pings, which only run on the session connection
+        protocol::write_frame(&mut extra, MessageKind::Ping, &0u32.to_be_bytes()).unwrap();
+        let frame = protocol::read_frame(&mut extra).unwrap();
+        assert_eq!(frame.kind, MessageKind::Error);
+        assert!(extra_handle.join().unwrap().is_err());
+
+        protocol::write_frame(&mut control, MessageKind::Goodbye, &[]).unwrap();
+        assert!(control_handle.join().unwrap().is_ok());
+        assert!(sessions.lock().unwrap().is_empty());
+
+        // Latency probe joins last and only carries pings
+        let (mut control, control_handle) = connect();
+        let plan = TestPlan {
+            tests: protocol::ALL_TESTS | protocol::TEST_LATENCY_PROBE,
+            streams: 1,
+            ..plan
+        };
+        protocol::write_frame(&mut control, MessageKind::SessionSetup, &plan.encode()).unwrap();
+        let frame = protocol::expect_frame(&mut control, MessageKind::SessionAccept).unwrap();
+        let session_id = protocol::decode_session_id(&frame.payload).unwrap();
+
+        let (mut probe, probe_handle) = connect();
+        let frame = join(&mut probe, session_id, 1);
+        assert_eq!(frame.kind, MessageKind::SessionAccept);
+        protocol::write_frame(&mut probe, MessageKind::Ping, &1u32.to_be_bytes()).unwrap();
+        let frame = protocol::expect_frame(&mut probe, MessageKind::Pong).unwrap();
+        assert_eq!(frame.payload, 1u32.to_be_bytes());
+        protocol::write_frame(&mut probe, MessageKind::PingDone, &[]).unwrap();
+        protocol::write_frame(&mut probe, MessageKind::UploadStart, &[]).unwrap();
+        let frame = protocol::read_frame(&mut probe).unwrap();
+        assert_eq!(frame.kind, MessageKind::Error);
+        assert!(probe_handle.join().unwrap().is_err());
+
+        protocol::write_frame(&mut control, MessageKind::Goodbye, &[]).unwrap();
+        assert!(control_handle.join().unwrap().is_ok());
+    }
+
     #[test]
     fn test_build_server_invalid_input() {
         // Invalid Chunk Size
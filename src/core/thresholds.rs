@@ -0,0 +1,232 @@
+//! Pass/fail thresholds checked against a finished netbeat report.
+
+use crate::{output::reports::NetbeatReport, utils::units};
+
+use std::{fmt, time::Duration};
+
+/// Limits a speed test must stay within to pass.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Thresholds {
+    /// Minimum download speed in bits per second
+    pub min_download: Option<u64>,
+    /// Minimum upload speed in bits per second
+    pub min_upload: Option<u64>,
+    /// Maximum average idle ping
+    pub max_latency: Option<Duration>,
+    /// Maximum ping loss, and datagram loss in UDP mode, in percent
+    pub max_loss: Option<f64>,
+}
+
+/// Class of a violated threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThresholdKind {
+    Download,
+    Upload,
+    Latency,
+    Loss,
+}
+
+/// A threshold the report did not meet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Class of the violated threshold
+    pub kind: ThresholdKind,
+    /// Description of the measured value against the threshold
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Thresholds {
+    /// Whether no threshold is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Thresholds::default()
+    }
+
+    /// Check the report against every threshold set, returning the violations in threshold order.
+    pub fn check(&self, report: &NetbeatReport) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for (kind, min, speed_report) in [
+            (
+                ThresholdKind::Download,
+                self.min_download,
+                &report.download_report,
+            ),
+            (
+                ThresholdKind::Upload,
+                self.min_upload,
+                &report.upload_report,
+            ),
+        ] {
+            let bits_per_second = speed_report.speed * 8.0;
+            if let Some(min) = min
+                && bits_per_second < min as f64
+            {
+                violations.push(Violation {
+                    kind,
+                    message: format!(
+                        "{} speed {} is below the minimum of {}",
+                        capitalize(speed_report.report_type),
+                        units::format_bitrate(bits_per_second),
+                        units::format_bitrate(min as f64)
+                    ),
+                });
+            }
+        }
+
+        let ping_report = &report.ping_report;
+        if let Some(max) = self.max_latency
+            && (ping_report.successful_pings == 0 || ping_report.avg_ping > max)
+        {
+            violations.push(Violation {
+                kind: ThresholdKind::Latency,
+                message: format!(
+                    "Average ping {:.2?} is above the maximum of {max:.2?}",
+                    ping_report.avg_ping
+                ),
+            });
+        }
+
+        if let Some(max) = self.max_loss {
+            if ping_report.packet_loss > max {
+                violations.push(Violation {
+                    kind: ThresholdKind::Loss,
+                    message: format!(
+                        "Packet loss {:.1}% is above the maximum of {max}%",
+                        ping_report.packet_loss
+                    ),
+                });
+            }
+            for speed_report in [&report.upload_report, &report.download_report] {
+                if let Some(stats) = speed_report.udp
+                    && stats.loss_percent() > max
+                {
+                    violations.push(Violation {
+                        kind: ThresholdKind::Loss,
+                        message: format!(
+                            "{} datagram loss {:.1}% is above the maximum of {max}%",
+                            capitalize(speed_report.report_type),
+                            stats.loss_percent()
+                        ),
+                    });
+                }
+            }
+        }
+        violations
+    }
+}
+
+/// Capitalize the first letter of a test direction.
+fn capitalize(direction: &str) -> String {
+    let mut chars = direction.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::udp::UdpStats,
+        output::reports::{PingReport, SpeedReport},
+    };
+
+    fn create_report() -> NetbeatReport {
+        let ms = Duration::from_millis;
+        NetbeatReport::new(
+            PingReport::new(4, 3, vec![ms(1), ms(2), ms(3)]),
+            SpeedReport::new("upload", Duration::from_secs(1), 100_000_000).unwrap(),
+            SpeedReport::new("download", Duration::from_secs(1), 125_000_000).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_check_passes() {
+        let report = create_report();
+        assert!(Thresholds::default().is_empty());
+        assert!(Thresholds::default().check(&report).is_empty());
+
+        let thresholds = Thresholds {
+            min_download: Some(900_000_000),
+            min_upload: Some(800_000_000),
+            max_latency: Some(Duration::from_millis(2)),
+            max_loss: Some(25.0),
+        };
+        assert!(!thresholds.is_empty());
+        assert!(thresholds.check(&report).is_empty());
+    }
+
+    #[test]
+    fn test_check_violations() {
+        let report = create_report();
+        let thresholds = Thresholds {
+            min_download: Some(1_200_000_000),
+            min_upload: Some(900_000_000),
+            max_latency: Some(Duration::from_micros(1500)),
+            max_loss: Some(0.0),
+        };
+        let violations = thresholds.check(&report);
+        assert_eq!(
+            violations.iter().map(|v| v.kind).collect::<Vec<_>>(),
+            vec![
+                ThresholdKind::Download,
+                ThresholdKind::Upload,
+                ThresholdKind::Latency,
+                ThresholdKind::Loss
+            ]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "Download speed 1000.00 Mbps is below the minimum of 1200.00 Mbps"
+        );
+        assert_eq!(
+            violations[1].to_string(),
+            "Upload speed 800.00 Mbps is below the minimum of 900.00 Mbps"
+        );
+        assert_eq!(
+            violations[2].to_string(),
+            "Average ping 2.00ms is above the maximum of 1.50ms"
+        );
+        assert_eq!(
+            violations[3].to_string(),
+            "Packet loss 25.0% is above the maximum of 0%"
+        );
+    }
+
+    #[test]
+    fn test_check_datagram_loss() {
+        let ms = Duration::from_millis;
+        let stats = UdpStats {
+            sent: 100,
+            received: 90,
+            lost: 10,
+            out_of_order: 0,
+            bytes: 90_000,
+            duration: Duration::from_secs(1),
+            jitter: ms(1),
+        };
+        let report = NetbeatReport::new(
+            PingReport::new(2, 2, vec![ms(1), ms(1)]),
+            SpeedReport::new("upload", Duration::from_secs(1), 90_000)
+                .unwrap()
+                .with_udp(stats, 1_000_000),
+            SpeedReport::new("download", Duration::from_secs(1), 90_000).unwrap(),
+        );
+        let thresholds = Thresholds {
+            max_loss: Some(5.0),
+            ..Default::default()
+        };
+        let violations = thresholds.check(&report);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].to_string(),
+            "Upload datagram loss 10.0% is above the maximum of 5%"
+        );
+    }
+}
@@ -0,0 +1,642 @@
+//! TLS transport for netbeat connections, through rustls and the ring crypto provider.
+//!
+//! A server with TLS enabled sets [`protocol::CAP_TLS`] in its hello, and both sides run a TLS handshake right after
+//! the hello exchange, before authentication and session setup. The server presents a certificate of its own, or a
+//! self-signed one generated on first use and kept in the netbeat config directory. Clients verify the certificate
+//! against the CA certificates of a file, or pin its fingerprint on first use in a known hosts file.
+//!
+//! [`protocol::CAP_TLS`]: super::protocol::CAP_TLS
+
+use super::config;
+use crate::{
+    output::reports::TlsReport,
+    utils::error::{NetbeatError, Result},
+};
+use rustls::{
+    ClientConfig, ClientConnection, CommonState, DigitallySignedStruct, ProtocolVersion,
+    RootCertStore, ServerConfig, ServerConnection, SignatureScheme, StreamOwned,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Names the generated self-signed certificate is issued for
+const SELF_SIGNED_NAMES: [&str; 2] = ["localhost", "netbeat"];
+
+/// Connection to a peer, in the clear or over TLS.
+#[derive(Debug)]
+pub enum Transport {
+    /// Plain TCP connection
+    Tcp(TcpStream),
+    /// TLS connection to a server
+    TlsClient(Box<StreamOwned<ClientConnection, TcpStream>>),
+    /// TLS connection from a client
+    TlsServer(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Transport {
+    /// Underlying TCP socket, for socket options and addresses.
+    pub fn socket(&self) -> &TcpStream {
+        match self {
+            Transport::Tcp(stream) => stream,
+            Transport::TlsClient(stream) => stream.get_ref(),
+            Transport::TlsServer(stream) => stream.get_ref(),
+        }
+    }
+
+    /// Close the connection, notifying the peer first over TLS.
+    ///
+    /// The peer may close its side as soon as it reads the last message, so closing an already closed connection
+    /// is not an error.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        let notified = match self {
+            Transport::Tcp(_) => Ok(()),
+            Transport::TlsClient(stream) => {
+                stream.conn.send_close_notify();
+                stream.flush()
+            }
+            Transport::TlsServer(stream) => {
+                stream.conn.send_close_notify();
+                stream.flush()
+            }
+        };
+        match notified.and_then(|_| self.socket().shutdown(Shutdown::Both)) {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::NotConnected | ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+                ) =>
+            {
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+impl From<TcpStream> for Transport {
+    fn from(stream: TcpStream) -> Self {
+        Transport::Tcp(stream)
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(stream) => stream.read(buf),
+            Transport::TlsClient(stream) => stream.read(buf),
+            Transport::TlsServer(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(stream) => stream.write(buf),
+            Transport::TlsClient(stream) => stream.write(buf),
+            Transport::TlsServer(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Tcp(stream) => stream.flush(),
+            Transport::TlsClient(stream) => stream.flush(),
+            Transport::TlsServer(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Server side TLS settings, holding the certificate presented to clients.
+#[derive(Debug, Clone)]
+pub struct TlsServer {
+    config: Arc<ServerConfig>,
+    /// SHA-256 fingerprint of the server certificate
+    pub fingerprint: String,
+}
+
+impl TlsServer {
+    /// Present the certificate chain and private key of the given PEM files.
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<TlsServer> {
+        let certs = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| {
+                NetbeatError::tls(format!(
+                    "Failed to read certificate {} - {e}",
+                    cert_path.display()
+                ))
+            })?;
+        let Some(cert) = certs.first() else {
+            return Err(NetbeatError::tls(format!(
+                "No certificate found in {}",
+                cert_path.display()
+            )));
+        };
+        let fingerprint = fingerprint(cert);
+        let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| {
+            NetbeatError::tls(format!(
+                "Failed to read private key {} - {e}",
+                key_path.display()
+            ))
+        })?;
+
+        let mut config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| NetbeatError::tls(format!("Failed to set up TLS - {e}")))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| NetbeatError::tls(format!("Invalid certificate or private key - {e}")))?;
+        // Every connection runs a full handshake, so there is no use for resumption tickets
+        config.send_tls13_tickets = 0;
+        Ok(TlsServer {
+            config: Arc::new(config),
+            fingerprint,
+        })
+    }
+
+    /// Present the self-signed certificate kept in `dir`, generating it on first use.
+    pub fn load_or_generate(dir: &Path) -> Result<TlsServer> {
+        let cert_path = dir.join(config::TLS_CERT_FILE_NAME);
+        let key_path = dir.join(config::TLS_KEY_FILE_NAME);
+        if !cert_path.exists() && !key_path.exists() {
+            generate_self_signed(&cert_path, &key_path)?;
+        }
+        TlsServer::load(&cert_path, &key_path)
+    }
+
+    /// Directory the self-signed certificate is kept in, the netbeat config directory of the current user.
+    pub fn default_dir() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("netbeat"))
+            .ok_or_else(|| {
+                NetbeatError::tls("Failed to locate the user config directory".to_string())
+            })
+    }
+
+    /// Run the server side of the TLS handshake, once hellos have been exchanged.
+    pub fn accept(&self, mut stream: TcpStream) -> Result<(Transport, TlsReport)> {
+        let start_time = Instant::now();
+        let mut conn = ServerConnection::new(Arc::clone(&self.config))
+            .map_err(|e| NetbeatError::tls(format!("Failed to start TLS session - {e}")))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)
+                .map_err(|e| NetbeatError::tls(format!("Handshake failed - {e}")))?;
+        }
+
+        let report = session_report(&conn, start_time.elapsed(), self.fingerprint.clone());
+        let stream = StreamOwned::new(conn, stream);
+        Ok((Transport::TlsServer(Box::new(stream)), report))
+    }
+}
+
+/// Client side TLS settings, verifying the certificate presented by the server.
+#[derive(Debug, Clone)]
+pub struct TlsClient {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+    /// Known hosts file and entry the server certificate is pinned under, unless verified by a CA
+    pin: Option<(KnownHosts, String)>,
+}
+
+impl TlsClient {
+    /// Verify the server certificate and name against the CA certificates of a PEM file.
+    pub fn with_ca_file(host: &str, ca_path: &Path) -> Result<TlsClient> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(ca_path)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| {
+                NetbeatError::tls(format!(
+                    "Failed to read CA certificates {} - {e}",
+                    ca_path.display()
+                ))
+            })?
+        {
+            roots.add(cert).map_err(|e| {
+                NetbeatError::tls(format!(
+                    "Invalid CA certificate in {} - {e}",
+                    ca_path.display()
+                ))
+            })?;
+        }
+        if roots.is_empty() {
+            return Err(NetbeatError::tls(format!(
+                "No CA certificate found in {}",
+                ca_path.display()
+            )));
+        }
+
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider())
+            .build()
+            .map_err(|e| NetbeatError::tls(format!("Failed to set up TLS - {e}")))?;
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| NetbeatError::tls(format!("Failed to set up TLS - {e}")))?
+            .with_webpki_verifier(verifier)
+            .with_no_client_auth();
+        Ok(TlsClient {
+            config: Arc::new(config),
+            server_name: server_name(host)?,
+            pin: None,
+        })
+    }
+
+    /// Pin the server certificate fingerprint in the known hosts file on first use, and require it afterwards.
+    pub fn trust_on_first_use(host: &str, port: u16, known_hosts: KnownHosts) -> Result<TlsClient> {
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| NetbeatError::tls(format!("Failed to set up TLS - {e}")))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                provider: provider(),
+            }))
+            .with_no_client_auth();
+        Ok(TlsClient {
+            config: Arc::new(config),
+            server_name: server_name(host)?,
+            pin: Some((known_hosts, host_key(host, port))),
+        })
+    }
+
+    /// Whether the server certificate is yet to be pinned, and will be trusted on the next connection.
+    pub fn first_use(&self) -> Result<bool> {
+        match &self.pin {
+            Some((known_hosts, host)) => Ok(known_hosts.lookup(host)?.is_none()),
+            None => Ok(false),
+        }
+    }
+
+    /// Known hosts file the server certificate is pinned in, unless verified by a CA.
+    pub fn known_hosts(&self) -> Option<&KnownHosts> {
+        self.pin.as_ref().map(|(known_hosts, _)| known_hosts)
+    }
+
+    /// Run the client side of the TLS handshake, once hellos have been exchanged.
+    pub fn connect(&self, mut stream: TcpStream) -> Result<(Transport, TlsReport)> {
+        let start_time = Instant::now();
+        let mut conn = ClientConnection::new(Arc::clone(&self.config), self.server_name.clone())
+            .map_err(|e| NetbeatError::tls(format!("Failed to start TLS session - {e}")))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)
+                .map_err(|e| NetbeatError::tls(format!("Handshake failed - {e}")))?;
+        }
+        let handshake_time = start_time.elapsed();
+
+        let fingerprint = conn
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(fingerprint)
+            .ok_or_else(|| NetbeatError::tls("Server presented no certificate".to_string()))?;
+        // Checked before anything else goes over the connection
+        if let Some((known_hosts, host)) = &self.pin {
+            match known_hosts.lookup(host)? {
+                Some(pinned) if pinned == fingerprint => {}
+                Some(pinned) => {
+                    return Err(NetbeatError::tls(format!(
+                        "Certificate of {host} ({fingerprint}) does not match the fingerprint pinned in {} ({pinned}) - remove the entry if the server certificate was replaced",
+                        known_hosts.path().display()
+                    )));
+                }
+                None => known_hosts.pin(host, &fingerprint)?,
+            }
+        }
+
+        let report = session_report(&conn, handshake_time, fingerprint);
+        let stream = StreamOwned::new(conn, stream);
+        Ok((Transport::TlsClient(Box::new(stream)), report))
+    }
+}
+
+/// File pinning the certificate fingerprint of each server, trusted on first use.
+///
+/// Each line holds a server (`host:port`) and the SHA-256 fingerprint of its certificate, separated by a space.
+#[derive(Debug, Clone)]
+pub struct KnownHosts {
+    path: PathBuf,
+}
+
+impl KnownHosts {
+    /// Known hosts stored in the given file.
+    pub fn new(path: impl Into<PathBuf>) -> KnownHosts {
+        KnownHosts { path: path.into() }
+    }
+
+    /// Known hosts stored in the netbeat data directory of the current user.
+    pub fn open_default() -> Result<KnownHosts> {
+        let data_dir = dirs::data_dir().ok_or_else(|| {
+            NetbeatError::tls("Failed to locate the user data directory".to_string())
+        })?;
+        Ok(KnownHosts::new(
+            data_dir.join("netbeat").join(config::KNOWN_HOSTS_FILE_NAME),
+        ))
+    }
+
+    /// Path of the known hosts file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Fingerprint pinned for a server, if any.
+    pub fn lookup(&self, host: &str) -> Result<Option<String>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(NetbeatError::tls(format!(
+                    "Failed to read {} - {e}",
+                    self.path.display()
+                )));
+            }
+        };
+        Ok(contents
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(entry, _)| *entry == host)
+            .map(|(_, fingerprint)| fingerprint.trim().to_string()))
+    }
+
+    /// Pin the fingerprint for a server, creating the file and its directory as needed.
+    pub fn pin(&self, host: &str, fingerprint: &str) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                NetbeatError::tls(format!("Failed to create {} - {e}", parent.display()))
+            })?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(format!("{host} {fingerprint}\n").as_bytes()))
+            .map_err(|e| {
+                NetbeatError::tls(format!("Failed to write to {} - {e}", self.path.display()))
+            })
+    }
+}
+
+/// Certificate verifier accepting any server certificate, whose fingerprint is checked against the pin right
+/// after the handshake. Handshake signatures are still verified, so the server holds the certificate key.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// SHA-256 fingerprint of a certificate, as printed by `openssl x509 -fingerprint -sha256`.
+pub fn fingerprint(cert: &CertificateDer) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// Entry of a server in the known hosts file.
+fn host_key(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+fn server_name(host: &str) -> Result<ServerName<'static>> {
+    ServerName::try_from(host.to_string())
+        .map_err(|e| NetbeatError::tls(format!("Invalid server name ({host}) - {e}")))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+/// Negotiated parameters of a finished handshake.
+fn session_report(conn: &CommonState, handshake_time: Duration, fingerprint: String) -> TlsReport {
+    TlsReport {
+        version: match conn.protocol_version() {
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3",
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2",
+            _ => "unknown",
+        }
+        .to_string(),
+        cipher_suite: conn
+            .negotiated_cipher_suite()
+            .and_then(|suite| suite.suite().as_str())
+            .unwrap_or("unknown")
+            .to_string(),
+        handshake_time,
+        fingerprint,
+    }
+}
+
+/// Generate a self-signed certificate and its private key, readable by the current user only.
+fn generate_self_signed(cert_path: &Path, key_path: &Path) -> Result<()> {
+    let certified = rcgen::generate_simple_self_signed(SELF_SIGNED_NAMES.map(String::from))
+        .map_err(|e| NetbeatError::tls(format!("Failed to generate certificate - {e}")))?;
+    if let Some(parent) = cert_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            NetbeatError::tls(format!("Failed to create {} - {e}", parent.display()))
+        })?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(key_path)
+        .and_then(|mut file| file.write_all(certified.signing_key.serialize_pem().as_bytes()))
+        .map_err(|e| NetbeatError::tls(format!("Failed to write {} - {e}", key_path.display())))?;
+    fs::write(cert_path, certified.cert.pem())
+        .map_err(|e| NetbeatError::tls(format!("Failed to write {} - {e}", cert_path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("netbeat-test-{}-{name}", std::process::id()))
+    }
+
+    /// Run a TLS handshake between `server` and `client` over a local connection, then echo a message.
+    fn exchange(server: TlsServer, client: &TlsClient) -> Result<TlsReport> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_stream, _) = listener.accept().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = server.accept(server_stream)?;
+            let mut message = [0u8; 5];
+            stream.read_exact(&mut message)?;
+            stream.write_all(&message)?;
+            stream.flush()?;
+            Ok::<_, NetbeatError>(())
+        });
+        let result = client.connect(stream).and_then(|(mut stream, report)| {
+            stream.write_all(b"hello")?;
+            let mut message = [0u8; 5];
+            stream.read_exact(&mut message)?;
+            assert_eq!(&message, b"hello");
+            stream.shutdown()?;
+            Ok(report)
+        });
+        if result.is_ok() {
+            handle.join().unwrap()?;
+        }
+        result
+    }
+
+    #[test]
+    fn test_load_or_generate() {
+        let dir = temp_dir("tls-generate");
+        let server = TlsServer::load_or_generate(&dir).unwrap();
+        assert!(dir.join(config::TLS_CERT_FILE_NAME).exists());
+        assert_eq!(server.fingerprint.len(), 32 * 3 - 1);
+
+        // Kept across restarts
+        let reloaded = TlsServer::load_or_generate(&dir).unwrap();
+        assert_eq!(reloaded.fingerprint, server.fingerprint);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(dir.join(config::TLS_KEY_FILE_NAME)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+
+        let missing = TlsServer::load(&dir.join("missing.pem"), &dir.join("key.pem"));
+        assert!(matches!(missing, Err(NetbeatError::TlsError { .. })));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trust_on_first_use() {
+        let dir = temp_dir("tls-tofu");
+        let server = TlsServer::load_or_generate(&dir.join("server")).unwrap();
+        let known_hosts = KnownHosts::new(dir.join(config::KNOWN_HOSTS_FILE_NAME));
+        let client = TlsClient::trust_on_first_use("127.0.0.1", 5050, known_hosts.clone()).unwrap();
+
+        // First use pins the certificate, later connections must match it
+        assert!(client.first_use().unwrap());
+        let report = exchange(server.clone(), &client).unwrap();
+        assert_eq!(report.version, "TLSv1.3");
+        assert_eq!(report.fingerprint, server.fingerprint);
+        assert_eq!(
+            known_hosts.lookup("127.0.0.1:5050").unwrap(),
+            Some(server.fingerprint.clone())
+        );
+        assert!(!client.first_use().unwrap());
+        assert!(exchange(server, &client).is_ok());
+
+        // A replaced certificate is refused
+        let replaced = TlsServer::load_or_generate(&dir.join("replaced")).unwrap();
+        let error = exchange(replaced, &client).unwrap_err().to_string();
+        assert!(
+            error.contains("does not match the fingerprint pinned"),
+            "{error}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ca_file() {
+        let dir = temp_dir("tls-ca");
+        let server = TlsServer::load_or_generate(&dir).unwrap();
+        let ca_path = dir.join(config::TLS_CERT_FILE_NAME);
+
+        // The self-signed certificate is its own CA, issued for localhost
+        let client = TlsClient::with_ca_file("localhost", &ca_path).unwrap();
+        assert!(client.known_hosts().is_none());
+        assert!(!client.first_use().unwrap());
+        exchange(server.clone(), &client).unwrap();
+
+        let client = TlsClient::with_ca_file("nas.lan", &ca_path).unwrap();
+        let error = exchange(server, &client).unwrap_err().to_string();
+        assert!(error.contains("Handshake failed"), "{error}");
+
+        let empty = dir.join("empty.pem");
+        fs::write(&empty, "").unwrap();
+        assert!(TlsClient::with_ca_file("localhost", &empty).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_known_hosts() {
+        let dir = temp_dir("known-hosts");
+        let known_hosts = KnownHosts::new(dir.join(config::KNOWN_HOSTS_FILE_NAME));
+        assert_eq!(known_hosts.lookup("nas.lan:5050").unwrap(), None);
+
+        known_hosts.pin("nas.lan:5050", "AB:CD").unwrap();
+        known_hosts.pin("[::1]:5050", "EF:01").unwrap();
+        assert_eq!(
+            known_hosts.lookup("nas.lan:5050").unwrap().as_deref(),
+            Some("AB:CD")
+        );
+        assert_eq!(
+            known_hosts.lookup("[::1]:5050").unwrap().as_deref(),
+            Some("EF:01")
+        );
+        assert_eq!(known_hosts.lookup("nas.lan:6000").unwrap(), None);
+
+        assert_eq!(host_key("nas.lan", 5050), "nas.lan:5050");
+        assert_eq!(host_key("::1", 5050), "[::1]:5050");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,594 @@
+//! UDP test utilities for netbeat.
+//!
+//! The UDP test is set up over the TCP session, then sends sequence-numbered, timestamped datagrams at a fixed bitrate.
+//! The receiving side counts lost and out-of-order datagrams and estimates interarrival jitter as per RFC 3550.
+
+use super::{config, protocol};
+use crate::utils::error::{NetbeatError, Result};
+use std::{
+    io::{self, ErrorKind},
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Length of the datagram header: sequence number and send timestamp in nanoseconds (8 bytes each, big endian)
+pub const DATAGRAM_HEADER_LEN: usize = 16;
+
+/// Interval between checks on the control connection while receiving datagrams
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Time to wait for datagrams still in flight once the sender is done
+pub const DRAIN_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Interval between announces from the client until the first datagram of a download arrives
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time the server waits for the client to announce itself before a download
+pub const ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Parameters of a UDP test direction, sent by the client to start it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpParams {
+    /// Direction of the test (`TEST_UPLOAD` or `TEST_DOWNLOAD`)
+    pub direction: u8,
+    /// Target bitrate in bits per second
+    pub bitrate: u64,
+    /// Size of each datagram in bytes, header included
+    pub datagram_size: u32,
+}
+
+impl UdpParams {
+    /// Encode UDP parameters payload: direction, bitrate and datagram size
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(13);
+        payload.push(self.direction);
+        payload.extend_from_slice(&self.bitrate.to_be_bytes());
+        payload.extend_from_slice(&self.datagram_size.to_be_bytes());
+        payload
+    }
+
+    /// Decode UDP parameters payload written by [`UdpParams::encode`]
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let invalid = || NetbeatError::protocol("Malformed UDP start message".to_string());
+        let bytes: [u8; 13] = payload.try_into().map_err(|_| invalid())?;
+        Ok(Self {
+            direction: bytes[0],
+            bitrate: u64::from_be_bytes(bytes[1..9].try_into().map_err(|_| invalid())?),
+            datagram_size: u32::from_be_bytes(bytes[9..13].try_into().map_err(|_| invalid())?),
+        })
+    }
+
+    /// Check the parameters against the allowed ranges, returning the reason when invalid.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if !(1..=config::MAX_UDP_BITRATE).contains(&self.bitrate) {
+            return Err(format!(
+                "UDP bitrate of {} bps outside allowed range of 1-{} bps",
+                self.bitrate,
+                config::MAX_UDP_BITRATE
+            ));
+        }
+        if !(config::MIN_DATAGRAM_SIZE..=config::MAX_DATAGRAM_SIZE).contains(&self.datagram_size) {
+            return Err(format!(
+                "Datagram size of {} bytes outside allowed range of {}-{} bytes",
+                self.datagram_size,
+                config::MIN_DATAGRAM_SIZE,
+                config::MAX_DATAGRAM_SIZE
+            ));
+        }
+        Ok(())
+    }
+
+    /// Interval between datagrams to hold the target bitrate
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(self.datagram_size as f64 * 8.0 / self.bitrate as f64)
+    }
+}
+
+/// Server UDP socket details, sent in reply to a UDP start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpReady {
+    /// Port of the server UDP socket
+    pub port: u16,
+    /// Token the client announces itself with before a download
+    pub token: u64,
+}
+
+impl UdpReady {
+    /// Encode UDP ready payload: port and token
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(10);
+        payload.extend_from_slice(&self.port.to_be_bytes());
+        payload.extend_from_slice(&self.token.to_be_bytes());
+        payload
+    }
+
+    /// Decode UDP ready payload written by [`UdpReady::encode`]
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let invalid = || NetbeatError::protocol("Malformed UDP ready message".to_string());
+        let bytes: [u8; 10] = payload.try_into().map_err(|_| invalid())?;
+        Ok(Self {
+            port: u16::from_be_bytes([bytes[0], bytes[1]]),
+            token: u64::from_be_bytes(bytes[2..10].try_into().map_err(|_| invalid())?),
+        })
+    }
+}
+
+/// What the sending side sent, reported once it is done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpSent {
+    /// Number of datagrams sent
+    pub datagrams: u64,
+    /// Time spent sending
+    pub duration: Duration,
+}
+
+impl UdpSent {
+    /// Encode UDP sent payload: datagram count and duration in nanoseconds
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(16);
+        payload.extend_from_slice(&self.datagrams.to_be_bytes());
+        payload.extend_from_slice(&(self.duration.as_nanos() as u64).to_be_bytes());
+        payload
+    }
+
+    /// Decode UDP sent payload written by [`UdpSent::encode`]
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let invalid = || NetbeatError::protocol("Malformed UDP done message".to_string());
+        let bytes: [u8; 16] = payload.try_into().map_err(|_| invalid())?;
+        Ok(Self {
+            datagrams: u64::from_be_bytes(bytes[..8].try_into().map_err(|_| invalid())?),
+            duration: Duration::from_nanos(u64::from_be_bytes(
+                bytes[8..].try_into().map_err(|_| invalid())?,
+            )),
+        })
+    }
+}
+
+/// Outcome of a UDP test direction as measured by the receiving side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UdpStats {
+    /// Datagrams sent
+    pub sent: u64,
+    /// Distinct datagrams received
+    pub received: u64,
+    /// Datagrams never received
+    pub lost: u64,
+    /// Datagrams received after one with a higher sequence number
+    pub out_of_order: u64,
+    /// Bytes received, datagram headers included
+    pub bytes: u64,
+    /// Time spent sending
+    pub duration: Duration,
+    /// Interarrival jitter (RFC 3550)
+    pub jitter: Duration,
+}
+
+impl UdpStats {
+    /// Share of sent datagrams that were lost, in percent
+    pub fn loss_percent(&self) -> f64 {
+        if self.sent == 0 {
+            0.0
+        } else {
+            self.lost as f64 / self.sent as f64 * 100.0
+        }
+    }
+
+    /// Encode UDP stats payload: counters, bytes, duration and jitter in nanoseconds
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(56);
+        for value in [
+            self.sent,
+            self.received,
+            self.lost,
+            self.out_of_order,
+            self.bytes,
+            self.duration.as_nanos() as u64,
+            self.jitter.as_nanos() as u64,
+        ] {
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        payload
+    }
+
+    /// Decode UDP stats payload written by [`UdpStats::encode`]
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let invalid = || NetbeatError::protocol("Malformed UDP result message".to_string());
+        if payload.len() != 56 {
+            return Err(invalid());
+        }
+        let u64_at = |i: usize| -> Result<u64> {
+            Ok(u64::from_be_bytes(
+                payload[i * 8..i * 8 + 8]
+                    .try_into()
+                    .map_err(|_| invalid())?,
+            ))
+        };
+        Ok(Self {
+            sent: u64_at(0)?,
+            received: u64_at(1)?,
+            lost: u64_at(2)?,
+            out_of_order: u64_at(3)?,
+            bytes: u64_at(4)?,
+            duration: Duration::from_nanos(u64_at(5)?),
+            jitter: Duration::from_nanos(u64_at(6)?),
+        })
+    }
+}
+
+/// Accounting of received datagrams.
+#[derive(Debug)]
+pub struct UdpReceiver {
+    start_time: Instant,
+    received: u64,
+    bytes: u64,
+    highest_seq: Option<u64>,
+    out_of_order: u64,
+    last_transit: Option<f64>,
+    jitter: f64,
+}
+
+impl Default for UdpReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UdpReceiver {
+    /// Create a new receiver, with arrival times relative to now.
+    pub fn new() -> Self {
+        Self {
+            start_time: Instant::now(),
+            received: 0,
+            bytes: 0,
+            highest_seq: None,
+            out_of_order: 0,
+            last_transit: None,
+            jitter: 0.0,
+        }
+    }
+
+    /// Number of datagrams received so far
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Number of bytes received so far, headers included
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Record a received datagram, ignoring anything too short to carry a header.
+    pub fn record(&mut self, datagram: &[u8]) {
+        let arrival = self.start_time.elapsed();
+        let Some((seq, sent_at)) = decode_header(datagram) else {
+            return;
+        };
+        self.received += 1;
+        self.bytes += datagram.len() as u64;
+        match self.highest_seq {
+            Some(highest) if seq < highest => self.out_of_order += 1,
+            _ => self.highest_seq = Some(seq),
+        }
+
+        // Clocks of both sides only differ by a constant offset, which cancels out in transit differences
+        let transit = arrival.as_nanos() as f64 - sent_at.as_nanos() as f64;
+        if let Some(last_transit) = self.last_transit {
+            let d = (transit - last_transit).abs();
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.last_transit = Some(transit);
+    }
+
+    /// Summarize the test given what the sender reported.
+    pub fn stats(&self, sent: &UdpSent) -> UdpStats {
+        UdpStats {
+            sent: sent.datagrams,
+            received: self.received,
+            lost: sent.datagrams.saturating_sub(self.received),
+            out_of_order: self.out_of_order,
+            bytes: self.bytes,
+            duration: sent.duration,
+            jitter: Duration::from_nanos(self.jitter as u64),
+        }
+    }
+}
+
+/// Write the datagram header at the start of `datagram`.
+pub fn encode_header(datagram: &mut [u8], seq: u64, sent_at: Duration) {
+    datagram[..8].copy_from_slice(&seq.to_be_bytes());
+    datagram[8..DATAGRAM_HEADER_LEN].copy_from_slice(&(sent_at.as_nanos() as u64).to_be_bytes());
+}
+
+/// Read the sequence number and send timestamp from a datagram.
+pub fn decode_header(datagram: &[u8]) -> Option<(u64, Duration)> {
+    let seq = u64::from_be_bytes(datagram.get(..8)?.try_into().ok()?);
+    let sent_at = u64::from_be_bytes(datagram.get(8..DATAGRAM_HEADER_LEN)?.try_into().ok()?);
+    Some((seq, Duration::from_nanos(sent_at)))
+}
+
+/// Send datagrams on a connected socket at the target bitrate, for `duration` or until `target_bytes` are sent.
+pub fn send_datagrams(
+    socket: &UdpSocket,
+    params: &UdpParams,
+    duration: Duration,
+    target_bytes: Option<u64>,
+) -> io::Result<UdpSent> {
+    let mut datagram = protocol::generate_random_buffer(params.datagram_size as usize);
+    let interval = params.interval();
+
+    let start_time = Instant::now();
+    let mut seq: u64 = 0;
+    loop {
+        let elapsed = start_time.elapsed();
+        let done = match target_bytes {
+            Some(target_bytes) => seq * params.datagram_size as u64 >= target_bytes,
+            None => elapsed >= duration,
+        };
+        if done {
+            break;
+        }
+
+        // Hold the schedule, catching up on datagrams that are due
+        let due = interval.mul_f64(seq as f64);
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+            continue;
+        }
+
+        encode_header(&mut datagram, seq, start_time.elapsed());
+        match socket.send(&datagram) {
+            Ok(_) => {}
+            // Rejected datagrams count as lost, just like datagrams dropped on the way
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::ConnectionRefused | ErrorKind::WouldBlock
+                ) => {}
+            Err(e) => return Err(e),
+        }
+        seq += 1;
+    }
+
+    Ok(UdpSent {
+        datagrams: seq,
+        duration: start_time.elapsed(),
+    })
+}
+
+/// Receive datagrams until `poll` returns a value, then drain datagrams still in flight.
+///
+/// `poll` is called every few milliseconds, typically to check the control connection for the end of the test.
+pub fn receive_datagrams<T>(
+    socket: &UdpSocket,
+    receiver: &mut UdpReceiver,
+    max_datagram_size: usize,
+    mut poll: impl FnMut(&UdpReceiver) -> Result<Option<T>>,
+) -> Result<T> {
+    let mut datagram = vec![0u8; max_datagram_size];
+    socket
+        .set_read_timeout(Some(RECEIVE_POLL_INTERVAL))
+        .map_err(NetbeatError::ConnectionError)?;
+
+    let mut last_poll = Instant::now();
+    let value = loop {
+        match socket.recv(&mut datagram) {
+            Ok(len) => receiver.record(&datagram[..len]),
+            Err(e) if is_receive_timeout(&e) => {}
+            Err(e) => return Err(NetbeatError::ConnectionError(e)),
+        }
+        if last_poll.elapsed() >= RECEIVE_POLL_INTERVAL {
+            last_poll = Instant::now();
+            if let Some(value) = poll(receiver)? {
+                break value;
+            }
+        }
+    };
+
+    socket
+        .set_read_timeout(Some(DRAIN_TIMEOUT))
+        .map_err(NetbeatError::ConnectionError)?;
+    loop {
+        match socket.recv(&mut datagram) {
+            Ok(len) => receiver.record(&datagram[..len]),
+            Err(e) if is_receive_timeout(&e) => break,
+            Err(e) => return Err(NetbeatError::ConnectionError(e)),
+        }
+    }
+    Ok(value)
+}
+
+/// Let the server know where to send download datagrams, from the socket they should go to.
+pub fn announce(socket: &UdpSocket, token: u64) -> Result<()> {
+    match socket.send(&token.to_be_bytes()) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => Ok(()),
+        Err(e) => Err(NetbeatError::ConnectionError(e)),
+    }
+}
+
+/// Wait for the client announce carrying `token`, returning the address it came from.
+pub fn wait_for_announce(socket: &UdpSocket, token: u64) -> Result<SocketAddr> {
+    let mut datagram = [0u8; 8];
+    socket
+        .set_read_timeout(Some(ANNOUNCE_INTERVAL))
+        .map_err(NetbeatError::ConnectionError)?;
+
+    let start_time = Instant::now();
+    while start_time.elapsed() < ANNOUNCE_TIMEOUT {
+        match socket.recv_from(&mut datagram) {
+            Ok((8, addr)) if u64::from_be_bytes(datagram) == token => return Ok(addr),
+            Ok(_) => {}
+            Err(e) if is_receive_timeout(&e) => {}
+            Err(e) => return Err(NetbeatError::ConnectionError(e)),
+        }
+    }
+    Err(NetbeatError::protocol(format!(
+        "No UDP datagram from client within {ANNOUNCE_TIMEOUT:?}, UDP may be blocked"
+    )))
+}
+
+/// Whether a receive error only means no datagram arrived in time.
+fn is_receive_timeout(e: &io::Error) -> bool {
+    // Connected sockets may report an earlier datagram rejected by the peer, which is not fatal
+    matches!(
+        e.kind(),
+        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::ConnectionRefused
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_udp_messages_round_trip() {
+        let params = UdpParams {
+            direction: protocol::TEST_UPLOAD,
+            bitrate: 100_000_000,
+            datagram_size: 1250,
+        };
+        assert_eq!(UdpParams::decode(&params.encode()).unwrap(), params);
+        assert_eq!(params.interval(), Duration::from_micros(100));
+        assert!(UdpParams::decode(&[0; 12]).is_err());
+
+        let ready = UdpReady {
+            port: 40000,
+            token: 42,
+        };
+        assert_eq!(UdpReady::decode(&ready.encode()).unwrap(), ready);
+        assert!(UdpReady::decode(&[]).is_err());
+
+        let sent = UdpSent {
+            datagrams: 1000,
+            duration: Duration::from_millis(1500),
+        };
+        assert_eq!(UdpSent::decode(&sent.encode()).unwrap(), sent);
+        assert!(UdpSent::decode(&[0; 8]).is_err());
+
+        let stats = UdpStats {
+            sent: 1000,
+            received: 990,
+            lost: 10,
+            out_of_order: 3,
+            bytes: 990 * 1250,
+            duration: Duration::from_secs(1),
+            jitter: Duration::from_micros(120),
+        };
+        assert_eq!(UdpStats::decode(&stats.encode()).unwrap(), stats);
+        assert_eq!(stats.loss_percent(), 1.0);
+        let result = UdpStats::decode(&[0; 55]);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e.to_string().contains("Malformed UDP result"));
+        }
+    }
+
+    #[test]
+    fn test_udp_params_validate() {
+        let params = UdpParams {
+            direction: protocol::TEST_DOWNLOAD,
+            bitrate: 1_000_000,
+            datagram_size: config::DEFAULT_DATAGRAM_SIZE,
+        };
+        assert!(params.validate().is_ok());
+
+        let reason = UdpParams {
+            bitrate: 0,
+            ..params
+        }
+        .validate()
+        .unwrap_err();
+        assert!(reason.contains("UDP bitrate of 0 bps"));
+        let reason = UdpParams {
+            datagram_size: 8,
+            ..params
+        }
+        .validate()
+        .unwrap_err();
+        assert!(reason.contains("Datagram size of 8 bytes"));
+    }
+
+    #[test]
+    fn test_announce() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket
+            .connect(server_socket.local_addr().unwrap())
+            .unwrap();
+
+        // Stray datagrams are ignored
+        client_socket.send(b"stray").unwrap();
+        announce(&client_socket, 42).unwrap();
+        let addr = wait_for_announce(&server_socket, 42).unwrap();
+        assert_eq!(addr, client_socket.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_datagram_header() {
+        let mut datagram = vec![0u8; 64];
+        encode_header(&mut datagram, 7, Duration::from_micros(1500));
+        assert_eq!(
+            decode_header(&datagram),
+            Some((7, Duration::from_micros(1500)))
+        );
+        assert_eq!(decode_header(&datagram[..10]), None);
+    }
+
+    #[test]
+    fn test_udp_receiver() {
+        let mut receiver = UdpReceiver::new();
+        let mut datagram = vec![0u8; 100];
+        for seq in [0, 1, 3, 2, 5] {
+            encode_header(&mut datagram, seq, Duration::ZERO);
+            receiver.record(&datagram);
+        }
+        // Too short to be a datagram
+        receiver.record(&[0u8; 4]);
+
+        let stats = receiver.stats(&UdpSent {
+            datagrams: 6,
+            duration: Duration::from_secs(1),
+        });
+        assert_eq!(stats.sent, 6);
+        assert_eq!(stats.received, 5);
+        assert_eq!(stats.lost, 1);
+        assert_eq!(stats.out_of_order, 1);
+        assert_eq!(stats.bytes, 500);
+        assert_eq!(stats.duration, Duration::from_secs(1));
+        // Same send timestamp but increasing arrival times
+        assert!(stats.jitter > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_send_receive_datagrams() {
+        let receiver_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender_socket
+            .connect(receiver_socket.local_addr().unwrap())
+            .unwrap();
+
+        let params = UdpParams {
+            direction: protocol::TEST_UPLOAD,
+            bitrate: 8_000_000,
+            datagram_size: 1000,
+        };
+        let handle = thread::spawn(move || {
+            send_datagrams(&sender_socket, &params, Duration::ZERO, Some(100_000)).unwrap()
+        });
+
+        let mut receiver = UdpReceiver::new();
+        let sent = receive_datagrams(&receiver_socket, &mut receiver, 1000, |_| {
+            Ok(handle.is_finished().then_some(()))
+        })
+        .map(|_| handle.join().unwrap())
+        .unwrap();
+
+        // 100 datagrams at 1000 datagrams per second
+        assert_eq!(sent.datagrams, 100);
+        assert!(sent.duration >= Duration::from_millis(95));
+        let stats = receiver.stats(&sent);
+        assert_eq!(stats.received + stats.lost, 100);
+        assert!(stats.received > 0);
+    }
+}
@@ -6,6 +6,6 @@ pub mod output;
 pub mod utils;
 
 pub use core::config::BindInterface;
-pub use core::{Client, Server};
+pub use core::{Client, Exporter, Monitor, Server, ServerHandle};
 pub use output::reports::{NetbeatReport, PingReport, SpeedReport};
 pub use utils::error::{NetbeatError, Result};
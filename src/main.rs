@@ -4,49 +4,199 @@ use anyhow::Result;
 use clap::Parser;
 use netbeat::{
     cli::{Cli, Commands},
-    core::{Client, Server},
+    core::{
+        Exporter, Monitor,
+        client::ClientBuilder,
+        profile::{ClientProfile, ConfigFile, ServerProfile},
+        server::ServerBuilder,
+        thresholds::ThresholdKind,
+    },
+    output::{
+        compare::{self, Comparison},
+        formats::OutputFormat,
+        history::{self, History, HistoryFilter},
+    },
 };
+use std::{
+    io::IsTerminal,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Exit code for a run that failed with an error
+const EXIT_ERROR: i32 = 1;
 
 fn main() {
     let args = Cli::parse();
 
-    run(args).unwrap_or_else(|err| {
+    let code = run(args).unwrap_or_else(|err| {
         eprintln!("❌ {err}");
-        std::process::exit(1);
+        std::process::exit(EXIT_ERROR);
     });
+    std::process::exit(code);
+}
+
+/// Exit code for a completed test that did not meet a threshold, or a comparison that regressed beyond it.
+///
+/// When several thresholds fail, the first in download, upload, latency, loss order sets the code.
+fn threshold_exit_code(kind: ThresholdKind) -> i32 {
+    match kind {
+        ThresholdKind::Download => 3,
+        ThresholdKind::Upload => 4,
+        ThresholdKind::Latency => 5,
+        ThresholdKind::Loss => 6,
+    }
 }
 
-fn run(args: Cli) -> Result<()> {
+/// Exit code when interrupted a second time, without waiting for a graceful stop
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Flag set on the first SIGINT/SIGTERM, exiting right away on the next one.
+fn stop_on_signal(message: &'static str) -> Result<Arc<AtomicBool>> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&stop);
+    ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        eprintln!("🛑 {message} (interrupt again to exit now)");
+    })?;
+    Ok(stop)
+}
+
+/// Run the command, returning the process exit code.
+fn run(args: Cli) -> Result<i32> {
     match args.command {
         Commands::Run(run_args) => {
-            let client = Client::builder(run_args.target)
-                .port(run_args.port)
-                .data(run_args.data)
-                .time(run_args.time)
-                .chunk_size(run_args.chunk_size)?
-                .ping_count(run_args.ping_count)
-                .return_json(run_args.json)
-                .timeout(run_args.timeout)
-                .retries(run_args.retries)
+            let config = ConfigFile::open(run_args.config.as_deref())?;
+            let profile = run_args
+                .profile()
+                .or(ClientProfile::from_env()?)
+                .or(config.client_profile(&run_args.target));
+            let client = ClientBuilder::from_profile(&run_args.target, &profile)?
+                .history(profile.history.unwrap_or(true))
+                .csv_header(!run_args.no_header)
                 .quiet(run_args.quiet)
                 .verbose(run_args.verbose)
                 .build()?;
 
-            client.contact()?;
-            Ok(())
+            let report = client.contact()?;
+            Ok(client
+                .thresholds
+                .check(&report)
+                .first()
+                .map_or(0, |violation| threshold_exit_code(violation.kind)))
         }
-        Commands::Serve(run_args) => {
-            let server = Server::builder()
-                .interface(run_args.interface)
-                .port(run_args.port)
-                .chunk_size(run_args.chunk_size)?
-                .max_connections(run_args.connections)
-                .quiet(run_args.quiet)
-                .verbose(run_args.verbose)
+        Commands::Serve(serve_args) => {
+            let config = ConfigFile::open(serve_args.config.as_deref())?;
+            let profile = serve_args
+                .profile()
+                .or(ServerProfile::from_env()?)
+                .or(config.server_profile());
+            let server = ServerBuilder::from_profile(&profile)?
+                .quiet(serve_args.quiet)
+                .verbose(serve_args.verbose)
                 .build()?;
 
-            server.listen()?;
-            Ok(())
+            let stop = stop_on_signal("Shutting down, draining active connections...")?;
+            server.listen_until(&stop)?;
+            Ok(0)
+        }
+        Commands::Exporter(exporter_args) => {
+            let exporter = Exporter::builder(exporter_args.targets)
+                .listen_addr(exporter_args.listen)
+                .every(exporter_args.every)
+                .port(exporter_args.port)
+                .time(exporter_args.time)
+                .ping_count(exporter_args.ping_count)
+                .parallel(exporter_args.parallel)
+                .timeout(exporter_args.timeout)
+                .retries(exporter_args.retries)
+                .psk(exporter_args.psk)
+                .tls(exporter_args.tls)
+                .tls_ca(exporter_args.tls_ca)
+                .quiet(exporter_args.quiet)
+                .verbose(exporter_args.verbose)
+                .build()?;
+
+            exporter.run()?;
+            Ok(0)
+        }
+        Commands::Monitor(monitor_args) => {
+            let monitor = Monitor::builder(monitor_args.targets)
+                .every(monitor_args.every)
+                .jitter(monitor_args.jitter)
+                .max_backoff(monitor_args.max_backoff)
+                .output(monitor_args.output)
+                .history(!monitor_args.no_history)
+                .port(monitor_args.port)
+                .time(monitor_args.time)
+                .ping_count(monitor_args.ping_count)
+                .parallel(monitor_args.parallel)
+                .timeout(monitor_args.timeout)
+                .retries(monitor_args.retries)
+                .psk(monitor_args.psk)
+                .tls(monitor_args.tls)
+                .tls_ca(monitor_args.tls_ca)
+                .quiet(monitor_args.quiet)
+                .verbose(monitor_args.verbose)
+                .build()?;
+
+            let stop = stop_on_signal("Stopping after the current test...")?;
+            monitor.run(&stop)?;
+            Ok(0)
+        }
+        Commands::History(history_args) => {
+            let history = match history_args.file {
+                Some(path) => History::new(path),
+                None => History::open_default()?,
+            };
+            let reports = history.load(&HistoryFilter {
+                target: history_args.target,
+                since: history_args.since,
+                until: history_args.until,
+                limit: history_args.limit,
+            })?;
+
+            if reports.is_empty() && history_args.format == OutputFormat::Table {
+                eprintln!("📭 No runs found in {}", history.path().display());
+            } else {
+                print!(
+                    "{}",
+                    history::render(
+                        &reports,
+                        history_args.trend,
+                        history_args.format,
+                        !history_args.no_header
+                    )
+                );
+            }
+            Ok(0)
+        }
+        Commands::Compare(compare_args) => {
+            let mut runs = Vec::new();
+            for path in &compare_args.files {
+                runs.extend(compare::load_runs(path)?);
+            }
+            let comparison = Comparison::new(runs)?;
+
+            let color = !compare_args.no_color
+                && std::env::var_os("NO_COLOR").is_none()
+                && std::io::stdout().is_terminal();
+            print!("{}", comparison.to_table(color));
+
+            let regressions = compare_args
+                .threshold
+                .map(|threshold| comparison.regressions(threshold))
+                .unwrap_or_default();
+            for regression in &regressions {
+                eprintln!("❌ {regression}");
+            }
+            Ok(regressions
+                .first()
+                .map_or(0, |regression| threshold_exit_code(regression.kind)))
         }
     }
 }
@@ -54,22 +204,52 @@ fn run(args: Cli) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::thread;
-    use std::time::Duration;
+    use netbeat::{BindInterface, Server};
 
     #[test]
     fn test_client_server_flow() {
         // Reserve output testing to integration
-        let server_args = Cli::parse_from(["netbeat", "serve", "-q"]);
+        let server = Server::builder()
+            .interface(BindInterface::Localhost)
+            .port(0)
+            .quiet(true)
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let port = server.local_addr().port().to_string();
 
-        let _server_handle = thread::spawn(move || {
-            run(server_args).expect("Failed to start server");
-        });
+        let client_args = Cli::parse_from([
+            "netbeat",
+            "run",
+            "127.0.0.1",
+            "-p",
+            &port,
+            "-t",
+            "1",
+            "-q",
+            "--no-history",
+        ]);
+        assert_eq!(run(client_args).expect("Failed to run client"), 0);
 
-        // Give server time to start
-        thread::sleep(Duration::from_millis(500));
+        let client_args = Cli::parse_from([
+            "netbeat",
+            "run",
+            "127.0.0.1",
+            "-p",
+            &port,
+            "-t",
+            "1",
+            "-q",
+            "--no-history",
+            "--min-download",
+            "100Tbps",
+            "--max-latency",
+            "1ns",
+        ]);
+        assert_eq!(run(client_args).expect("Failed to run client"), 3);
 
-        let client_args = Cli::parse_from(["netbeat", "run", "0.0.0.0", "-t", "1", "-q"]);
-        run(client_args).expect("Failed to run client");
+        server.shutdown();
+        server.join().unwrap();
     }
 }
@@ -0,0 +1,493 @@
+//! Comparison of saved netbeat reports.
+//!
+//! Runs are compared against the first one given (the baseline), with absolute and percentage deltas for each metric.
+//! A change for the worse (eg, slower download, higher ping) is a regression.
+
+use crate::{
+    core::thresholds::ThresholdKind,
+    output::reports::NetbeatReport,
+    utils::{
+        error::{NetbeatError, Result},
+        units,
+    },
+};
+
+use anstyle::{AnsiColor, Color, Style};
+use std::{fmt, fs, path::Path, time::Duration};
+use tabled::{
+    builder::Builder,
+    settings::{Alignment, Modify, Panel, Style as TableStyle, object::Rows},
+};
+
+/// A report to compare, with the label it is shown under.
+pub struct Run {
+    /// Label of the run (eg, the file it was read from)
+    pub label: String,
+    /// Report of the run
+    pub report: NetbeatReport,
+}
+
+/// Direction in which a metric improves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Better {
+    Higher,
+    Lower,
+}
+
+/// A metric compared across runs.
+struct CompareMetric {
+    name: &'static str,
+    kind: ThresholdKind,
+    better: Better,
+    value: fn(&NetbeatReport) -> Option<f64>,
+    format: fn(f64) -> String,
+}
+
+/// Metrics compared across runs, in order.
+const METRICS: &[CompareMetric] = &[
+    CompareMetric {
+        name: "Download speed",
+        kind: ThresholdKind::Download,
+        better: Better::Higher,
+        value: |report| Some(report.download_report.speed * 8.0),
+        format: units::format_bitrate,
+    },
+    CompareMetric {
+        name: "Upload speed",
+        kind: ThresholdKind::Upload,
+        better: Better::Higher,
+        value: |report| Some(report.upload_report.speed * 8.0),
+        format: units::format_bitrate,
+    },
+    CompareMetric {
+        name: "Average ping",
+        kind: ThresholdKind::Latency,
+        better: Better::Lower,
+        value: |report| Some(report.ping_report.avg_ping.as_secs_f64()),
+        format: format_seconds,
+    },
+    CompareMetric {
+        name: "P95 ping",
+        kind: ThresholdKind::Latency,
+        better: Better::Lower,
+        value: |report| Some(report.ping_report.p95_ping.as_secs_f64()),
+        format: format_seconds,
+    },
+    CompareMetric {
+        name: "Jitter",
+        kind: ThresholdKind::Latency,
+        better: Better::Lower,
+        value: |report| Some(report.ping_report.jitter.as_secs_f64()),
+        format: format_seconds,
+    },
+    CompareMetric {
+        name: "Loaded latency",
+        kind: ThresholdKind::Latency,
+        better: Better::Lower,
+        value: |report| {
+            report
+                .latency_report
+                .as_ref()
+                .and_then(|latency| latency.loaded.iter().map(|phase| phase.avg).max())
+                .map(|avg| avg.as_secs_f64())
+        },
+        format: format_seconds,
+    },
+    CompareMetric {
+        name: "TLS handshake",
+        kind: ThresholdKind::Latency,
+        better: Better::Lower,
+        value: |report| {
+            report
+                .tls_report
+                .as_ref()
+                .map(|tls| tls.handshake_time.as_secs_f64())
+        },
+        format: format_seconds,
+    },
+    CompareMetric {
+        name: "Packet loss",
+        kind: ThresholdKind::Loss,
+        better: Better::Lower,
+        value: |report| Some(report.ping_report.packet_loss),
+        format: format_percent,
+    },
+    CompareMetric {
+        name: "Upload datagram loss",
+        kind: ThresholdKind::Loss,
+        better: Better::Lower,
+        value: |report| report.upload_report.udp.map(|stats| stats.loss_percent()),
+        format: format_percent,
+    },
+    CompareMetric {
+        name: "Download datagram loss",
+        kind: ThresholdKind::Loss,
+        better: Better::Lower,
+        value: |report| report.download_report.udp.map(|stats| stats.loss_percent()),
+        format: format_percent,
+    },
+];
+
+/// Change of a metric from the baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Delta {
+    /// Difference from the baseline value
+    pub absolute: f64,
+    /// Difference relative to the baseline value in percent, unless the baseline is zero
+    pub percent: Option<f64>,
+    /// Whether the change is for the worse
+    pub regression: bool,
+}
+
+/// A metric regression beyond the threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    /// Name of the metric
+    pub metric: &'static str,
+    /// Class of the metric
+    pub kind: ThresholdKind,
+    /// Label of the run that regressed
+    pub run: String,
+    /// Change from the baseline
+    pub delta: Delta,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.delta.percent {
+            Some(percent) => write!(
+                f,
+                "{} regressed by {:.1}% in {}",
+                self.metric,
+                percent.abs(),
+                self.run
+            ),
+            None => write!(f, "{} regressed from zero in {}", self.metric, self.run),
+        }
+    }
+}
+
+/// Reports compared against the first (baseline) report.
+pub struct Comparison {
+    runs: Vec<Run>,
+}
+
+impl Comparison {
+    /// Compare runs against the first one, requiring at least two runs.
+    pub fn new(runs: Vec<Run>) -> Result<Comparison> {
+        if runs.len() < 2 {
+            return Err(NetbeatError::client(format!(
+                "Compare requires at least two runs - found {}",
+                runs.len()
+            )));
+        }
+        Ok(Comparison { runs })
+    }
+
+    /// Change of each run from the baseline for a metric, with `None` for the baseline and missing values.
+    fn deltas(&self, metric: &CompareMetric) -> Vec<Option<Delta>> {
+        let baseline = (metric.value)(&self.runs[0].report);
+        self.runs
+            .iter()
+            .enumerate()
+            .map(|(i, run)| {
+                let (baseline, value) = (baseline?, (metric.value)(&run.report)?);
+                if i == 0 {
+                    return None;
+                }
+                let absolute = value - baseline;
+                Some(Delta {
+                    absolute,
+                    percent: (baseline != 0.0).then(|| absolute / baseline * 100.0),
+                    regression: match metric.better {
+                        Better::Higher => absolute < 0.0,
+                        Better::Lower => absolute > 0.0,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Regressions from the baseline larger than the threshold, in percent.
+    ///
+    /// A metric that regresses from a zero baseline (eg, packet loss) counts whatever the threshold.
+    pub fn regressions(&self, threshold: f64) -> Vec<Regression> {
+        let mut regressions = Vec::new();
+        for metric in METRICS {
+            for (run, delta) in self.runs.iter().zip(self.deltas(metric)) {
+                if let Some(delta) = delta
+                    && delta.regression
+                    && delta
+                        .percent
+                        .is_none_or(|percent| percent.abs() > threshold)
+                {
+                    regressions.push(Regression {
+                        metric: metric.name,
+                        kind: metric.kind,
+                        run: run.label.clone(),
+                        delta,
+                    });
+                }
+            }
+        }
+        regressions
+    }
+
+    /// Side by side table of the runs, with the deltas of each run from the baseline.
+    ///
+    /// With `color`, regressions are shown in red and improvements in green.
+    pub fn to_table(&self, color: bool) -> String {
+        let mut builder = Builder::default();
+        let mut header = vec!["Metric".to_string(), self.runs[0].label.clone()];
+        let mut time = vec!["Time".to_string(), timestamp(&self.runs[0].report)];
+        let mut target = vec!["Target".to_string(), target(&self.runs[0].report)];
+        for run in &self.runs[1..] {
+            header.extend([run.label.clone(), "Δ".to_string(), "Δ%".to_string()]);
+            time.extend([timestamp(&run.report), String::new(), String::new()]);
+            target.extend([self::target(&run.report), String::new(), String::new()]);
+        }
+        builder.push_record(header);
+        builder.push_record(time);
+        builder.push_record(target);
+
+        for metric in METRICS {
+            let values: Vec<Option<f64>> = self
+                .runs
+                .iter()
+                .map(|run| (metric.value)(&run.report))
+                .collect();
+            if values.iter().all(Option::is_none) {
+                continue;
+            }
+            let format = |value: Option<f64>| value.map(metric.format).unwrap_or_default();
+            let mut record = vec![metric.name.to_string(), format(values[0])];
+            for (value, delta) in values.iter().zip(self.deltas(metric)).skip(1) {
+                record.push(format(*value));
+                match delta {
+                    Some(delta) => {
+                        let sign = if delta.absolute < 0.0 { "-" } else { "+" };
+                        let absolute = format!("{sign}{}", (metric.format)(delta.absolute.abs()));
+                        let percent = delta
+                            .percent
+                            .map(|percent| format!("{percent:+.1}%"))
+                            .unwrap_or_default();
+                        let style = match delta.regression {
+                            _ if !color || delta.absolute == 0.0 => Style::new(),
+                            true => Style::new().fg_color(Some(Color::Ansi(AnsiColor::Red))),
+                            false => Style::new().fg_color(Some(Color::Ansi(AnsiColor::Green))),
+                        };
+                        record.push(format!("{style}{absolute}{style:#}"));
+                        record.push(format!("{style}{percent}{style:#}"));
+                    }
+                    None => record.extend([String::new(), String::new()]),
+                }
+            }
+            builder.push_record(record);
+        }
+
+        let mut table = builder.build();
+        table.with((
+            Panel::header("⚖️ Netbeat Comparison"),
+            TableStyle::re_structured_text().remove_top(),
+            Modify::new(Rows::first()).with(Alignment::center()),
+        ));
+        format!("\n{table}\n")
+    }
+}
+
+/// Load the reports saved in a file, labeled by the file name.
+///
+/// The file may hold a single json report (`--format json`), a json array of reports (`netbeat history --format json`),
+/// or one report per line (the history file, or `--format ndjson` output).
+pub fn load_runs(path: &Path) -> Result<Vec<Run>> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        NetbeatError::client(format!("Failed to read report {} - {e}", path.display()))
+    })?;
+    let reports = serde_json::from_str::<NetbeatReport>(&content)
+        .map(|report| vec![report])
+        .or_else(|e| serde_json::from_str::<Vec<NetbeatReport>>(&content).map_err(|_| e))
+        .or_else(|e| {
+            let reports: Vec<NetbeatReport> = content
+                .lines()
+                .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+                .filter_map(|value| match value.get("event") {
+                    Some(event) if event == "report" => value.get("report").cloned(),
+                    Some(_) => None,
+                    None => Some(value),
+                })
+                .filter_map(|value| serde_json::from_value(value).ok())
+                .collect();
+            if reports.is_empty() {
+                Err(e)
+            } else {
+                Ok(reports)
+            }
+        })
+        .map_err(|e| {
+            NetbeatError::client(format!("Failed to read report {} - {e}", path.display()))
+        })?;
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string());
+    let count = reports.len();
+    Ok(reports
+        .into_iter()
+        .enumerate()
+        .map(|(i, report)| Run {
+            label: if count == 1 {
+                name.clone()
+            } else {
+                format!("{name}#{}", i + 1)
+            },
+            report,
+        })
+        .collect())
+}
+
+/// Format a number of seconds as a duration.
+fn format_seconds(seconds: f64) -> String {
+    format!("{:.2?}", Duration::from_secs_f64(seconds))
+}
+
+/// Format a percentage.
+fn format_percent(percent: f64) -> String {
+    format!("{percent:.1}%")
+}
+
+/// Time of a run, to the second.
+fn timestamp(report: &NetbeatReport) -> String {
+    humantime::format_rfc3339_seconds(report.timestamp).to_string()
+}
+
+/// Target of a run.
+fn target(report: &NetbeatReport) -> String {
+    report.target.clone().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{
+        events::Event,
+        reports::{PingReport, Report, SpeedReport},
+    };
+
+    fn create_run(label: &str, download_bytes: u64, pings: &[u64]) -> Run {
+        let pings: Vec<Duration> = pings.iter().map(|ms| Duration::from_millis(*ms)).collect();
+        Run {
+            label: label.to_string(),
+            report: NetbeatReport::new(
+                PingReport::new(4, pings.len() as u32, pings),
+                SpeedReport::new("upload", Duration::from_secs(1), 100_000_000).unwrap(),
+                SpeedReport::new("download", Duration::from_secs(1), download_bytes).unwrap(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_regressions() {
+        let comparison = Comparison::new(vec![
+            create_run("before.json", 100_000_000, &[10, 10, 10, 10]),
+            create_run("after.json", 90_000_000, &[10, 10, 11]),
+        ])
+        .unwrap();
+
+        let regressions = comparison.regressions(5.0);
+        assert_eq!(
+            regressions
+                .iter()
+                .map(|regression| regression.metric)
+                .collect::<Vec<_>>(),
+            vec!["Download speed", "P95 ping", "Jitter", "Packet loss"]
+        );
+        assert_eq!(regressions[0].kind, ThresholdKind::Download);
+        assert_eq!(regressions[0].delta.absolute, -80_000_000.0);
+        assert_eq!(regressions[0].delta.percent, Some(-10.0));
+        assert_eq!(
+            regressions[0].to_string(),
+            "Download speed regressed by 10.0% in after.json"
+        );
+        assert_eq!(
+            regressions[3].to_string(),
+            "Packet loss regressed from zero in after.json"
+        );
+
+        // Jitter and packet loss regressed from zero, which counts whatever the threshold
+        let regressions = comparison.regressions(50.0);
+        assert_eq!(regressions.len(), 2);
+        assert_eq!(regressions[0].kind, ThresholdKind::Latency);
+        assert_eq!(regressions[1].kind, ThresholdKind::Loss);
+
+        assert!(Comparison::new(vec![create_run("only.json", 1, &[1])]).is_err());
+    }
+
+    #[test]
+    fn test_to_table() {
+        let comparison = Comparison::new(vec![
+            create_run("before.json", 100_000_000, &[10, 10, 10, 10]),
+            create_run("after.json", 125_000_000, &[10, 10, 10, 10]),
+            create_run("later.json", 50_000_000, &[10, 10, 10, 10]),
+        ])
+        .unwrap();
+
+        let table = comparison.to_table(false);
+        assert!(table.contains("before.json"));
+        assert!(table.contains("Δ%"));
+        assert!(table.contains("+200.00 Mbps"));
+        assert!(table.contains("+25.0%"));
+        assert!(table.contains("-50.0%"));
+        // Metrics without values in any run are left out
+        assert!(!table.contains("Loaded latency"));
+        assert!(!table.contains("TLS handshake"));
+        assert!(!table.contains("\u{1b}["));
+
+        let table = comparison.to_table(true);
+        assert!(table.contains("\u{1b}[31m-400.00 Mbps"));
+        assert!(table.contains("\u{1b}[32m+25.0%"));
+    }
+
+    #[test]
+    fn test_load_runs() {
+        let dir = std::env::temp_dir().join(format!("netbeat-test-{}-compare", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let report = create_run("", 100_000_000, &[10]).report;
+
+        let path = dir.join("report.json");
+        fs::write(&path, report.to_json().to_string()).unwrap();
+        let runs = load_runs(&path).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].label, "report.json");
+
+        let path = dir.join("history.json");
+        fs::write(
+            &path,
+            serde_json::to_string(&[report.clone(), report.clone()]).unwrap(),
+        )
+        .unwrap();
+        let runs = load_runs(&path).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].label, "history.json#2");
+
+        let path = dir.join("run.ndjson");
+        fs::write(
+            &path,
+            format!(
+                "{}\n{}\n",
+                Event::PhaseStart { phase: "ping" }.to_json(),
+                Event::Report { report: &report }.to_json()
+            ),
+        )
+        .unwrap();
+        assert_eq!(load_runs(&path).unwrap().len(), 1);
+
+        let path = dir.join("broken.json");
+        fs::write(&path, "{\"schema_version\":1").unwrap();
+        let error = load_runs(&path).err().unwrap().to_string();
+        assert!(error.contains("Failed to read report"));
+        assert!(load_runs(&dir.join("missing.json")).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+//! Events streamed while a speed test runs, written one per line with `--format ndjson`.
+
+use crate::{
+    core::intervals::IntervalSample,
+    output::{
+        reports::{LatencyReport, NetbeatReport, PingReport, SpeedReport},
+        schema::{self, IntervalSchema},
+    },
+};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Progress of a speed test, tagged by its `event` field.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A test phase (ping/upload/download/bidirectional) started, or the latency probe spanning the loaded phases
+    PhaseStart { phase: &'a str },
+    /// Bytes moved during one reporting interval, as counted by the client while the phase runs
+    Interval {
+        phase: &'a str,
+        #[serde(flatten)]
+        sample: IntervalSchema,
+    },
+    /// A test phase completed
+    PhaseEnd {
+        phase: &'a str,
+        report: PhaseReport<'a>,
+    },
+    /// All tests completed
+    Report { report: &'a NetbeatReport },
+    /// A scheduled test failed, to be retried after a backoff
+    Error {
+        target: &'a str,
+        timestamp: String,
+        error: String,
+        consecutive_failures: u32,
+        #[serde(with = "schema::nanos")]
+        retry_in_ns: Duration,
+    },
+}
+
+/// Report of a completed test phase.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum PhaseReport<'a> {
+    Ping(&'a PingReport),
+    Speed(&'a SpeedReport),
+    Bidirectional {
+        upload: &'a SpeedReport,
+        download: &'a SpeedReport,
+    },
+    Latency(&'a LatencyReport),
+}
+
+impl<'a> Event<'a> {
+    /// Interval event for a sample recorded during a phase.
+    pub fn interval(phase: &'a str, sample: IntervalSample) -> Event<'a> {
+        Event::Interval {
+            phase,
+            sample: sample.into(),
+        }
+    }
+
+    /// Convert the event to a single line of json.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Events always serialize to json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::reports::LoadedLatency;
+
+    #[test]
+    fn test_event_to_json() {
+        assert_eq!(
+            Event::PhaseStart { phase: "upload" }.to_json(),
+            r#"{"event":"phase_start","phase":"upload"}"#
+        );
+
+        let sample = IntervalSample {
+            start: Duration::from_secs(1),
+            end: Duration::from_secs(2),
+            bytes: 1_000_000,
+        };
+        assert_eq!(
+            Event::interval("download", sample).to_json(),
+            r#"{"event":"interval","phase":"download","start_ns":1000000000,"end_ns":2000000000,"bytes":1000000,"bits_per_second":8000000.0}"#
+        );
+
+        let report = SpeedReport::new("upload", Duration::from_secs(1), 1_000_000).unwrap();
+        let json: serde_json::Value = serde_json::from_str(
+            &Event::PhaseEnd {
+                phase: "upload",
+                report: PhaseReport::Speed(&report),
+            }
+            .to_json(),
+        )
+        .unwrap();
+        assert_eq!(json["event"], "phase_end");
+        assert_eq!(json["report"]["direction"], "upload");
+        assert_eq!(json["report"]["bits_per_second"], 8e6);
+
+        let ms = Duration::from_millis;
+        let report = LatencyReport::new(
+            ms(10),
+            vec![LoadedLatency::new("upload", vec![ms(40), ms(60)])],
+        );
+        let json: serde_json::Value = serde_json::from_str(
+            &Event::PhaseEnd {
+                phase: "latency",
+                report: PhaseReport::Latency(&report),
+            }
+            .to_json(),
+        )
+        .unwrap();
+        assert_eq!(json["phase"], "latency");
+        assert_eq!(json["report"]["idle_ns"], 10_000_000);
+        assert_eq!(json["report"]["phases"][0]["phase"], "upload");
+
+        let error = Event::Error {
+            target: "nas.lan",
+            timestamp: "2025-08-20T18:04:12.311Z".to_string(),
+            error: "Connection error: refused".to_string(),
+            consecutive_failures: 2,
+            retry_in_ns: Duration::from_secs(600),
+        };
+        assert_eq!(
+            error.to_json(),
+            r#"{"event":"error","target":"nas.lan","timestamp":"2025-08-20T18:04:12.311Z","error":"Connection error: refused","consecutive_failures":2,"retry_in_ns":600000000000}"#
+        );
+    }
+}
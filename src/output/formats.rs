@@ -0,0 +1,200 @@
+//! Output formats for netbeat results.
+//!
+//! Report tables always go to stderr. Results go to stdout as a single json object, newline delimited json events
+//! streamed while the tests run, or a csv row with the fixed columns in [`CSV_COLUMNS`].
+
+use crate::output::reports::NetbeatReport;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Format of the results written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Report tables only, no results on stdout
+    Table,
+    /// Full report as a single json object
+    Json,
+    /// Json events, one per line, streamed as the tests run
+    Ndjson,
+    /// Single csv row with fixed columns
+    Csv,
+}
+
+/// Columns of the csv output, in order. Durations are in nanoseconds and speeds in bits per second.
+///
+/// Columns that do not apply to a run (eg, datagram loss outside UDP mode) are left empty.
+pub const CSV_COLUMNS: &[&str] = &[
+    "timestamp",
+    "target",
+    "server_address",
+    "parallel_streams",
+    "bidirectional",
+    "udp",
+    "packets_sent",
+    "packets_received",
+    "packet_loss_percent",
+    "min_ping_ns",
+    "avg_ping_ns",
+    "max_ping_ns",
+    "median_ping_ns",
+    "p90_ping_ns",
+    "p95_ping_ns",
+    "p99_ping_ns",
+    "stddev_ping_ns",
+    "jitter_ns",
+    "upload_bytes",
+    "upload_duration_ns",
+    "upload_bps",
+    "download_bytes",
+    "download_duration_ns",
+    "download_bps",
+    "upload_datagram_loss_percent",
+    "download_datagram_loss_percent",
+    "idle_latency_ns",
+    "loaded_latency_ns",
+    "bufferbloat_grade",
+    "tls_version",
+    "tls_handshake_ns",
+];
+
+/// Header row of the csv output.
+pub fn csv_header() -> String {
+    CSV_COLUMNS.join(",")
+}
+
+/// Csv row holding the report values for each of [`CSV_COLUMNS`].
+pub fn csv_row(report: &NetbeatReport) -> String {
+    let nanos = |duration: Duration| duration.as_nanos().to_string();
+    let ping = &report.ping_report;
+    let (upload, download) = (&report.upload_report, &report.download_report);
+    let latency = report.latency_report.as_ref();
+    let tls = report.tls_report.as_ref();
+
+    let values = [
+        humantime::format_rfc3339_millis(report.timestamp).to_string(),
+        report.target.clone().unwrap_or_default(),
+        report
+            .server_addr
+            .map(|addr| addr.to_string())
+            .unwrap_or_default(),
+        upload.streams.len().to_string(),
+        report.bidirectional.to_string(),
+        upload.udp.is_some().to_string(),
+        ping.ping_count.to_string(),
+        ping.successful_pings.to_string(),
+        ping.packet_loss.to_string(),
+        nanos(ping.min_ping),
+        nanos(ping.avg_ping),
+        nanos(ping.max_ping),
+        nanos(ping.median_ping),
+        nanos(ping.p90_ping),
+        nanos(ping.p95_ping),
+        nanos(ping.p99_ping),
+        nanos(ping.stddev_ping),
+        nanos(ping.jitter),
+        upload.bytes.to_string(),
+        nanos(upload.duration),
+        (upload.speed * 8.0).to_string(),
+        download.bytes.to_string(),
+        nanos(download.duration),
+        (download.speed * 8.0).to_string(),
+        upload
+            .udp
+            .map(|stats| stats.loss_percent().to_string())
+            .unwrap_or_default(),
+        download
+            .udp
+            .map(|stats| stats.loss_percent().to_string())
+            .unwrap_or_default(),
+        latency
+            .map(|latency| nanos(latency.idle))
+            .unwrap_or_default(),
+        latency
+            .and_then(|latency| latency.loaded.iter().map(|phase| phase.avg).max())
+            .map(nanos)
+            .unwrap_or_default(),
+        latency
+            .and_then(|latency| latency.grade)
+            .unwrap_or_default()
+            .to_string(),
+        tls.map(|tls| tls.version.clone()).unwrap_or_default(),
+        tls.map(|tls| nanos(tls.handshake_time)).unwrap_or_default(),
+    ];
+    values
+        .iter()
+        .map(|value| csv_field(value))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Quote a csv field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::reports::{
+        LatencyReport, LoadedLatency, PingReport, SpeedReport, TlsReport,
+    };
+
+    #[test]
+    fn test_csv_row() {
+        let ms = Duration::from_millis;
+        let report = NetbeatReport::new(
+            PingReport::new(4, 4, vec![ms(10), ms(12), ms(9), ms(11)]),
+            SpeedReport::new("upload", Duration::from_secs(2), 250_000_000).unwrap(),
+            SpeedReport::new("download", Duration::from_secs(2), 125_000_000).unwrap(),
+        )
+        .with_server("nas.lan", "[fe80::1]:5050".parse().unwrap())
+        .with_latency(LatencyReport::new(
+            ms(10),
+            vec![
+                LoadedLatency::new("upload", vec![ms(40), ms(60)]),
+                LoadedLatency::new("download", vec![ms(20)]),
+            ],
+        ))
+        .with_tls(TlsReport {
+            version: "TLSv1.3".to_string(),
+            cipher_suite: "TLS13_AES_256_GCM_SHA384".to_string(),
+            handshake_time: ms(3),
+            fingerprint: "AB:CD".to_string(),
+        });
+
+        let row = csv_row(&report);
+        let values: Vec<&str> = row.split(',').collect();
+        assert_eq!(values.len(), CSV_COLUMNS.len());
+        let value = |column: &str| values[CSV_COLUMNS.iter().position(|c| *c == column).unwrap()];
+        assert!(value("timestamp").ends_with('Z'));
+        assert_eq!(value("target"), "nas.lan");
+        assert_eq!(value("server_address"), "[fe80::1]:5050");
+        assert_eq!(value("parallel_streams"), "1");
+        assert_eq!(value("udp"), "false");
+        assert_eq!(value("packet_loss_percent"), "0");
+        assert_eq!(value("avg_ping_ns"), "10500000");
+        assert_eq!(value("upload_bps"), "1000000000");
+        assert_eq!(value("download_bytes"), "125000000");
+        assert_eq!(value("download_duration_ns"), "2000000000");
+        assert_eq!(value("upload_datagram_loss_percent"), "");
+        assert_eq!(value("loaded_latency_ns"), "50000000");
+        assert_eq!(value("bufferbloat_grade"), "B");
+        assert_eq!(value("tls_version"), "TLSv1.3");
+        assert_eq!(value("tls_handshake_ns"), "3000000");
+
+        assert_eq!(csv_header().split(',').count(), CSV_COLUMNS.len());
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("nas.lan"), "nas.lan");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,533 @@
+//! Local history of netbeat reports.
+//!
+//! Each report is appended as a line of versioned json (see [`schema`](super::schema)) to `history.jsonl` in the
+//! netbeat data directory (eg, `~/.local/share/netbeat` on Linux), then read back to list past runs and daily trends.
+
+use crate::{
+    core::config,
+    output::{
+        formats::{self, OutputFormat},
+        reports::{NetbeatReport, Report},
+        schema,
+    },
+    utils::{
+        error::{NetbeatError, Result},
+        units,
+    },
+};
+
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tabled::{
+    Table, Tabled,
+    settings::{Alignment, Modify, Panel, Style, object::Rows},
+};
+
+/// Columns of the csv trend output, in order.
+pub const TREND_CSV_COLUMNS: &[&str] = &[
+    "day",
+    "target",
+    "runs",
+    "download_bps",
+    "upload_bps",
+    "ping_ns",
+    "download_change_percent",
+];
+
+/// Append-only store of netbeat reports, one json report per line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    path: PathBuf,
+}
+
+/// Selection of past runs to load from the history.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    /// Target as given to `netbeat run`, or the server address it resolved to
+    pub target: Option<String>,
+    /// Only runs at or after this time
+    pub since: Option<SystemTime>,
+    /// Only runs at or before this time
+    pub until: Option<SystemTime>,
+    /// Only the most recent runs, up to this many
+    pub limit: Option<usize>,
+}
+
+/// Average results of a target over a single day (UTC).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrendRow {
+    /// Day of the runs (eg, 2025-08-20)
+    pub day: String,
+    /// Target of the runs
+    pub target: String,
+    /// Number of runs on the day
+    pub runs: usize,
+    /// Average download speed in bits per second
+    pub download_bps: f64,
+    /// Average upload speed in bits per second
+    pub upload_bps: f64,
+    /// Average of the average pings
+    #[serde(rename = "ping_ns", with = "schema::nanos")]
+    pub ping: Duration,
+    /// Change in average download speed from the previous day with runs, in percent
+    pub download_change_percent: Option<f64>,
+}
+
+/// Row of the past runs table.
+#[derive(Tabled)]
+struct HistoryRow {
+    #[tabled(rename = "Time")]
+    time: String,
+    #[tabled(rename = "Target")]
+    target: String,
+    #[tabled(rename = "Download")]
+    download: String,
+    #[tabled(rename = "Upload")]
+    upload: String,
+    #[tabled(rename = "Ping")]
+    ping: String,
+    #[tabled(rename = "Loss")]
+    loss: String,
+}
+
+/// Row of the daily trend table.
+#[derive(Tabled)]
+struct TrendTableRow {
+    #[tabled(rename = "Day")]
+    day: String,
+    #[tabled(rename = "Target")]
+    target: String,
+    #[tabled(rename = "Runs")]
+    runs: usize,
+    #[tabled(rename = "Download")]
+    download: String,
+    #[tabled(rename = "Upload")]
+    upload: String,
+    #[tabled(rename = "Ping")]
+    ping: String,
+    #[tabled(rename = "Change")]
+    change: String,
+}
+
+impl History {
+    /// History stored in the given file.
+    pub fn new(path: impl Into<PathBuf>) -> History {
+        History { path: path.into() }
+    }
+
+    /// History stored in the netbeat data directory of the current user.
+    pub fn open_default() -> Result<History> {
+        let data_dir = dirs::data_dir().ok_or_else(|| {
+            NetbeatError::history("Failed to locate the user data directory".to_string())
+        })?;
+        Ok(History::new(
+            data_dir.join("netbeat").join(config::HISTORY_FILE_NAME),
+        ))
+    }
+
+    /// Path of the history file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a report to the history, creating the file and its directory as needed.
+    pub fn append(&self, report: &NetbeatReport) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                NetbeatError::history(format!("Failed to create {} - {e}", parent.display()))
+            })?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| {
+                NetbeatError::history(format!("Failed to open {} - {e}", self.path.display()))
+            })?;
+        // A single write per report keeps lines whole when several runs finish at once
+        file.write_all(format!("{}\n", report.to_json()).as_bytes())
+            .map_err(|e| {
+                NetbeatError::history(format!("Failed to write to {} - {e}", self.path.display()))
+            })
+    }
+
+    /// Load the runs matching the filter, oldest first.
+    ///
+    /// Lines that do not hold a readable report (eg, an interrupted write) are skipped.
+    pub fn load(&self, filter: &HistoryFilter) -> Result<Vec<NetbeatReport>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(NetbeatError::history(format!(
+                    "Failed to open {} - {e}",
+                    self.path.display()
+                )));
+            }
+        };
+        let mut reports = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| {
+                NetbeatError::history(format!("Failed to read {} - {e}", self.path.display()))
+            })?;
+            if let Ok(report) = serde_json::from_str::<NetbeatReport>(&line)
+                && filter.matches(&report)
+            {
+                reports.push(report);
+            }
+        }
+        reports.sort_by_key(|report| report.timestamp);
+        if let Some(limit) = filter.limit {
+            reports.drain(..reports.len().saturating_sub(limit));
+        }
+        Ok(reports)
+    }
+}
+
+impl HistoryFilter {
+    /// Whether a report matches the target and time range of the filter.
+    pub fn matches(&self, report: &NetbeatReport) -> bool {
+        let target = self.target.as_deref().is_none_or(|target| {
+            report.target.as_deref() == Some(target)
+                || report.server_addr.is_some_and(|addr| {
+                    addr.to_string() == target
+                        || report
+                            .target
+                            .as_ref()
+                            .is_some_and(|host| format!("{host}:{}", addr.port()) == target)
+                })
+        });
+        target
+            && self.since.is_none_or(|since| report.timestamp >= since)
+            && self.until.is_none_or(|until| report.timestamp <= until)
+    }
+}
+
+/// Parse a point in time, as a date (eg, 2025-08-01), a date and time (eg, 2025-08-01 18:00:00) in UTC, or an age
+/// relative to now (eg, 7d, 12h).
+pub fn parse_time(s: &str) -> std::result::Result<SystemTime, String> {
+    let trimmed = s.trim();
+    if let Ok(age) = humantime::parse_duration(trimmed) {
+        return SystemTime::now()
+            .checked_sub(age)
+            .ok_or_else(|| format!("Invalid time ({s:?}) - too far in the past"));
+    }
+    humantime::parse_rfc3339_weak(trimmed)
+        .or_else(|_| humantime::parse_rfc3339_weak(&format!("{trimmed} 00:00:00")))
+        .map_err(|_| {
+            format!(
+                "Invalid time ({s:?}) - expected a date (eg, 2025-08-01), a date and time (eg, 2025-08-01 18:00:00) or an age (eg, 7d)"
+            )
+        })
+}
+
+/// Average the runs of each target per day, with the change in download speed from the previous day.
+pub fn daily_trend(reports: &[NetbeatReport]) -> Vec<TrendRow> {
+    let mut days: BTreeMap<(String, String), Vec<&NetbeatReport>> = BTreeMap::new();
+    for report in reports {
+        let day = humantime::format_rfc3339_seconds(report.timestamp).to_string()[..10].to_string();
+        days.entry((target_name(report), day))
+            .or_default()
+            .push(report);
+    }
+
+    let mut rows: Vec<TrendRow> = Vec::new();
+    for ((target, day), runs) in days {
+        let average = |value: fn(&NetbeatReport) -> f64| {
+            runs.iter().map(|report| value(report)).sum::<f64>() / runs.len() as f64
+        };
+        let download_bps = average(|report| report.download_report.speed * 8.0);
+        let download_change_percent = rows
+            .last()
+            .filter(|previous| previous.target == target && previous.download_bps > 0.0)
+            .map(|previous| (download_bps - previous.download_bps) / previous.download_bps * 100.0);
+        rows.push(TrendRow {
+            day,
+            target,
+            runs: runs.len(),
+            download_bps,
+            upload_bps: average(|report| report.upload_report.speed * 8.0),
+            ping: Duration::from_secs_f64(average(|report| {
+                report.ping_report.avg_ping.as_secs_f64()
+            })),
+            download_change_percent,
+        });
+    }
+    rows
+}
+
+/// Render past runs, or their daily trend, in the given format.
+pub fn render(
+    reports: &[NetbeatReport],
+    trend: bool,
+    format: OutputFormat,
+    csv_header: bool,
+) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    if trend {
+        let rows = daily_trend(reports);
+        match format {
+            OutputFormat::Table => lines.push(to_trend_table(&rows).to_string()),
+            OutputFormat::Json => {
+                lines.push(serde_json::to_string(&rows).expect("Trends always serialize to json"))
+            }
+            OutputFormat::Ndjson => {
+                lines.extend(rows.iter().map(|row| {
+                    serde_json::to_string(row).expect("Trends always serialize to json")
+                }))
+            }
+            OutputFormat::Csv => {
+                if csv_header {
+                    lines.push(TREND_CSV_COLUMNS.join(","));
+                }
+                lines.extend(rows.iter().map(trend_csv_row));
+            }
+        }
+    } else {
+        match format {
+            OutputFormat::Table => lines.push(to_history_table(reports).to_string()),
+            OutputFormat::Json => lines
+                .push(serde_json::to_string(reports).expect("Reports always serialize to json")),
+            OutputFormat::Ndjson => {
+                lines.extend(reports.iter().map(|report| report.to_json().to_string()))
+            }
+            OutputFormat::Csv => {
+                if csv_header {
+                    lines.push(formats::csv_header());
+                }
+                lines.extend(reports.iter().map(formats::csv_row));
+            }
+        }
+    }
+    lines.iter().map(|line| format!("{line}\n")).collect()
+}
+
+/// Convert past runs to a table, one row per run.
+pub fn to_history_table(reports: &[NetbeatReport]) -> impl Display {
+    let rows = reports.iter().map(|report| HistoryRow {
+        time: humantime::format_rfc3339_seconds(report.timestamp).to_string(),
+        target: target_name(report),
+        download: units::format_bitrate(report.download_report.speed * 8.0),
+        upload: units::format_bitrate(report.upload_report.speed * 8.0),
+        ping: format!("{:.2?}", report.ping_report.avg_ping),
+        loss: format!("{:.1}%", report.ping_report.packet_loss),
+    });
+    styled_table(Table::new(rows), "🗂️ Netbeat History")
+}
+
+/// Convert a daily trend to a table, marking the change in download speed from the previous day.
+pub fn to_trend_table(rows: &[TrendRow]) -> impl Display {
+    let rows = rows.iter().map(|row| TrendTableRow {
+        day: row.day.clone(),
+        target: row.target.clone(),
+        runs: row.runs,
+        download: units::format_bitrate(row.download_bps),
+        upload: units::format_bitrate(row.upload_bps),
+        ping: format!("{:.2?}", row.ping),
+        change: match row.download_change_percent {
+            Some(change) if change < 0.0 => format!("▼ {:.1}%", -change),
+            Some(change) => format!("▲ {change:.1}%"),
+            None => String::new(),
+        },
+    });
+    styled_table(Table::new(rows), "📈 Netbeat Trend")
+}
+
+/// Apply the netbeat table style with a title.
+fn styled_table(mut table: Table, title: &str) -> String {
+    table.with((
+        Panel::header(title),
+        Style::re_structured_text().remove_top(),
+        Modify::new(Rows::first()).with(Alignment::center()),
+    ));
+    format!("\n{table}\n")
+}
+
+/// Csv row holding the values for each of [`TREND_CSV_COLUMNS`].
+fn trend_csv_row(row: &TrendRow) -> String {
+    [
+        row.day.clone(),
+        row.target.clone(),
+        row.runs.to_string(),
+        row.download_bps.to_string(),
+        row.upload_bps.to_string(),
+        row.ping.as_nanos().to_string(),
+        row.download_change_percent
+            .map(|change| change.to_string())
+            .unwrap_or_default(),
+    ]
+    .join(",")
+}
+
+/// Name a run by its target, falling back to the server address.
+fn target_name(report: &NetbeatReport) -> String {
+    report
+        .target
+        .clone()
+        .or_else(|| report.server_addr.map(|addr| addr.to_string()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::reports::{PingReport, SpeedReport};
+
+    fn create_report(target: &str, timestamp: &str, download_bytes: u64) -> NetbeatReport {
+        let ms = Duration::from_millis;
+        let mut report = NetbeatReport::new(
+            PingReport::new(2, 2, vec![ms(1), ms(3)]),
+            SpeedReport::new("upload", Duration::from_secs(1), 100_000_000).unwrap(),
+            SpeedReport::new("download", Duration::from_secs(1), download_bytes).unwrap(),
+        )
+        .with_server(target, "10.1.1.11:5050".parse().unwrap());
+        report.timestamp = humantime::parse_rfc3339(timestamp).unwrap();
+        report
+    }
+
+    fn temp_history(name: &str) -> History {
+        let path = std::env::temp_dir()
+            .join(format!("netbeat-test-{}-{name}", std::process::id()))
+            .join(config::HISTORY_FILE_NAME);
+        let _ = fs::remove_file(&path);
+        History::new(path)
+    }
+
+    #[test]
+    fn test_append_and_load() {
+        let history = temp_history("append");
+        assert!(history.load(&HistoryFilter::default()).unwrap().is_empty());
+
+        history
+            .append(&create_report(
+                "nas.lan",
+                "2025-08-02T10:00:00Z",
+                125_000_000,
+            ))
+            .unwrap();
+        history
+            .append(&create_report(
+                "nas.lan",
+                "2025-08-01T10:00:00Z",
+                100_000_000,
+            ))
+            .unwrap();
+        history
+            .append(&create_report("pi.lan", "2025-08-03T10:00:00Z", 10_000_000))
+            .unwrap();
+        // An interrupted write is skipped
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(history.path())
+            .unwrap();
+        file.write_all(b"{\"schema_version\":1,\"timest").unwrap();
+
+        let reports = history.load(&HistoryFilter::default()).unwrap();
+        assert_eq!(reports.len(), 3);
+        assert!(reports.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert_eq!(reports[0].download_report.bytes, 100_000_000);
+
+        let filter = HistoryFilter {
+            target: Some("nas.lan".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(history.load(&filter).unwrap().len(), 2);
+        let filter = HistoryFilter {
+            target: Some("pi.lan:5050".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(history.load(&filter).unwrap().len(), 1);
+        let filter = HistoryFilter {
+            since: Some(parse_time("2025-08-02").unwrap()),
+            until: Some(parse_time("2025-08-02 23:59:59").unwrap()),
+            ..Default::default()
+        };
+        let reports = history.load(&filter).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].download_report.bytes, 125_000_000);
+        let filter = HistoryFilter {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            history.load(&filter).unwrap()[0].target.as_deref(),
+            Some("pi.lan")
+        );
+
+        fs::remove_dir_all(history.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2025-08-01").unwrap(),
+            humantime::parse_rfc3339("2025-08-01T00:00:00Z").unwrap()
+        );
+        assert_eq!(
+            parse_time("2025-08-01T18:30:00Z").unwrap(),
+            humantime::parse_rfc3339("2025-08-01T18:30:00Z").unwrap()
+        );
+        let since = parse_time("7d").unwrap();
+        let age = SystemTime::now().duration_since(since).unwrap();
+        assert!(age >= Duration::from_secs(7 * 24 * 3600));
+        assert!(parse_time("last tuesday").is_err());
+    }
+
+    #[test]
+    fn test_daily_trend() {
+        let reports = [
+            create_report("nas.lan", "2025-08-01T10:00:00Z", 100_000_000),
+            create_report("nas.lan", "2025-08-01T22:00:00Z", 120_000_000),
+            create_report("nas.lan", "2025-08-02T10:00:00Z", 55_000_000),
+            create_report("pi.lan", "2025-08-02T10:00:00Z", 10_000_000),
+        ];
+        let rows = daily_trend(&reports);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].day, "2025-08-01");
+        assert_eq!(rows[0].runs, 2);
+        assert_eq!(rows[0].download_bps, 880_000_000.0);
+        assert_eq!(rows[0].ping, Duration::from_millis(2));
+        assert_eq!(rows[0].download_change_percent, None);
+        assert_eq!(rows[1].download_change_percent, Some(-50.0));
+        // Changes are only tracked within a target
+        assert_eq!(rows[2].target, "pi.lan");
+        assert_eq!(rows[2].download_change_percent, None);
+
+        let table = to_trend_table(&rows).to_string();
+        assert!(table.contains("▼ 50.0%"));
+        let csv = render(&reports, true, OutputFormat::Csv, true);
+        assert_eq!(
+            csv.lines().nth(2).unwrap(),
+            "2025-08-02,nas.lan,1,440000000,800000000,2000000,-50"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let reports = [
+            create_report("nas.lan", "2025-08-01T10:00:00Z", 100_000_000),
+            create_report("nas.lan", "2025-08-02T10:00:00Z", 55_000_000),
+        ];
+        let table = render(&reports, false, OutputFormat::Table, true);
+        assert!(table.contains("2025-08-01T10:00:00Z"));
+        assert!(table.contains("440.00 Mbps"));
+
+        let ndjson = render(&reports, false, OutputFormat::Ndjson, true);
+        assert_eq!(ndjson.lines().count(), 2);
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&reports, false, OutputFormat::Json, true)).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert_eq!(json[1]["download"]["bytes"], 55_000_000);
+
+        let csv = render(&reports, false, OutputFormat::Csv, false);
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.starts_with("2025-08-01T10:00:00.000Z,nas.lan,"));
+    }
+}
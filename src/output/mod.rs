@@ -1,3 +1,9 @@
 //! Key outputs for netbeat.
 
+pub mod compare;
+pub mod events;
+pub mod formats;
+pub mod history;
+pub mod prometheus;
 pub mod reports;
+pub mod schema;
@@ -0,0 +1,380 @@
+//! Prometheus text exposition of netbeat reports.
+//!
+//! Used by the exporter to serve the latest results of each target on `/metrics`. Report values come from the latest
+//! successful test of a target and are kept while later tests fail, with `netbeat_up` and the error metrics showing
+//! the state of the latest test.
+
+use crate::output::reports::NetbeatReport;
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Latest test results and test counts for a single target.
+#[derive(Clone)]
+pub struct TargetMetrics {
+    /// Target server, as given by the user
+    pub target: String,
+    /// Report of the latest successful test
+    pub report: Option<NetbeatReport>,
+    /// Error of the latest test, when it failed
+    pub last_error: Option<String>,
+    /// Number of tests run
+    pub tests: u64,
+    /// Number of tests that failed
+    pub failures: u64,
+    /// Time the latest test finished
+    pub last_test: Option<SystemTime>,
+    /// Time the latest successful test finished
+    pub last_success: Option<SystemTime>,
+    /// Wall clock time of the latest test, connection and all phases included
+    pub last_duration: Duration,
+}
+
+impl TargetMetrics {
+    /// Create metrics for a target that has not been tested yet.
+    pub fn new(target: impl Into<String>) -> TargetMetrics {
+        TargetMetrics {
+            target: target.into(),
+            report: None,
+            last_error: None,
+            tests: 0,
+            failures: 0,
+            last_test: None,
+            last_success: None,
+            last_duration: Duration::ZERO,
+        }
+    }
+
+    /// Record the outcome of a test that took `duration`.
+    pub fn record<E: std::fmt::Display>(
+        &mut self,
+        result: Result<NetbeatReport, E>,
+        duration: Duration,
+    ) {
+        let now = SystemTime::now();
+        self.tests += 1;
+        self.last_test = Some(now);
+        self.last_duration = duration;
+        match result {
+            Ok(report) => {
+                self.report = Some(report);
+                self.last_error = None;
+                self.last_success = Some(now);
+            }
+            Err(e) => {
+                self.failures += 1;
+                self.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Single metric family with its samples.
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    samples: Vec<(String, f64)>,
+}
+
+impl Family {
+    fn new(name: &'static str, kind: &'static str, help: &'static str) -> Family {
+        Family {
+            name,
+            help,
+            kind,
+            samples: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, labels: &[(&str, &str)], value: f64) {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| format!("{name}=\"{}\"", escape_label(value)))
+            .collect::<Vec<String>>()
+            .join(",");
+        self.samples.push((labels, value));
+    }
+}
+
+/// Render the metrics of all targets in the Prometheus text exposition format.
+pub fn render(targets: &[TargetMetrics]) -> String {
+    let mut up = Family::new(
+        "netbeat_up",
+        "gauge",
+        "Whether the latest test of the target succeeded",
+    );
+    let mut tests = Family::new(
+        "netbeat_tests_total",
+        "counter",
+        "Tests run against the target",
+    );
+    let mut failures = Family::new(
+        "netbeat_test_failures_total",
+        "counter",
+        "Tests against the target that failed",
+    );
+    let mut last_error = Family::new(
+        "netbeat_last_error_info",
+        "gauge",
+        "Error of the latest test, when it failed",
+    );
+    let mut last_test = Family::new(
+        "netbeat_last_test_timestamp_seconds",
+        "gauge",
+        "Time the latest test finished",
+    );
+    let mut last_success = Family::new(
+        "netbeat_last_success_timestamp_seconds",
+        "gauge",
+        "Time the latest successful test finished",
+    );
+    let mut test_duration = Family::new(
+        "netbeat_test_duration_seconds",
+        "gauge",
+        "Wall clock time of the latest test",
+    );
+    let mut throughput = Family::new(
+        "netbeat_throughput_bits_per_second",
+        "gauge",
+        "Throughput of the latest successful test",
+    );
+    let mut transferred = Family::new(
+        "netbeat_transferred_bytes",
+        "gauge",
+        "Bytes transferred in the latest successful test",
+    );
+    let mut transfer_duration = Family::new(
+        "netbeat_transfer_duration_seconds",
+        "gauge",
+        "Duration of the transfer in the latest successful test",
+    );
+    let mut ping = Family::new(
+        "netbeat_ping_latency_seconds",
+        "gauge",
+        "Idle round trip time statistics of the latest successful test",
+    );
+    let mut ping_jitter = Family::new(
+        "netbeat_ping_jitter_seconds",
+        "gauge",
+        "Idle round trip time jitter of the latest successful test",
+    );
+    let mut ping_loss = Family::new(
+        "netbeat_ping_loss_ratio",
+        "gauge",
+        "Share of pings lost in the latest successful test",
+    );
+    let mut loaded_latency = Family::new(
+        "netbeat_loaded_latency_seconds",
+        "gauge",
+        "Average round trip time while transferring in the latest successful test",
+    );
+    let mut datagram_loss = Family::new(
+        "netbeat_datagram_loss_ratio",
+        "gauge",
+        "Share of UDP datagrams lost in the latest successful test",
+    );
+    let mut datagram_jitter = Family::new(
+        "netbeat_datagram_jitter_seconds",
+        "gauge",
+        "UDP interarrival jitter in the latest successful test",
+    );
+
+    for metrics in targets {
+        let target = [("target", metrics.target.as_str())];
+        up.add(
+            &target,
+            (metrics.tests > 0 && metrics.last_error.is_none()) as u8 as f64,
+        );
+        tests.add(&target, metrics.tests as f64);
+        failures.add(&target, metrics.failures as f64);
+        if let Some(error) = &metrics.last_error {
+            last_error.add(&[target[0], ("error", error)], 1.0);
+        }
+        if let Some(time) = metrics.last_test {
+            last_test.add(&target, timestamp(time));
+            test_duration.add(&target, metrics.last_duration.as_secs_f64());
+        }
+        if let Some(time) = metrics.last_success {
+            last_success.add(&target, timestamp(time));
+        }
+
+        let Some(report) = &metrics.report else {
+            continue;
+        };
+        let ping_report = &report.ping_report;
+        for (statistic, value) in [
+            ("min", ping_report.min_ping),
+            ("avg", ping_report.avg_ping),
+            ("max", ping_report.max_ping),
+            ("median", ping_report.median_ping),
+            ("p90", ping_report.p90_ping),
+            ("p95", ping_report.p95_ping),
+            ("p99", ping_report.p99_ping),
+        ] {
+            ping.add(&[target[0], ("statistic", statistic)], value.as_secs_f64());
+        }
+        ping_jitter.add(&target, ping_report.jitter.as_secs_f64());
+        ping_loss.add(&target, ping_report.packet_loss / 100.0);
+
+        for speed_report in [&report.upload_report, &report.download_report] {
+            let labels = [target[0], ("direction", speed_report.report_type)];
+            throughput.add(&labels, speed_report.speed * 8.0);
+            transferred.add(&labels, speed_report.bytes as f64);
+            transfer_duration.add(&labels, speed_report.duration.as_secs_f64());
+            if let Some(stats) = speed_report.udp {
+                datagram_loss.add(&labels, stats.loss_percent() / 100.0);
+                datagram_jitter.add(&labels, stats.jitter.as_secs_f64());
+            }
+        }
+        if let Some(latency_report) = &report.latency_report {
+            for phase in &latency_report.loaded {
+                loaded_latency.add(
+                    &[target[0], ("direction", phase.phase)],
+                    phase.avg.as_secs_f64(),
+                );
+            }
+        }
+    }
+
+    let mut output = String::new();
+    for family in [
+        up,
+        tests,
+        failures,
+        last_error,
+        last_test,
+        last_success,
+        test_duration,
+        throughput,
+        transferred,
+        transfer_duration,
+        ping,
+        ping_jitter,
+        ping_loss,
+        loaded_latency,
+        datagram_loss,
+        datagram_jitter,
+    ] {
+        if family.samples.is_empty() {
+            continue;
+        }
+        let _ = writeln!(output, "# HELP {} {}", family.name, family.help);
+        let _ = writeln!(output, "# TYPE {} {}", family.name, family.kind);
+        for (labels, value) in family.samples {
+            let _ = writeln!(
+                output,
+                "{}{{{labels}}} {}",
+                family.name,
+                format_value(value)
+            );
+        }
+    }
+    output
+}
+
+/// Seconds since the unix epoch.
+fn timestamp(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Format a sample value, spelling infinities the way Prometheus expects.
+fn format_value(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Escape a label value (backslash, double quote and line feed).
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::reports::{LatencyReport, LoadedLatency, PingReport, SpeedReport};
+
+    fn create_report() -> NetbeatReport {
+        let ms = Duration::from_millis;
+        NetbeatReport::new(
+            PingReport::new(4, 3, vec![ms(10), ms(20), ms(30)]),
+            SpeedReport::new("upload", Duration::from_secs(2), 250_000_000).unwrap(),
+            SpeedReport::new("download", Duration::from_secs(1), 125_000_000).unwrap(),
+        )
+        .with_latency(LatencyReport::new(
+            ms(20),
+            vec![LoadedLatency::new("upload", vec![ms(50)])],
+        ))
+    }
+
+    #[test]
+    fn test_render() {
+        let mut healthy = TargetMetrics::new("nas.lan");
+        healthy.record::<String>(Ok(create_report()), Duration::from_secs(5));
+        let mut failing = TargetMetrics::new("10.1.1.12:6000");
+        failing.record(
+            Err("Connection error: \"refused\"\nretrying"),
+            Duration::from_secs(1),
+        );
+
+        let metrics = render(&[healthy, failing]);
+        assert!(metrics.contains("# TYPE netbeat_up gauge\n"));
+        assert!(metrics.contains("netbeat_up{target=\"nas.lan\"} 1\n"));
+        assert!(metrics.contains("netbeat_up{target=\"10.1.1.12:6000\"} 0\n"));
+        assert!(metrics.contains("netbeat_tests_total{target=\"nas.lan\"} 1\n"));
+        assert!(metrics.contains("netbeat_test_failures_total{target=\"10.1.1.12:6000\"} 1\n"));
+        assert!(metrics.contains(
+            "netbeat_last_error_info{target=\"10.1.1.12:6000\",error=\"Connection error: \\\"refused\\\"\\nretrying\"} 1\n"
+        ));
+        assert!(metrics.contains("netbeat_test_duration_seconds{target=\"nas.lan\"} 5\n"));
+        assert!(metrics.contains(
+            "netbeat_throughput_bits_per_second{target=\"nas.lan\",direction=\"upload\"} 1000000000\n"
+        ));
+        assert!(metrics.contains(
+            "netbeat_transferred_bytes{target=\"nas.lan\",direction=\"download\"} 125000000\n"
+        ));
+        assert!(
+            metrics.contains(
+                "netbeat_ping_latency_seconds{target=\"nas.lan\",statistic=\"avg\"} 0.02\n"
+            )
+        );
+        assert!(metrics.contains("netbeat_ping_loss_ratio{target=\"nas.lan\"} 0.25\n"));
+        assert!(metrics.contains(
+            "netbeat_loaded_latency_seconds{target=\"nas.lan\",direction=\"upload\"} 0.05\n"
+        ));
+        // Families without samples are left out
+        assert!(!metrics.contains("netbeat_datagram_loss_ratio"));
+        assert_eq!(metrics.matches("# HELP netbeat_up ").count(), 1);
+    }
+
+    #[test]
+    fn test_record_keeps_last_report() {
+        let mut metrics = TargetMetrics::new("nas.lan");
+        assert!(
+            render(std::slice::from_ref(&metrics)).contains("netbeat_up{target=\"nas.lan\"} 0")
+        );
+
+        metrics.record::<String>(Ok(create_report()), Duration::from_secs(5));
+        metrics.record(Err("timed out"), Duration::from_secs(30));
+        assert_eq!((metrics.tests, metrics.failures), (2, 1));
+        assert!(metrics.report.is_some());
+        assert_eq!(metrics.last_error.as_deref(), Some("timed out"));
+        assert!(metrics.last_success.unwrap() <= metrics.last_test.unwrap());
+    }
+
+    #[test]
+    fn test_format_value() {
+        assert_eq!(format_value(1.5), "1.5");
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NAN), "NaN");
+    }
+}
@@ -1,13 +1,28 @@
 //! Key test reports for netbeat.
 //!
-//! This module contains the primary reports for netbeat, including the PingReport, SpeedReport, and NetbeatReport.
+//! This module contains the primary reports for netbeat, including the PingReport, SpeedReport, LatencyReport and NetbeatReport.
 //! These reports are used to provide detailed information about the network performance of the system after running
-//! a speed test against a target server.
+//! a speed test against a target server. Reports serialize to json through the versioned schema in [`super::schema`].
 
+use crate::{
+    core::{
+        intervals::{self, IntervalSample},
+        udp::UdpStats,
+    },
+    output::schema::{
+        self, LatencyReportSchema, NetbeatReportSchema, PingReportSchema, SpeedReportSchema,
+    },
+    utils::units,
+};
 use anyhow::Result;
 use byte_unit::{Byte, UnitType};
+use serde::{Deserialize, Deserializer, Serialize, de::Error as _};
 use spinners::{Spinner, Spinners};
-use std::{fmt::Display, time::Duration};
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
 use tabled::{
     Table, Tabled,
     settings::{Alignment, Modify, Panel, Remove, Style, object::Rows},
@@ -64,23 +79,83 @@ pub trait Report {
     }
 
     /// Convert report to json output
-    fn to_json(&self) -> impl Display {
-        let mut data = json::object![];
-
-        for metric in self.get_metrics() {
-            let var_name = metric.var_name.to_string();
-            let value = metric.value.to_string();
-            data[var_name] = value.into();
-        }
-        data.dump()
+    fn to_json(&self) -> impl Display
+    where
+        Self: Serialize,
+    {
+        serde_json::to_string(self).expect("Reports always serialize to json")
     }
 }
 
+/// Parameters a speed test was run with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestParameters {
+    /// Time limit per test direction
+    #[serde(rename = "time_limit_ns", with = "schema::nanos")]
+    pub time_limit: Duration,
+    /// Target size of data per test direction in bytes, instead of time
+    #[serde(rename = "data_bytes")]
+    pub data: Option<u64>,
+    /// Buffer size for read/write operations in bytes
+    #[serde(rename = "chunk_size_bytes")]
+    pub chunk_size: u64,
+    /// Number of pings for the ping test
+    pub ping_count: u32,
+    /// Number of parallel TCP streams per test direction
+    pub parallel: u32,
+    /// Whether upload and download ran at the same time
+    pub bidirectional: bool,
+    /// Whether upload and download ran over UDP
+    pub udp: bool,
+    /// Target bitrate for UDP tests in bits per second
+    #[serde(rename = "bitrate_bps")]
+    pub bitrate: u64,
+    /// UDP datagram size in bytes
+    #[serde(rename = "datagram_size_bytes")]
+    pub datagram_size: u32,
+    /// Whether latency was probed during upload and download tests
+    pub latency_probe: bool,
+    /// Throughput reporting interval
+    #[serde(rename = "interval_ns", with = "schema::nanos")]
+    pub interval: Duration,
+}
+
+/// TLS session a speed test ran over.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsReport {
+    /// Negotiated protocol version (eg, TLSv1.3)
+    pub version: String,
+    /// Negotiated cipher suite
+    pub cipher_suite: String,
+    /// Duration of the TLS handshake on the session connection
+    #[serde(rename = "handshake_time_ns", with = "schema::nanos")]
+    pub handshake_time: Duration,
+    /// SHA-256 fingerprint of the server certificate
+    #[serde(rename = "certificate_sha256")]
+    pub fingerprint: String,
+}
+
 /// Primary report for Netbeat, including ping, upload, and download metrics.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(into = "NetbeatReportSchema", try_from = "NetbeatReportSchema")]
 pub struct NetbeatReport {
+    /// Time the tests completed
+    pub timestamp: SystemTime,
+    /// Parameters the tests ran with
+    pub parameters: Option<TestParameters>,
+    /// Target server, as given by the user
+    pub target: Option<String>,
+    /// Server address the client connected to
+    pub server_addr: Option<SocketAddr>,
+    /// Whether upload and download were measured at the same time
+    pub bidirectional: bool,
     pub ping_report: PingReport,
     pub upload_report: SpeedReport,
     pub download_report: SpeedReport,
+    /// Latency measured while the upload and download tests ran
+    pub latency_report: Option<LatencyReport>,
+    /// TLS session the tests ran over, when encrypted
+    pub tls_report: Option<TlsReport>,
     pub metrics: Vec<Metric<String>>,
 }
 
@@ -101,14 +176,109 @@ impl NetbeatReport {
         }
 
         NetbeatReport {
+            timestamp: SystemTime::now(),
+            parameters: None,
+            target: None,
+            server_addr: None,
+            bidirectional: false,
             ping_report,
             upload_report,
             download_report,
+            latency_report: None,
+            tls_report: None,
             metrics,
         }
     }
 }
 
+impl NetbeatReport {
+    /// Record the target and the resolved server address that was used for the tests.
+    pub fn with_server(mut self, target: &str, server_addr: SocketAddr) -> NetbeatReport {
+        self.metrics.splice(
+            0..0,
+            [
+                Metric {
+                    emoji: "🎯",
+                    var_name: "target".to_string(),
+                    pretty_name: "Target".to_string(),
+                    value: target.to_string(),
+                },
+                Metric {
+                    emoji: "🔗",
+                    var_name: "server_address".to_string(),
+                    pretty_name: "Server address".to_string(),
+                    value: server_addr.to_string(),
+                },
+            ],
+        );
+        self.target = Some(target.to_string());
+        self.server_addr = Some(server_addr);
+        self
+    }
+
+    /// Mark upload and download as measured over the same window.
+    pub fn with_bidirectional(mut self) -> NetbeatReport {
+        let index = self
+            .metrics
+            .iter()
+            .position(|metric| metric.var_name == self.upload_report.metrics[0].var_name)
+            .unwrap_or(self.metrics.len());
+        self.metrics.insert(
+            index,
+            Metric {
+                emoji: "🔁",
+                var_name: "test_mode".to_string(),
+                pretty_name: "Test mode".to_string(),
+                value: "bidirectional".to_string(),
+            },
+        );
+        self.bidirectional = true;
+        self
+    }
+
+    /// Record the parameters the tests ran with.
+    pub fn with_parameters(mut self, parameters: TestParameters) -> NetbeatReport {
+        self.parameters = Some(parameters);
+        self
+    }
+
+    /// Record the TLS session the tests ran over, after the server metrics.
+    pub fn with_tls(mut self, tls_report: TlsReport) -> NetbeatReport {
+        let index = self
+            .metrics
+            .iter()
+            .position(|metric| metric.var_name == "server_address")
+            .map_or(0, |index| index + 1);
+        self.metrics.splice(
+            index..index,
+            [
+                Metric {
+                    emoji: "🔐",
+                    var_name: "tls".to_string(),
+                    pretty_name: "TLS".to_string(),
+                    value: format!("{} ({})", tls_report.version, tls_report.cipher_suite),
+                },
+                Metric {
+                    emoji: "🤝",
+                    var_name: "tls_handshake".to_string(),
+                    pretty_name: "TLS handshake".to_string(),
+                    value: format!("{:.2?}", tls_report.handshake_time),
+                },
+            ],
+        );
+        self.tls_report = Some(tls_report);
+        self
+    }
+
+    /// Attach latency measured under load, after the speed metrics.
+    pub fn with_latency(mut self, latency_report: LatencyReport) -> NetbeatReport {
+        self.metrics
+            .extend(latency_report.get_metrics().iter().cloned());
+        self.latency_report = Some(latency_report);
+        self
+    }
+}
+
 impl Report for NetbeatReport {
     fn get_metrics(&self) -> &[Metric<String>] {
         &self.metrics
@@ -120,7 +290,8 @@ impl Report for NetbeatReport {
 }
 
 /// Report for ping speed test.
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(into = "PingReportSchema", try_from = "PingReportSchema")]
 pub struct PingReport {
     /// Number of targeted pings
     pub ping_count: u32,
@@ -134,6 +305,18 @@ pub struct PingReport {
     pub max_ping: Duration,
     /// Average ping time
     pub avg_ping: Duration,
+    /// Median ping time
+    pub median_ping: Duration,
+    /// 90th percentile ping time
+    pub p90_ping: Duration,
+    /// 95th percentile ping time
+    pub p95_ping: Duration,
+    /// 99th percentile ping time
+    pub p99_ping: Duration,
+    /// Population standard deviation of ping times
+    pub stddev_ping: Duration,
+    /// Mean difference between consecutive ping times
+    pub jitter: Duration,
     /// Packet loss percentage
     pub packet_loss: f64,
     /// Key Metric objects
@@ -150,7 +333,38 @@ impl PingReport {
         } else {
             ping_times.iter().sum::<Duration>() / ping_times.len() as u32
         };
-        let packet_loss = (ping_count - successful_pings) as f64 / ping_count as f64 * 100.0;
+        let packet_loss = if ping_count == 0 {
+            0.0
+        } else {
+            (ping_count - successful_pings) as f64 / ping_count as f64 * 100.0
+        };
+
+        let mut sorted = ping_times.clone();
+        sorted.sort();
+        let median_ping = percentile(&sorted, 50.0);
+        let p90_ping = percentile(&sorted, 90.0);
+        let p95_ping = percentile(&sorted, 95.0);
+        let p99_ping = percentile(&sorted, 99.0);
+        let stddev_ping = if ping_times.is_empty() {
+            Duration::ZERO
+        } else {
+            let mean = avg_ping.as_secs_f64();
+            let variance = ping_times
+                .iter()
+                .map(|time| (time.as_secs_f64() - mean).powi(2))
+                .sum::<f64>()
+                / ping_times.len() as f64;
+            Duration::from_secs_f64(variance.sqrt())
+        };
+        let jitter = if ping_times.len() < 2 {
+            Duration::ZERO
+        } else {
+            ping_times
+                .windows(2)
+                .map(|pair| pair[0].abs_diff(pair[1]))
+                .sum::<Duration>()
+                / (ping_times.len() - 1) as u32
+        };
 
         let metrics = vec![
             Metric {
@@ -189,6 +403,42 @@ impl PingReport {
                 pretty_name: "Average ping".to_string(),
                 value: format!("{avg_ping:.2?}"),
             },
+            Metric {
+                emoji: "◽",
+                var_name: "median_ping".to_string(),
+                pretty_name: "Median ping".to_string(),
+                value: format!("{median_ping:.2?}"),
+            },
+            Metric {
+                emoji: "🔸",
+                var_name: "p90_ping".to_string(),
+                pretty_name: "90th percentile ping".to_string(),
+                value: format!("{p90_ping:.2?}"),
+            },
+            Metric {
+                emoji: "🔸",
+                var_name: "p95_ping".to_string(),
+                pretty_name: "95th percentile ping".to_string(),
+                value: format!("{p95_ping:.2?}"),
+            },
+            Metric {
+                emoji: "🔸",
+                var_name: "p99_ping".to_string(),
+                pretty_name: "99th percentile ping".to_string(),
+                value: format!("{p99_ping:.2?}"),
+            },
+            Metric {
+                emoji: "📐",
+                var_name: "stddev_ping".to_string(),
+                pretty_name: "Ping standard deviation".to_string(),
+                value: format!("{stddev_ping:.2?}"),
+            },
+            Metric {
+                emoji: "〰️",
+                var_name: "jitter".to_string(),
+                pretty_name: "Jitter".to_string(),
+                value: format!("{jitter:.2?}"),
+            },
         ];
 
         PingReport {
@@ -198,10 +448,100 @@ impl PingReport {
             min_ping,
             max_ping,
             avg_ping,
+            median_ping,
+            p90_ping,
+            p95_ping,
+            p99_ping,
+            stddev_ping,
+            jitter,
             packet_loss,
             metrics,
         }
     }
+
+    /// Bucket ping times into equal width latency ranges between the minimum and maximum ping.
+    pub fn histogram(&self, buckets: usize) -> Vec<HistogramBucket> {
+        if self.ping_times.is_empty() || buckets == 0 {
+            return Vec::new();
+        }
+        let width = (self.max_ping - self.min_ping) / buckets as u32;
+        let mut histogram: Vec<HistogramBucket> = (0..buckets)
+            .map(|i| HistogramBucket {
+                lower: self.min_ping + width * i as u32,
+                upper: if i + 1 == buckets {
+                    self.max_ping
+                } else {
+                    self.min_ping + width * (i + 1) as u32
+                },
+                count: 0,
+            })
+            .collect();
+        for time in &self.ping_times {
+            let index = if width.is_zero() {
+                0
+            } else {
+                (((*time - self.min_ping).as_nanos() / width.as_nanos()) as usize).min(buckets - 1)
+            };
+            histogram[index].count += 1;
+        }
+        histogram
+    }
+
+    /// Convert the latency histogram to a terminal bar chart.
+    pub fn to_histogram_chart(&self, buckets: usize) -> impl Display {
+        let histogram = self.histogram(buckets);
+        let most = histogram
+            .iter()
+            .map(|bucket| bucket.count)
+            .max()
+            .unwrap_or(0);
+        let labels: Vec<String> = histogram
+            .iter()
+            .map(|bucket| format!("{:.2?} - {:.2?}", bucket.lower, bucket.upper))
+            .collect();
+        let label_width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+
+        let mut chart = String::from("\n📊 Ping Histogram\n");
+        for (bucket, label) in histogram.iter().zip(labels) {
+            let bar = "█".repeat((bucket.count * HISTOGRAM_BAR_WIDTH).div_ceil(most.max(1)));
+            chart.push_str(&format!(
+                " {label:>label_width$} │ {bar:<HISTOGRAM_BAR_WIDTH$} {}\n",
+                bucket.count
+            ));
+        }
+        chart
+    }
+}
+
+/// Width in characters of the longest histogram bar
+const HISTOGRAM_BAR_WIDTH: usize = 40;
+
+/// Default number of buckets for the ping histogram
+pub const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Range of ping times and the number of pings that fell within it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistogramBucket {
+    /// Lower bound of the range
+    pub lower: Duration,
+    /// Upper bound of the range, inclusive for the last bucket
+    pub upper: Duration,
+    /// Number of pings within the range
+    pub count: usize,
+}
+
+/// Percentile of sorted durations, interpolating linearly between the closest ranks.
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    match sorted {
+        [] => Duration::ZERO,
+        [only] => *only,
+        _ => {
+            let rank = percentile / 100.0 * (sorted.len() - 1) as f64;
+            let lower = sorted[rank.floor() as usize];
+            let upper = sorted[rank.ceil() as usize];
+            lower + (upper - lower).mul_f64(rank.fract())
+        }
+    }
 }
 
 impl Report for PingReport {
@@ -214,8 +554,211 @@ impl Report for PingReport {
     }
 }
 
+/// Latency probes taken while a single test phase was loading the link.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedLatency {
+    /// Test phase the probes ran during (upload/download/bidirectional)
+    pub phase: &'static str,
+    /// Round trip time of each successful probe
+    pub samples: Vec<Duration>,
+    /// Average round trip time
+    pub avg: Duration,
+    /// Maximum round trip time
+    pub max: Duration,
+}
+
+impl LoadedLatency {
+    /// Create a new LoadedLatency instance from the probe round trip times of a phase.
+    pub fn new(phase: &'static str, samples: Vec<Duration>) -> LoadedLatency {
+        let avg = if samples.is_empty() {
+            Duration::ZERO
+        } else {
+            samples.iter().sum::<Duration>() / samples.len() as u32
+        };
+        let max = samples.iter().max().copied().unwrap_or_default();
+        LoadedLatency {
+            phase,
+            samples,
+            avg,
+            max,
+        }
+    }
+}
+
+/// Grade the latency increase under load, on the scale commonly used for bufferbloat tests.
+pub fn bufferbloat_grade(increase: Duration) -> &'static str {
+    match increase.as_millis() {
+        0..5 => "A+",
+        5..30 => "A",
+        30..60 => "B",
+        60..200 => "C",
+        200..400 => "D",
+        _ => "F",
+    }
+}
+
+/// Report for latency under load, comparing idle latency with latency during each test phase.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(into = "LatencyReportSchema", try_from = "LatencyReportSchema")]
+pub struct LatencyReport {
+    /// Average latency on the idle link
+    pub idle: Duration,
+    /// Latency during each test phase with probe results
+    pub loaded: Vec<LoadedLatency>,
+    /// Largest increase of average latency over idle, across test phases
+    pub increase: Option<Duration>,
+    /// Bufferbloat grade for the largest increase
+    pub grade: Option<&'static str>,
+    /// Key Metric objects
+    pub metrics: Vec<Metric<String>>,
+}
+
+impl LatencyReport {
+    /// Create a new LatencyReport instance, ignoring phases without successful probes.
+    pub fn new(idle: Duration, loaded: Vec<LoadedLatency>) -> LatencyReport {
+        let loaded: Vec<LoadedLatency> = loaded
+            .into_iter()
+            .filter(|phase| !phase.samples.is_empty())
+            .collect();
+        let increase = loaded
+            .iter()
+            .map(|phase| phase.avg.saturating_sub(idle))
+            .max();
+        let grade = increase.map(bufferbloat_grade);
+
+        let mut metrics = vec![Metric {
+            emoji: "💤",
+            var_name: "idle_latency".to_string(),
+            pretty_name: "Idle latency".to_string(),
+            value: format!("{idle:.2?}"),
+        }];
+        for phase in &loaded {
+            let (emoji, name) = match phase.phase {
+                "upload" => ("⬆️", "Upload"),
+                "download" => ("⬇️", "Download"),
+                _ => ("🔁", "Bidirectional"),
+            };
+            metrics.push(Metric {
+                emoji,
+                var_name: format!("{}_latency", phase.phase),
+                pretty_name: format!("{name} latency"),
+                value: format!(
+                    "{:.2?} (+{:.2?})",
+                    phase.avg,
+                    phase.avg.saturating_sub(idle)
+                ),
+            });
+            metrics.push(Metric {
+                emoji,
+                var_name: format!("{}_latency_max", phase.phase),
+                pretty_name: format!("{name} latency (max)"),
+                value: format!("{:.2?}", phase.max),
+            });
+        }
+        metrics.push(Metric {
+            emoji: "🧮",
+            var_name: "bufferbloat_grade".to_string(),
+            pretty_name: "Bufferbloat grade".to_string(),
+            value: grade.unwrap_or("N/A").to_string(),
+        });
+
+        LatencyReport {
+            idle,
+            loaded,
+            increase,
+            grade,
+            metrics,
+        }
+    }
+}
+
+impl Report for LatencyReport {
+    fn get_metrics(&self) -> &[Metric<String>] {
+        &self.metrics
+    }
+
+    fn get_report_title(&self) -> &str {
+        "⏱️ Latency Under Load Report"
+    }
+}
+
+/// Result of a single stream in an upload/download speed test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamResult {
+    /// Elapsed duration of the stream
+    pub duration: Duration,
+    /// Bytes transferred on the stream
+    pub bytes: u64,
+    /// Speed in bytes per second
+    pub speed: f64,
+    /// Bytes received per reporting interval, as recorded by the receiving side
+    pub intervals: Vec<IntervalSample>,
+}
+
+impl StreamResult {
+    /// Create a new StreamResult instance.
+    pub fn new(duration: Duration, bytes: u64) -> StreamResult {
+        StreamResult {
+            duration,
+            bytes,
+            speed: bytes as f64 / duration.as_secs_f64(),
+            intervals: Vec::new(),
+        }
+    }
+
+    /// Attach the per-interval samples recorded for the stream.
+    pub fn with_intervals(mut self, intervals: Vec<IntervalSample>) -> StreamResult {
+        self.intervals = intervals;
+        self
+    }
+}
+
+/// Spread of the per-interval speed over a test, in bytes per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntervalStats {
+    /// Slowest interval
+    pub min: f64,
+    /// Fastest interval
+    pub max: f64,
+    /// Population standard deviation across intervals
+    pub stddev: f64,
+}
+
+impl IntervalStats {
+    /// Compute the spread of the given samples, `None` when there are none.
+    pub fn new(samples: &[IntervalSample]) -> Option<IntervalStats> {
+        if samples.is_empty() {
+            return None;
+        }
+        let speeds: Vec<f64> = samples.iter().map(IntervalSample::speed).collect();
+        let mean = speeds.iter().sum::<f64>() / speeds.len() as f64;
+        let variance = speeds
+            .iter()
+            .map(|speed| (speed - mean).powi(2))
+            .sum::<f64>()
+            / speeds.len() as f64;
+        Some(IntervalStats {
+            min: speeds.iter().copied().fold(f64::INFINITY, f64::min),
+            max: speeds.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            stddev: variance.sqrt(),
+        })
+    }
+}
+
+/// Row of the per-interval table.
+#[derive(Tabled)]
+struct IntervalRow {
+    #[tabled(rename = "Interval")]
+    interval: String,
+    #[tabled(rename = "Transfer")]
+    transfer: String,
+    #[tabled(rename = "Bitrate")]
+    bitrate: String,
+}
+
 /// Report for upload/download speed test.
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
+#[serde(into = "SpeedReportSchema")]
 pub struct SpeedReport {
     /// Report type (upload/download)
     pub report_type: &'static str,
@@ -225,6 +768,18 @@ pub struct SpeedReport {
     pub bytes: u64,
     /// Speed in bytes per second
     pub speed: f64,
+    /// Transfer as measured by the client, when the report is based on server-side measurement
+    pub client: Option<StreamResult>,
+    /// Results of the individual streams making up the test
+    pub streams: Vec<StreamResult>,
+    /// Datagram statistics, when the test ran over UDP
+    pub udp: Option<UdpStats>,
+    /// Target bitrate in bits per second, when the test ran over UDP
+    pub target_bitrate: Option<u64>,
+    /// Bytes received per reporting interval, summed across streams
+    pub intervals: Vec<IntervalSample>,
+    /// Spread of the per-interval speed
+    pub interval_stats: Option<IntervalStats>,
     /// Key Metric Objects
     pub metrics: Vec<Metric<String>>,
 }
@@ -278,9 +833,195 @@ impl SpeedReport {
             duration,
             bytes,
             speed: bytes as f64 / duration.as_secs_f64(),
+            client: None,
+            streams: vec![StreamResult::new(duration, bytes)],
+            udp: None,
+            target_bitrate: None,
+            intervals: Vec::new(),
+            interval_stats: None,
             metrics,
         })
     }
+
+    /// Create a SpeedReport aggregating parallel streams.
+    ///
+    /// The aggregate covers all bytes over the longest stream duration, with per-stream speeds listed after it.
+    pub fn from_streams(
+        report_type: &'static str,
+        streams: Vec<StreamResult>,
+    ) -> Result<SpeedReport> {
+        anyhow::ensure!(!streams.is_empty(), "Expected at least one stream");
+
+        let bytes = streams.iter().map(|stream| stream.bytes).sum();
+        let duration = streams
+            .iter()
+            .map(|stream| stream.duration)
+            .max()
+            .unwrap_or_default();
+        let mut report = SpeedReport::new(report_type, duration, bytes)?;
+        let samples: Vec<Vec<IntervalSample>> = streams
+            .iter()
+            .map(|stream| stream.intervals.clone())
+            .collect();
+        let samples = intervals::merge(&samples);
+        if !samples.is_empty() {
+            report = report.with_intervals(samples);
+        }
+        if streams.len() > 1 {
+            let speed_metric = match report_type {
+                "upload" => "Upload speed",
+                _ => "Download speed",
+            };
+            report.metrics.push(Metric {
+                emoji: "🔀",
+                var_name: "parallel_streams".to_string(),
+                pretty_name: "Parallel streams".to_string(),
+                value: streams.len().to_string(),
+            });
+            for (i, stream) in streams.iter().enumerate() {
+                let speed_megabit = stream.speed / 1e6 * 8.0;
+                report.metrics.push(Metric {
+                    emoji: "🔹",
+                    var_name: format!(
+                        "{}_stream_{}_Mbps",
+                        speed_metric.to_lowercase().replace(" ", "_"),
+                        i + 1
+                    ),
+                    pretty_name: format!("{speed_metric} stream {} (Mbps)", i + 1),
+                    value: format!("{speed_megabit:.2} Mbps"),
+                });
+            }
+        }
+        report.streams = streams;
+        Ok(report)
+    }
+
+    /// Attach the client-side measurement to a report built from server-side measurement.
+    ///
+    /// The client only sees how fast data is handed to the kernel, so its figure is kept for reference alongside the primary speed.
+    pub fn with_client_measurement(mut self, duration: Duration, bytes: u64) -> SpeedReport {
+        let client = StreamResult::new(duration, bytes);
+        let speed_megabit = client.speed / 1e6 * 8.0;
+        let (speed_emoji, speed_metric) = match self.report_type {
+            "upload" => ("⏫", "Upload speed"),
+            "download" => ("⏬", "Download speed"),
+            _ => unreachable!(),
+        };
+        // Keep alongside the aggregate speeds, ahead of any per-stream metrics
+        self.metrics.insert(
+            4,
+            Metric {
+                emoji: speed_emoji,
+                var_name: speed_metric.to_lowercase().replace(" ", "_") + "_client_Mbps",
+                pretty_name: format!("{} (client, Mbps)", speed_metric),
+                value: format!("{speed_megabit:.2} Mbps"),
+            },
+        );
+        self.client = Some(client);
+        self
+    }
+
+    /// Attach per-interval samples, adding the min, max and standard deviation of the interval speed.
+    pub fn with_intervals(mut self, samples: Vec<IntervalSample>) -> SpeedReport {
+        let speed_metric = match self.report_type {
+            "upload" => "Upload speed",
+            "download" => "Download speed",
+            _ => unreachable!(),
+        };
+        let var_prefix = speed_metric.to_lowercase().replace(" ", "_");
+        if let Some(stats) = IntervalStats::new(&samples) {
+            for (emoji, name, value) in [
+                ("🔻", "min", stats.min),
+                ("🔺", "max", stats.max),
+                ("📐", "stddev", stats.stddev),
+            ] {
+                self.metrics.push(Metric {
+                    emoji,
+                    var_name: format!("{var_prefix}_interval_{name}_Mbps"),
+                    pretty_name: format!("{speed_metric} interval {name} (Mbps)"),
+                    value: format!("{:.2} Mbps", value / 1e6 * 8.0),
+                });
+            }
+            self.interval_stats = Some(stats);
+        }
+        self.intervals = samples;
+        self
+    }
+
+    /// Convert the per-interval samples to an iperf-style table.
+    pub fn to_interval_table(&self) -> impl Display {
+        let rows = self.intervals.iter().map(|sample| IntervalRow {
+            interval: format!(
+                "{:.2}-{:.2} sec",
+                sample.start.as_secs_f64(),
+                sample.end.as_secs_f64()
+            ),
+            transfer: format!(
+                "{:.2}",
+                Byte::from_u64(sample.bytes).get_appropriate_unit(UnitType::Decimal)
+            ),
+            bitrate: format!("{:.2} Mbps", sample.speed() / 1e6 * 8.0),
+        });
+        let title = match self.report_type {
+            "download" => "⬇️ Download Intervals",
+            _ => "⬆️ Upload Intervals",
+        };
+        let mut table = Table::new(rows);
+        table.with((
+            Panel::header(title),
+            Style::re_structured_text().remove_top(),
+            Modify::new(Rows::first()).with(Alignment::center()),
+        ));
+        format!("\n{table}\n")
+    }
+
+    /// Attach the datagram statistics of a UDP test, as measured by the receiving side.
+    pub fn with_udp(mut self, stats: UdpStats, bitrate: u64) -> SpeedReport {
+        let direction = match self.report_type {
+            "upload" => "Upload",
+            "download" => "Download",
+            _ => unreachable!(),
+        };
+        let metric = |emoji: &'static str, name: &str, value: String| Metric {
+            emoji,
+            var_name: format!(
+                "{}_{}",
+                self.report_type,
+                name.to_lowercase().replace(" ", "_")
+            ),
+            pretty_name: format!("{direction} {name}"),
+            value,
+        };
+        let metrics = [
+            metric(
+                "🎯",
+                "target bitrate",
+                units::format_bitrate(bitrate as f64),
+            ),
+            metric("📤", "datagrams sent", stats.sent.to_string()),
+            metric("📥", "datagrams received", stats.received.to_string()),
+            metric(
+                "📉",
+                "datagram loss",
+                format!("{} ({:.2}%)", stats.lost, stats.loss_percent()),
+            ),
+            metric("🔀", "out of order", stats.out_of_order.to_string()),
+            metric("〰️", "jitter", format!("{:.2?}", stats.jitter)),
+        ];
+        self.metrics.extend(metrics);
+        self.udp = Some(stats);
+        self.target_bitrate = Some(bitrate);
+        self
+    }
+}
+
+// Derived `Deserialize` treats the `&'static str` report type as borrowed from the input, requiring `'de: 'static`
+impl<'de> Deserialize<'de> for SpeedReport {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        SpeedReportSchema::deserialize(deserializer)?
+            .try_into()
+            .map_err(D::Error::custom)
+    }
 }
 
 impl Report for SpeedReport {
@@ -289,9 +1030,11 @@ impl Report for SpeedReport {
     }
 
     fn get_report_title(&self) -> &str {
-        match self.report_type {
-            "download" => "⬇️ Download Report",
-            "upload" => "⬆️ Upload Report",
+        match (self.report_type, self.udp.is_some()) {
+            ("download", true) => "⬇️ UDP Download Report",
+            ("upload", true) => "⬆️ UDP Upload Report",
+            ("download", false) => "⬇️ Download Report",
+            ("upload", false) => "⬆️ Upload Report",
             _ => "📊 Speed Report",
         }
     }
@@ -373,11 +1116,200 @@ mod tests {
         assert_eq!(report_title, "⬇️ Download Report");
     }
 
+    #[test]
+    fn test_speed_report_with_client_measurement() {
+        let report = create_speed_report("upload")
+            .with_client_measurement(Duration::from_millis(500), 1e6 as u64);
+        assert_eq!(report.speed, 1e6);
+        assert_eq!(report.client.as_ref().map(|client| client.speed), Some(2e6));
+
+        let metrics = report.get_metrics();
+        assert_eq!(metrics.len(), 5);
+        assert_eq!(metrics[2].value, "8.00 Mbps");
+        assert_eq!(metrics[4].emoji, "⏫");
+        assert_eq!(metrics[4].var_name, "upload_speed_client_Mbps");
+        assert_eq!(metrics[4].pretty_name, "Upload speed (client, Mbps)");
+        assert_eq!(metrics[4].value, "16.00 Mbps");
+
+        let report = create_speed_report("download");
+        assert!(report.client.is_none());
+    }
+
+    #[test]
+    fn test_speed_report_with_udp() {
+        let stats = UdpStats {
+            sent: 100,
+            received: 98,
+            lost: 2,
+            out_of_order: 1,
+            bytes: 98_000,
+            duration: Duration::from_secs(1),
+            jitter: Duration::from_micros(150),
+        };
+        let report = SpeedReport::new("download", stats.duration, stats.bytes)
+            .unwrap()
+            .with_udp(stats, 1_000_000);
+        assert_eq!(report.udp, Some(stats));
+        assert_eq!(report.target_bitrate, Some(1_000_000));
+        assert_eq!(report.get_report_title(), "⬇️ UDP Download Report");
+
+        let metrics = report.get_metrics();
+        assert_eq!(metrics.len(), 10);
+        assert_eq!(metrics[4].var_name, "download_target_bitrate");
+        assert_eq!(metrics[4].pretty_name, "Download target bitrate");
+        assert_eq!(metrics[4].value, "1.00 Mbps");
+        assert_eq!(metrics[7].var_name, "download_datagram_loss");
+        assert_eq!(metrics[7].value, "2 (2.00%)");
+        assert_eq!(metrics[9].var_name, "download_jitter");
+        assert_eq!(metrics[9].pretty_name, "Download jitter");
+        assert_eq!(metrics[9].value, "150.00µs");
+    }
+
+    #[test]
+    fn test_bufferbloat_grade() {
+        assert_eq!(bufferbloat_grade(Duration::from_millis(2)), "A+");
+        assert_eq!(bufferbloat_grade(Duration::from_millis(5)), "A");
+        assert_eq!(bufferbloat_grade(Duration::from_millis(45)), "B");
+        assert_eq!(bufferbloat_grade(Duration::from_millis(150)), "C");
+        assert_eq!(bufferbloat_grade(Duration::from_millis(399)), "D");
+        assert_eq!(bufferbloat_grade(Duration::from_secs(1)), "F");
+    }
+
+    #[test]
+    fn test_latency_report() {
+        let ms = Duration::from_millis;
+        let report = LatencyReport::new(
+            ms(10),
+            vec![
+                LoadedLatency::new("upload", vec![ms(40), ms(60)]),
+                LoadedLatency::new("download", vec![ms(12), ms(14)]),
+            ],
+        );
+        assert_eq!(report.loaded[0].avg, ms(50));
+        assert_eq!(report.loaded[0].max, ms(60));
+        assert_eq!(report.increase, Some(ms(40)));
+        assert_eq!(report.grade, Some("B"));
+        assert_eq!(report.get_report_title(), "⏱️ Latency Under Load Report");
+
+        let metrics = report.get_metrics();
+        assert_eq!(metrics.len(), 6);
+        assert_eq!(metrics[0].var_name, "idle_latency");
+        assert_eq!(metrics[0].value, "10.00ms");
+        assert_eq!(metrics[1].var_name, "upload_latency");
+        assert_eq!(metrics[1].pretty_name, "Upload latency");
+        assert_eq!(metrics[1].value, "50.00ms (+40.00ms)");
+        assert_eq!(metrics[4].var_name, "download_latency_max");
+        assert_eq!(metrics[5].var_name, "bufferbloat_grade");
+        assert_eq!(metrics[5].value, "B");
+
+        // Phases without successful probes are left out
+        let report = LatencyReport::new(ms(10), vec![LoadedLatency::new("upload", vec![])]);
+        assert!(report.loaded.is_empty());
+        assert_eq!(report.grade, None);
+        assert_eq!(report.get_metrics().len(), 2);
+        assert_eq!(report.get_metrics()[1].value, "N/A");
+    }
+
+    #[test]
+    fn test_speed_report_from_streams() {
+        let report = SpeedReport::from_streams(
+            "upload",
+            vec![
+                StreamResult::new(Duration::from_secs(1), 1e6 as u64),
+                StreamResult::new(Duration::from_secs(2), 3e6 as u64),
+            ],
+        )
+        .unwrap();
+        assert_eq!(report.bytes, 4e6 as u64);
+        assert_eq!(report.duration, Duration::from_secs(2));
+        assert_eq!(report.speed, 2e6);
+        assert_eq!(report.streams.len(), 2);
+        assert_eq!(report.streams[1].speed, 1.5e6);
+
+        let metrics = report.get_metrics();
+        assert_eq!(metrics.len(), 7);
+        assert_eq!(metrics[2].value, "16.00 Mbps");
+        assert_eq!(metrics[4].var_name, "parallel_streams");
+        assert_eq!(metrics[4].value, "2");
+        assert_eq!(metrics[5].var_name, "upload_speed_stream_1_Mbps");
+        assert_eq!(metrics[5].pretty_name, "Upload speed stream 1 (Mbps)");
+        assert_eq!(metrics[5].value, "8.00 Mbps");
+        assert_eq!(metrics[6].var_name, "upload_speed_stream_2_Mbps");
+        assert_eq!(metrics[6].value, "12.00 Mbps");
+
+        // Single stream matches a plain report
+        let report = SpeedReport::from_streams(
+            "download",
+            vec![StreamResult::new(Duration::from_secs(1), 1e6 as u64)],
+        )
+        .unwrap();
+        assert_eq!(report.get_metrics().len(), 4);
+        assert_eq!(report.streams.len(), 1);
+
+        assert!(SpeedReport::from_streams("download", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_speed_report_with_intervals() {
+        let sample = |start_ms: u64, end_ms: u64, bytes: u64| IntervalSample {
+            start: Duration::from_millis(start_ms),
+            end: Duration::from_millis(end_ms),
+            bytes,
+        };
+        let report = SpeedReport::from_streams(
+            "download",
+            vec![
+                StreamResult::new(Duration::from_secs(2), 3e6 as u64).with_intervals(vec![
+                    sample(0, 1000, 1e6 as u64),
+                    sample(1000, 2000, 2e6 as u64),
+                ]),
+                StreamResult::new(Duration::from_secs(2), 2e6 as u64).with_intervals(vec![
+                    sample(0, 1000, 1e6 as u64),
+                    sample(1000, 2000, 1e6 as u64),
+                ]),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            report.intervals,
+            vec![sample(0, 1000, 2e6 as u64), sample(1000, 2000, 3e6 as u64)]
+        );
+        let stats = report.interval_stats.unwrap();
+        assert_eq!((stats.min, stats.max, stats.stddev), (2e6, 3e6, 0.5e6));
+
+        let metrics = report.get_metrics();
+        assert_eq!(metrics[4].var_name, "download_speed_interval_min_Mbps");
+        assert_eq!(metrics[4].pretty_name, "Download speed interval min (Mbps)");
+        assert_eq!(metrics[4].value, "16.00 Mbps");
+        assert_eq!(metrics[5].value, "24.00 Mbps");
+        assert_eq!(metrics[6].var_name, "download_speed_interval_stddev_Mbps");
+        assert_eq!(metrics[6].value, "4.00 Mbps");
+        assert_eq!(metrics[7].var_name, "parallel_streams");
+
+        let table = report.to_interval_table().to_string();
+        assert!(table.contains("⬇️ Download Intervals"));
+        assert!(table.contains("1.00-2.00 sec"));
+        assert!(table.contains("3.00 MB"));
+        assert!(table.contains("24.00 Mbps"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().to_string()).unwrap();
+        assert_eq!(json["intervals"].as_array().unwrap().len(), 2);
+        assert_eq!(json["intervals"][1]["bytes"], 3_000_000);
+        assert_eq!(json["intervals"][1]["bits_per_second"], 24e6);
+        assert_eq!(json["interval_stats"]["max_bps"], 24e6);
+
+        // Reports without samples have no interval metrics
+        let report = create_speed_report("upload");
+        assert!(report.interval_stats.is_none());
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().to_string()).unwrap();
+        assert_eq!(json["intervals"], serde_json::json!([]));
+        assert!(json["interval_stats"].is_null());
+    }
+
     #[test]
     fn test_ping_report() {
         let report = create_ping_report(false);
         let metrics = report.get_metrics();
-        assert_eq!(metrics.len(), 6);
         assert_eq!(metrics[0].emoji, "📊");
         assert_eq!(metrics[0].var_name, "packets_sent");
         assert_eq!(metrics[0].pretty_name, "Packets sent");
@@ -402,13 +1334,27 @@ mod tests {
         assert_eq!(metrics[5].var_name, "avg_ping");
         assert_eq!(metrics[5].pretty_name, "Average ping");
         assert_eq!(metrics[5].value, "2.50s");
+        assert_eq!(metrics.len(), 12);
+        assert_eq!(metrics[6].var_name, "median_ping");
+        assert_eq!(metrics[6].pretty_name, "Median ping");
+        assert_eq!(metrics[6].value, "2.50s");
+        assert_eq!(metrics[7].var_name, "p90_ping");
+        assert_eq!(metrics[7].value, "3.70s");
+        assert_eq!(metrics[8].var_name, "p95_ping");
+        assert_eq!(metrics[9].var_name, "p99_ping");
+        assert_eq!(metrics[9].value, "3.97s");
+        assert_eq!(metrics[10].var_name, "stddev_ping");
+        assert_eq!(metrics[10].value, "1.12s");
+        assert_eq!(metrics[11].var_name, "jitter");
+        assert_eq!(metrics[11].pretty_name, "Jitter");
+        assert_eq!(metrics[11].value, "1.00s");
         let report_title = report.get_report_title();
         assert_eq!(report_title, "🏓 Ping Report");
 
         // No successful pings
         let report = create_ping_report(true);
         let metrics = report.get_metrics();
-        assert_eq!(metrics.len(), 6);
+        assert_eq!(metrics.len(), 12);
         assert_eq!(metrics[0].emoji, "📊");
         assert_eq!(metrics[0].var_name, "packets_sent");
         assert_eq!(metrics[0].pretty_name, "Packets sent");
@@ -433,6 +1379,45 @@ mod tests {
         assert_eq!(metrics[5].var_name, "avg_ping");
         assert_eq!(metrics[5].pretty_name, "Average ping");
         assert_eq!(metrics[5].value, "0.00ns");
+        assert!(metrics[6..].iter().all(|metric| metric.value == "0.00ns"));
+        assert!(report.histogram(HISTOGRAM_BUCKETS).is_empty());
+
+        // No pings sent
+        let report = PingReport::new(0, 0, vec![]);
+        assert_eq!(report.packet_loss, 0.0);
+        assert_eq!(report.get_metrics()[2].value, "0.0%");
+    }
+
+    #[test]
+    fn test_ping_histogram() {
+        let ms = Duration::from_millis;
+        let report = PingReport::new(5, 5, vec![ms(10), ms(11), ms(12), ms(19), ms(30)]);
+        let histogram = report.histogram(4);
+        assert_eq!(histogram.len(), 4);
+        assert_eq!(histogram[0].lower, ms(10));
+        assert_eq!(histogram[0].upper, ms(15));
+        assert_eq!(histogram[3].upper, ms(30));
+        let counts: Vec<usize> = histogram.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, vec![3, 1, 0, 1]);
+
+        let chart = report.to_histogram_chart(4).to_string();
+        assert!(chart.contains("📊 Ping Histogram"));
+        assert!(chart.contains(&format!("10.00ms - 15.00ms │ {} 3", "█".repeat(40))));
+        assert!(chart.contains(&format!("25.00ms - 30.00ms │ {:<40} 1", "█".repeat(14))));
+
+        // Identical ping times land in the first bucket
+        let report = PingReport::new(2, 2, vec![ms(5), ms(5)]);
+        assert_eq!(report.histogram(3)[0].count, 2);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_micros(5500));
+        assert_eq!(percentile(&sorted, 90.0), Duration::from_micros(9100));
+        assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(10));
+        assert_eq!(percentile(&sorted[..1], 99.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
     }
 
     #[test]
@@ -469,6 +1454,53 @@ mod tests {
         assert_eq!(report_title, "🦀 Netbeat Report");
     }
 
+    #[test]
+    fn test_netbeat_report_with_server() {
+        let netbeat_report = NetbeatReport::new(
+            create_ping_report(false),
+            create_speed_report("upload"),
+            create_speed_report("download"),
+        );
+        let metric_count = netbeat_report.get_metrics().len();
+        assert!(netbeat_report.target.is_none());
+        assert!(netbeat_report.server_addr.is_none());
+
+        let server_addr: SocketAddr = "[fe80::1]:5050".parse().unwrap();
+        let netbeat_report = netbeat_report.with_server("nas.lan", server_addr);
+        assert_eq!(netbeat_report.target.as_deref(), Some("nas.lan"));
+        assert_eq!(netbeat_report.server_addr, Some(server_addr));
+
+        let metrics = netbeat_report.get_metrics();
+        assert_eq!(metrics.len(), metric_count + 2);
+        assert_eq!(metrics[0].var_name, "target");
+        assert_eq!(metrics[0].value, "nas.lan");
+        assert_eq!(metrics[1].var_name, "server_address");
+        assert_eq!(metrics[1].pretty_name, "Server address");
+        assert_eq!(metrics[1].value, "[fe80::1]:5050");
+    }
+
+    #[test]
+    fn test_netbeat_report_with_bidirectional() {
+        let netbeat_report = NetbeatReport::new(
+            create_ping_report(false),
+            create_speed_report("upload"),
+            create_speed_report("download"),
+        );
+        assert!(!netbeat_report.bidirectional);
+        let metric_count = netbeat_report.get_metrics().len();
+
+        let netbeat_report = netbeat_report.with_bidirectional();
+        assert!(netbeat_report.bidirectional);
+
+        // Placed ahead of the speed metrics
+        let metrics = netbeat_report.get_metrics();
+        assert_eq!(metrics.len(), metric_count + 1);
+        assert_eq!(metrics[12].var_name, "test_mode");
+        assert_eq!(metrics[12].pretty_name, "Test mode");
+        assert_eq!(metrics[12].value, "bidirectional");
+        assert_eq!(metrics[13].var_name, "uploaded");
+    }
+
     #[test]
     fn test_report_to_json() {
         let upload_report = create_speed_report("upload");
@@ -476,7 +1508,7 @@ mod tests {
 
         assert_eq!(
             json.to_string(),
-            "{\"uploaded\":\"1.00 MB\",\"upload_time\":\"1.00s\",\"upload_speed_Mbps\":\"8.00 Mbps\",\"upload_speed_MBs\":\"1.00 MB/s\"}"
+            "{\"direction\":\"upload\",\"bytes\":1000000,\"duration_ns\":1000000000,\"bits_per_second\":8000000.0,\"client\":null,\"streams\":[{\"bytes\":1000000,\"duration_ns\":1000000000,\"bits_per_second\":8000000.0,\"intervals\":[]}],\"intervals\":[],\"interval_stats\":null,\"udp\":null}"
         );
     }
 
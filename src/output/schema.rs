@@ -0,0 +1,627 @@
+//! Versioned JSON schema for netbeat reports.
+//!
+//! Reports serialize through the types in this module, which hold raw numbers (bytes, nanoseconds, bits per second)
+//! instead of the preformatted values shown in tables. Derived figures such as speeds, percentiles and grades are
+//! written for convenience but recomputed when a report is deserialized, so reading a report back rebuilds it exactly
+//! as the client produced it.
+
+use crate::{
+    core::{intervals::IntervalSample, udp::UdpStats},
+    output::reports::{
+        IntervalStats, LatencyReport, LoadedLatency, NetbeatReport, PingReport, SpeedReport,
+        StreamResult, TestParameters, TlsReport,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+/// Current version of the report schema, bumped on incompatible changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Serialize durations as whole nanoseconds.
+pub(crate) mod nanos {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_nanos() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_nanos)
+    }
+}
+
+fn to_nanos(duration: Duration) -> u64 {
+    duration.as_nanos() as u64
+}
+
+/// Map a speed test direction onto the static names used by the reports.
+fn direction(name: &str) -> Result<&'static str, String> {
+    match name {
+        "upload" => Ok("upload"),
+        "download" => Ok("download"),
+        "bidirectional" => Ok("bidirectional"),
+        _ => Err(format!("Unknown test direction ({name})")),
+    }
+}
+
+/// Full netbeat report, as written by `--json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetbeatReportSchema {
+    /// Schema version the report was written with
+    pub schema_version: u32,
+    /// Time the tests completed (RFC 3339, UTC)
+    pub timestamp: String,
+    /// Target server, as given by the user
+    pub target: Option<String>,
+    /// Server address the client connected to
+    pub server_address: Option<SocketAddr>,
+    /// Parameters the tests ran with
+    pub parameters: Option<TestParameters>,
+    /// Whether upload and download were measured at the same time
+    pub bidirectional: bool,
+    pub ping: PingReportSchema,
+    pub upload: SpeedReportSchema,
+    pub download: SpeedReportSchema,
+    /// Latency measured while the upload and download tests ran
+    pub latency: Option<LatencyReportSchema>,
+    /// TLS session the tests ran over, when encrypted
+    pub tls: Option<TlsReport>,
+}
+
+impl From<NetbeatReport> for NetbeatReportSchema {
+    fn from(report: NetbeatReport) -> Self {
+        NetbeatReportSchema {
+            schema_version: SCHEMA_VERSION,
+            timestamp: humantime::format_rfc3339_millis(report.timestamp).to_string(),
+            target: report.target,
+            server_address: report.server_addr,
+            parameters: report.parameters,
+            bidirectional: report.bidirectional,
+            ping: report.ping_report.into(),
+            upload: report.upload_report.into(),
+            download: report.download_report.into(),
+            latency: report.latency_report.map(Into::into),
+            tls: report.tls_report,
+        }
+    }
+}
+
+impl TryFrom<NetbeatReportSchema> for NetbeatReport {
+    type Error = String;
+
+    fn try_from(schema: NetbeatReportSchema) -> Result<Self, Self::Error> {
+        if schema.schema_version != SCHEMA_VERSION {
+            return Err(format!(
+                "Unsupported report schema version ({}) - expected {SCHEMA_VERSION}",
+                schema.schema_version
+            ));
+        }
+        let timestamp: SystemTime = humantime::parse_rfc3339_weak(&schema.timestamp)
+            .map_err(|e| format!("Invalid timestamp ({}) - {e}", schema.timestamp))?;
+
+        let mut report = NetbeatReport::new(
+            schema.ping.try_into()?,
+            schema.upload.try_into()?,
+            schema.download.try_into()?,
+        );
+        if let (Some(target), Some(server_addr)) = (&schema.target, schema.server_address) {
+            report = report.with_server(target, server_addr);
+        }
+        if let Some(tls) = schema.tls {
+            report = report.with_tls(tls);
+        }
+        if schema.bidirectional {
+            report = report.with_bidirectional();
+        }
+        if let Some(latency) = schema.latency {
+            report = report.with_latency(latency.try_into()?);
+        }
+        if let Some(parameters) = schema.parameters {
+            report = report.with_parameters(parameters);
+        }
+        report.timestamp = timestamp;
+        Ok(report)
+    }
+}
+
+/// Ping test results, durations in nanoseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingReportSchema {
+    pub ping_count: u32,
+    pub successful_pings: u32,
+    pub ping_times_ns: Vec<u64>,
+    #[serde(default, skip_deserializing)]
+    pub packet_loss_percent: f64,
+    #[serde(default, skip_deserializing)]
+    pub min_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub max_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub avg_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub median_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub p90_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub p95_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub p99_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub stddev_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub jitter_ns: u64,
+}
+
+impl From<PingReport> for PingReportSchema {
+    fn from(report: PingReport) -> Self {
+        PingReportSchema {
+            ping_count: report.ping_count,
+            successful_pings: report.successful_pings,
+            ping_times_ns: report.ping_times.iter().copied().map(to_nanos).collect(),
+            packet_loss_percent: report.packet_loss,
+            min_ns: to_nanos(report.min_ping),
+            max_ns: to_nanos(report.max_ping),
+            avg_ns: to_nanos(report.avg_ping),
+            median_ns: to_nanos(report.median_ping),
+            p90_ns: to_nanos(report.p90_ping),
+            p95_ns: to_nanos(report.p95_ping),
+            p99_ns: to_nanos(report.p99_ping),
+            stddev_ns: to_nanos(report.stddev_ping),
+            jitter_ns: to_nanos(report.jitter),
+        }
+    }
+}
+
+impl TryFrom<PingReportSchema> for PingReport {
+    type Error = String;
+
+    fn try_from(schema: PingReportSchema) -> Result<Self, Self::Error> {
+        if schema.successful_pings > schema.ping_count {
+            return Err(format!(
+                "Invalid ping report - {} successful pings out of {}",
+                schema.successful_pings, schema.ping_count
+            ));
+        }
+        let ping_times = schema
+            .ping_times_ns
+            .into_iter()
+            .map(Duration::from_nanos)
+            .collect();
+        Ok(PingReport::new(
+            schema.ping_count,
+            schema.successful_pings,
+            ping_times,
+        ))
+    }
+}
+
+/// Bytes transferred over a period, as measured by one side of a stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferSchema {
+    pub bytes: u64,
+    pub duration_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub bits_per_second: f64,
+}
+
+/// Bytes received during one reporting interval, relative to the start of the transfer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntervalSchema {
+    pub start_ns: u64,
+    pub end_ns: u64,
+    pub bytes: u64,
+    #[serde(default, skip_deserializing)]
+    pub bits_per_second: f64,
+}
+
+impl From<IntervalSample> for IntervalSchema {
+    fn from(sample: IntervalSample) -> Self {
+        IntervalSchema {
+            start_ns: to_nanos(sample.start),
+            end_ns: to_nanos(sample.end),
+            bytes: sample.bytes,
+            bits_per_second: sample.speed() * 8.0,
+        }
+    }
+}
+
+impl From<IntervalSchema> for IntervalSample {
+    fn from(schema: IntervalSchema) -> Self {
+        IntervalSample {
+            start: Duration::from_nanos(schema.start_ns),
+            end: Duration::from_nanos(schema.end_ns),
+            bytes: schema.bytes,
+        }
+    }
+}
+
+/// Single stream of a speed test with its per-interval samples.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamSchema {
+    pub bytes: u64,
+    pub duration_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub bits_per_second: f64,
+    #[serde(default)]
+    pub intervals: Vec<IntervalSchema>,
+}
+
+/// Spread of the per-interval speed, in bits per second.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntervalStatsSchema {
+    pub min_bps: f64,
+    pub max_bps: f64,
+    pub stddev_bps: f64,
+}
+
+impl From<IntervalStats> for IntervalStatsSchema {
+    fn from(stats: IntervalStats) -> Self {
+        IntervalStatsSchema {
+            min_bps: stats.min * 8.0,
+            max_bps: stats.max * 8.0,
+            stddev_bps: stats.stddev * 8.0,
+        }
+    }
+}
+
+/// Datagram statistics of a UDP test, as measured by the receiving side.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UdpSchema {
+    pub target_bitrate_bps: u64,
+    pub sent: u64,
+    pub received: u64,
+    pub lost: u64,
+    #[serde(default, skip_deserializing)]
+    pub loss_percent: f64,
+    pub out_of_order: u64,
+    pub bytes: u64,
+    pub duration_ns: u64,
+    pub jitter_ns: u64,
+}
+
+/// Upload or download test results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeedReportSchema {
+    /// Test direction (upload/download)
+    pub direction: String,
+    pub bytes: u64,
+    pub duration_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub bits_per_second: f64,
+    /// Client-side measurement, when the report is based on server-side measurement
+    pub client: Option<TransferSchema>,
+    pub streams: Vec<StreamSchema>,
+    /// Per-interval samples summed across streams
+    #[serde(default, skip_deserializing)]
+    pub intervals: Vec<IntervalSchema>,
+    #[serde(default, skip_deserializing)]
+    pub interval_stats: Option<IntervalStatsSchema>,
+    pub udp: Option<UdpSchema>,
+}
+
+impl From<SpeedReport> for SpeedReportSchema {
+    fn from(report: SpeedReport) -> Self {
+        SpeedReportSchema {
+            direction: report.report_type.to_string(),
+            bytes: report.bytes,
+            duration_ns: to_nanos(report.duration),
+            bits_per_second: report.speed * 8.0,
+            client: report.client.map(|client| TransferSchema {
+                bytes: client.bytes,
+                duration_ns: to_nanos(client.duration),
+                bits_per_second: client.speed * 8.0,
+            }),
+            streams: report
+                .streams
+                .into_iter()
+                .map(|stream| StreamSchema {
+                    bytes: stream.bytes,
+                    duration_ns: to_nanos(stream.duration),
+                    bits_per_second: stream.speed * 8.0,
+                    intervals: stream.intervals.into_iter().map(Into::into).collect(),
+                })
+                .collect(),
+            intervals: report.intervals.into_iter().map(Into::into).collect(),
+            interval_stats: report.interval_stats.map(Into::into),
+            udp: report.udp.map(|stats| UdpSchema {
+                target_bitrate_bps: report.target_bitrate.unwrap_or(0),
+                sent: stats.sent,
+                received: stats.received,
+                lost: stats.lost,
+                loss_percent: stats.loss_percent(),
+                out_of_order: stats.out_of_order,
+                bytes: stats.bytes,
+                duration_ns: to_nanos(stats.duration),
+                jitter_ns: to_nanos(stats.jitter),
+            }),
+        }
+    }
+}
+
+impl TryFrom<SpeedReportSchema> for SpeedReport {
+    type Error = String;
+
+    fn try_from(schema: SpeedReportSchema) -> Result<Self, Self::Error> {
+        let report_type = direction(&schema.direction)?;
+        let streams = schema
+            .streams
+            .into_iter()
+            .map(|stream| {
+                StreamResult::new(Duration::from_nanos(stream.duration_ns), stream.bytes)
+                    .with_intervals(stream.intervals.into_iter().map(Into::into).collect())
+            })
+            .collect();
+        let mut report =
+            SpeedReport::from_streams(report_type, streams).map_err(|e| e.to_string())?;
+        if let Some(client) = schema.client {
+            report = report
+                .with_client_measurement(Duration::from_nanos(client.duration_ns), client.bytes);
+        }
+        if let Some(udp) = schema.udp {
+            let stats = UdpStats {
+                sent: udp.sent,
+                received: udp.received,
+                lost: udp.lost,
+                out_of_order: udp.out_of_order,
+                bytes: udp.bytes,
+                duration: Duration::from_nanos(udp.duration_ns),
+                jitter: Duration::from_nanos(udp.jitter_ns),
+            };
+            report = report.with_udp(stats, udp.target_bitrate_bps);
+        }
+        Ok(report)
+    }
+}
+
+/// Latency probes taken during one test phase, durations in nanoseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadedLatencySchema {
+    /// Test phase the probes ran during (upload/download/bidirectional)
+    pub phase: String,
+    pub samples_ns: Vec<u64>,
+    #[serde(default, skip_deserializing)]
+    pub avg_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub max_ns: u64,
+    #[serde(default, skip_deserializing)]
+    pub increase_ns: u64,
+}
+
+/// Latency under load, durations in nanoseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyReportSchema {
+    pub idle_ns: u64,
+    pub phases: Vec<LoadedLatencySchema>,
+    #[serde(default, skip_deserializing)]
+    pub increase_ns: Option<u64>,
+    #[serde(default, skip_deserializing)]
+    pub bufferbloat_grade: Option<String>,
+}
+
+impl From<LatencyReport> for LatencyReportSchema {
+    fn from(report: LatencyReport) -> Self {
+        LatencyReportSchema {
+            idle_ns: to_nanos(report.idle),
+            phases: report
+                .loaded
+                .into_iter()
+                .map(|phase| LoadedLatencySchema {
+                    phase: phase.phase.to_string(),
+                    samples_ns: phase.samples.iter().copied().map(to_nanos).collect(),
+                    avg_ns: to_nanos(phase.avg),
+                    max_ns: to_nanos(phase.max),
+                    increase_ns: to_nanos(phase.avg.saturating_sub(report.idle)),
+                })
+                .collect(),
+            increase_ns: report.increase.map(to_nanos),
+            bufferbloat_grade: report.grade.map(str::to_string),
+        }
+    }
+}
+
+impl TryFrom<LatencyReportSchema> for LatencyReport {
+    type Error = String;
+
+    fn try_from(schema: LatencyReportSchema) -> Result<Self, Self::Error> {
+        let loaded = schema
+            .phases
+            .into_iter()
+            .map(|phase| {
+                Ok(LoadedLatency::new(
+                    direction(&phase.phase)?,
+                    phase
+                        .samples_ns
+                        .into_iter()
+                        .map(Duration::from_nanos)
+                        .collect(),
+                ))
+            })
+            .collect::<Result<Vec<LoadedLatency>, String>>()?;
+        Ok(LatencyReport::new(
+            Duration::from_nanos(schema.idle_ns),
+            loaded,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::reports::Report;
+
+    fn metric_values(report: &impl Report) -> String {
+        report.to_table_report().to_string()
+    }
+
+    fn create_netbeat_report() -> NetbeatReport {
+        let ms = Duration::from_millis;
+        let sample = |start: u64, bytes: u64| IntervalSample {
+            start: ms(start),
+            end: ms(start + 1000),
+            bytes,
+        };
+        let upload = SpeedReport::from_streams(
+            "upload",
+            vec![
+                StreamResult::new(ms(2000), 3_000_000)
+                    .with_intervals(vec![sample(0, 1_000_000), sample(1000, 2_000_000)]),
+                StreamResult::new(ms(2000), 2_000_000)
+                    .with_intervals(vec![sample(0, 1_000_000), sample(1000, 1_000_000)]),
+            ],
+        )
+        .unwrap()
+        .with_client_measurement(ms(1900), 5_000_000);
+        let download = SpeedReport::new("download", ms(1000), 98_000)
+            .unwrap()
+            .with_udp(
+                UdpStats {
+                    sent: 100,
+                    received: 98,
+                    lost: 2,
+                    out_of_order: 1,
+                    bytes: 98_000,
+                    duration: ms(1000),
+                    jitter: Duration::from_micros(150),
+                },
+                1_000_000,
+            );
+        let latency = LatencyReport::new(
+            ms(10),
+            vec![
+                LoadedLatency::new("upload", vec![ms(40), ms(60)]),
+                LoadedLatency::new("download", vec![ms(12)]),
+            ],
+        );
+        NetbeatReport::new(
+            PingReport::new(4, 3, vec![ms(10), ms(12), ms(9)]),
+            upload,
+            download,
+        )
+        .with_server("nas.lan", "[fe80::1]:5050".parse().unwrap())
+        .with_tls(TlsReport {
+            version: "TLSv1.3".to_string(),
+            cipher_suite: "TLS13_AES_256_GCM_SHA384".to_string(),
+            handshake_time: ms(3),
+            fingerprint: "AB:CD".to_string(),
+        })
+        .with_bidirectional()
+        .with_latency(latency)
+        .with_parameters(TestParameters {
+            time_limit: Duration::from_secs(10),
+            data: None,
+            chunk_size: 65536,
+            ping_count: 4,
+            parallel: 2,
+            bidirectional: true,
+            udp: false,
+            bitrate: 1_000_000,
+            datagram_size: 1400,
+            latency_probe: true,
+            interval: Duration::from_secs(1),
+        })
+    }
+
+    #[test]
+    fn test_netbeat_report_schema() {
+        let report = create_netbeat_report();
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().to_string()).unwrap();
+
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["target"], "nas.lan");
+        assert_eq!(json["server_address"], "[fe80::1]:5050");
+        assert_eq!(json["parameters"]["time_limit_ns"], 10_000_000_000u64);
+        assert_eq!(json["parameters"]["chunk_size_bytes"], 65536);
+        assert_eq!(json["ping"]["ping_times_ns"][1], 12_000_000);
+        assert_eq!(json["ping"]["avg_ns"], 10_333_333);
+        assert_eq!(json["ping"]["packet_loss_percent"], 25.0);
+        assert_eq!(json["upload"]["bytes"], 5_000_000);
+        assert_eq!(json["upload"]["duration_ns"], 2_000_000_000u64);
+        assert_eq!(json["upload"]["bits_per_second"], 20e6);
+        assert_eq!(json["upload"]["client"]["bytes"], 5_000_000);
+        assert_eq!(json["upload"]["streams"].as_array().unwrap().len(), 2);
+        assert_eq!(json["upload"]["intervals"][1]["bytes"], 3_000_000);
+        assert_eq!(json["download"]["udp"]["target_bitrate_bps"], 1_000_000);
+        assert_eq!(json["download"]["udp"]["jitter_ns"], 150_000);
+        assert_eq!(json["latency"]["phases"][0]["increase_ns"], 40_000_000);
+        assert_eq!(json["latency"]["bufferbloat_grade"], "B");
+        assert_eq!(json["tls"]["version"], "TLSv1.3");
+        assert_eq!(json["tls"]["handshake_time_ns"], 3_000_000);
+        assert_eq!(json["tls"]["certificate_sha256"], "AB:CD");
+        assert!(json["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn test_netbeat_report_round_trip() {
+        let report = create_netbeat_report();
+        let parsed: NetbeatReport = serde_json::from_str(&report.to_json().to_string()).unwrap();
+
+        assert_eq!(metric_values(&parsed), metric_values(&report));
+        assert_eq!(parsed.target, report.target);
+        assert_eq!(parsed.server_addr, report.server_addr);
+        assert_eq!(parsed.parameters, report.parameters);
+        assert_eq!(parsed.tls_report, report.tls_report);
+        assert!(parsed.bidirectional);
+        assert_eq!(parsed.ping_report.ping_times, report.ping_report.ping_times);
+        assert_eq!(parsed.upload_report.streams, report.upload_report.streams);
+        assert_eq!(
+            parsed.upload_report.intervals,
+            report.upload_report.intervals
+        );
+        assert_eq!(parsed.upload_report.client, report.upload_report.client);
+        assert_eq!(parsed.download_report.udp, report.download_report.udp);
+        assert_eq!(
+            parsed.latency_report.unwrap().loaded,
+            report.latency_report.unwrap().loaded
+        );
+        // Timestamps keep millisecond precision
+        let drift = report
+            .timestamp
+            .duration_since(parsed.timestamp)
+            .unwrap_or_default();
+        assert!(drift < Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_report_round_trip() {
+        let ping = PingReport::new(4, 0, vec![]);
+        let json = ping.to_json().to_string();
+        let parsed: PingReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(metric_values(&parsed), metric_values(&ping));
+
+        let speed = SpeedReport::new("download", Duration::from_secs(1), 1_000_000).unwrap();
+        let json = speed.to_json().to_string();
+        let parsed: SpeedReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(metric_values(&parsed), metric_values(&speed));
+    }
+
+    #[test]
+    fn test_invalid_report_schema() {
+        let report = create_netbeat_report();
+        let mut json: serde_json::Value =
+            serde_json::from_str(&report.to_json().to_string()).unwrap();
+
+        json["schema_version"] = (SCHEMA_VERSION + 1).into();
+        let result = serde_json::from_value::<NetbeatReport>(json.clone());
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e.to_string().contains("Unsupported report schema version"));
+        }
+
+        json["schema_version"] = SCHEMA_VERSION.into();
+        json["upload"]["direction"] = "sideways".into();
+        let result = serde_json::from_value::<NetbeatReport>(json.clone());
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e.to_string().contains("Unknown test direction (sideways)"));
+        }
+
+        json["upload"]["direction"] = "upload".into();
+        json["ping"]["successful_pings"] = 5.into();
+        assert!(serde_json::from_value::<NetbeatReport>(json).is_err());
+    }
+}
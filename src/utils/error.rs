@@ -22,9 +22,37 @@ pub enum NetbeatError {
     #[error("Client error: {message}")]
     ClientError { message: String },
 
+    /// Protocol version mismatch between client and server
+    #[error("Protocol version mismatch: local {local}, remote {remote}")]
+    VersionMismatch { local: String, remote: String },
+
+    /// Test plan rejected by the server
+    #[error("Session rejected by server: {reason}")]
+    SessionRejected { reason: String },
+
+    /// Pre-shared key authentication errors
+    #[error("Authentication error: {message}")]
+    AuthError { message: String },
+
+    /// TLS transport, certificate and known hosts errors
+    #[error("TLS error: {message}")]
+    TlsError { message: String },
+
     /// Test execution errors
     #[error("Test execution error: {message}")]
     TestExecutionError { message: String },
+
+    /// Result history store errors
+    #[error("History error: {message}")]
+    HistoryError { message: String },
+
+    /// Configuration file and environment errors
+    #[error("Config error: {message}")]
+    ConfigError { message: String },
+
+    /// Session ended for going over a server limit
+    #[error("Session limit reached: {message}")]
+    SessionLimit { message: String },
 }
 
 /// Result type for netbeat operations
@@ -46,10 +74,45 @@ impl NetbeatError {
         Self::ClientError { message }
     }
 
+    /// Create a protocol version mismatch error
+    pub fn version_mismatch(local: String, remote: String) -> Self {
+        Self::VersionMismatch { local, remote }
+    }
+
+    /// Create a session rejected error
+    pub fn session_rejected(reason: String) -> Self {
+        Self::SessionRejected { reason }
+    }
+
+    /// Create a pre-shared key authentication error
+    pub fn auth(message: String) -> Self {
+        Self::AuthError { message }
+    }
+
+    /// Create a TLS error
+    pub fn tls(message: String) -> Self {
+        Self::TlsError { message }
+    }
+
     /// Create a test execution error
     pub fn test_execution(message: String) -> Self {
         Self::TestExecutionError { message }
     }
+
+    /// Create a result history store error
+    pub fn history(message: String) -> Self {
+        Self::HistoryError { message }
+    }
+
+    /// Create a configuration error
+    pub fn config(message: String) -> Self {
+        Self::ConfigError { message }
+    }
+
+    /// Create a session limit error
+    pub fn session_limit(message: String) -> Self {
+        Self::SessionLimit { message }
+    }
 }
 
 #[cfg(test)]
@@ -67,10 +130,44 @@ mod tests {
         let error = NetbeatError::client("Client not found".to_string());
         assert_eq!(error.to_string(), "Client error: Client not found");
 
+        let error =
+            NetbeatError::version_mismatch("NETBEAT_2.0".to_string(), "NETBEAT_1.0".to_string());
+        assert_eq!(
+            error.to_string(),
+            "Protocol version mismatch: local NETBEAT_2.0, remote NETBEAT_1.0"
+        );
+
+        let error = NetbeatError::session_rejected("Too many streams".to_string());
+        assert_eq!(
+            error.to_string(),
+            "Session rejected by server: Too many streams"
+        );
+
+        let error = NetbeatError::auth("Invalid pre-shared key".to_string());
+        assert_eq!(
+            error.to_string(),
+            "Authentication error: Invalid pre-shared key"
+        );
+
+        let error = NetbeatError::tls("Server does not support TLS".to_string());
+        assert_eq!(error.to_string(), "TLS error: Server does not support TLS");
+
         let error = NetbeatError::test_execution("Test execution failed".to_string());
         assert_eq!(
             error.to_string(),
             "Test execution error: Test execution failed"
         );
+
+        let error = NetbeatError::history("No data directory".to_string());
+        assert_eq!(error.to_string(), "History error: No data directory");
+
+        let error = NetbeatError::config("Unknown target profile".to_string());
+        assert_eq!(error.to_string(), "Config error: Unknown target profile");
+
+        let error = NetbeatError::session_limit("maximum upload of 1.00 GB exceeded".to_string());
+        assert_eq!(
+            error.to_string(),
+            "Session limit reached: maximum upload of 1.00 GB exceeded"
+        );
     }
 }
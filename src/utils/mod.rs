@@ -1,8 +1,9 @@
 //! Utility modules, providing various functionality.
 //!
 //! This module contains utility functions and modules that are used throughout the application for
-//! error handling and logging.
+//! error handling, logging and unit parsing.
 //!
 
 pub mod error;
 pub mod logging;
+pub mod units;
@@ -0,0 +1,127 @@
+//! Unit parsing utilities for netbeat.
+
+use byte_unit::Bit;
+use std::time::Duration;
+
+/// Parse a bitrate in bits per second, with optional units (eg, 500Kbps, 100Mbps, 2.5Gbps, 1G).
+///
+/// Units default to bits, a `B` denotes bytes (eg, 10MB/s is 80Mbps).
+pub fn parse_bitrate(s: &str) -> Result<u64, String> {
+    let trimmed = s.trim();
+    let value = trimmed
+        .strip_suffix("ps")
+        .or_else(|| trimmed.strip_suffix("/s"))
+        .unwrap_or(trimmed);
+    let bits = Bit::parse_str(value)
+        .map_err(|e| format!("Invalid bitrate ({s:?}) - {e}"))?
+        .as_u128();
+    match u64::try_from(bits) {
+        Ok(0) => Err(format!(
+            "Invalid bitrate ({s:?}) - must be greater than zero"
+        )),
+        Ok(bits) => Ok(bits),
+        Err(_) => Err(format!("Invalid bitrate ({s:?}) - too large")),
+    }
+}
+
+/// Parse a duration with units (eg, 500us, 2ms, 1.5s).
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let trimmed = s.trim();
+    // humantime has no fractional values, so fall back to a number of seconds with a suffix
+    humantime::parse_duration(trimmed)
+        .or_else(|e| {
+            let (value, scale) = [
+                ("ms", 1e-3),
+                ("us", 1e-6),
+                ("µs", 1e-6),
+                ("ns", 1e-9),
+                ("s", 1.0),
+            ]
+            .iter()
+            .find_map(|(suffix, scale)| trimmed.strip_suffix(suffix).map(|v| (v, *scale)))
+            .ok_or(e.to_string())?;
+            let value: f64 = value.trim().parse().map_err(|_| e.to_string())?;
+            Duration::try_from_secs_f64(value * scale).map_err(|e| e.to_string())
+        })
+        .map_err(|e| format!("Invalid duration ({s:?}) - {e}"))
+}
+
+/// Parse a percentage between 0 and 100, with or without a `%` sign (eg, 0%, 0.5%, 2).
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let trimmed = s.trim();
+    let percent: f64 = trimmed
+        .strip_suffix('%')
+        .unwrap_or(trimmed)
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid percentage ({s:?}) - expected a number, eg 0.5%"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!(
+            "Invalid percentage ({s:?}) - must be between 0% and 100%"
+        ));
+    }
+    Ok(percent)
+}
+
+/// Format a bitrate in bits per second as Mbps.
+pub fn format_bitrate(bits_per_second: f64) -> String {
+    format!("{:.2} Mbps", bits_per_second / 1e6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bitrate() {
+        assert_eq!(parse_bitrate("1000").unwrap(), 1000);
+        assert_eq!(parse_bitrate("500Kbps").unwrap(), 500_000);
+        assert_eq!(parse_bitrate("100Mbps").unwrap(), 100_000_000);
+        assert_eq!(parse_bitrate("100Mb").unwrap(), 100_000_000);
+        assert_eq!(parse_bitrate("2.5Gbps").unwrap(), 2_500_000_000);
+        assert_eq!(parse_bitrate("1G").unwrap(), 1_000_000_000);
+        assert_eq!(parse_bitrate("10MB/s").unwrap(), 80_000_000);
+
+        for invalid in ["", "fast", "0Mbps", "100Xbps"] {
+            let result = parse_bitrate(invalid);
+            assert!(result.is_err(), "{invalid} should be invalid");
+            assert!(result.unwrap_err().contains("Invalid bitrate"));
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("2ms").unwrap(), Duration::from_millis(2));
+        assert_eq!(parse_duration("500us").unwrap(), Duration::from_micros(500));
+        assert_eq!(
+            parse_duration("1.5ms").unwrap(),
+            Duration::from_micros(1500)
+        );
+        assert_eq!(parse_duration("1s").unwrap(), Duration::from_secs(1));
+
+        for invalid in ["", "2", "fast", "-1ms"] {
+            let result = parse_duration(invalid);
+            assert!(result.is_err(), "{invalid} should be invalid");
+            assert!(result.unwrap_err().contains("Invalid duration"));
+        }
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("0%").unwrap(), 0.0);
+        assert_eq!(parse_percent("0.5%").unwrap(), 0.5);
+        assert_eq!(parse_percent("2").unwrap(), 2.0);
+
+        for invalid in ["", "%", "lots", "101%", "-1%"] {
+            let result = parse_percent(invalid);
+            assert!(result.is_err(), "{invalid} should be invalid");
+            assert!(result.unwrap_err().contains("Invalid percentage"));
+        }
+    }
+
+    #[test]
+    fn test_format_bitrate() {
+        assert_eq!(format_bitrate(1e6), "1.00 Mbps");
+        assert_eq!(format_bitrate(9.4e8), "940.00 Mbps");
+    }
+}
@@ -52,6 +52,32 @@ fn test_run_with_invalid_target_fails() {
         .stderr(predicate::str::contains("❌"));
 }
 
+#[test]
+fn test_run_with_invalid_config_fails() {
+    let path = std::env::temp_dir().join(format!(
+        "netbeat-test-{}-invalid-config.toml",
+        std::process::id()
+    ));
+    std::fs::write(&path, "[targets.nas]\naddress = \"nas.lan\"\nthreads = 4\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("netbeat").unwrap();
+    cmd.args(["run", "nas", "--config"]).arg(&path);
+
+    cmd.assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains("unknown field `threads`"));
+    std::fs::remove_file(&path).unwrap();
+
+    let mut cmd = Command::cargo_bin("netbeat").unwrap();
+    cmd.args(["serve", "--config"]).arg(&path);
+
+    cmd.assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains("Config error: Failed to read"));
+}
+
 #[test]
 fn test_serve_help() {
     let mut cmd = Command::cargo_bin("netbeat").unwrap();
@@ -105,7 +131,15 @@ fn test_client_server_flow_cli() {
     for i in 1..=10 {
         println!("Attempt {}", i);
         let mut run_cmd = Command::cargo_bin("netbeat").unwrap();
-        run_cmd.args(["run", "0.0.0.0", "-t", "2", "--retries", "10"]);
+        run_cmd.args([
+            "run",
+            "0.0.0.0",
+            "-t",
+            "2",
+            "--retries",
+            "10",
+            "--no-history",
+        ]);
         match run_cmd.ok() {
             Ok(_) => break,
             Err(e) => {
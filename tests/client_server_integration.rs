@@ -1,23 +1,49 @@
-use netbeat::{BindInterface, Client, Server};
+use netbeat::core::{server::ServerBuilder, tls::TlsServer};
+use netbeat::{BindInterface, Client, Server, ServerHandle};
 use std::thread;
 use std::time::Duration;
 
+/// Server spawned on a free localhost port, shut down when dropped so a failing test doesn't leave it running.
+struct TestServer(Option<ServerHandle>);
+
+impl TestServer {
+    /// Spawn a quiet server from the given builder.
+    fn start(builder: ServerBuilder) -> Self {
+        let handle = builder
+            .interface(BindInterface::Localhost)
+            .port(0)
+            .quiet(true)
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        TestServer(Some(handle))
+    }
+
+    /// Address for clients to target.
+    fn addr(&self) -> String {
+        self.0.as_ref().unwrap().local_addr().to_string()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.take() {
+            handle.shutdown();
+            let result = handle.join();
+            // Don't panic again while unwinding from a failed assertion
+            if !thread::panicking() {
+                result.unwrap();
+            }
+        }
+    }
+}
+
 #[test]
 fn test_basic_client_server_flow() {
     // Start server in background
-    let server = Server::builder()
-        .interface(BindInterface::Localhost)
-        .quiet(true)
-        .build()
-        .unwrap();
-
-    let ip_addr = server.socket_addr.ip().to_string();
-    let _server_handle = thread::spawn(move || {
-        let _ = server.listen();
-    });
-
-    // Give server time to start
-    thread::sleep(Duration::from_millis(500));
+    let server = TestServer::start(Server::builder());
+    let ip_addr = server.addr();
 
     // Test both time and target data based speed test
     for i in [None, Some("100MB")] {
@@ -41,19 +67,51 @@ fn test_basic_client_server_flow() {
 }
 
 #[test]
-fn test_multiple_clients() {
-    let server = Server::builder()
-        .interface(BindInterface::Localhost)
+fn test_interval_reports() {
+    let server = TestServer::start(Server::builder());
+
+    let report = Client::builder(server.addr())
+        .time(2)
         .quiet(true)
         .build()
+        .unwrap()
+        .contact()
         .unwrap();
 
-    let ip_addr = server.socket_addr.ip().to_string();
-    let _server_handle = thread::spawn(move || {
-        let _ = server.listen();
-    });
+    // One second intervals recorded by the receiving side
+    assert_eq!(report.upload_report.intervals.len(), 2);
+    assert_eq!(report.download_report.intervals.len(), 2);
+    assert!(report.download_report.interval_stats.is_some());
+}
 
-    thread::sleep(Duration::from_millis(100));
+#[test]
+fn test_latency_probe() {
+    let server = TestServer::start(Server::builder());
+
+    let report = Client::builder(server.addr())
+        .time(2)
+        .latency_probe(true)
+        .quiet(true)
+        .build()
+        .unwrap()
+        .contact()
+        .unwrap();
+
+    // Latency probed under load in both directions
+    let latency_report = report.latency_report.unwrap();
+    let phases: Vec<&str> = latency_report
+        .loaded
+        .iter()
+        .map(|phase| phase.phase)
+        .collect();
+    assert_eq!(phases, ["upload", "download"]);
+    assert!(latency_report.grade.is_some());
+}
+
+#[test]
+fn test_multiple_clients() {
+    let server = TestServer::start(Server::builder());
+    let ip_addr = server.addr();
 
     // Launch multiple clients
     let mut handles = vec![];
@@ -81,3 +139,237 @@ fn test_multiple_clients() {
         assert!(result.is_ok());
     }
 }
+
+#[test]
+fn test_parallel_streams() {
+    let server = TestServer::start(Server::builder());
+    let ip_addr = server.addr();
+
+    // Test both time and target data based speed test
+    for i in [None, Some("100MB")] {
+        let client = Client::builder(ip_addr.clone())
+            .data(i)
+            .time(2)
+            .parallel(4)
+            .quiet(true)
+            .build()
+            .unwrap();
+
+        let report = client.contact().unwrap();
+        for speed_report in [&report.upload_report, &report.download_report] {
+            assert_eq!(speed_report.streams.len(), 4);
+            assert!(speed_report.streams.iter().all(|stream| stream.bytes > 0));
+            assert_eq!(
+                speed_report.bytes,
+                speed_report.streams.iter().map(|s| s.bytes).sum::<u64>()
+            );
+        }
+        if i.is_some() {
+            assert_eq!(report.upload_report.bytes, 100_000_000);
+            // Downloads stop at the first whole frame past the target
+            assert!(report.download_report.bytes >= 100_000_000);
+        }
+    }
+}
+
+#[test]
+fn test_bidirectional() {
+    let server = TestServer::start(Server::builder());
+    let ip_addr = server.addr();
+
+    for parallel in [1, 2] {
+        let client = Client::builder(ip_addr.clone())
+            .time(2)
+            .parallel(parallel)
+            .bidirectional(true)
+            .latency_probe(true)
+            .quiet(true)
+            .build()
+            .unwrap();
+
+        let report = client.contact().unwrap();
+        assert!(report.bidirectional);
+        assert_eq!(
+            report.latency_report.unwrap().loaded[0].phase,
+            "bidirectional"
+        );
+        for speed_report in [&report.upload_report, &report.download_report] {
+            assert_eq!(speed_report.streams.len(), parallel as usize);
+            assert!(speed_report.bytes > 0);
+            // Both directions run over the same window
+            assert!(speed_report.duration > Duration::from_millis(1500));
+            assert!(speed_report.duration < Duration::from_secs(3));
+        }
+    }
+}
+
+#[test]
+fn test_udp() {
+    let server = TestServer::start(Server::builder());
+    let ip_addr = server.addr();
+
+    // Test both time and target data based UDP test
+    for data in [None, Some("1MB")] {
+        let client = Client::builder(ip_addr.clone())
+            .data(data)
+            .time(1)
+            .ping_count(2)
+            .udp(true)
+            .bitrate("10Mbps")
+            .quiet(true)
+            .build()
+            .unwrap();
+
+        let report = client.contact().unwrap();
+        for speed_report in [&report.upload_report, &report.download_report] {
+            let stats = speed_report.udp.unwrap();
+            assert!(stats.sent > 0);
+            assert!(stats.received > 0);
+            assert_eq!(stats.sent, stats.received + stats.lost);
+            assert_eq!(speed_report.bytes, stats.bytes);
+        }
+    }
+}
+
+#[test]
+fn test_pre_shared_key() {
+    let server = TestServer::start(Server::builder().psk(Some("correct horse")));
+    let ip_addr = server.addr();
+
+    let client = |psk: Option<&str>| {
+        Client::builder(ip_addr.clone())
+            .time(1)
+            .ping_count(2)
+            .psk(psk)
+            .quiet(true)
+            .build()
+            .unwrap()
+            .contact()
+    };
+
+    let report = client(Some("correct horse")).unwrap();
+    assert!(report.upload_report.bytes > 0);
+    assert!(report.download_report.bytes > 0);
+
+    for (psk, message) in [
+        (Some("battery staple"), "Invalid pre-shared key"),
+        (None, "Server requires a pre-shared key"),
+    ] {
+        let Err(error) = client(psk) else {
+            panic!("Expected authentication to fail");
+        };
+        assert!(error.to_string().contains(message), "{error}");
+    }
+}
+
+#[test]
+fn test_tls() {
+    let dir = std::env::temp_dir().join(format!("netbeat-test-{}-tls", std::process::id()));
+    let certificate = TlsServer::load_or_generate(&dir).unwrap();
+    let known_hosts = dir.join("known_hosts");
+
+    let server = TestServer::start(
+        Server::builder()
+            .tls_cert(Some(dir.join("cert.pem")))
+            .tls_key(Some(dir.join("key.pem"))),
+    );
+    let plain_server = TestServer::start(Server::builder());
+
+    let client = |server: &TestServer, tls: bool| {
+        Client::builder(server.addr())
+            .time(1)
+            .ping_count(2)
+            .parallel(2)
+            .tls(tls)
+            .known_hosts_file(&known_hosts)
+            .quiet(true)
+            .build()
+            .unwrap()
+            .contact()
+    };
+
+    // Pinned on first use, then checked against the pin
+    for _ in 0..2 {
+        let report = client(&server, true).unwrap();
+        let tls_report = report.tls_report.unwrap();
+        assert_eq!(tls_report.fingerprint, certificate.fingerprint);
+        assert!(tls_report.handshake_time > Duration::ZERO);
+        assert!(report.upload_report.bytes > 0);
+        assert!(report.download_report.bytes > 0);
+    }
+    let pins = std::fs::read_to_string(&known_hosts).unwrap();
+    assert_eq!(pins.lines().count(), 1);
+
+    for (target, tls, message) in [
+        (&server, false, "Server requires TLS"),
+        (&plain_server, true, "Server does not support TLS"),
+    ] {
+        let Err(error) = client(target, tls) else {
+            panic!("Expected TLS negotiation to fail");
+        };
+        assert!(error.to_string().contains(message), "{error}");
+    }
+
+    // A replaced certificate no longer matches the pin
+    std::fs::write(
+        &known_hosts,
+        pins.replace(&certificate.fingerprint, "00:11"),
+    )
+    .unwrap();
+    let Err(error) = client(&server, true) else {
+        panic!("Expected the pinned fingerprint check to fail");
+    };
+    assert!(error.to_string().contains("does not match"), "{error}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_session_limits() {
+    // Downloads run past their target until the client's stop arrives, so the TCP limit leaves headroom
+    for (limit, udp, within, over, message) in [
+        (
+            "100MB",
+            false,
+            "10MB",
+            "200MB",
+            "maximum upload of 100.00 MB exceeded",
+        ),
+        (
+            "1MB",
+            true,
+            "500KB",
+            "2MB",
+            "maximum upload of 1.00 MB exceeded",
+        ),
+    ] {
+        let server = TestServer::start(Server::builder().max_direction_bytes(Some(limit)));
+
+        let client = |data: &str| {
+            Client::builder(server.addr())
+                .data(Some(data))
+                .ping_count(2)
+                .udp(udp)
+                .bitrate("10Mbps")
+                .quiet(true)
+                .build()
+                .unwrap()
+                .contact()
+        };
+
+        let report = client(within).unwrap();
+        assert!(report.upload_report.bytes > 0);
+        assert!(report.download_report.bytes > 0);
+
+        // Sessions going over the limit are ended with the limit reported to the client
+        let Err(error) = client(over) else {
+            panic!("Expected the session limit to end the test");
+        };
+        assert!(
+            error
+                .to_string()
+                .contains(&format!("Session limit reached: {message}")),
+            "{error}"
+        );
+    }
+}